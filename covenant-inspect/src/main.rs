@@ -0,0 +1,107 @@
+//! Dumps the on-disk structures of an archive, for debugging corrupted archives in the field.
+//!
+//! Nothing is ever written, so this is safe to run against an archive that is in use.
+
+use std::path::Path;
+
+use anyhow::bail;
+use bstr::BStr;
+use phobos::inspect::LogEntry;
+use seqstore::raw_store::{Anomaly, RegionKind};
+
+const USAGE: &str = "\
+usage:
+    covenant-inspect index <dir> <prefix>   list the FSTs in a phobos index
+    covenant-inspect log <dir> <prefix>     print the entries in a phobos write-ahead log
+    covenant-inspect store <file>           list the entries and gaps in a seqstore file";
+
+fn main() -> anyhow::Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    match args[..] {
+        ["index", dir, prefix] => index(Path::new(dir), prefix),
+        ["log", dir, prefix] => log(Path::new(dir), prefix),
+        ["store", file] => store(Path::new(file)),
+        _ => {
+            eprintln!("{USAGE}");
+            bail!("invalid arguments");
+        }
+    }
+}
+
+fn index(dir: &Path, prefix: &str) -> anyhow::Result<()> {
+    let fsts = phobos::inspect::fsts(dir, prefix)?;
+    println!("{} FSTs, {} items total", fsts.len(), fsts.iter().map(|f| f.count).sum::<u64>());
+    for fst in fsts {
+        let file = match fst.file_len {
+            Some(len) => format!("{len} B"),
+            None => "MISSING".to_owned(),
+        };
+        println!("id {:>6}  level {:>3}  count {:>10}  {file:>12}  {}", fst.id, fst.level, fst.count, fst.path.display());
+    }
+    Ok(())
+}
+
+fn log(dir: &Path, prefix: &str) -> anyhow::Result<()> {
+    let contents = phobos::inspect::log(dir, prefix)?;
+    for (offset, entry) in &contents.entries {
+        match entry {
+            LogEntry::Insert { key, value } => println!("{offset:>10}  insert {:?} -> {value}", BStr::new(key)),
+            LogEntry::Flushed => println!("{offset:>10}  flushed"),
+            LogEntry::InsertValue { key, value } => println!("{offset:>10}  insert {:?} -> {:?}", BStr::new(key), BStr::new(value)),
+            LogEntry::Batch { items } => {
+                println!("{offset:>10}  batch of {}", items.len());
+                for (key, value) in items {
+                    println!("{:>10}    insert {:?} -> {value}", "", BStr::new(key));
+                }
+            }
+        }
+    }
+    if let Some((offset, e)) = contents.error {
+        println!("{offset:>10}  unreadable: {e}");
+    }
+    Ok(())
+}
+
+fn store(file: &Path) -> anyhow::Result<()> {
+    let bytes = fs_err::read(file)?;
+    let report = seqstore::raw_store::describe(&bytes)?;
+    println!(
+        "version {:?}  spec magic {:?}  header {} B  {}",
+        report.version,
+        report.spec_magic,
+        report.header_length,
+        if report.free_list { "with free list" } else { "no free list" }
+    );
+    if let Some(c) = report.counters {
+        println!("{} adds  {} removes  {} B written", c.adds, c.removes, c.bytes_written);
+    }
+    for region in &report.regions {
+        let kind = match region.kind {
+            RegionKind::Written => "written",
+            RegionKind::Continued => "continued",
+            RegionKind::Writing => "writing",
+            RegionKind::Deleted => "deleted",
+            _ => "unknown",
+        };
+        let data = &bytes[region.at + region.tag_length..region.at + region.tag_length + region.length];
+        match region.kind {
+            RegionKind::Deleted => println!("{:>10}  {kind:<9}  {:>10} B", region.at, region.length),
+            _ => println!("{:>10}  {kind:<9}  {:>10} B  {:?}", region.at, region.length, BStr::new(&data[..data.len().min(32)])),
+        }
+    }
+    if let Some(end) = report.end {
+        println!("{end:>10}  end");
+    }
+    for anomaly in &report.anomalies {
+        match anomaly {
+            Anomaly::PartialWrite { position } => println!("{position:>10}  partial write"),
+            Anomaly::OrphanedContinuation { position } => println!("{position:>10}  continuation of nothing"),
+            Anomaly::UnreadableTag { position, byte } => println!("{position:>10}  unreadable tag {byte:08b}"),
+            Anomaly::DataAfterEnd { first_data_at, .. } => println!("{first_data_at:>10}  data after end"),
+            Anomaly::NoEnd => println!("{:>10}  no end tag", bytes.len()),
+            other => println!("{:>10}  {other:?}", ""),
+        }
+    }
+    Ok(())
+}
@@ -1,75 +1,191 @@
-use std::ops::{Index, IndexMut};
+use std::{
+    fmt::{Debug, Display, Formatter},
+    ops::{Index, IndexMut},
+};
 
-use digest::{Digest, Output};
+use digest::Digest;
 
+/// The digests of a single object, one for each [`HashKind`].
+///
+/// These are exactly the digests that an [`Ark`][crate::Ark] uses to index objects, so they can be
+/// computed locally and compared against [`Ark::hashes_of`][crate::Ark::hashes_of] to verify
+/// an object end-to-end.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Hashes(HashesMap<Box<[u8]>>);
+
+impl Hashes {
+    /// Computes all digests of `b`.
+    ///
+    /// For larger inputs each digest is computed on its own thread, so this should be called
+    /// from a blocking context (_e.g._ [`spawn_blocking`][tokio::task::spawn_blocking]). Prefer
+    /// this over a [`HashesBuilder`] whenever the whole input is at hand (_e.g._ a memory-mapped
+    /// file), as threads are only spawned once for the whole input rather than for every chunk.
+    pub fn extract(b: &[u8]) -> Self {
+        let mut hashers = HashesMap::new_with(HashKind::hasher);
+        update_all((&mut hashers).into_iter().map(|(_, h)| h), b);
+        Self(hashers.map(Hasher::finalize))
+    }
+
+    /// Creates a [`HashesBuilder`] that can be fed data incrementally.
+    pub fn hasher() -> HashesBuilder {
+        HashesBuilder(HashesMap::new_with(HashKind::hasher))
+    }
+
+    /// Returns the raw digest for `kind`.
+    pub fn get(&self, kind: HashKind) -> &[u8] {
+        &self.0[kind]
+    }
+
+    /// Returns the digest for `kind` as a lowercase hex string.
+    pub fn hex(&self, kind: HashKind) -> String {
+        hex(self.get(kind))
+    }
+
+    /// Iterates over every `(kind, digest)` pair, in the order of [`HashKind::ALL`].
+    pub fn iter(&self) -> <&Self as IntoIterator>::IntoIter {
+        self.into_iter()
+    }
+}
+
+impl Display for Hashes {
+    /// Writes one `name:hex` pair per line.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, (kind, digest)) in self.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}:{}", kind.name(), hex(digest))?;
+        }
+        Ok(())
+    }
+}
+
+fn hex(b: &[u8]) -> String {
+    use std::fmt::Write;
+
+    let mut s = String::with_capacity(b.len() * 2);
+    for byte in b {
+        let _ = write!(s, "{byte:02x}");
+    }
+    s
+}
+
+/// Computes [`Hashes`] from data that is fed in one chunk at a time.
+///
+/// Created with [`Hashes::hasher`].
 #[derive(Debug)]
-pub(crate) struct Hashes {
-    md5: [u8; 16],
-    sha1: [u8; 20],
-    sha2: [u8; 32],
-    sha3: [u8; 32],
-    blake2b: [u8; 64],
-    blake3: [u8; 32],
+pub struct HashesBuilder(HashesMap<Hasher>);
+
+impl HashesBuilder {
+    /// Feeds `b` into every digest, one after another on the current thread.
+    ///
+    /// Spawning a thread per digest for every chunk would cost more than it saves for typical
+    /// chunk sizes, so use [`Hashes::extract`] instead to hash a large input in parallel.
+    pub fn update(&mut self, b: &[u8]) {
+        for (_, hasher) in &mut self.0 {
+            hasher.update(b);
+        }
+    }
+
+    /// Finishes every digest, returning the [`Hashes`] of everything fed in so far.
+    pub fn finalize(self) -> Hashes {
+        Hashes(self.0.map(Hasher::finalize))
+    }
 }
 
-fn hash<H: Digest, const N: usize>(b: &[u8]) -> [u8; N]
-where
-    [u8; N]: From<Output<H>>,
-{
-    H::new().chain_update(b).finalize().into()
+/// Inputs smaller than this are hashed on the current thread by [`update_all`], as spawning
+/// threads would cost more than it saves.
+const PARALLEL_THRESHOLD: usize = 64 * 1024;
+
+/// Feeds all of `b` into each of `hashers`, each on its own thread for larger inputs.
+pub(crate) fn update_all<'a>(hashers: impl IntoIterator<Item = &'a mut Hasher>, b: &[u8]) {
+    let mut hashers = hashers.into_iter();
+    if b.len() < PARALLEL_THRESHOLD {
+        hashers.for_each(|hasher| hasher.update(b));
+        return;
+    }
+
+    std::thread::scope(|s| {
+        // The current thread may as well do something useful rather than just waiting
+        let first = hashers.next();
+        for hasher in hashers {
+            s.spawn(|| hasher.update(b));
+        }
+        if let Some(first) = first {
+            first.update(b);
+        }
+    });
 }
 
-impl Hashes {
-    pub(crate) fn extract(b: &[u8]) -> anyhow::Result<Self> {
-        let md5 = md5::compute(b).0;
-        let sha1 = hash::<sha1::Sha1, 20>(b);
-        let sha2 = hash::<sha2::Sha256, 32>(b);
-        let sha3 = hash::<sha3::Sha3_256, 32>(b);
-        let blake2b = hash::<blake2::Blake2b512, 64>(b);
-        let blake3 = blake3::hash(b).into();
+/// The in-progress state of a single digest.
+pub(crate) enum Hasher {
+    MD5(md5::Context),
+    SHA1(sha1::Sha1),
+    SHA2(sha2::Sha256),
+    SHA3(sha3::Sha3_256),
+    Blake2b(blake2::Blake2b512),
+    Blake3(Box<blake3::Hasher>),
+}
 
-        Ok(Self {
-            md5,
-            sha1,
-            sha2,
-            sha3,
-            blake2b,
-            blake3,
-        })
+impl Hasher {
+    pub(crate) fn update(&mut self, b: &[u8]) {
+        match self {
+            Self::MD5(h) => h.consume(b),
+            Self::SHA1(h) => Digest::update(h, b),
+            Self::SHA2(h) => Digest::update(h, b),
+            Self::SHA3(h) => Digest::update(h, b),
+            Self::Blake2b(h) => Digest::update(h, b),
+            Self::Blake3(h) => {
+                h.update(b);
+            }
+        }
+    }
+
+    pub(crate) fn finalize(self) -> Box<[u8]> {
+        match self {
+            Self::MD5(h) => Box::new(h.compute().0),
+            Self::SHA1(h) => h.finalize().to_vec().into_boxed_slice(),
+            Self::SHA2(h) => h.finalize().to_vec().into_boxed_slice(),
+            Self::SHA3(h) => h.finalize().to_vec().into_boxed_slice(),
+            Self::Blake2b(h) => h.finalize().to_vec().into_boxed_slice(),
+            Self::Blake3(h) => Box::new(*h.finalize().as_bytes()),
+        }
     }
 }
 
-#[derive(Debug, Clone)]
-pub(crate) struct HashesMap<V>([V; 6]);
+impl Debug for Hasher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hasher").finish_non_exhaustive()
+    }
+}
+
+/// A map with exactly one value for every [`HashKind`].
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub(crate) struct HashesMap<V>([V; HashKind::COUNT]);
 
 impl<V> HashesMap<V> {
     pub(crate) fn new_with(mut f: impl FnMut(HashKind) -> V) -> Self {
-        Self([
-            f(HashKind::MD5),
-            f(HashKind::SHA1),
-            f(HashKind::SHA2),
-            f(HashKind::SHA3),
-            f(HashKind::Blake2b),
-            f(HashKind::Blake3),
-        ])
+        Self(std::array::from_fn(|i| f(HashKind::ALL[i])))
     }
 
     pub(crate) fn try_new_with<E>(mut f: impl FnMut(HashKind) -> Result<V, E>) -> Result<Self, E> {
-        Ok(Self([
-            f(HashKind::MD5)?,
-            f(HashKind::SHA1)?,
-            f(HashKind::SHA2)?,
-            f(HashKind::SHA3)?,
-            f(HashKind::Blake2b)?,
-            f(HashKind::Blake3)?,
-        ]))
+        let mut values = Vec::with_capacity(HashKind::COUNT);
+        for kind in HashKind::ALL {
+            values.push(f(kind)?);
+        }
+        Ok(Self(values.try_into().unwrap_or_else(|_| unreachable!())))
     }
 
+    #[allow(dead_code)]
     pub(crate) fn new_from(v: V) -> Self
     where
         V: Clone,
     {
-        Self([v.clone(), v.clone(), v.clone(), v.clone(), v.clone(), v])
+        Self(std::array::from_fn(|_| v.clone()))
+    }
+
+    pub(crate) fn map<U>(self, f: impl FnMut(V) -> U) -> HashesMap<U> {
+        HashesMap(self.0.map(f))
     }
 }
 
@@ -77,18 +193,18 @@ impl<V> Index<HashKind> for HashesMap<V> {
     type Output = V;
 
     fn index(&self, index: HashKind) -> &Self::Output {
-        &self.0[index as u8 as usize]
+        &self.0[index.idx()]
     }
 }
 
 impl<V> IndexMut<HashKind> for HashesMap<V> {
     fn index_mut(&mut self, index: HashKind) -> &mut Self::Output {
-        &mut self.0[index as u8 as usize]
+        &mut self.0[index.idx()]
     }
 }
 
 fn into_iter_map<V>((i, v): (usize, &mut V)) -> (HashKind, &mut V) {
-    (HashKind::from_idx(i), v)
+    (HashKind::ALL[i], v)
 }
 
 impl<'a, V> IntoIterator for &'a mut HashesMap<V> {
@@ -100,9 +216,17 @@ impl<'a, V> IntoIterator for &'a mut HashesMap<V> {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-#[repr(u8)]
-pub(crate) enum HashKind {
+/// The registry of every supported hash algorithm.
+///
+/// Which algorithms are supported may change between versions, so this should not be matched on
+/// exhaustively. Use [`ALL`][Self::ALL] to iterate over the currently supported algorithms.
+///
+/// To add a new algorithm, add a variant here with a new, never-before-used [id][Self::id], add
+/// it to [`ALL`][Self::ALL] and fill in the `match`es. Archives created before the algorithm was
+/// added will [backfill][crate::Ark::backfill] its index on demand.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum HashKind {
     MD5,
     SHA1,
     SHA2,
@@ -112,20 +236,41 @@ pub(crate) enum HashKind {
 }
 
 impl HashKind {
+    pub(crate) const COUNT: usize = Self::ALL.len();
+    /// Every supported algorithm.
+    pub const ALL: [Self; 6] = [Self::MD5, Self::SHA1, Self::SHA2, Self::SHA3, Self::Blake2b, Self::Blake3];
+
+    /// The position of this kind within [`ALL`][Self::ALL].
+    ///
+    /// Unlike [`id`][Self::id], this is **not** stable and must not be written to disk.
     #[inline]
-    fn from_idx(i: usize) -> Self {
-        match i as u8 {
-            0 => Self::MD5,
-            1 => Self::SHA1,
-            2 => Self::SHA2,
-            3 => Self::SHA3,
-            4 => Self::Blake2b,
-            5 => Self::Blake3,
-            _ => unreachable!(),
+    fn idx(self) -> usize {
+        match self {
+            Self::MD5 => 0,
+            Self::SHA1 => 1,
+            Self::SHA2 => 2,
+            Self::SHA3 => 3,
+            Self::Blake2b => 4,
+            Self::Blake3 => 5,
+        }
+    }
+
+    /// The stable on-disk id of this kind.
+    ///
+    /// Ids must never be changed or reused, even if a kind is removed.
+    pub fn id(self) -> u16 {
+        match self {
+            Self::MD5 => 1,
+            Self::SHA1 => 2,
+            Self::SHA2 => 3,
+            Self::SHA3 => 4,
+            Self::Blake2b => 5,
+            Self::Blake3 => 6,
         }
     }
 
-    pub(crate) fn name(self) -> &'static str {
+    /// A short, lowercase name for this algorithm, _e.g._ `"sha2"`.
+    pub fn name(self) -> &'static str {
         match self {
             Self::MD5 => "md5",
             Self::SHA1 => "sha1",
@@ -135,21 +280,66 @@ impl HashKind {
             Self::Blake3 => "blake3",
         }
     }
+
+    pub(crate) fn hasher(self) -> Hasher {
+        match self {
+            Self::MD5 => Hasher::MD5(md5::Context::new()),
+            Self::SHA1 => Hasher::SHA1(sha1::Sha1::new()),
+            Self::SHA2 => Hasher::SHA2(sha2::Sha256::new()),
+            Self::SHA3 => Hasher::SHA3(sha3::Sha3_256::new()),
+            Self::Blake2b => Hasher::Blake2b(blake2::Blake2b512::new()),
+            Self::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+#[allow(clippy::borrowed_box)]
+fn into_iter_ref_map((i, v): (usize, &Box<[u8]>)) -> (HashKind, &[u8]) {
+    (HashKind::ALL[i], v)
 }
 
 impl<'a> IntoIterator for &'a Hashes {
     type Item = (HashKind, &'a [u8]);
-    type IntoIter = std::array::IntoIter<(HashKind, &'a [u8]), 6>;
+    type IntoIter = std::iter::Map<std::iter::Enumerate<std::slice::Iter<'a, Box<[u8]>>>, fn((usize, &Box<[u8]>)) -> (HashKind, &[u8])>;
 
     fn into_iter(self) -> Self::IntoIter {
-        [
-            (HashKind::MD5, self.md5.as_ref()),
-            (HashKind::SHA1, self.sha1.as_ref()),
-            (HashKind::SHA2, self.sha2.as_ref()),
-            (HashKind::SHA3, self.sha3.as_ref()),
-            (HashKind::Blake2b, self.blake2b.as_ref()),
-            (HashKind::Blake3, self.blake3.as_ref()),
-        ]
-        .into_iter()
+        self.0 .0.iter().enumerate().map(into_iter_ref_map)
+    }
+}
+
+impl Display for HashKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunked() {
+        let data = (0..3 * PARALLEL_THRESHOLD).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let whole = Hashes::extract(&data);
+        assert_eq!(whole.hex(HashKind::SHA2).len(), 64);
+
+        // The parallel and sequential paths must give the same digests, however the input is split
+        let mut hasher = Hashes::hasher();
+        hasher.update(&data[..10]);
+        hasher.update(&data[10..2 * PARALLEL_THRESHOLD]);
+        hasher.update(&data[2 * PARALLEL_THRESHOLD..]);
+        assert_eq!(hasher.finalize(), whole);
+        assert_eq!(Hashes::extract(&data[..10]), {
+            let mut hasher = Hashes::hasher();
+            hasher.update(&data[..10]);
+            hasher.finalize()
+        });
+    }
+
+    #[test]
+    fn known_digest() {
+        let hashes = Hashes::extract(b"abc");
+        assert_eq!(hashes.hex(HashKind::MD5), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(hashes.hex(HashKind::SHA2), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
     }
 }
@@ -0,0 +1,77 @@
+use std::{
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::bail;
+
+use crate::hashes::HashKind;
+
+/// The archive-level index, recording which hash kinds have a complete lookup.
+///
+/// Hash kinds are stored by their [stable id][HashKind::id], so that kinds can be added to (or
+/// removed from) the registry without invalidating existing archives.
+#[derive(Debug, Clone)]
+pub(crate) struct ArkIndex {
+    /// Includes ids that are unknown to this version, so that they are preserved on rewrite.
+    kinds: Vec<u16>,
+}
+
+impl ArkIndex {
+    const MAGIC: &'static [u8] = b"\xFEARKidx";
+    const VERSION: u8 = 0;
+
+    pub(crate) fn new(kinds: impl IntoIterator<Item = HashKind>) -> Self {
+        Self {
+            kinds: kinds.into_iter().map(HashKind::id).collect(),
+        }
+    }
+
+    pub(crate) fn contains(&self, kind: HashKind) -> bool {
+        self.kinds.contains(&kind.id())
+    }
+
+    pub(crate) fn insert(&mut self, kind: HashKind) {
+        if !self.contains(kind) {
+            self.kinds.push(kind.id());
+        }
+    }
+
+    pub(crate) fn read(at: &Path) -> anyhow::Result<Self> {
+        let mut r = fs_err::File::open(at)?;
+        let mut magic = [0; Self::MAGIC.len()];
+        r.read_exact(&mut magic)?;
+        if magic != Self::MAGIC {
+            bail!("invalid magic bytes in {}", at.display());
+        }
+        let mut version = [0];
+        r.read_exact(&mut version)?;
+        if version[0] != Self::VERSION {
+            bail!("unknown index version {} in {}", version[0], at.display());
+        }
+
+        let mut buf = [0; 2];
+        r.read_exact(&mut buf)?;
+        let len = u16::from_le_bytes(buf);
+        let mut kinds = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            r.read_exact(&mut buf)?;
+            kinds.push(u16::from_le_bytes(buf));
+        }
+        Ok(Self { kinds })
+    }
+
+    /// Atomically replaces the index at `at`, using `write_at` as a temporary file.
+    pub(crate) fn write(&self, at: &Path, write_at: &Path) -> anyhow::Result<()> {
+        let mut w = BufWriter::new(fs_err::File::create(write_at)?);
+        w.write_all(Self::MAGIC)?;
+        w.write_all(&[Self::VERSION])?;
+        w.write_all(&(self.kinds.len() as u16).to_le_bytes())?;
+        for id in &self.kinds {
+            w.write_all(&id.to_le_bytes())?;
+        }
+        w.into_inner()?.sync_all()?;
+        fs_err::rename(write_at, at)?;
+        Ok(())
+    }
+}
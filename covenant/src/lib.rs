@@ -4,16 +4,22 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use memmap2::Mmap;
 use tokio::{
     io::{AsyncRead, AsyncWriteExt},
     pin,
-    sync::RwLock,
+    sync::{Mutex, RwLock},
 };
 
 mod hashes;
+mod index;
+pub use hashes::{HashKind, Hashes, HashesBuilder};
+pub use token::Priority;
 mod lock;
+use lock::LockMode;
+#[cfg(test)]
+mod temp_dir;
 mod token;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -22,14 +28,34 @@ pub struct ObjectId(NonZeroU64);
 #[derive(Debug)]
 pub struct Ark {
     paths: Pather,
+    // These are held to keep the directories locked for the lifetime of the Ark. Their mode
+    // determines whether the Ark is writable.
     data_lock: lock::Lock,
     objects_lock: lock::Lock,
     inner: RwLock<Inner>,
+    /// Held by [`backfill`][Self::backfill] throughout, as it only takes `inner` in between
+    /// hashing objects, and two at once would insert every object twice.
+    backfilling: Mutex<()>,
 }
 
 impl Ark {
     pub async fn open(data_dir: &Path, object_dir: &Path) -> anyhow::Result<Self> {
+        Self::open_with(data_dir, object_dir, LockMode::Exclusive).await
+    }
+
+    /// Opens an existing archive without taking exclusive ownership of it, so that it can be
+    /// read by several processes at once.
+    ///
+    /// Anything that would modify the archive fails until it is [upgraded][Self::upgrade].
+    pub async fn open_read_only(data_dir: &Path, object_dir: &Path) -> anyhow::Result<Self> {
+        Self::open_with(data_dir, object_dir, LockMode::Shared).await
+    }
+
+    async fn open_with(data_dir: &Path, object_dir: &Path, mode: LockMode) -> anyhow::Result<Self> {
         let paths = Pather::new(data_dir, object_dir);
+        if mode == LockMode::Shared && !paths.index_file.exists() {
+            bail!("no archive exists in {}", data_dir.display());
+        }
         if !data_dir.exists() {
             fs_err::tokio::create_dir_all(data_dir).await?;
         };
@@ -37,30 +63,59 @@ impl Ark {
             fs_err::tokio::create_dir_all(object_dir).await?;
             fs_err::tokio::create_dir_all(&paths.objects_staging).await?;
         }
-        let data_lock = lock::Lock::new(&paths.data_lock)?;
-        let objects_lock = lock::Lock::new(&paths.objects_staging_lock)?;
+        // Opening the lookups replays any logs left behind by a writer, so is done exclusively
+        // where possible. If only shared locks can be taken, the other holders are read-only and
+        // never write to the logs, so there is nothing to replay.
+        let lock = |at: &Path| match mode {
+            LockMode::Exclusive => lock::Lock::new(at, LockMode::Exclusive),
+            LockMode::Shared => lock::Lock::new(at, LockMode::Exclusive).or_else(|_| lock::Lock::new(at, LockMode::Shared)),
+        };
+        let mut data_lock = lock(&paths.data_lock)?;
+        let mut objects_lock = lock(&paths.objects_staging_lock)?;
+        // If only a shared lock was taken, other processes are reading the lookups, so they must
+        // not be modified
+        let writable = data_lock.mode() == LockMode::Exclusive;
 
         // Now that we have the locks, we can begin opening files
-        let maps = if !paths.index_file.exists() {
-            hashes::HashesMap::try_new_with(|k| {
-                let name = k.name();
-                let dir = paths.hash_base.join(name);
-                fs_err::create_dir_all(&dir)?;
-                // # Safety
-                // The relevant files have been locked for the duration of the Lookup's
-                // existence
-                unsafe { int_multistore::Lookup::new(dir, name) }
-            })?
+        let existing = if paths.index_file.exists() {
+            Some(index::ArkIndex::read(&paths.index_file)?)
         } else {
-            hashes::HashesMap::try_new_with(|k| {
-                let name = k.name();
-                let dir = paths.hash_base.join(name);
-                // # Safety
-                // The relevant files have been locked for the duration of the Lookup's
-                // existence
-                unsafe { int_multistore::Lookup::open(dir, name) }
-            })?
+            None
         };
+        let mut pending = HashSet::new();
+        let maps = hashes::HashesMap::try_new_with(|k| {
+            // # Safety
+            // The relevant files have been locked for the duration of the Lookup's
+            // existence
+            if existing.as_ref().is_some_and(|index| index.contains(k)) {
+                unsafe { int_multistore::Lookup::open(paths.hash_base.join(k.name()), k.name()) }.map(Some)
+            } else {
+                if existing.is_some() {
+                    // This kind was added after the archive was created, so its lookup needs
+                    // backfilling. It isn't used until then, so if the archive can't be written
+                    // to it is left for `upgrade` to create.
+                    pending.insert(k);
+                    if !writable {
+                        return Ok(None);
+                    }
+                }
+                unsafe { paths.new_lookup(k) }.map(Some)
+            }
+        })?;
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                debug_assert_eq!(mode, LockMode::Exclusive);
+                let index = index::ArkIndex::new(hashes::HashKind::ALL);
+                index.write(&paths.index_file, &paths.index_write)?;
+                index
+            }
+        };
+
+        if mode == LockMode::Shared {
+            data_lock.downgrade()?;
+            objects_lock.downgrade()?;
+        }
 
         Ok(Self {
             paths,
@@ -68,16 +123,26 @@ impl Ark {
             objects_lock,
             inner: RwLock::new(Inner {
                 maps,
+                index,
+                pending,
                 tokens: token::TokenDistributor::new(32).await,
             }),
+            backfilling: Mutex::new(()),
         })
     }
 
     pub async fn add(&self, stream: impl AsyncRead) -> anyhow::Result<ObjectId> {
-        let token = {
-            let read = self.inner.read().await;
-            read.tokens.acquire().await
-        };
+        self.add_with_priority(stream, Priority::Interactive).await
+    }
+
+    /// Like [`add`][Self::add], but allows maintenance work to mark itself as
+    /// [`Priority::Background`] so that it does not compete with user-facing uploads.
+    pub async fn add_with_priority(&self, stream: impl AsyncRead, priority: Priority) -> anyhow::Result<ObjectId> {
+        self.ensure_writable()?;
+        // The read lock must not be held while waiting, or it would block the uploads that are
+        // about to return their tokens from ever taking the write lock
+        let tokens = self.inner.read().await.tokens.clone();
+        let token = tokens.acquire(priority).await;
 
         let to_path = self.paths.objects_staging.join(format!("current-{}", token.id()));
         let mut to_file = fs_err::tokio::OpenOptions::new()
@@ -87,21 +152,27 @@ impl Ark {
             .open(&to_path)
             .await?;
         pin!(stream);
+
+        // We specifically do not want to be holding any form of lock here, as this is the
+        // expensive part and want this to be able to run on multiple uploads concurrently.
         tokio::io::copy(&mut stream, &mut to_file).await?;
         to_file.flush().await?;
         let to_file = to_file.into_std().await;
         let map = unsafe { Mmap::map(&to_file) }?;
-
-        // We specifically do not want to be holding any form of lock here, as this is the
-        // expensive part and want this to be able to run on multiple uploads concurrently.
-        let hashes = hashes::Hashes::extract(&map)?;
+        // Hashing the staged file as a whole (most likely still in the page cache) spawns just one
+        // thread per digest for the whole upload, rather than for every chunk as it streams in
+        let (hashes, map) = tokio::task::spawn_blocking(move || (Hashes::extract(&map), map)).await?;
 
         {
             let mut write = self.inner.write().await;
             'unfound: {
                 let mut candidates = None::<HashSet<_>>;
                 for (kind, b) in &hashes {
-                    let map = &write.maps[kind];
+                    if write.pending.contains(&kind) {
+                        // Not every object is in this lookup yet, so it can't rule anything out
+                        continue;
+                    }
+                    let map = write.maps[kind].as_ref().expect("only pending lookups are missing");
                     let Some(idx) = map.get_idx(b) else {
                         // `get_idx` returning None means that the hash is unseen, which means that
                         // the file must be new
@@ -117,7 +188,9 @@ impl Ark {
                         candidates = Some(nc);
                     }
                 }
-                let candidates = candidates.expect("there is at least one hash");
+                let Some(candidates) = candidates else {
+                    break 'unfound;
+                };
 
                 // If all hashes consistent, check candidate's bytes
 
@@ -152,22 +225,137 @@ impl Ark {
 
             // TODO: Store metadata in a sidecar file
             for (kind, b) in &hashes {
-                let map = &mut write.maps[kind];
-                if let Some(idx) = map.get_idx(b) {
-                    map.insert(idx, b, id.0)?;
-                } else {
-                    map.set(b, id.0)?;
-                }
+                write.insert_hash(kind, b, id)?;
             }
             drop(token);
             Ok(id)
         }
     }
 
+    /// Computes the [`Hashes`] of a stored object from its contents on disk.
+    ///
+    /// This always rehashes the object, so it can be used to verify that it has not been
+    /// corrupted since it was added.
+    pub async fn hashes_of(&self, id: ObjectId) -> anyhow::Result<Hashes> {
+        let path = self.paths.path_for(id);
+        let file = fs_err::tokio::File::open(&path).await.context("object does not exist")?;
+        let file = file.into_std().await;
+        let map = unsafe { Mmap::map(&file) }?;
+        Ok(tokio::task::spawn_blocking(move || Hashes::extract(&map)).await?)
+    }
+
+    /// Fills in the lookups of any hash kinds that were added after this archive was created, by
+    /// rehashing every stored object.
+    ///
+    /// Until this is called, such lookups are still kept up to date with new objects, but are not
+    /// used for deduplication. Objects can still be added while this runs, as it only locks the
+    /// archive to insert each object's digests once they have been computed.
+    pub async fn backfill(&self) -> anyhow::Result<()> {
+        self.ensure_writable()?;
+        let _backfilling = self.backfilling.lock().await;
+        let (kinds, objects) = {
+            let read = self.inner.read().await;
+            if read.pending.is_empty() {
+                return Ok(());
+            }
+            // Objects added after this are inserted into the pending lookups as they are added
+            (read.pending.iter().copied().collect::<Vec<_>>(), self.paths.objects()?)
+        };
+
+        for (id, path) in objects {
+            let kinds = kinds.clone();
+            let digests = tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<_>> {
+                let file = fs_err::File::open(&path)?;
+                let map = unsafe { Mmap::map(file.file()) }?;
+                let mut hashers = kinds.iter().map(|kind| kind.hasher()).collect::<Vec<_>>();
+                hashes::update_all(&mut hashers, &map);
+                Ok(kinds.into_iter().zip(hashers.into_iter().map(hashes::Hasher::finalize)).collect())
+            })
+            .await??;
+            let mut write = self.inner.write().await;
+            for (kind, digest) in digests {
+                write.insert_hash(kind, &digest, id)?;
+            }
+        }
+
+        let mut write = self.inner.write().await;
+        for kind in kinds {
+            write.lookup(kind).flush()?;
+            write.pending.remove(&kind);
+            write.index.insert(kind);
+        }
+        write.index.write(&self.paths.index_file, &self.paths.index_write)?;
+        Ok(())
+    }
+
+    /// Sets the maximum number of uploads that can be staged concurrently.
+    ///
+    /// When lowering the limit, uploads that are already in progress are not interrupted.
+    pub async fn set_upload_limit(&self, limit: usize) {
+        self.inner.read().await.tokens.resize(limit);
+    }
+
+    /// Whether this archive can be modified, _i.e._ it was not opened with
+    /// [`open_read_only`][Self::open_read_only], or has since been [upgraded][Self::upgrade].
+    pub fn is_writable(&self) -> bool {
+        self.data_lock.mode() == LockMode::Exclusive
+    }
+
+    fn ensure_writable(&self) -> anyhow::Result<()> {
+        if !self.is_writable() {
+            bail!("archive is open read-only");
+        }
+        Ok(())
+    }
+
+    /// Makes a read-only archive writable, without reopening any of its lookups.
+    ///
+    /// This fails if any other process has the archive open, in which case it is left read-only.
+    pub fn upgrade(&mut self) -> anyhow::Result<()> {
+        self.data_lock.upgrade()?;
+        if let Err(e) = self.objects_lock.upgrade() {
+            self.data_lock.downgrade()?;
+            return Err(e);
+        }
+        if let Err(e) = self.create_pending_lookups() {
+            self.objects_lock.downgrade()?;
+            self.data_lock.downgrade()?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Creates the lookups of any kinds awaiting [backfill][Self::backfill] that were skipped
+    /// when opening the archive read-only.
+    fn create_pending_lookups(&mut self) -> anyhow::Result<()> {
+        let inner = self.inner.get_mut();
+        for &kind in &inner.pending {
+            let map = &mut inner.maps[kind];
+            if map.is_none() {
+                // # Safety
+                // The data directory has just been locked exclusively
+                *map = Some(unsafe { self.paths.new_lookup(kind) }?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the archive read-only again after an [`upgrade`][Self::upgrade], allowing other
+    /// processes to open it read-only.
+    ///
+    /// Any buffered writes are flushed first.
+    pub async fn downgrade(&mut self) -> anyhow::Result<()> {
+        self.flush().await?;
+        self.objects_lock.downgrade()?;
+        self.data_lock.downgrade()
+    }
+
     pub async fn flush(&mut self) -> anyhow::Result<()> {
         let mut s = self.inner.write().await;
         for (_, map) in &mut s.maps {
-            map.flush()?;
+            if let Some(map) = map {
+                map.flush()?;
+            }
         }
 
         Ok(())
@@ -176,11 +364,30 @@ impl Ark {
 
 #[derive(Debug)]
 struct Inner {
-    maps: hashes::HashesMap<int_multistore::Lookup>,
+    /// Only missing for [pending][Self::pending] kinds of an archive opened read-only.
+    maps: hashes::HashesMap<Option<int_multistore::Lookup>>,
+    index: index::ArkIndex,
+    /// Hash kinds whose lookups do not yet contain every object.
+    pending: HashSet<hashes::HashKind>,
     tokens: token::TokenDistributor,
 }
 
 impl Inner {
+    /// The lookup for `kind`, which is always there once the archive is writable.
+    fn lookup(&mut self, kind: hashes::HashKind) -> &mut int_multistore::Lookup {
+        self.maps[kind].as_mut().expect("only pending lookups of read-only archives are missing")
+    }
+
+    fn insert_hash(&mut self, kind: hashes::HashKind, hash: &[u8], id: ObjectId) -> anyhow::Result<()> {
+        let map = self.lookup(kind);
+        if let Some(idx) = map.get_idx(hash) {
+            map.insert(idx, hash, id.0)?;
+        } else {
+            map.set(hash, id.0)?;
+        }
+        Ok(())
+    }
+
     fn next_id(&mut self) -> anyhow::Result<ObjectId> {
         use std::sync::atomic::{AtomicU64, Ordering};
         static N: AtomicU64 = AtomicU64::new(1);
@@ -215,6 +422,21 @@ impl Pather {
         }
     }
 
+    /// Creates an empty lookup for `kind`. Anything already there is left over from an
+    /// interrupted backfill or creation, and can't be trusted, so is removed first.
+    ///
+    /// # Safety
+    /// The data directory must be locked for as long as the lookup exists, and exclusively while
+    /// it is created.
+    unsafe fn new_lookup(&self, kind: hashes::HashKind) -> anyhow::Result<int_multistore::Lookup> {
+        let dir = self.hash_base.join(kind.name());
+        if dir.exists() {
+            fs_err::remove_dir_all(&dir)?;
+        }
+        fs_err::create_dir_all(&dir)?;
+        unsafe { int_multistore::Lookup::new(dir, kind.name()) }
+    }
+
     fn path_for(&self, id: ObjectId) -> PathBuf {
         let n = id.0.get();
         let last = n & 0xFF;
@@ -222,4 +444,141 @@ impl Pather {
 
         self.objects_storage.join(format!("{pen:02X}/{last:02X}/{n}"))
     }
+
+    /// Lists every stored object, in no particular order.
+    fn objects(&self) -> anyhow::Result<Vec<(ObjectId, PathBuf)>> {
+        fn subdirs(dir: &Path) -> anyhow::Result<impl Iterator<Item = PathBuf>> {
+            Ok(fs_err::read_dir(dir)?.filter_map(Result::ok).filter_map(|e| {
+                // Object directories are always two hex digits, which skips staging and the lock
+                let name = e.file_name();
+                let is_bucket = name.len() == 2 && name.to_str().is_some_and(|n| n.bytes().all(|b| b.is_ascii_hexdigit()));
+                (is_bucket && e.path().is_dir()).then(|| e.path())
+            }))
+        }
+
+        let mut objects = Vec::new();
+        for pen in subdirs(&self.objects_storage)? {
+            for last in subdirs(&pen)? {
+                for entry in fs_err::read_dir(&last)? {
+                    let entry = entry?;
+                    let id = entry.file_name().to_str().and_then(|n| n.parse().ok()).and_then(NonZeroU64::new);
+                    if let Some(id) = id {
+                        objects.push((ObjectId(id), entry.path()));
+                    }
+                }
+            }
+        }
+        Ok(objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[tokio::test]
+    async fn read_only_leaves_pending_lookups() -> anyhow::Result<()> {
+        let base = TempDir::new()?;
+        let (data, objects) = (base.path().join("data"), base.path().join("objects"));
+        drop(Ark::open(&data, &objects).await?);
+
+        // Make Blake3 look like it was added after the archive was created, with a lookup left
+        // over from an interrupted backfill
+        let paths = Pather::new(&data, &objects);
+        let kinds = HashKind::ALL.into_iter().filter(|&k| k != HashKind::Blake3);
+        index::ArkIndex::new(kinds).write(&paths.index_file, &paths.index_write)?;
+        let leftover = paths.hash_base.join(HashKind::Blake3.name()).join("leftover");
+        fs_err::write(&leftover, b"")?;
+
+        // Holding shared locks stands in for another reader, so only shared locks can be taken
+        let reader = (lock::Lock::new(&paths.data_lock, LockMode::Shared)?, lock::Lock::new(&paths.objects_staging_lock, LockMode::Shared)?);
+        let mut ark = Ark::open_read_only(&data, &objects).await?;
+        assert!(!ark.is_writable());
+        assert!(leftover.exists());
+
+        drop(reader);
+        ark.upgrade()?;
+        assert!(!leftover.exists());
+        ark.backfill().await?;
+        drop(ark);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_short_reads() -> anyhow::Result<()> {
+        let base = TempDir::new()?;
+        let ark = Ark::open(&base.path().join("data"), &base.path().join("objects")).await?;
+
+        let data = (0..3 * 1024 * 1024 + 5).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        // Each part is returned by a separate read
+        let stream = data[..10].chain(&data[10..100_000]).chain(&data[100_000..]);
+        let id = ark.add(stream).await?;
+        assert_eq!(ark.hashes_of(id).await?, Hashes::extract(&data));
+        drop(ark);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn backfill_then_dedup() -> anyhow::Result<()> {
+        let base = TempDir::new()?;
+        let (data, objects) = (base.path().join("data"), base.path().join("objects"));
+        let contents = b"backfilled".repeat(1000);
+        let ark = Ark::open(&data, &objects).await?;
+        let id = ark.add(&contents[..]).await?;
+        drop(ark);
+
+        // Make Blake3 look like it was added after the archive was created
+        let paths = Pather::new(&data, &objects);
+        let kinds = HashKind::ALL.into_iter().filter(|&k| k != HashKind::Blake3);
+        index::ArkIndex::new(kinds).write(&paths.index_file, &paths.index_write)?;
+        let ark = Ark::open(&data, &objects).await?;
+        let digest = Hashes::extract(&contents);
+        let digest = digest.get(HashKind::Blake3);
+        assert!(ark.inner.write().await.lookup(HashKind::Blake3).get_idx(digest).is_none());
+
+        ark.backfill().await?;
+        {
+            let mut inner = ark.inner.write().await;
+            assert!(inner.pending.is_empty());
+            let lookup = inner.lookup(HashKind::Blake3);
+            let idx = lookup.get_idx(digest).expect("the object was backfilled");
+            assert_eq!(lookup.get(idx)?.collect::<Vec<_>>(), [id.0]);
+        }
+        // Every lookup must know of the object for it to be found again
+        assert_eq!(ark.add(&contents[..]).await?, id);
+        drop(ark);
+
+        let ark = Ark::open(&data, &objects).await?;
+        assert!(ark.inner.read().await.pending.is_empty());
+        drop(ark);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn downgrade_then_share() -> anyhow::Result<()> {
+        let base = TempDir::new()?;
+        let (data, objects) = (base.path().join("data"), base.path().join("objects"));
+        let mut ark = Ark::open(&data, &objects).await?;
+        let id = ark.add(&b"shared"[..]).await?;
+        assert!(Ark::open_read_only(&data, &objects).await.is_err());
+
+        ark.downgrade().await?;
+        assert!(!ark.is_writable());
+        let mut reader = Ark::open_read_only(&data, &objects).await?;
+        // Written before the downgrade, so visible to the new reader
+        assert_eq!(reader.hashes_of(id).await?, Hashes::extract(b"shared"));
+        assert!(Ark::open(&data, &objects).await.is_err());
+        assert!(reader.upgrade().is_err());
+        assert!(!reader.is_writable());
+
+        drop(ark);
+        reader.upgrade()?;
+        assert!(reader.is_writable());
+        Ok(())
+    }
 }
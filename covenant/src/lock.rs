@@ -1,6 +1,7 @@
 use std::{
-    fmt::{Debug, Formatter},
+    fmt::{Debug, Display, Formatter},
     fs::File,
+    io::{Read, Seek, Write},
     path::{Path, PathBuf},
 };
 
@@ -10,19 +11,95 @@ use fs4::FileExt;
 pub(crate) struct Lock {
     file: File,
     path: PathBuf,
+    mode: LockMode,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum LockMode {
+    /// Any number of processes can hold a shared lock at once, but only while nobody holds an
+    /// exclusive lock.
+    Shared,
+    Exclusive,
 }
 
 impl Lock {
-    pub(crate) fn new(at: &Path) -> anyhow::Result<Self> {
-        let lock_file = fs_err::OpenOptions::new().read(true).write(true).create(true).open(at)?.into_parts().0;
-        lock_file.try_lock_exclusive().context(format!("could not lock {}", at.display()))?;
-        Ok(Self {
+    pub(crate) fn new(at: &Path, mode: LockMode) -> anyhow::Result<Self> {
+        let mut lock_file = fs_err::OpenOptions::new().read(true).write(true).create(true).open(at)?.into_parts().0;
+        let locked = match mode {
+            LockMode::Shared => FileExt::try_lock_shared(&lock_file),
+            LockMode::Exclusive => FileExt::try_lock_exclusive(&lock_file),
+        };
+        if let Err(e) = locked {
+            // The holder info is only advisory, so failing to read it shouldn't hide the real error
+            let context = match Holder::read(&mut lock_file) {
+                Ok(Some(holder)) => format!("could not lock {}, held by {holder}", at.display()),
+                _ => format!("could not lock {}", at.display()),
+            };
+            return Err(anyhow::Error::new(e).context(context));
+        }
+        let mut lock = Self {
             file: lock_file,
             path: at.to_owned(),
-        })
+            mode,
+        };
+        if mode == LockMode::Exclusive {
+            lock.write_holder()?;
+        }
+        Ok(lock)
+    }
+
+    pub(crate) fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    /// Converts a shared lock into an exclusive one. Does nothing if already exclusive.
+    ///
+    /// This fails if any other process holds a shared lock. If this fails, the shared lock is
+    /// still held.
+    pub(crate) fn upgrade(&mut self) -> anyhow::Result<()> {
+        if self.mode == LockMode::Exclusive {
+            return Ok(());
+        }
+        if let Err(e) = FileExt::try_lock_exclusive(&self.file) {
+            // Converting a lock is not guaranteed to be atomic, so make sure we still hold it
+            FileExt::try_lock_shared(&self.file).context(format!("lost shared lock on {}", self.path.display()))?;
+            return Err(anyhow::Error::new(e).context(format!("could not upgrade lock on {}, it has other holders", self.path.display())));
+        }
+        self.mode = LockMode::Exclusive;
+        self.write_holder()
+    }
+
+    /// Converts an exclusive lock into a shared one. Does nothing if already shared.
+    pub(crate) fn downgrade(&mut self) -> anyhow::Result<()> {
+        if self.mode == LockMode::Shared {
+            return Ok(());
+        }
+        // Shared holders don't record themselves, as they would overwrite each other
+        self.clear_holder();
+        FileExt::try_lock_shared(&self.file).context(format!("could not downgrade lock on {}", self.path.display()))?;
+        self.mode = LockMode::Shared;
+        Ok(())
+    }
+
+    fn write_holder(&mut self) -> anyhow::Result<()> {
+        Holder::current()
+            .write(&mut self.file)
+            .context(format!("could not write holder info to {}", self.path.display()))
+    }
+
+    /// Stale holder info only makes conflict errors misleading, so failing to clear it is not an
+    /// error.
+    fn clear_holder(&mut self) {
+        if let Err(e) = self.file.set_len(0) {
+            log::warn!("could not clear holder info from {}: {e}", self.path.display());
+        }
     }
 
     fn unlock(&mut self) -> anyhow::Result<()> {
+        // Clear the holder info first, as once unlocked the file is no longer ours to modify
+        if self.mode == LockMode::Exclusive {
+            self.clear_holder();
+        }
         self.file.unlock().context(format!("could not unlock {}", self.path.display()))?;
         Ok(())
     }
@@ -30,13 +107,120 @@ impl Lock {
 
 impl Drop for Lock {
     fn drop(&mut self) {
-        // TODO: logging
-        let _ = self.unlock();
+        if let Err(e) = self.unlock() {
+            log::warn!("{e:#}");
+        }
     }
 }
 
 impl Debug for Lock {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Lock").field("path", &self.path).finish_non_exhaustive()
+        f.debug_struct("Lock")
+            .field("path", &self.path)
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Information about the process holding a [`Lock`], stored in the lock file itself.
+///
+/// Stored as `pid\nhostname\ntimestamp\n`, where `timestamp` is in seconds since the unix epoch.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Holder {
+    pid: u32,
+    hostname: String,
+    timestamp: i64,
+}
+
+impl Holder {
+    fn current() -> Self {
+        Self {
+            pid: std::process::id(),
+            hostname: hostname(),
+            timestamp: time::OffsetDateTime::now_utc().unix_timestamp(),
+        }
+    }
+
+    fn write(&self, file: &mut File) -> std::io::Result<()> {
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}\n{}\n{}\n", self.pid, self.hostname, self.timestamp)?;
+        file.sync_data()
+    }
+
+    /// Returns `Ok(None)` if the file does not contain valid holder info (_e.g._ if it was written
+    /// by an older version, or the holder crashed mid-write).
+    fn read(file: &mut File) -> std::io::Result<Option<Self>> {
+        let mut s = String::new();
+        file.rewind()?;
+        file.read_to_string(&mut s)?;
+        let mut lines = s.lines();
+        let holder = (|| {
+            let pid = lines.next()?.parse().ok()?;
+            let hostname = lines.next()?.to_owned();
+            let timestamp = lines.next()?.parse().ok()?;
+            Some(Self { pid, hostname, timestamp })
+        })();
+        Ok(holder)
+    }
+
+    /// Whether the holding process is known to no longer be running.
+    ///
+    /// This can only be determined for processes on the same host, and only on platforms where
+    /// process liveness can be checked.
+    fn is_dead(&self) -> bool {
+        self.hostname == hostname() && process_alive(self.pid) == Some(false)
+    }
+}
+
+impl Display for Holder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pid {} on {}", self.pid, self.hostname)?;
+        match time::OffsetDateTime::from_unix_timestamp(self.timestamp) {
+            Ok(at) => write!(f, " since {at}")?,
+            Err(_) => write!(f, " since <invalid timestamp {}>", self.timestamp)?,
+        }
+        if self.is_dead() {
+            // The OS releases advisory locks when the holder exits, so this most likely means a
+            // child process inherited the lock or the filesystem does not support locking properly
+            write!(f, " (process is no longer running, lock is likely stale)")?;
+        }
+        Ok(())
+    }
+}
+
+fn hostname() -> String {
+    gethostname::gethostname().to_string_lossy().into_owned()
+}
+
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> Option<bool> {
+    Some(Path::new("/proc").join(pid.to_string()).exists())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_alive(_pid: u32) -> Option<bool> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn reports_holder() -> anyhow::Result<()> {
+        let dir = TempDir::new()?;
+        let at = dir.path().join("ARK.LOCK");
+        let mut lock = Lock::new(&at, LockMode::Exclusive)?;
+        let e = Lock::new(&at, LockMode::Shared).unwrap_err();
+        let held_by = format!("held by pid {} on {}", std::process::id(), hostname());
+        assert!(format!("{e:#}").contains(&held_by), "{e:#}");
+
+        // Shared holders are not recorded, so a downgraded lock no longer names its holder
+        lock.downgrade()?;
+        let e = Lock::new(&at, LockMode::Exclusive).unwrap_err();
+        assert!(!format!("{e:#}").contains("held by"), "{e:#}");
+        Ok(())
     }
 }
@@ -23,6 +23,7 @@ async fn run() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[allow(dead_code)]
 fn recursive_files(base: &Path) -> impl Iterator<Item = PathBuf> {
     fs_err::read_dir(base)
         .into_iter()
@@ -1,31 +1,163 @@
-use std::fmt::{Debug, Formatter};
+use std::{
+    collections::HashSet,
+    fmt::{Debug, Formatter},
+    sync::{Arc, Mutex, PoisonError},
+};
 
-use async_channel::{bounded as channel, Receiver, Sender};
+use async_channel::{unbounded as channel, Receiver, Sender};
 
-pub(crate) struct TokenDistributor {
+/// How urgently a staging token is needed.
+///
+/// While any [`Interactive`][Self::Interactive] acquisitions are waiting, returned tokens are
+/// handed to them before any [`Background`][Self::Background] acquisitions, so that maintenance
+/// work cannot starve user-facing uploads.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub enum Priority {
+    /// User-facing work. This is the default.
+    #[default]
+    Interactive,
+    /// Maintenance work such as scrubbing or replication.
+    Background,
+}
+
+/// Cheaply cloneable, as all clones share the same pool of tokens.
+#[derive(Clone)]
+pub(crate) struct TokenDistributor(Arc<Pool>);
+
+struct Pool {
     tx: Sender<usize>,
     rx: Receiver<usize>,
+    /// Tokens returned while interactive acquisitions were waiting. Only those acquisitions
+    /// receive from this.
+    priority_tx: Sender<usize>,
+    priority_rx: Receiver<usize>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    limit: usize,
+    /// Every ID that is either waiting in a channel or held by a [`Token`].
+    alive: HashSet<usize>,
+    waiting_interactive: usize,
 }
 
 impl TokenDistributor {
     pub(crate) async fn new(limit: usize) -> Self {
-        let (tx, rx) = channel(limit);
+        let (tx, rx) = channel();
+        let (priority_tx, priority_rx) = channel();
         for i in 0..limit {
             let _ = tx.send(i).await;
         }
-        Self { rx, tx }
+        let state = State {
+            limit,
+            alive: (0..limit).collect(),
+            waiting_interactive: 0,
+        };
+        Self(Arc::new(Pool {
+            tx,
+            rx,
+            priority_tx,
+            priority_rx,
+            state: Mutex::new(state),
+        }))
+    }
+
+    pub(crate) async fn acquire(&self, priority: Priority) -> Token {
+        loop {
+            // The pool holds both ends of each channel, so they can never be closed
+            let id = match priority {
+                Priority::Interactive => {
+                    let _waiting = InteractiveWaiter::new(&self.0);
+                    tokio::select! {
+                        id = self.0.priority_rx.recv() => id.unwrap(),
+                        id = self.0.rx.recv() => id.unwrap(),
+                    }
+                }
+                Priority::Background => self.0.rx.recv().await.unwrap(),
+            };
+            let mut state = self.0.state.lock().unwrap();
+            if id < state.limit {
+                return Token {
+                    id,
+                    pool: Arc::clone(&self.0),
+                };
+            }
+            // This ID was retired by a shrinking resize while waiting in the channel
+            state.alive.remove(&id);
+        }
+    }
+
+    /// Changes the maximum number of tokens that can be held at once.
+    ///
+    /// Growing takes effect immediately. When shrinking, tokens that are currently held remain
+    /// valid, and are retired as they are returned - so it may take some time for the number of
+    /// held tokens to drop below the new limit.
+    pub(crate) fn resize(&self, limit: usize) {
+        let mut state = self.0.state.lock().unwrap();
+        state.limit = limit;
+        // Retire any waiting IDs that are now out of range, and put the rest back
+        for (tx, rx) in [(&self.0.tx, &self.0.rx), (&self.0.priority_tx, &self.0.priority_rx)] {
+            let mut waiting = Vec::with_capacity(rx.len());
+            while let Ok(id) = rx.try_recv() {
+                waiting.push(id);
+            }
+            for id in waiting {
+                if id < limit {
+                    let _ = tx.try_send(id);
+                } else {
+                    state.alive.remove(&id);
+                }
+            }
+        }
+        let tx = self.0.return_tx(&state);
+        for id in 0..limit {
+            if state.alive.insert(id) {
+                let _ = tx.try_send(id);
+            }
+        }
     }
+}
+
+impl Pool {
+    /// The channel that available IDs go to, which only reaches interactive acquisitions while
+    /// any are waiting.
+    fn return_tx(&self, state: &State) -> &Sender<usize> {
+        if state.waiting_interactive > 0 {
+            &self.priority_tx
+        } else {
+            &self.tx
+        }
+    }
+}
+
+/// Tracks an interactive acquisition for as long as it is waiting, including if the waiting
+/// future is cancelled.
+struct InteractiveWaiter<'a>(&'a Pool);
 
-    pub(crate) async fn acquire(&self) -> Token {
-        let id = self.rx.recv().await.unwrap(); // If rx still exists then tx does too
-        let tx = self.tx.clone();
-        Token { id, tx }
+impl<'a> InteractiveWaiter<'a> {
+    fn new(pool: &'a Pool) -> Self {
+        pool.state.lock().unwrap().waiting_interactive += 1;
+        Self(pool)
+    }
+}
+
+impl Drop for InteractiveWaiter<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.waiting_interactive -= 1;
+        if state.waiting_interactive == 0 {
+            // Nobody else will receive these, so make them available to everyone again
+            while let Ok(id) = self.0.priority_rx.try_recv() {
+                let _ = self.0.tx.try_send(id);
+            }
+        }
     }
 }
 
 pub(crate) struct Token {
     id: usize,
-    tx: Sender<usize>,
+    pool: Arc<Pool>,
 }
 
 impl Token {
@@ -36,15 +168,26 @@ impl Token {
 
 impl Drop for Token {
     fn drop(&mut self) {
-        let _ = self.tx.send_blocking(self.id);
+        // Tokens are usually dropped inside async tasks, so this must never block or panic
+        let mut state = self.pool.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.id >= state.limit {
+            state.alive.remove(&self.id);
+            return;
+        }
+
+        // The channels are unbounded, so this never waits, and the pool holds both ends of each,
+        // so they can't be closed
+        let _ = self.pool.return_tx(&state).try_send(self.id);
     }
 }
 
 impl Debug for TokenDistributor {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let state = self.0.state.lock().unwrap();
         f.debug_struct("TokenDistributor")
-            .field("limit", &self.rx.capacity())
-            .field("available_ids", &self.rx.len())
+            .field("limit", &state.limit)
+            .field("available_ids", &(self.0.rx.len() + self.0.priority_rx.len()))
+            .field("waiting_interactive", &state.waiting_interactive)
             .finish_non_exhaustive()
     }
 }
@@ -54,3 +197,108 @@ impl Debug for Token {
         f.debug_struct("Token").field("id", &self.id).finish_non_exhaustive()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Whether a token can be acquired without waiting.
+    async fn available(tokens: &TokenDistributor) -> Option<Token> {
+        tokio::time::timeout(Duration::from_millis(10), tokens.acquire(Priority::Interactive)).await.ok()
+    }
+
+    #[tokio::test]
+    async fn resize() {
+        let tokens = TokenDistributor::new(2).await;
+        let a = tokens.acquire(Priority::Interactive).await;
+        let b = tokens.acquire(Priority::Interactive).await;
+        let (a, b) = if a.id() == 0 { (a, b) } else { (b, a) };
+
+        // Held tokens stay valid after shrinking, and are retired as they are returned
+        tokens.resize(1);
+        drop(b);
+        assert_eq!(tokens.0.state.lock().unwrap().alive, HashSet::from([0]));
+        assert!(available(&tokens).await.is_none());
+        drop(a);
+        let a = available(&tokens).await.unwrap();
+        assert_eq!(a.id(), 0);
+        assert!(available(&tokens).await.is_none());
+
+        // Growing makes new IDs available straight away
+        tokens.resize(3);
+        let mut ids = vec![a.id()];
+        ids.extend([available(&tokens).await.unwrap(), available(&tokens).await.unwrap()].map(|t| t.id()));
+        ids.sort();
+        assert_eq!(ids, [0, 1, 2]);
+        assert_eq!(tokens.0.state.lock().unwrap().alive, HashSet::from([0, 1, 2]));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn drop_in_busy_runtime() {
+        let tokens = TokenDistributor::new(1).await;
+        let token = tokens.acquire(Priority::Background).await;
+        let returned = tokio::spawn({
+            let tokens = tokens.clone();
+            async move {
+                // The only worker is busy with this task until it returns, so the token must be
+                // back before then
+                drop(token);
+                tokens.0.rx.len()
+            }
+        });
+        assert_eq!(returned.await.unwrap(), 1);
+        assert_eq!(available(&tokens).await.unwrap().id(), 0);
+    }
+
+    /// Spawns an acquisition, and waits for it to start waiting.
+    async fn waiting(tokens: &TokenDistributor, priority: Priority) -> tokio::task::JoinHandle<Token> {
+        let task = tokio::spawn({
+            let tokens = tokens.clone();
+            async move { tokens.acquire(priority).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!task.is_finished());
+        task
+    }
+
+    #[tokio::test]
+    async fn interactive_first() {
+        let tokens = TokenDistributor::new(1).await;
+        let token = tokens.acquire(Priority::Background).await;
+        let background = waiting(&tokens, Priority::Background).await;
+        let interactive = waiting(&tokens, Priority::Interactive).await;
+        assert_eq!(tokens.0.state.lock().unwrap().waiting_interactive, 1);
+
+        // The background acquisition has waited longer, but the interactive one goes first
+        drop(token);
+        let token = interactive.await.unwrap();
+        assert_eq!(token.id(), 0);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!background.is_finished());
+        assert_eq!(tokens.0.state.lock().unwrap().waiting_interactive, 0);
+
+        drop(token);
+        assert_eq!(background.await.unwrap().id(), 0);
+    }
+
+    #[tokio::test]
+    async fn grow_while_interactive_waiting() {
+        let tokens = TokenDistributor::new(1).await;
+        let token = tokens.acquire(Priority::Interactive).await;
+        let background = waiting(&tokens, Priority::Background).await;
+        let interactive = waiting(&tokens, Priority::Interactive).await;
+
+        // New IDs are handed out like returned ones
+        tokens.resize(2);
+        let grown = tokio::time::timeout(Duration::from_secs(1), interactive).await.unwrap().unwrap();
+        assert_eq!(grown.id(), 1);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!background.is_finished());
+
+        drop(grown);
+        assert_eq!(background.await.unwrap().id(), 1);
+        drop(token);
+    }
+}
@@ -1,8 +1,8 @@
-use std::{io::Cursor, num::NonZeroU64};
+use std::{io::Cursor, num::NonZeroU64, path::Path};
 
 use seqstore::{
-    error::Error,
-    raw_store::{OpenStoreOptions, RawStore, RecoveryStrategy},
+    error::{Error, OpenError},
+    raw_store::{CompactReport, Filter, OpenStoreOptions, RawStore, RecoveryStrategy},
     Backing,
 };
 use varuint::{ReadVarint, VarintSizeHint, WriteVarint};
@@ -11,13 +11,16 @@ use varuint::{ReadVarint, VarintSizeHint, WriteVarint};
 pub struct IntsStore(RawStore);
 
 impl IntsStore {
+    fn options<'a>() -> OpenStoreOptions<'a> {
+        RawStore::options()
+            .exact_spec_magic(b"[varNZu64]")
+            .recovery_strategy(RecoveryStrategy::Rollback)
+    }
+
     fn create<'a, E: Into<anyhow::Error>>(
         backing: Backing, f: impl FnOnce(OpenStoreOptions<'a>, Backing) -> Result<RawStore, E>,
     ) -> anyhow::Result<Self> {
-        let op = RawStore::options()
-            .exact_spec_magic(b"[varNZu64]")
-            .recovery_strategy(RecoveryStrategy::Rollback);
-        f(op, backing).map(Self).map_err(Into::into)
+        f(Self::options(), backing).map(Self).map_err(Into::into)
     }
 
     pub fn new(backing: Backing) -> anyhow::Result<Self> {
@@ -32,10 +35,21 @@ impl IntsStore {
         self.0.close()
     }
 
-    pub fn filter(&self, to: Backing) -> Result<seqstore::raw_store::Filter<'_>, Error> {
+    pub fn filter(&self, to: Backing) -> Result<Filter<'_>, Error> {
         self.0.filter(to)
     }
 
+    /// See [`RawStore::compact_to`].
+    ///
+    /// # Safety
+    ///
+    /// See [`RawStore::compact_to`].
+    pub unsafe fn compact_to<E: From<OpenError>>(
+        &mut self, path: &Path, select: impl FnOnce(&mut Filter<'_>) -> Result<(), E>,
+    ) -> Result<CompactReport, E> {
+        unsafe { self.0.compact_to(path, Self::options(), select) }
+    }
+
     pub fn get(&self, idx: Idx) -> anyhow::Result<impl Iterator<Item = NonZeroU64>> {
         self.0.get(idx.0, Stored::load).map(Stored::items).map_err(Into::into)
     }
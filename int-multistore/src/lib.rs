@@ -13,6 +13,13 @@ pub struct Lookup {
 }
 
 impl Lookup {
+    /// Creates a new lookup in `dir`.
+    ///
+    /// # Safety
+    ///
+    /// The files in `dir` belonging to this lookup (all starting with `name`) **must not** be
+    /// modified in- or out-of-process until the returned [`Lookup`] is dropped. See
+    /// [`Backing::new_file`] and [`phobos::DatabaseOptions::open_unlocked`] for more detail.
     pub unsafe fn new(dir: PathBuf, name: &str) -> anyhow::Result<Self> {
         let lookup_file = fs_err::OpenOptions::new()
             .read(true)
@@ -22,7 +29,7 @@ impl Lookup {
         let backing = unsafe { Backing::new_file(lookup_file.into_parts().0) }?;
         let lookup = ints_store::IntsStore::new(backing)?;
         let opts = phobos::Database::builder(dir.clone(), name.to_owned()).create(true);
-        let fsts = unsafe { opts.open() }?;
+        let fsts = unsafe { opts.open_unlocked() }?;
         Ok(Self {
             fsts,
             lookup,
@@ -31,6 +38,13 @@ impl Lookup {
         })
     }
 
+    /// Opens an existing lookup in `dir`.
+    ///
+    /// # Safety
+    ///
+    /// The files in `dir` belonging to this lookup (all starting with `name`) **must not** be
+    /// modified in- or out-of-process until the returned [`Lookup`] is dropped. See
+    /// [`Backing::new_file`] and [`phobos::DatabaseOptions::open_unlocked`] for more detail.
     pub unsafe fn open(dir: PathBuf, name: &str) -> anyhow::Result<Self> {
         let lookup_file = fs_err::OpenOptions::new()
             .read(true)
@@ -40,7 +54,7 @@ impl Lookup {
         let backing = unsafe { Backing::new_file(lookup_file.into_parts().0) }?;
         let lookup = ints_store::IntsStore::open(backing)?;
         let opts = phobos::Database::builder(dir.clone(), name.to_owned()).create(false);
-        let fsts = unsafe { opts.open() }?;
+        let fsts = unsafe { opts.open_unlocked() }?;
         Ok(Self {
             fsts,
             lookup,
@@ -50,29 +64,20 @@ impl Lookup {
     }
 
     pub fn cleanup(&mut self) -> anyhow::Result<()> {
-        let write_path = self.dir.join(format!(".{}.lkp~", self.name));
-        let new_file = fs_err::OpenOptions::new().read(true).write(true).create(true).open(&write_path)?;
-        let working = unsafe { Backing::new_file(new_file.into_parts().0) }?;
-        let mut filter = self.lookup.filter(working)?;
-        self.fsts.merge(|_, id| {
-            if let Some(id) = ints_store::Idx::new(id) {
-                filter.add(id.as_id())?;
-            }
-            Ok(())
-        })?;
-        filter.finish()?;
-        let old = std::mem::replace(&mut self.lookup, ints_store::IntsStore::new(Backing::new_anon()?)?);
-        // We want to _explicitly_ drop this here before moving files around on disk
-        // to make it clear that the safety requirements are met. (simply discarding it would
-        // still uphold the requirements, but it's better to be explicit)
-        drop(old.close()?);
-
         let active_path = self.dir.join(file_name(&self.name));
-        fs_err::rename(&write_path, &active_path)?;
-
-        let file = fs_err::OpenOptions::new().read(true).write(true).create(false).open(active_path)?;
-        let new = unsafe { Backing::new_file(file.into_parts().0) }?;
-        self.lookup = ints_store::IntsStore::open(new)?;
+        let fsts = &mut self.fsts;
+        // The lookup's files are covered by the same requirements as when it was opened
+        unsafe {
+            self.lookup.compact_to(&active_path, |filter| {
+                fsts.merge(|_, id| {
+                    if let Some(id) = ints_store::Idx::new(id) {
+                        filter.add(id.as_id())?;
+                    }
+                    Ok::<_, seqstore::error::Error>(())
+                })?;
+                Ok::<_, anyhow::Error>(())
+            })
+        }?;
         Ok(())
     }
 
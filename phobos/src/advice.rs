@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use crate::LevelFst;
+
+/// How FSTs are expected to be read, passed on to the OS (with `madvise`) to tune how much it
+/// reads ahead of each access.
+///
+/// See [`DatabaseOptions::access_pattern`][crate::DatabaseOptions::access_pattern] and
+/// [`DatabaseOptions::merge_access_pattern`][crate::DatabaseOptions::merge_access_pattern].
+///
+/// This has no effect on platforms other than unix, or on FSTs that are not mapped from their
+/// files (as they are compressed or [loaded][crate::DatabaseOptions::load_levels]).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum AccessPattern {
+    /// The OS's default, with a moderate amount of read-ahead.
+    #[default]
+    Normal,
+    /// Scattered reads, such as point lookups in FSTs much larger than memory, so reading ahead is
+    /// wasted.
+    Random,
+    /// Reads from start to end, such as merges or full iteration, so reading far ahead pays off.
+    Sequential,
+    /// Reads soon and throughout, so the whole FST is read in ahead of time.
+    WillNeed,
+}
+
+/// Applies `pattern` to `fst`, logging rather than failing, as advice is only ever a hint.
+pub(crate) fn apply(fst: &LevelFst, pattern: AccessPattern) {
+    #[cfg(unix)]
+    {
+        let advice = match pattern {
+            AccessPattern::Normal => memmap2::Advice::Normal,
+            AccessPattern::Random => memmap2::Advice::Random,
+            AccessPattern::Sequential => memmap2::Advice::Sequential,
+            AccessPattern::WillNeed => memmap2::Advice::WillNeed,
+        };
+        if let Err(e) = fst.fst.as_fst().as_inner().advise(advice) {
+            log::warn!("failed to advise the OS on FST {}: {e}", fst.id);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (fst, pattern);
+}
+
+/// Switches FSTs to the pattern used while merging them, switching them back if the merge fails.
+///
+/// They are left as they are once [`merged`][Self::merged], as they are about to be removed.
+pub(crate) struct MergeAdvice {
+    fsts: Vec<Arc<LevelFst>>,
+    restore: AccessPattern,
+}
+
+impl MergeAdvice {
+    pub(crate) fn new(fsts: &[&Arc<LevelFst>], merging: Option<AccessPattern>, serving: Option<AccessPattern>) -> Self {
+        let Some(merging) = merging else {
+            return Self {
+                fsts: Vec::new(),
+                restore: AccessPattern::Normal,
+            };
+        };
+        for fst in fsts {
+            apply(fst, merging);
+        }
+        Self {
+            fsts: fsts.iter().map(|&f| Arc::clone(f)).collect(),
+            restore: serving.unwrap_or_default(),
+        }
+    }
+
+    pub(crate) fn merged(mut self) {
+        self.fsts.clear();
+    }
+}
+
+impl Drop for MergeAdvice {
+    fn drop(&mut self) {
+        for fst in &self.fsts {
+            apply(fst, self.restore);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+
+    use super::{AccessPattern, MergeAdvice};
+    use crate::{temp_dir::TempDir, Database, Error};
+
+    #[test]
+    fn merge_advice() {
+        let mut db = Database::in_memory().unwrap();
+        for key in [b"a", b"b"] {
+            db.set(Bytes::from_static(key), 1).unwrap();
+            db.flush().unwrap();
+        }
+        let fsts = db.fsts.iter().collect::<Vec<_>>();
+
+        // Without a merge pattern, nothing is switched so nothing needs switching back
+        assert!(MergeAdvice::new(&fsts, None, Some(AccessPattern::Random)).fsts.is_empty());
+        let advice = MergeAdvice::new(&fsts, Some(AccessPattern::Sequential), None);
+        assert_eq!(advice.fsts.len(), 2);
+        assert!(advice.fsts.iter().zip(&db.fsts).all(|(a, b)| Arc::ptr_eq(a, b)));
+        assert_eq!(advice.restore, AccessPattern::Normal);
+        assert_eq!(MergeAdvice::new(&fsts, Some(AccessPattern::Sequential), Some(AccessPattern::Random)).restore, AccessPattern::Random);
+        // Merged FSTs are dropped without being switched back
+        advice.merged();
+    }
+
+    #[test]
+    fn access_patterns() {
+        let dir = TempDir::new().unwrap();
+        let options = || {
+            Database::builder(dir.path().to_owned(), "db".to_owned())
+                .access_pattern(AccessPattern::Random)
+                .merge_access_pattern(AccessPattern::Sequential)
+        };
+        let mut db = options().open().unwrap();
+        for i in 0..4_u64 {
+            db.set(Bytes::from(format!("k{i}")), i).unwrap();
+            db.flush().unwrap();
+        }
+        // A failed merge switches the FSTs back, and leaves them in place
+        let failed = db.merge(|_, _| Err("failed"));
+        assert!(matches!(failed, Err(Error::Callback(_))));
+        assert_eq!(db.fsts.len(), 4);
+        assert_eq!(db.get(b"k3"), Some(3));
+        db.merge(|_, _| Ok::<_, Error>(())).unwrap();
+        assert_eq!(db.fsts.len(), 1);
+
+        drop(db);
+        let db = options().open().unwrap();
+        assert_eq!((0..4).map(|i| db.get(format!("k{i}").as_bytes())).collect::<Vec<_>>(), [Some(0), Some(1), Some(2), Some(3)]);
+    }
+}
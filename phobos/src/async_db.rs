@@ -0,0 +1,216 @@
+use std::{
+    fmt::{Debug, Formatter},
+    mem,
+    sync::{Arc, Mutex, PoisonError, RwLock},
+};
+
+use bytes::Bytes;
+use tokio::sync::oneshot;
+
+use crate::{Database, Error};
+
+/// An async wrapper around a [`Database`], which runs every operation on tokio's blocking thread
+/// pool.
+///
+/// Concurrent [`set`][Self::set]s are written together as a single
+/// [batch][Database::set_batch], so that they share a sync of the write-ahead log. Reads share
+/// the database, but wait for any write in progress.
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    db: RwLock<Database>,
+    pending: Mutex<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+    writes: Vec<PendingWrite>,
+    /// Whether a blocking task is currently writing batches.
+    writing: bool,
+}
+
+struct PendingWrite {
+    key: Bytes,
+    value: u64,
+    done: oneshot::Sender<Result<(), Error>>,
+}
+
+impl AsyncDatabase {
+    pub fn new(db: Database) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                db: RwLock::new(db),
+                pending: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Stores `key`->`value`, as [`Database::set`].
+    ///
+    /// This returns once the write is durable, which may be after writes from other tasks that
+    /// were batched with it.
+    pub async fn set(&self, key: Bytes, value: u64) -> Result<(), Error> {
+        let (done, receiver) = oneshot::channel();
+        let start_writing = {
+            let mut pending = self.inner.pending.lock().unwrap_or_else(PoisonError::into_inner);
+            pending.writes.push(PendingWrite { key, value, done });
+            !mem::replace(&mut pending.writing, true)
+        };
+        if start_writing {
+            let inner = Arc::clone(&self.inner);
+            tokio::task::spawn_blocking(move || inner.write_pending());
+        }
+        receiver.await.expect("phobos writer task panicked")
+    }
+
+    /// Retrieves the latest value for `key`, as [`Database::get`].
+    pub async fn get(&self, key: Bytes) -> Option<u64> {
+        self.read(move |db| db.get(&key)).await
+    }
+
+    /// Flushes all in-memory data to the filesystem, as [`Database::flush`].
+    pub async fn flush(&self) -> Result<(), Error> {
+        self.write(Database::flush).await
+    }
+
+    /// Merges all in-memory and on-disk data into a single FST, as [`Database::merge`].
+    pub async fn merge<E>(&self, callback: impl FnMut(Bytes, u64) -> Result<(), E> + Send + 'static) -> Result<(), Error>
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        self.write(move |db| db.merge(callback)).await
+    }
+
+    /// Runs `f` with shared access to the database on the blocking thread pool, for anything not
+    /// covered by the other methods.
+    pub async fn read<T: Send + 'static>(&self, f: impl FnOnce(&Database) -> T + Send + 'static) -> T {
+        let inner = Arc::clone(&self.inner);
+        blocking(move || f(&inner.db.read().unwrap_or_else(PoisonError::into_inner))).await
+    }
+
+    /// Runs `f` with exclusive access to the database on the blocking thread pool, for anything
+    /// not covered by the other methods.
+    pub async fn write<T: Send + 'static>(&self, f: impl FnOnce(&mut Database) -> T + Send + 'static) -> T {
+        let inner = Arc::clone(&self.inner);
+        blocking(move || f(&mut inner.db.write().unwrap_or_else(PoisonError::into_inner))).await
+    }
+}
+
+impl Inner {
+    /// Writes batches of pending writes until there are none left.
+    fn write_pending(&self) {
+        let _guard = WritingGuard(&self.pending);
+        loop {
+            let writes = {
+                let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+                if pending.writes.is_empty() {
+                    pending.writing = false;
+                    return;
+                }
+                mem::take(&mut pending.writes)
+            };
+
+            let mut db = self.db.write().unwrap_or_else(PoisonError::into_inner);
+            let batch = writes.iter().map(|w| (w.key.clone(), w.value));
+            match db.set_batch(batch) {
+                Ok(()) => {
+                    for write in writes {
+                        // The task waiting for this may have been cancelled
+                        let _ = write.done.send(Ok(()));
+                    }
+                }
+                Err(_) => {
+                    // Retry each write on its own, so that every caller gets its own error. Some
+                    // writes may already have been applied, but setting them again is harmless
+                    for write in writes {
+                        let _ = write.done.send(db.set(write.key, write.value));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Lets a later write start a new writer task if this one panics.
+struct WritingGuard<'a>(&'a Mutex<Pending>);
+
+impl Drop for WritingGuard<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.0.lock().unwrap_or_else(PoisonError::into_inner).writing = false;
+        }
+    }
+}
+
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => panic!("phobos blocking task failed: {e}"),
+    }
+}
+
+impl Debug for AsyncDatabase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncDatabase").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::PoisonError, time::Duration};
+
+    use bytes::Bytes;
+
+    use super::AsyncDatabase;
+    use crate::Database;
+
+    #[test]
+    // Holding the database lock across awaits is the point: it stalls the writer task
+    #[allow(clippy::await_holding_lock)]
+    fn batches_concurrent_sets() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let db = AsyncDatabase::new(Database::in_memory().unwrap());
+        runtime.block_on(async {
+            // Keep the writer task waiting on the database until every set has been queued
+            let guard = db.inner.db.read().unwrap();
+            let first = tokio::spawn({
+                let db = db.clone();
+                async move { db.set(Bytes::from_static(b"k0"), 0).await }
+            });
+            // Wait for the writer task to take the first set
+            while !db.inner.pending.lock().map(|p| p.writing && p.writes.is_empty()).unwrap() {
+                tokio::task::yield_now().await;
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            let rest = (1..10_u64)
+                .map(|i| {
+                    let db = db.clone();
+                    tokio::spawn(async move { db.set(Bytes::from(format!("k{i}")), i).await })
+                })
+                .collect::<Vec<_>>();
+            while db.inner.pending.lock().unwrap().writes.len() < rest.len() {
+                tokio::task::yield_now().await;
+            }
+            // The writer took the first set on its own, and will take the rest as one batch
+            assert_eq!(db.inner.pending.lock().unwrap().writes.len(), 9);
+            drop(guard);
+
+            first.await.unwrap().unwrap();
+            for task in rest {
+                task.await.unwrap().unwrap();
+            }
+            for i in 0..10_u64 {
+                assert_eq!(db.get(Bytes::from(format!("k{i}"))).await, Some(i));
+            }
+            assert_eq!(db.read(Database::len).await, 10);
+        });
+        // Nothing is left queued once every set has returned
+        let pending = db.inner.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        assert!(pending.writes.is_empty());
+        assert!(!pending.writing);
+    }
+}
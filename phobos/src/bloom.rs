@@ -0,0 +1,138 @@
+use std::{
+    io::{Read, Write},
+    path::Path,
+};
+
+/// A Bloom filter over the keys of a single FST, used to skip FSTs that definitely do not contain
+/// a key.
+///
+/// Stored as magic bytes, the number of hashes (`u32` LE), the number of bits (`u64` LE), then the
+/// bits themselves.
+pub(crate) struct Bloom {
+    hashes: u32,
+    bits: Vec<u64>,
+}
+
+impl Bloom {
+    const MAGIC: &'static [u8] = b"\xFEruBLMf\xAA";
+
+    /// Builds a filter from the [`hash`]es of every key.
+    pub(crate) fn new(key_hashes: &[u64], bits_per_key: usize) -> Self {
+        let bit_count = (key_hashes.len() * bits_per_key).max(64);
+        // The optimal number of hashes is `bits_per_key * ln(2)`
+        let hashes = ((bits_per_key as f64 * std::f64::consts::LN_2).round() as u32).clamp(1, 30);
+        let mut bloom = Self {
+            hashes,
+            bits: vec![0; bit_count.div_ceil(64)],
+        };
+        for &h in key_hashes {
+            for bit in bloom.bits_for(h) {
+                bloom.bits[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        bloom
+    }
+
+    /// Whether `key` may be in the FST. If this returns `false`, it definitely is not.
+    pub(crate) fn may_contain(&self, key: &[u8]) -> bool {
+        self.bits_for(hash(key)).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    fn bits_for(&self, h: u64) -> impl Iterator<Item = usize> {
+        // Double hashing, see Kirsch & Mitzenmacher, "Less Hashing, Same Performance"
+        let (h1, h2) = (h as u32 as u64, h >> 32);
+        let bit_count = self.bits.len() as u64 * 64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bit_count) as usize)
+    }
+
+    pub(crate) fn write(&self, w: &mut impl Write) -> std::io::Result<()> {
+        w.write_all(Self::MAGIC)?;
+        w.write_all(&self.hashes.to_le_bytes())?;
+        w.write_all(&(self.bits.len() as u64 * 64).to_le_bytes())?;
+        for word in &self.bits {
+            w.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Returns `None` if the filter is missing or unreadable, in which case the FST is simply
+    /// always probed.
+    pub(crate) fn read(at: &Path) -> Option<Self> {
+        let mut data = Vec::new();
+        fs_err::File::open(at).ok()?.read_to_end(&mut data).ok()?;
+        let data = data.strip_prefix(Self::MAGIC)?;
+        let hashes = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+        let bit_count = u64::from_le_bytes(data.get(4..12)?.try_into().ok()?);
+        let words = data.get(12..)?;
+        if hashes == 0 || bit_count == 0 || bit_count % 64 != 0 || words.len() as u64 * 8 != bit_count {
+            return None;
+        }
+        let bits = words.chunks_exact(8).map(|w| u64::from_le_bytes(w.try_into().unwrap())).collect();
+        Some(Self { hashes, bits })
+    }
+}
+
+/// A hash of `key` that is stable across versions and platforms, as filters are persisted.
+pub(crate) fn hash(key: &[u8]) -> u64 {
+    // FNV-1a, followed by the splitmix64 finalizer to spread it over all bits
+    let mut h = 0xcbf2_9ce4_8422_2325_u64;
+    for &b in key {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{temp_dir::TempDir, Database};
+
+    #[test]
+    fn no_false_negatives() {
+        let keys = (0..10_000_u32).map(|i| i.to_be_bytes()).collect::<Vec<_>>();
+        let bloom = Bloom::new(&keys.iter().map(|k| hash(k)).collect::<Vec<_>>(), 10);
+        assert!(keys.iter().all(|k| bloom.may_contain(k)));
+        // About 1% with 10 bits per key, so this leaves plenty of room
+        let false_positives = (10_000..20_000_u32).filter(|i| bloom.may_contain(&i.to_be_bytes())).count();
+        assert!(false_positives < 500, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn persisted() {
+        let dir = TempDir::new().unwrap();
+        let open = || Database::builder(dir.path().to_owned(), "db".to_owned()).bloom_filter(10).open().unwrap();
+        let mut db = open();
+        for i in 0..1000_u64 {
+            db.set(Bytes::from(format!("k{i}")), i).unwrap();
+        }
+        db.flush().unwrap();
+        let fst = db.fsts[0].clone();
+        let path = db.paths.bloom(fst.id, fst.level);
+        drop(db);
+
+        let mut written = Vec::new();
+        Bloom::read(&path).unwrap().write(&mut written).unwrap();
+        assert_eq!(written, fs_err::read(&path).unwrap());
+
+        let db = open();
+        assert!(db.fsts[0].bloom.is_some());
+        for i in 0..1000_u64 {
+            assert_eq!(db.get(format!("k{i}").as_bytes()), Some(i));
+        }
+        assert_eq!(db.get(b"missing"), None);
+
+        // A damaged filter is ignored rather than hiding keys
+        drop(db);
+        fs_err::write(&path, b"garbage").unwrap();
+        let db = open();
+        assert!(db.fsts[0].bloom.is_none());
+        assert_eq!(db.get(b"k500"), Some(500));
+    }
+}
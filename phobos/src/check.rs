@@ -0,0 +1,284 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use log::warn;
+
+use memmap2::Mmap;
+
+use crate::{checksum, compression, inspect, lock::Lock, replace, Database, Error, Index, IndexFst, LevelFst, Pather};
+
+/// The problems found by [`Database::check`] or [`Database::check_and_repair`].
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    /// Why the index could not be read, if it could not.
+    pub index_error: Option<Error>,
+    /// FSTs referenced by the index whose files do not exist.
+    pub missing_fsts: Vec<PathBuf>,
+    /// FSTs whose files could not be read or do not match their checksum.
+    pub corrupt_fsts: Vec<PathBuf>,
+    /// FST files that are not referenced by the index, _e.g._ left behind by an interrupted merge.
+    ///
+    /// These are never used, so are harmless, and are not touched by a repair. They are removed
+    /// the next time the database is opened.
+    pub orphaned_fsts: Vec<PathBuf>,
+    /// Write-ahead logs (or their backups) that could not be read to the end, along with the
+    /// offset that reading stopped at.
+    pub truncated_logs: Vec<(PathBuf, u64)>,
+    /// Whether [`check_and_repair`][Database::check_and_repair] changed anything.
+    pub repaired: bool,
+}
+
+impl CheckReport {
+    /// Whether no problems were found, ignoring orphaned FSTs.
+    pub fn is_ok(&self) -> bool {
+        self.index_error.is_none() && self.missing_fsts.is_empty() && self.corrupt_fsts.is_empty() && self.truncated_logs.is_empty()
+    }
+}
+
+impl Database {
+    /// Scans the index, FSTs and write-ahead log of the database with `prefix` in `at` for
+    /// problems, without modifying anything.
+    ///
+    /// Like [`inspect`][crate::inspect], this is safe to use on a database that is open.
+    pub fn check(at: &Path, prefix: &str) -> Result<CheckReport, Error> {
+        Ok(Check::run(at, prefix)?.report)
+    }
+
+    /// [Checks](Self::check) the database with `prefix` in `at`, then repairs what it can so that
+    /// the database can be opened again.
+    ///
+    /// If the index is unreadable or references FSTs that are missing or corrupt, it is rebuilt
+    /// from the FSTs that survive (all valid FSTs in the directory, if the index is unreadable).
+    /// Items only stored in lost FSTs are lost. Truncated logs are cut back to their last
+    /// complete entry.
+    ///
+    /// Fails with [`Error::Locked`] if the database is open.
+    pub fn check_and_repair(at: &Path, prefix: &str) -> Result<CheckReport, Error> {
+        // A missing directory is reported by the check itself
+        let lock_path = Pather::new(at.to_owned(), prefix.to_owned()).lock;
+        let _lock = at.exists().then(|| Lock::new(&lock_path)).transpose()?;
+        let mut check = Check::run(at, prefix)?;
+
+        for (path, offset) in &check.report.truncated_logs {
+            warn!("truncating {} to its last complete entry, at {offset}", path.display());
+            fs_err::OpenOptions::new().write(true).open(path)?.set_len(*offset)?;
+            check.report.repaired = true;
+        }
+
+        let index_damaged = check.report.index_error.is_some() || !check.report.missing_fsts.is_empty() || !check.report.corrupt_fsts.is_empty();
+        if index_damaged {
+            warn!("rebuilding {} from {} surviving FSTs", check.paths.index.display(), check.surviving.len());
+            let mut fsts = check.surviving;
+            fsts.sort_by_key(|f| f.id);
+            let index = Index {
+                fsts,
+                value_log_generation: check.value_log_generation,
+                next_fst_id: check.next_fst_id,
+            };
+            let mut file = fs_err::File::create(&check.paths.index_write)?;
+            index.write(&mut file)?;
+            drop(file);
+            replace::replace(&check.paths.index_write, &check.paths.index)?;
+            check.report.repaired = true;
+        }
+
+        Ok(check.report)
+    }
+}
+
+struct Check {
+    paths: Pather,
+    report: CheckReport,
+    /// The FSTs that a rebuilt index would reference.
+    surviving: Vec<IndexFst>,
+    value_log_generation: u64,
+    /// Past every FST in the directory, so that a rebuilt index never reuses an id.
+    next_fst_id: u64,
+}
+
+impl Check {
+    fn run(at: &Path, prefix: &str) -> Result<Self, Error> {
+        let paths = Pather::new(at.to_owned(), prefix.to_owned());
+        let on_disk = list_files(&paths)?;
+        if !paths.index.exists() && on_disk.fsts.is_empty() {
+            return Err(Error::NotFound(paths.base));
+        }
+
+        let mut report = CheckReport::default();
+        let mut surviving = Vec::new();
+        let mut next_fst_id = on_disk.fsts.iter().map(|&(id, _)| id + 1).max().unwrap_or(0);
+
+        let index = fs_err::File::open(&paths.index).map_err(Error::from).and_then(|mut f| {
+            Index::read(&mut f).map_err(|source| Error::CorruptIndex {
+                path: paths.index.clone(),
+                source,
+            })
+        });
+        let value_log_generation = match index {
+            Ok(index) => {
+                for fst in index.fsts {
+                    let path = paths.fst(fst.id, fst.level);
+                    match compression::locate(&path) {
+                        None => report.missing_fsts.push(path),
+                        Some(file) if !fst_is_valid(&path, fst.checksum) => report.corrupt_fsts.push(file),
+                        Some(_) => surviving.push(fst),
+                    }
+                }
+                report.orphaned_fsts = on_disk
+                    .fsts
+                    .into_iter()
+                    .filter(|&(id, level)| !surviving.iter().any(|f| f.id == id && f.level == level))
+                    .filter_map(|(id, level)| compression::locate(&paths.fst(id, level)))
+                    .filter(|p| !report.corrupt_fsts.contains(p))
+                    .collect();
+                next_fst_id = next_fst_id.max(index.next_fst_id);
+                index.value_log_generation
+            }
+            Err(e) => {
+                report.index_error = Some(e);
+                // Without an index, every FST in the directory is a candidate
+                for (id, level) in on_disk.fsts {
+                    let path = paths.fst(id, level);
+                    let fst = read_fst(&path).map(|fst| (checksum::fst_checksum(fst.as_fst().as_bytes()), fst));
+                    match fst {
+                        Some((sum, fst)) if checksum::verify_fst(fst.as_fst(), sum) => {
+                            surviving.push(IndexFst {
+                                id,
+                                level,
+                                count: fst.len() as u64,
+                                checksum: Some(sum),
+                                bounds: None,
+                            });
+                        }
+                        _ => report.corrupt_fsts.push(compression::locate(&path).unwrap_or(path)),
+                    }
+                }
+                on_disk.value_log_generations.into_iter().max().unwrap_or(0)
+            }
+        };
+
+        for path in [&paths.log_backup, &paths.log] {
+            if !path.exists() {
+                continue;
+            }
+            let contents = inspect::decode_log(fs_err::read(path)?);
+            if let Some((offset, _)) = contents.error {
+                report.truncated_logs.push((path.clone(), offset));
+            }
+        }
+
+        Ok(Self {
+            paths,
+            report,
+            surviving,
+            value_log_generation,
+            next_fst_id,
+        })
+    }
+}
+
+/// Whether the FST at `path` can be read and matches its checksum, if there is one.
+fn fst_is_valid(path: &Path, expected: Option<u32>) -> bool {
+    read_fst(path).is_some_and(|fst| {
+        let expected = expected.unwrap_or_else(|| checksum::fst_checksum(fst.as_fst().as_bytes()));
+        checksum::verify_fst(fst.as_fst(), expected)
+    })
+}
+
+fn read_fst(path: &Path) -> Option<fst::Map<Mmap>> {
+    fst::Map::new(compression::map_fst(path).ok()?).ok()
+}
+
+struct OnDisk {
+    fsts: Vec<(u64, u8)>,
+    value_log_generations: Vec<u64>,
+}
+
+/// Removes the FST files (and their filters) in the directory that are not among `fsts`, which
+/// were left behind by a flush or merge that was interrupted.
+pub(crate) fn remove_orphaned_fsts(paths: &Pather, fsts: &[Arc<LevelFst>]) -> Result<(), Error> {
+    for (id, level) in list_files(paths)?.fsts {
+        if fsts.iter().any(|f| f.id == id && f.level == level) {
+            continue;
+        }
+        let path = paths.fst(id, level);
+        warn!("removing orphaned FST {}", path.display());
+        compression::remove(&path)?;
+        let _ = fs_err::remove_file(paths.bloom(id, level));
+    }
+    Ok(())
+}
+
+/// Lists the FSTs and value logs in the database's directory by their file names.
+fn list_files(paths: &Pather) -> Result<OnDisk, Error> {
+    let mut on_disk = OnDisk {
+        fsts: Vec::new(),
+        value_log_generations: Vec::new(),
+    };
+    if !paths.base.exists() {
+        return Ok(on_disk);
+    }
+    for entry in fs_err::read_dir(&paths.base)? {
+        let name = entry?.file_name();
+        let Some(rest) = name.to_str().and_then(|n| n.strip_prefix(&paths.prefix)).and_then(|n| n.strip_prefix('_')) else {
+            continue;
+        };
+        if let Some(rest) = rest.strip_suffix(".fst").or_else(|| rest.strip_suffix(".fst.zst")) {
+            if let Some((id, level)) = rest.split_once('.').and_then(|(id, level)| Some((id.parse().ok()?, level.parse().ok()?))) {
+                on_disk.fsts.push((id, level));
+            }
+        } else if let Some(generation) = rest.strip_suffix(".vlog").and_then(|g| g.parse().ok()) {
+            on_disk.value_log_generations.push(generation);
+        }
+    }
+    Ok(on_disk)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{temp_dir::TempDir, LogItem};
+
+    #[test]
+    fn check_and_repair() {
+        let dir = TempDir::new().unwrap();
+        let open = || Database::builder(dir.path().to_owned(), "db".to_owned()).open().unwrap();
+        let mut db = open();
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"b"), 2).unwrap();
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"c"), 3).unwrap();
+        let damaged = db.paths.fst(db.fsts[1].id, db.fsts[1].level);
+        let log = db.paths.log.clone();
+        assert!(Database::check(dir.path(), "db").unwrap().is_ok());
+
+        // Lose the second FST and tear the last log entry
+        fs_err::write(&damaged, b"garbage").unwrap();
+        let mut entry = Vec::new();
+        LogItem::Insert { key: Bytes::from_static(b"d"), value: 4 }.write(&mut entry).unwrap();
+        fs_err::OpenOptions::new().append(true).open(&log).unwrap().write_all(&entry[..2]).unwrap();
+        let report = Database::check(dir.path(), "db").unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.corrupt_fsts, [damaged]);
+        assert_eq!(report.truncated_logs.len(), 1);
+        assert!(!report.repaired);
+        assert!(matches!(Database::check_and_repair(dir.path(), "db"), Err(Error::Locked { .. })));
+        drop(db);
+
+        let report = Database::check_and_repair(dir.path(), "db").unwrap();
+        assert!(report.repaired);
+        assert!(Database::check(dir.path(), "db").unwrap().is_ok());
+        let db = open();
+        assert_eq!(db.get(b"a"), Some(1));
+        assert_eq!(db.get(b"b"), None);
+        assert_eq!(db.get(b"c"), Some(3));
+        assert_eq!(db.get(b"d"), None);
+    }
+}
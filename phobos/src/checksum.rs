@@ -0,0 +1,137 @@
+use std::io::{Read, Write};
+
+/// CRC-32C (Castagnoli), the same checksum that the `fst` crate uses for its own files.
+pub(crate) fn crc32c(data: &[u8]) -> u32 {
+    !update(!0, data)
+}
+
+fn update(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc = TABLE[((crc as u8) ^ b) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// Computes the [`crc32c`] of everything read from or written to `inner`.
+#[derive(Debug)]
+pub(crate) struct Checksummed<T> {
+    inner: T,
+    state: u32,
+}
+
+impl<T> Checksummed<T> {
+    pub(crate) fn new(inner: T) -> Self {
+        Self { inner, state: !0 }
+    }
+
+    pub(crate) fn checksum(&self) -> u32 {
+        !self.state
+    }
+
+    pub(crate) fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.state = update(self.state, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.state = update(self.state, &buf[..n]);
+        Ok(n)
+    }
+}
+
+const TABLE: [u32; 256] = {
+    // Reversed Castagnoli polynomial
+    const POLY: u32 = 0x82F6_3B78;
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// The checksum of an FST's file, as recorded in the index.
+///
+/// FSTs written by `fst` 0.4 and later end in a checksum of their contents, which is used directly
+/// so that this does not need to read the whole file. Older FSTs are checksummed in full.
+pub(crate) fn fst_checksum(bytes: &[u8]) -> u32 {
+    match fst_trailer(bytes) {
+        Some(checksum) => checksum,
+        None => crc32c(bytes),
+    }
+}
+
+/// Whether the contents of an FST match `expected`, reading the whole file.
+pub(crate) fn verify_fst(fst: &fst::raw::Fst<impl AsRef<[u8]>>, expected: u32) -> bool {
+    let bytes = fst.as_bytes();
+    match fst_trailer(bytes) {
+        Some(checksum) => checksum == expected && fst.verify().is_ok(),
+        None => crc32c(bytes) == expected,
+    }
+}
+
+fn fst_trailer(bytes: &[u8]) -> Option<u32> {
+    // Checksums were added in version 3 of the format, as the final 4 bytes
+    let version = u64::from_le_bytes(bytes.get(..8)?.try_into().ok()?);
+    if version < 3 {
+        return None;
+    }
+    Some(u32::from_le_bytes(bytes.get(bytes.len().checked_sub(4)?..)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{temp_dir::TempDir, Database, Error};
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        let mut w = Checksummed::new(Vec::new());
+        w.write_all(b"1234").unwrap();
+        w.write_all(b"56789").unwrap();
+        assert_eq!(w.checksum(), 0xE306_9283);
+    }
+
+    #[test]
+    fn corrupt_fst() {
+        let dir = TempDir::new().unwrap();
+        let open = |verify| Database::builder(dir.path().to_owned(), "db".to_owned()).verify_on_open(verify).open();
+        let mut db = open(false).unwrap();
+        for i in 0..100_u64 {
+            db.set(Bytes::from(format!("key{i}")), i).unwrap();
+        }
+        db.flush().unwrap();
+        let path = db.paths.fst(db.fsts[0].id, db.fsts[0].level);
+        drop(db);
+
+        let mut bytes = fs_err::read(&path).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xFF;
+        fs_err::write(&path, bytes).unwrap();
+        assert!(matches!(open(true), Err(Error::ChecksumMismatch { path: p }) if p == path));
+    }
+}
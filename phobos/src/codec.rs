@@ -0,0 +1,113 @@
+use std::borrow::Cow;
+
+use bytes::Bytes;
+
+use crate::Database;
+
+/// Canonicalizes keys before they are stored or looked up, see
+/// [`DatabaseOptions::key_codec`][crate::DatabaseOptions::key_codec].
+///
+/// Any `Fn(&[u8]) -> Vec<u8>` can be used as a codec.
+pub trait KeyCodec: Send + Sync + 'static {
+    /// Returns the canonical form of `key`, borrowing it if it is already canonical.
+    fn encode<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]>;
+}
+
+impl<F: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static> KeyCodec for F {
+    fn encode<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        Cow::Owned(self(key))
+    }
+}
+
+/// Folds ASCII letters to lowercase, for case-insensitive keys.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AsciiLowercase;
+
+impl KeyCodec for AsciiLowercase {
+    fn encode<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        if key.iter().any(u8::is_ascii_uppercase) {
+            Cow::Owned(key.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(key)
+        }
+    }
+}
+
+/// Removes leading and trailing ASCII whitespace.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TrimAscii;
+
+impl KeyCodec for TrimAscii {
+    fn encode<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        Cow::Borrowed(key.trim_ascii())
+    }
+}
+
+impl Database {
+    pub(crate) fn encode_key<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        match &self.hooks.key_codec {
+            Some(codec) => codec.encode(key),
+            None => Cow::Borrowed(key),
+        }
+    }
+
+    /// Like [`encode_key`][Self::encode_key], but avoids copying `key` if it is already
+    /// canonical.
+    pub(crate) fn encode_owned_key(&self, key: Bytes) -> Bytes {
+        match self.encode_key(&key) {
+            Cow::Borrowed(encoded) if is_within(encoded, &key) => key.slice_ref(encoded),
+            Cow::Borrowed(encoded) => Bytes::copy_from_slice(encoded),
+            Cow::Owned(encoded) => Bytes::from(encoded),
+        }
+    }
+}
+
+/// Whether `part` borrows from `whole`, rather than from elsewhere (_e.g._ a constant).
+fn is_within(part: &[u8], whole: &[u8]) -> bool {
+    let (part, whole) = (part.as_ptr_range(), whole.as_ptr_range());
+    whole.start <= part.start && part.end <= whole.end
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use bytes::Bytes;
+
+    use super::{is_within, AsciiLowercase, KeyCodec, TrimAscii};
+    use crate::{temp_dir::TempDir, Database};
+
+    #[test]
+    fn builtin_codecs() {
+        assert!(matches!(AsciiLowercase.encode(b"key"), Cow::Borrowed(b"key")));
+        assert_eq!(&*AsciiLowercase.encode(b"Key-1"), b"key-1");
+        assert_eq!(&*TrimAscii.encode(b" \tkey\n"), b"key");
+        let upper = |key: &[u8]| key.to_ascii_uppercase();
+        assert_eq!(&*upper.encode(b"key"), b"KEY");
+    }
+
+    #[test]
+    fn encoded_keys() {
+        let dir = TempDir::new().unwrap();
+        let options = || Database::builder(dir.path().to_owned(), "db".to_owned()).key_codec(AsciiLowercase);
+        let mut db = options().open().unwrap();
+        db.set(Bytes::from_static(b"Key"), 1).unwrap();
+        db.set(Bytes::from_static(b"KEY"), 2).unwrap();
+        assert_eq!(db.get(b"key"), Some(2));
+        db.flush().unwrap();
+        assert_eq!(db.get(b"kEy"), Some(2));
+        assert!(db.contains_key(b"KEY"));
+        // Only the encoded key is stored
+        assert_eq!(db.iter().collect::<Vec<_>>(), [(Bytes::from_static(b"key"), 2)]);
+
+        drop(db);
+        let db = options().open().unwrap();
+        assert_eq!(db.get(b"KeY"), Some(2));
+
+        // Canonical keys are stored without being copied
+        let key = Bytes::from(b"canonical".to_vec());
+        let encoded = db.encode_owned_key(key.clone());
+        assert!(is_within(&encoded, &key));
+        assert!(!is_within(&db.encode_owned_key(Bytes::from_static(b"Copied")), &key));
+    }
+}
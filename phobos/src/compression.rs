@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+
+use fs_err::File;
+use memmap2::Mmap;
+
+use crate::{replace, Error};
+
+/// The path of the compressed form of the FST at `path`.
+pub(crate) fn compressed_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".zst");
+    path.into()
+}
+
+/// The file actually holding the FST at `path`, which is either `path` itself or its compressed
+/// form, or `None` if neither exists.
+pub(crate) fn locate(path: &Path) -> Option<PathBuf> {
+    if path.exists() {
+        return Some(path.to_owned());
+    }
+    let compressed = compressed_path(path);
+    compressed.exists().then_some(compressed)
+}
+
+/// Maps the FST at `path`, decompressing it into anonymous memory if only its compressed form
+/// exists.
+pub(crate) fn map_fst(path: &Path) -> Result<Mmap, Error> {
+    match File::open(path) {
+        Ok(file) => Ok(unsafe { Mmap::map(&file) }?),
+        #[cfg(feature = "zstd")]
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && compressed_path(path).exists() => decompress(&compressed_path(path)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Moves the FST just written to `from` into place at `to` and maps it, first compressing it at
+/// `level` if set.
+pub(crate) fn install(from: &Path, to: &Path, level: Option<i32>) -> Result<Mmap, Error> {
+    #[cfg(feature = "zstd")]
+    if let Some(level) = level {
+        let plain = unsafe { Mmap::map(&File::open(from)?) }?;
+        // Includes the uncompressed size in the frame header, so that it can be decompressed
+        // straight into a map of the right size
+        let compressed = zstd::bulk::compress(&plain, level)?;
+        let write = from.with_extension("zst~");
+        let mut file = File::create(&write)?;
+        std::io::Write::write_all(&mut file, &compressed)?;
+        drop(file);
+        replace::replace(&write, &compressed_path(to))?;
+        let map = copy_to_anon(&plain)?;
+        drop(plain);
+        fs_err::remove_file(from)?;
+        return Ok(map);
+    }
+    #[cfg(not(feature = "zstd"))]
+    let _ = level;
+
+    replace::replace(from, to)?;
+    Ok(unsafe { Mmap::map(&File::open(to)?) }?)
+}
+
+/// Removes the FST at `path`, in whichever form it exists. Returns `false` if it does not.
+pub(crate) fn remove(path: &Path) -> Result<bool, Error> {
+    match locate(path) {
+        Some(file) => {
+            fs_err::remove_file(file)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn decompress(path: &Path) -> Result<Mmap, Error> {
+    let compressed = unsafe { Mmap::map(&File::open(path)?) }?;
+    let corrupt = || Error::ChecksumMismatch { path: path.to_owned() };
+    let len = zstd::zstd_safe::get_frame_content_size(&compressed).ok().flatten().ok_or_else(corrupt)?;
+    let mut map = memmap2::MmapMut::map_anon(usize::try_from(len).map_err(|_| corrupt())?.max(1))?;
+    let written = zstd::bulk::decompress_to_buffer(&compressed, &mut map[..]).map_err(|_| corrupt())?;
+    if written as u64 != len {
+        return Err(corrupt());
+    }
+    Ok(map.make_read_only()?)
+}
+
+/// Copies `bytes` into anonymous memory, so that reading them never has to wait on the disk.
+pub(crate) fn copy_to_anon(bytes: &[u8]) -> Result<Mmap, Error> {
+    let mut map = memmap2::MmapMut::map_anon(bytes.len().max(1))?;
+    map[..bytes.len()].copy_from_slice(bytes);
+    Ok(map.make_read_only()?)
+}
+
+#[cfg(all(test, feature = "zstd"))]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{temp_dir::TempDir, Database};
+
+    #[test]
+    fn compressed_fsts() {
+        let dir = TempDir::new().unwrap();
+        let open = |verify| Database::builder(dir.path().to_owned(), "db".to_owned()).compress_fsts(3).verify_on_open(verify).open().unwrap();
+        let mut db = open(false);
+        for i in 0..1000_u64 {
+            db.set(Bytes::from(format!("k{i}")), i).unwrap();
+        }
+        db.merge(|_, _| Ok::<_, Error>(())).unwrap();
+        let path = db.paths.fst(db.fsts[0].id, db.fsts[0].level);
+        assert!(!path.exists());
+        assert_eq!(locate(&path), Some(compressed_path(&path)));
+        drop(db);
+
+        // Decompressed into memory on open, and checked against the uncompressed checksum
+        let db = open(true);
+        for i in 0..1000_u64 {
+            assert_eq!(db.get(format!("k{i}").as_bytes()), Some(i));
+        }
+        drop(db);
+
+        // A damaged file is reported rather than read
+        let compressed = compressed_path(&path);
+        let mut data = fs_err::read(&compressed).unwrap();
+        let len = data.len();
+        data.truncate(len / 2);
+        fs_err::write(&compressed, data).unwrap();
+        let reopened = Database::builder(dir.path().to_owned(), "db".to_owned()).open();
+        assert!(matches!(reopened, Err(Error::ChecksumMismatch { .. })));
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    fmt::{Debug, Formatter},
+    fs::File,
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+/// How durably writes to the write-ahead log are persisted.
+///
+/// See [`DatabaseOptions::durability`][crate::DatabaseOptions::durability].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum Durability {
+    /// Sync the log to disk before every write returns.
+    ///
+    /// This is the default.
+    #[default]
+    Sync,
+    /// Sync the log from a background thread, at most `max_delay` after each write.
+    ///
+    /// Writes return immediately, so a crash may lose up to `max_delay` worth of writes, but
+    /// many writes share a single sync.
+    Group { max_delay: Duration },
+    /// Never sync the log, leaving it to the OS to write back.
+    ///
+    /// A crash of the program itself loses nothing, but a crash of the OS or a power loss may
+    /// lose an unbounded amount of recent writes.
+    None,
+}
+
+/// What to do when opening a database whose write-ahead log contains an entry that cannot be
+/// read.
+///
+/// See [`DatabaseOptions::wal_recovery`][crate::DatabaseOptions::wal_recovery].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum WalRecovery {
+    /// Fail to open with [`Error::CorruptLog`][crate::Error::CorruptLog], leaving the log as it
+    /// is.
+    ///
+    /// This is the default.
+    #[default]
+    Error,
+    /// Restore every entry before the unreadable one, and discard the rest of the log.
+    ///
+    /// An entry torn by a crash partway through writing it was never acknowledged, so discarding
+    /// it loses nothing. Anything after an entry that was corrupted some other way is lost,
+    /// though.
+    TruncateAtLastValidRecord,
+}
+
+/// Syncs the write-ahead log according to a [`Durability`].
+#[derive(Debug)]
+pub(crate) enum LogSync {
+    Always,
+    Group(GroupCommit),
+    Never,
+}
+
+impl LogSync {
+    pub(crate) fn new(durability: Durability, log_file: &File) -> std::io::Result<Self> {
+        Ok(match durability {
+            Durability::Sync => Self::Always,
+            Durability::Group { max_delay } => Self::Group(GroupCommit::new(log_file.try_clone()?, max_delay)),
+            Durability::None => Self::Never,
+        })
+    }
+
+    /// Called after every write to the log.
+    pub(crate) fn written(&self, log_file: &File) -> std::io::Result<()> {
+        match self {
+            Self::Always => log_file.sync_data(),
+            Self::Group(group) => {
+                group.written();
+                Ok(())
+            }
+            Self::Never => Ok(()),
+        }
+    }
+}
+
+pub(crate) struct GroupCommit {
+    shared: Arc<GroupShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct GroupShared {
+    state: Mutex<GroupState>,
+    wake: Condvar,
+}
+
+#[derive(Default)]
+struct GroupState {
+    dirty: bool,
+    stop: bool,
+}
+
+impl GroupCommit {
+    fn new(log_file: File, max_delay: Duration) -> Self {
+        let shared = Arc::new(GroupShared {
+            state: Mutex::default(),
+            wake: Condvar::new(),
+        });
+        let thread = std::thread::Builder::new()
+            .name("phobos-group-commit".to_owned())
+            .spawn({
+                let shared = Arc::clone(&shared);
+                move || shared.run(&log_file, max_delay)
+            })
+            .expect("failed to spawn group commit thread");
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    fn written(&self) {
+        let mut state = self.shared.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if !state.dirty {
+            state.dirty = true;
+            self.shared.wake.notify_one();
+        }
+    }
+}
+
+impl GroupShared {
+    fn run(&self, log_file: &File, max_delay: Duration) {
+        loop {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            while !state.dirty && !state.stop {
+                state = self.wake.wait(state).unwrap_or_else(PoisonError::into_inner);
+            }
+            if !state.dirty {
+                return;
+            }
+
+            // Wait for more writes to arrive so that they can share this sync, unless the
+            // database is closing (including while waiting, so that closing never waits for
+            // `max_delay`)
+            if !state.stop {
+                state = self.wake.wait_timeout_while(state, max_delay, |state| !state.stop).unwrap_or_else(PoisonError::into_inner).0;
+            }
+            state.dirty = false;
+            drop(state);
+            // The next write will try again
+            if let Err(e) = log_file.sync_data() {
+                log::warn!("failed to sync the write-ahead log: {e}");
+            }
+        }
+    }
+}
+
+impl Drop for GroupCommit {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap_or_else(PoisonError::into_inner).stop = true;
+        self.shared.wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Debug for GroupCommit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GroupCommit").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Instant};
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{temp_dir::TempDir, Database, Error, LogItem};
+
+    #[test]
+    fn torn_log_entry() {
+        let dir = TempDir::new().unwrap();
+        let open = |recovery| Database::builder(dir.path().to_owned(), "db".to_owned()).wal_recovery(recovery).open();
+        let mut db = open(WalRecovery::Error).unwrap();
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        db.set(Bytes::from_static(b"b"), 2).unwrap();
+        let log = db.paths.log.clone();
+        drop(db);
+
+        // As if the process crashed partway through appending another entry
+        let mut entry = Vec::new();
+        LogItem::Insert { key: Bytes::from_static(b"c"), value: 3 }.write(&mut entry).unwrap();
+        let mut file = fs_err::OpenOptions::new().append(true).open(&log).unwrap();
+        file.write_all(&entry[..entry.len() - 1]).unwrap();
+        drop(file);
+
+        assert!(matches!(open(WalRecovery::Error), Err(Error::CorruptLog { .. })));
+        let db = open(WalRecovery::TruncateAtLastValidRecord).unwrap();
+        assert_eq!(db.get(b"a"), Some(1));
+        assert_eq!(db.get(b"b"), Some(2));
+        assert_eq!(db.get(b"c"), None);
+    }
+    #[cfg(feature = "failpoints")]
+    #[test]
+    fn crash_after_wal_append() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        use crate::Failpoint;
+
+        let modes = [
+            Durability::Sync,
+            Durability::Group { max_delay: Duration::from_millis(1) },
+            Durability::Group { max_delay: Duration::from_secs(3600) },
+            Durability::None,
+        ];
+        for durability in modes {
+            let dir = TempDir::new().unwrap();
+            let crash = Arc::new(AtomicBool::new(false));
+            let hook = crash.clone();
+            let mut db = Database::builder(dir.path().to_owned(), "db".to_owned())
+                .durability(durability)
+                .failpoint(move |point| point == Failpoint::AfterWalAppend && hook.load(Ordering::Relaxed))
+                .open()
+                .unwrap();
+            db.set(Bytes::from_static(b"a"), 1).unwrap();
+            crash.store(true, Ordering::Relaxed);
+            let crashed = db.set(Bytes::from_static(b"b"), 2);
+            assert!(matches!(crashed, Err(Error::Failpoint(Failpoint::AfterWalAppend))), "{durability:?}");
+            drop(db);
+
+            // Only the process crashed, so whatever reached the log survives whether or not it
+            // was synced
+            let db = Database::builder(dir.path().to_owned(), "db".to_owned()).durability(durability).open().unwrap();
+            assert_eq!(db.get(b"a"), Some(1), "{durability:?}");
+            assert_eq!(db.get(b"b"), Some(2), "{durability:?}");
+        }
+    }
+
+    #[test]
+    fn group_commit_close() {
+        let dir = TempDir::new().unwrap();
+        let open = || Database::builder(dir.path().to_owned(), "db".to_owned()).durability(Durability::Group { max_delay: Duration::from_secs(3600) }).open().unwrap();
+        let mut db = open();
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        // Let the sync thread start waiting for more writes
+        std::thread::sleep(Duration::from_millis(50));
+        db.set(Bytes::from_static(b"b"), 2).unwrap();
+
+        // Closing syncs straight away rather than waiting out the delay
+        let started = Instant::now();
+        drop(db);
+        assert!(started.elapsed() < Duration::from_secs(60));
+        let db = open();
+        assert_eq!(db.get(b"a"), Some(1));
+        assert_eq!(db.get(b"b"), Some(2));
+    }
+}
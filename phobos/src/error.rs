@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+/// Errors that can be encountered when using a [`Database`][crate::Database].
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or writing one of the database's files failed.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The index could not be read.
+    ///
+    /// This most likely means that the file has been externally modified.
+    #[error("corrupt index at {}", .path.display())]
+    CorruptIndex {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// An entry in the write-ahead log could not be read.
+    ///
+    /// This is expected for the final entry if the program terminated partway through writing it,
+    /// which [`WalRecovery::TruncateAtLastValidRecord`][crate::WalRecovery::TruncateAtLastValidRecord]
+    /// recovers from.
+    #[error("corrupt write-ahead log entry at offset {offset}")]
+    CorruptLog {
+        offset: u64,
+        #[source]
+        source: std::io::Error,
+    },
+    /// An FST's contents do not match the checksum recorded for it in the index.
+    ///
+    /// This means that the file has been corrupted or externally modified.
+    #[error("checksum mismatch in {}", .path.display())]
+    ChecksumMismatch { path: PathBuf },
+    /// An FST could not be built or read.
+    #[error(transparent)]
+    Fst(#[from] fst::Error),
+    /// A [`Regex`][crate::Regex] could not be compiled.
+    #[cfg(feature = "regex")]
+    #[error("invalid regex")]
+    Regex(#[from] regex_automata::Error),
+    /// The automaton for a [fuzzy search][crate::Database::fuzzy] would be too large.
+    #[cfg(feature = "fuzzy")]
+    #[error(transparent)]
+    Levenshtein(#[from] fst::automaton::LevenshteinError),
+    /// The database does not exist, and [creation][crate::DatabaseOptions::create] was disabled.
+    #[error("no database exists at {}", .0.display())]
+    NotFound(PathBuf),
+    /// The database is already open, so its lock could not be taken.
+    #[error("could not lock {}, the database is already open", .path.display())]
+    Locked {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// A database already exists where one was to be [imported][crate::Database::import].
+    #[error("a database already exists at {}", .0.display())]
+    AlreadyExists(PathBuf),
+    /// An [export][crate::Database::export] could not be read, or was not an export at all.
+    #[error("corrupt export")]
+    CorruptExport(#[source] std::io::Error),
+    /// A [value log][crate::DatabaseOptions::value_log] operation was used on a database without
+    /// one.
+    #[error("database does not have a value log")]
+    NoValueLog,
+    /// A plain `u64` value was [set][crate::Database::set] on a database with a
+    /// [value log][crate::DatabaseOptions::value_log].
+    #[error("cannot set a u64 value in a database with a value log")]
+    HasValueLog,
+    /// A fixed-width value was read from the [value log][crate::DatabaseOptions::value_log], but
+    /// the stored value has a different length.
+    #[error("expected a {expected}-byte value, found {found} bytes")]
+    ValueWidth { expected: usize, found: usize },
+    /// [`set_with_ttl`][crate::Database::set_with_ttl] was used on a database without
+    /// [expiry][crate::DatabaseOptions::expiry] enabled.
+    #[error("database does not have expiry enabled")]
+    NoExpiry,
+    /// A historical read was used on a database without
+    /// [versioning][crate::DatabaseOptions::versions] enabled.
+    #[error("database does not keep versions")]
+    NoVersions,
+    /// A historical read was older than the versioning horizon as of the last full merge.
+    #[error("version {0} has been compacted away")]
+    VersionCompacted(u64),
+    /// [`keys_for`][crate::Database::keys_for] was used on a database without a
+    /// [reverse index][crate::DatabaseOptions::reverse_index].
+    #[error("database does not have a reverse index")]
+    NoReverseIndex,
+    /// A [`failpoint`][crate::DatabaseOptions::failpoint] hook simulated a crash.
+    #[cfg(feature = "failpoints")]
+    #[error("simulated crash at {0:?}")]
+    Failpoint(crate::Failpoint),
+    /// A [merge][crate::Database::merge] callback returned an error.
+    #[error("merge callback failed")]
+    Callback(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
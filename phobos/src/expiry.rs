@@ -0,0 +1,129 @@
+use std::{
+    convert::Infallible,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+
+use crate::{Database, Error, LogItem, MergeAction};
+
+/// The expiry stored for keys that no longer expire, _e.g._ those set again with
+/// [`Database::set`] after [`Database::set_with_ttl`].
+const NEVER: u64 = 0;
+
+impl Database {
+    /// Stores `key`->`value` like [`set`][Self::set], but treats `key` as absent once `ttl` has
+    /// passed.
+    ///
+    /// Expired keys are hidden from [`get`][Self::get], [`contains_key`][Self::contains_key] and
+    /// [`multi_get`][Self::multi_get], and are dropped by the next full [`merge`][Self::merge].
+    /// Until then, they are still returned by iteration and [snapshots][Self::snapshot]. Setting
+    /// `key` again, with or without a TTL, replaces its expiry.
+    ///
+    /// Fails with [`Error::NoExpiry`] unless [`DatabaseOptions::expiry`][crate::DatabaseOptions::expiry]
+    /// is enabled, and cannot be used with a [value log][crate::DatabaseOptions::value_log].
+    pub fn set_with_ttl(&mut self, key: Bytes, value: u64, ttl: Duration) -> Result<(), Error> {
+        if self.value_log.is_some() {
+            return Err(Error::HasValueLog);
+        }
+        let key = self.encode_owned_key(key);
+        let Some(expiry) = &mut self.expiry else {
+            return Err(Error::NoExpiry);
+        };
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        // Written first, so that a crash in between can only make the previous value expire early
+        expiry.set(key.clone(), now_millis().saturating_add(ttl).max(NEVER + 1))?;
+        self.record_versions(&[(key.clone(), value)])?;
+        self.log(LogItem::Insert { key: key.clone(), value })?;
+        self.hold(key, value)
+    }
+
+    /// When `key` expires, or `None` if it was not set with a TTL (or expiry is not enabled).
+    ///
+    /// This is returned even once `key` has expired, until it is dropped by a merge.
+    pub fn expires_at(&self, key: &[u8]) -> Option<SystemTime> {
+        let at = self.expiry.as_ref()?.get(&self.encode_key(key)).filter(|&at| at != NEVER)?;
+        Some(UNIX_EPOCH + Duration::from_millis(at))
+    }
+
+    pub(crate) fn is_expired(&self, key: &[u8]) -> bool {
+        self.expiry.as_ref().is_some_and(|expiry| expired(expiry.get(key), now_millis()))
+    }
+
+    /// Removes the expiry of each of `keys` that has one, after they have been set without a TTL.
+    pub(crate) fn clear_expiry<'k>(&mut self, keys: impl IntoIterator<Item = &'k Bytes>) -> Result<(), Error> {
+        let Some(expiry) = &mut self.expiry else {
+            return Ok(());
+        };
+        let cleared = keys
+            .into_iter()
+            .filter(|key| expiry.get(key).is_some_and(|at| at != NEVER))
+            .map(|key| (key.clone(), NEVER))
+            .collect::<Vec<_>>();
+        expiry.set_batch(cleared)
+    }
+
+    /// Drops every expiry that is no longer needed after a full merge at `now`, which has already
+    /// dropped the expired keys themselves.
+    pub(crate) fn prune_expiry(&mut self, now: u64) -> Result<(), Error> {
+        let Some(expiry) = &mut self.expiry else {
+            return Ok(());
+        };
+        expiry.merge_matching(
+            |_| true,
+            |_, at| {
+                Ok::<_, Infallible>(if at == NEVER || at <= now { MergeAction::Drop } else { MergeAction::Keep })
+            },
+        )
+    }
+}
+
+/// Whether an expiry read from the expiry database has passed at `now`.
+pub(crate) fn expired(at: Option<u64>, now: u64) -> bool {
+    at.is_some_and(|at| at != NEVER && at <= now)
+}
+
+pub(crate) fn now_millis() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    u64::try_from(now.as_millis()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn set_with_ttl() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned()).expiry(true).open().unwrap();
+        db.set_with_ttl(Bytes::from_static(b"gone"), 1, Duration::ZERO).unwrap();
+        db.set_with_ttl(Bytes::from_static(b"kept"), 2, Duration::from_secs(3600)).unwrap();
+        db.set_with_ttl(Bytes::from_static(b"reset"), 3, Duration::ZERO).unwrap();
+        db.set(Bytes::from_static(b"reset"), 4).unwrap();
+        assert_eq!(db.get(b"gone"), None);
+        assert!(db.expires_at(b"gone").is_some());
+        assert_eq!(db.get(b"kept"), Some(2));
+        assert!(db.expires_at(b"kept").is_some());
+        assert_eq!(db.get(b"reset"), Some(4));
+        assert_eq!(db.expires_at(b"reset"), None);
+
+        // Expiries survive a reopen, and the next full merge drops expired keys
+        drop(db);
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned()).expiry(true).open().unwrap();
+        assert_eq!(db.get(b"gone"), None);
+        db.merge(|_, _| Ok::<_, Error>(())).unwrap();
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.expires_at(b"gone"), None);
+        assert_eq!(db.get(b"kept"), Some(2));
+    }
+
+    #[test]
+    fn not_enabled() {
+        let mut db = Database::in_memory().unwrap();
+        let set = db.set_with_ttl(Bytes::from_static(b"a"), 1, Duration::ZERO);
+        assert!(matches!(set, Err(Error::NoExpiry)));
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    io::{BufReader, BufWriter, Read, Write},
+    path::PathBuf,
+};
+
+use fs_err::File;
+use fst::{MapBuilder, Streamer};
+use memmap2::Mmap;
+
+use crate::{bloom, checksum::Checksummed, value_log::ValueLog, Database, Error, Pather};
+
+/// Identifies an [export][Database::export].
+///
+/// An export is the magic bytes, a flags byte (`1` if the database has a value log), the length
+/// (`u64` LE) and bytes of a single FST holding every item, then for a value log the length
+/// (`u64` LE) and bytes of the values the FST points into, and finally a CRC-32C (`u32` LE) of
+/// everything before it.
+const MAGIC: &[u8] = b"\xFEruEXPt\xAA";
+const HAS_VALUE_LOG: u8 = 1;
+
+impl Database {
+    /// Writes the latest value for every key to `w` as a single self-contained file, which can be
+    /// restored with [`import`][Self::import].
+    ///
+    /// This includes any in-memory items, and with a [value log][crate::DatabaseOptions::value_log]
+    /// only the values still referenced, so is usually smaller than the database itself.
+    pub fn export(&self, w: impl Write) -> Result<(), Error> {
+        let mut w = Checksummed::new(BufWriter::new(w));
+        w.write_all(MAGIC)?;
+        w.write_all(&[if self.value_log.is_some() { HAS_VALUE_LOG } else { 0 }])?;
+
+        // With a value log, each value is replaced by its offset in the exported values
+        let mut builder = MapBuilder::memory();
+        let mut values_len = 0;
+        for (key, value) in self.iter() {
+            let value = match &self.value_log {
+                Some(value_log) => {
+                    let offset = values_len;
+                    values_len += ValueLog::encoded_len(value_log.value_len(value)?);
+                    offset
+                }
+                None => value,
+            };
+            builder.insert(key, value)?;
+        }
+        let fst = builder.into_inner()?;
+        w.write_all(&(fst.len() as u64).to_le_bytes())?;
+        w.write_all(&fst)?;
+
+        if let Some(value_log) = &self.value_log {
+            w.write_all(&values_len.to_le_bytes())?;
+            for (_, offset) in self.iter() {
+                ValueLog::encode(&mut w, &value_log.read(offset)?)?;
+            }
+        }
+
+        let checksum = w.checksum();
+        let mut w = w.into_inner();
+        w.write_all(&checksum.to_le_bytes())?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Creates a new database with `prefix` in `at` from an [export][Self::export], using the
+    /// default options (other than [`value_log`][crate::DatabaseOptions::value_log], which
+    /// matches the exported database).
+    ///
+    /// Fails with [`Error::AlreadyExists`] if there is already a database there. If the export is
+    /// corrupt, this fails with [`Error::CorruptExport`] and leaves an empty database behind.
+    pub fn import(r: impl Read, at: PathBuf, prefix: String) -> Result<Database, Error> {
+        let paths = Pather::new(at.clone(), prefix.clone());
+        if paths.index.exists() {
+            return Err(Error::AlreadyExists(paths.index));
+        }
+
+        let mut r = Checksummed::new(BufReader::new(r));
+        let mut magic = [0; MAGIC.len()];
+        r.read_exact(&mut magic).map_err(Error::CorruptExport)?;
+        let mut flags = [0];
+        r.read_exact(&mut flags).map_err(Error::CorruptExport)?;
+        if magic != MAGIC || flags[0] & !HAS_VALUE_LOG != 0 {
+            return Err(Error::CorruptExport(std::io::ErrorKind::InvalidData.into()));
+        }
+
+        let mut db = Self::builder(at, prefix).value_log(flags[0] == HAS_VALUE_LOG).open()?;
+        if let Err(e) = db.import_from(r) {
+            let _ = fs_err::remove_file(&db.paths.write_fst);
+            if let Some(value_log) = &mut db.value_log {
+                let _ = value_log.truncate();
+            }
+            return Err(e);
+        }
+        Ok(db)
+    }
+
+    fn import_from(&mut self, mut r: Checksummed<impl Read>) -> Result<(), Error> {
+        let mut wtr = BufWriter::new(File::create(&self.paths.write_fst)?);
+        let fst_len = read_u64(&mut r)?;
+        if std::io::copy(&mut (&mut r).take(fst_len), &mut wtr).map_err(Error::CorruptExport)? != fst_len {
+            return Err(Error::CorruptExport(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+        wtr.flush()?;
+        drop(wtr);
+
+        if let Some(value_log) = &mut self.value_log {
+            let values_len = read_u64(&mut r)?;
+            value_log.append_raw(&mut r, values_len).map_err(Error::CorruptExport)?;
+            value_log.sync()?;
+        }
+
+        let expected = r.checksum();
+        let mut checksum = [0; 4];
+        r.read_exact(&mut checksum).map_err(Error::CorruptExport)?;
+        if u32::from_le_bytes(checksum) != expected {
+            return Err(Error::CorruptExport(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "export checksum mismatch",
+            )));
+        }
+
+        let file = File::open(&self.paths.write_fst)?;
+        let fst = fst::Map::new(unsafe { Mmap::map(&file) }?)?;
+        let count = fst.len() as u64;
+        if count == 0 {
+            drop(fst);
+            fs_err::remove_file(&self.paths.write_fst)?;
+            return Ok(());
+        }
+        let mut key_hashes = Vec::new();
+        if self.bloom_bits_per_key > 0 {
+            let mut keys = fst.keys();
+            while let Some(key) = keys.next() {
+                key_hashes.push(bloom::hash(key));
+            }
+        }
+        drop(fst);
+
+        let id = self.fst_count as u64;
+        self.fst_count += 1;
+        let new = self.install_fst(id, count, &key_hashes)?;
+        self.fsts.push(new);
+        self.count += count as usize;
+        self.write_index()
+    }
+}
+
+fn read_u64(r: &mut impl Read) -> Result<u64, Error> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf).map_err(Error::CorruptExport)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn roundtrip() {
+        let mut db = Database::in_memory().unwrap();
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"a"), 2).unwrap();
+        db.set(Bytes::from_static(b"b"), 3).unwrap();
+        let mut export = Vec::new();
+        db.export(&mut export).unwrap();
+
+        let dir = TempDir::new().unwrap();
+        let imported = Database::import(&export[..], dir.path().to_owned(), "db".to_owned()).unwrap();
+        assert_eq!(imported.iter().collect::<Vec<_>>(), db.iter().collect::<Vec<_>>());
+        assert!(matches!(
+            Database::import(&export[..], dir.path().to_owned(), "db".to_owned()),
+            Err(Error::AlreadyExists(_))
+        ));
+
+        let mut corrupt = export.clone();
+        corrupt[MAGIC.len() + 12] ^= 1;
+        let dir = TempDir::new().unwrap();
+        let imported = Database::import(&corrupt[..], dir.path().to_owned(), "db".to_owned());
+        assert!(matches!(imported, Err(Error::CorruptExport(_))));
+    }
+
+    #[test]
+    fn value_log() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned()).value_log(true).open().unwrap();
+        db.set_bytes(Bytes::from_static(b"a"), b"first").unwrap();
+        db.set_bytes(Bytes::from_static(b"a"), b"second").unwrap();
+        db.set_bytes(Bytes::from_static(b"b"), b"third").unwrap();
+        let mut export = Vec::new();
+        db.export(&mut export).unwrap();
+
+        let dir = TempDir::new().unwrap();
+        let imported = Database::import(&export[..], dir.path().to_owned(), "db".to_owned()).unwrap();
+        assert_eq!(imported.get_bytes(b"a").unwrap().as_deref(), Some(&b"second"[..]));
+        assert_eq!(imported.get_bytes(b"b").unwrap().as_deref(), Some(&b"third"[..]));
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{Database, Error};
+
+/// A point at which a [`failpoint`][crate::DatabaseOptions::failpoint] hook can simulate a crash.
+///
+/// A simulated crash returns [`Error::Failpoint`] from the operation, leaving the files exactly as
+/// they are at that point. The database must then be dropped and opened again, just as it would be
+/// after a real crash, to check what is recovered.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum Failpoint {
+    /// A new index has been written, but not yet renamed over the current one.
+    BeforeIndexRename,
+    /// A new index has just been renamed over the current one.
+    AfterIndexRename,
+    /// An item has been appended to the write-ahead log (and synced, if the
+    /// [`Durability`][crate::Durability] requires it), but not yet applied.
+    AfterWalAppend,
+    /// A flush or merge has written its new FST, but the index does not reference it yet.
+    MidMerge,
+}
+
+impl Database {
+    /// Fails with [`Error::Failpoint`] if the hook asks for a crash at `point`.
+    pub(crate) fn failpoint(&self, point: Failpoint) -> Result<(), Error> {
+        #[cfg(feature = "failpoints")]
+        if self.hooks.failpoint.as_ref().is_some_and(|hook| hook(point)) {
+            log::warn!("simulating a crash at {point:?}");
+            return Err(Error::Failpoint(point));
+        }
+        #[cfg(not(feature = "failpoints"))]
+        let _ = point;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "failpoints"))]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn after_wal_append() {
+        let dir = TempDir::new().unwrap();
+        let crash = Arc::new(AtomicBool::new(false));
+        let hook = crash.clone();
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned())
+            .failpoint(move |point| point == Failpoint::AfterWalAppend && hook.load(Ordering::Relaxed))
+            .open()
+            .unwrap();
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        crash.store(true, Ordering::Relaxed);
+        let crashed = db.set_batch([(Bytes::from_static(b"a"), 2), (Bytes::from_static(b"b"), 3)]);
+        assert!(matches!(crashed, Err(Error::Failpoint(Failpoint::AfterWalAppend))));
+        drop(db);
+
+        // The whole batch was logged before the crash, so it is all restored
+        let db = Database::builder(dir.path().to_owned(), "db".to_owned()).open().unwrap();
+        assert_eq!(db.get(b"a"), Some(2));
+        assert_eq!(db.get(b"b"), Some(3));
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    fmt::{Debug, Formatter},
+    time::Duration,
+};
+
+use crate::KeyCodec;
+
+/// Passed to [`on_flush`][crate::DatabaseOptions::on_flush] after in-memory items have been
+/// written to an FST.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct FlushInfo {
+    /// The number of in-memory items written.
+    pub items: usize,
+    /// The level of the FST they were written to. This is above `0` if the flush also merged
+    /// existing FSTs.
+    pub level: u8,
+    /// How long the flush took, including any merge.
+    pub duration: Duration,
+}
+
+/// Passed to [`on_merge_start`][crate::DatabaseOptions::on_merge_start] before existing FSTs are
+/// merged.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct MergeStartInfo {
+    /// The levels of the FSTs being merged, one per FST.
+    pub levels: Vec<u8>,
+    /// The total number of items in those FSTs and in memory, before removing duplicates.
+    pub items: u64,
+}
+
+/// Passed to [`on_merge_complete`][crate::DatabaseOptions::on_merge_complete] after existing FSTs
+/// have been merged.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct MergeInfo {
+    /// The levels of the FSTs that were merged, one per FST.
+    pub levels: Vec<u8>,
+    /// The level of the resulting FST, or `None` if nothing was left to write.
+    pub level: Option<u8>,
+    /// The number of items in the resulting FST.
+    pub items: u64,
+    pub duration: Duration,
+}
+
+pub(crate) type Hook<T> = Box<dyn Fn(&T) + Send + Sync>;
+
+#[derive(Default)]
+pub(crate) struct Hooks {
+    pub(crate) on_flush: Option<Hook<FlushInfo>>,
+    pub(crate) on_merge_start: Option<Hook<MergeStartInfo>>,
+    pub(crate) on_merge_complete: Option<Hook<MergeInfo>>,
+    pub(crate) key_codec: Option<Box<dyn KeyCodec>>,
+    #[cfg(feature = "failpoints")]
+    pub(crate) failpoint: Option<Box<dyn Fn(crate::Failpoint) -> bool + Send + Sync>>,
+}
+
+impl Debug for Hooks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut f = f.debug_struct("Hooks");
+        f.field("on_flush", &self.on_flush.is_some())
+            .field("on_merge_start", &self.on_merge_start.is_some())
+            .field("on_merge_complete", &self.on_merge_complete.is_some())
+            .field("key_codec", &self.key_codec.is_some());
+        #[cfg(feature = "failpoints")]
+        f.field("failpoint", &self.failpoint.is_some());
+        f.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use bytes::Bytes;
+
+    use crate::{temp_dir::TempDir, Database, Error};
+
+    #[test]
+    fn hooks() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let (flush, start, complete) = (events.clone(), events.clone(), events.clone());
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned())
+            .on_flush(move |info| flush.lock().unwrap().push(format!("flush {} at {}", info.items, info.level)))
+            .on_merge_start(move |info| start.lock().unwrap().push(format!("start {:?} {}", info.levels, info.items)))
+            .on_merge_complete(move |info| complete.lock().unwrap().push(format!("complete {:?} {:?} {}", info.levels, info.level, info.items)))
+            .open()
+            .unwrap();
+
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"a"), 2).unwrap();
+        db.set(Bytes::from_static(b"b"), 3).unwrap();
+        // Also writes the in-memory items, so reports a flush as well
+        db.merge(|_, _| Ok::<_, Error>(())).unwrap();
+        // Flushing nothing calls no hooks
+        db.flush().unwrap();
+        assert_eq!(*events.lock().unwrap(), ["flush 1 at 0", "start [0] 3", "complete [0] Some(0) 2", "flush 2 at 0"]);
+    }
+}
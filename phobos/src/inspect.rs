@@ -0,0 +1,108 @@
+//! Read-only access to a [`Database`][crate::Database]'s on-disk files, for debugging.
+//!
+//! Unlike [opening][crate::DatabaseOptions::open] a database, nothing here modifies any files (in
+//! particular, the log is not replayed), so these are safe to use on a database that is corrupt
+//! or in use by another process.
+
+use std::{
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+
+use crate::{compression, Error, Index, LogItem, Pather};
+
+/// An FST listed in a database's index.
+#[derive(Debug, Clone)]
+pub struct FstInfo {
+    pub id: u64,
+    pub level: u8,
+    /// The number of items in the FST, as recorded in the index.
+    pub count: u64,
+    /// The FST's file, which ends in `.zst` if it is compressed.
+    pub path: PathBuf,
+    /// The size of the FST's file, or `None` if it is missing.
+    pub file_len: Option<u64>,
+}
+
+/// Reads the index of the database with `prefix` in `at`, listing every FST it references.
+pub fn fsts(at: &Path, prefix: &str) -> Result<Vec<FstInfo>, Error> {
+    let paths = Pather::new(at.to_owned(), prefix.to_owned());
+    let index = Index::read(&mut fs_err::File::open(&paths.index)?).map_err(|source| Error::CorruptIndex {
+        path: paths.index.clone(),
+        source,
+    })?;
+    Ok(index
+        .fsts
+        .into_iter()
+        .map(|f| {
+            let path = paths.fst(f.id, f.level);
+            let path = compression::locate(&path).unwrap_or(path);
+            FstInfo {
+                id: f.id,
+                level: f.level,
+                count: f.count,
+                file_len: fs_err::metadata(&path).ok().map(|m| m.len()),
+                path,
+            }
+        })
+        .collect())
+}
+
+/// A single item from a database's write-ahead log.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LogEntry {
+    Insert { key: Bytes, value: u64 },
+    /// Everything before this has been written to an FST.
+    Flushed,
+    /// An insert into a database with a [value log][crate::DatabaseOptions::value_log].
+    InsertValue { key: Bytes, value: Bytes },
+    /// Several inserts written at once by [`Database::set_batch`][crate::Database::set_batch].
+    Batch { items: Vec<(Bytes, u64)> },
+}
+
+/// The decoded contents of a write-ahead log.
+#[derive(Debug)]
+pub struct LogContents {
+    /// Each entry along with its byte offset in the log.
+    pub entries: Vec<(u64, LogEntry)>,
+    /// If the log could not be read to the end, the offset that decoding stopped at and why.
+    ///
+    /// A partially-written final entry is expected after a crash.
+    pub error: Option<(u64, std::io::Error)>,
+}
+
+/// Reads the write-ahead log of the database with `prefix` in `at`.
+///
+/// If a backup of the log exists (_i.e._ a previous open was interrupted while replaying it),
+/// that is returned instead, as it is what will be replayed on the next open.
+pub fn log(at: &Path, prefix: &str) -> Result<LogContents, Error> {
+    let paths = Pather::new(at.to_owned(), prefix.to_owned());
+    let path = if paths.log_backup.exists() { &paths.log_backup } else { &paths.log };
+    let mut data = Vec::new();
+    fs_err::File::open(path)?.read_to_end(&mut data)?;
+    Ok(decode_log(data))
+}
+
+pub(crate) fn decode_log(data: Vec<u8>) -> LogContents {
+    let end = data.len() as u64;
+    let mut reader = Cursor::new(data);
+    let mut entries = Vec::new();
+    while reader.position() < end {
+        let offset = reader.position();
+        match LogItem::read(&mut reader) {
+            Ok(LogItem::Insert { key, value }) => entries.push((offset, LogEntry::Insert { key, value })),
+            Ok(LogItem::Flushed) => entries.push((offset, LogEntry::Flushed)),
+            Ok(LogItem::InsertValue { key, value }) => entries.push((offset, LogEntry::InsertValue { key, value })),
+            Ok(LogItem::Batch { items }) => entries.push((offset, LogEntry::Batch { items })),
+            Err(e) => {
+                return LogContents {
+                    entries,
+                    error: Some((offset, e)),
+                }
+            }
+        }
+    }
+    LogContents { entries, error: None }
+}
@@ -2,19 +2,81 @@
 #![warn(missing_debug_implementations)]
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fmt::{Debug, Formatter},
-    io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    io::{BufWriter, Cursor, Read, Seek, Write},
+    ops::RangeBounds,
     path::PathBuf,
+    sync::Arc,
+    time::Instant,
 };
 
-use anyhow::anyhow;
 use bytes::Bytes;
 use fs_err::{File, OpenOptions};
-use fst::{map::OpBuilder, MapBuilder, Streamer};
+use fst::{map::OpBuilder, Automaton, MapBuilder, Streamer};
+use log::{debug, warn};
 use memmap2::Mmap;
 use varuint::{ReadVarint, WriteVarint};
 
+mod advice;
+#[cfg(feature = "tokio")]
+mod async_db;
+mod bloom;
+mod check;
+mod checksum;
+mod codec;
+mod compression;
+mod durability;
+mod error;
+mod expiry;
+mod export;
+mod failpoint;
+mod hooks;
+pub mod inspect;
+mod lock;
+mod multimap;
+#[cfg(feature = "regex")]
+mod regex;
+mod replace;
+mod residency;
+mod reverse;
+mod stats;
+mod temp_dir;
+mod throttle;
+mod transaction;
+mod value_log;
+mod versions;
+
+pub use advice::AccessPattern;
+#[cfg(feature = "tokio")]
+pub use async_db::AsyncDatabase;
+pub use check::CheckReport;
+pub use codec::{AsciiLowercase, KeyCodec, TrimAscii};
+pub use durability::{Durability, WalRecovery};
+pub use error::Error;
+#[cfg(feature = "failpoints")]
+pub use failpoint::Failpoint;
+pub use hooks::{FlushInfo, MergeInfo, MergeStartInfo};
+pub use multimap::MultiMap;
+#[cfg(feature = "regex")]
+pub use regex::Regex;
+pub use stats::{GcReport, LevelStats, Stats};
+pub use transaction::Transaction;
+
+use advice::MergeAdvice;
+use bloom::Bloom;
+use durability::LogSync;
+#[cfg(not(feature = "failpoints"))]
+use failpoint::Failpoint;
+use hooks::Hooks;
+use lock::Lock;
+use residency::{MemoryBudget, Residency};
+use reverse::ReverseIndex;
+use temp_dir::TempDir;
+use throttle::Throttled;
+use value_log::ValueLog;
+use versions::Versions;
+
 /// Options to open a [`Database`] with.
 ///
 /// See [`Database::builder`].
@@ -25,7 +87,25 @@ pub struct DatabaseOptions {
     merge_on_open: bool,
     fanout: usize,
     memory_threshold: usize,
+    memory_threshold_bytes: Option<usize>,
     create: bool,
+    value_log: bool,
+    compact_value_log: bool,
+    bloom_bits_per_key: usize,
+    durability: Durability,
+    wal_recovery: WalRecovery,
+    max_wal_bytes: Option<u64>,
+    merge_rate_limit: Option<u64>,
+    verify_on_open: bool,
+    compression_level: Option<i32>,
+    expiry: bool,
+    versions: Option<u64>,
+    reverse_index: bool,
+    memory_budget: Option<u64>,
+    load_levels: Option<u8>,
+    access_pattern: Option<AccessPattern>,
+    merge_access_pattern: Option<AccessPattern>,
+    hooks: Hooks,
 }
 
 impl DatabaseOptions {
@@ -36,10 +116,42 @@ impl DatabaseOptions {
             merge_on_open: false,
             fanout: 6,
             memory_threshold: 128,
+            memory_threshold_bytes: None,
             create: true,
+            value_log: false,
+            compact_value_log: false,
+            bloom_bits_per_key: 0,
+            durability: Durability::default(),
+            wal_recovery: WalRecovery::default(),
+            max_wal_bytes: None,
+            merge_rate_limit: None,
+            verify_on_open: true,
+            compression_level: None,
+            expiry: false,
+            versions: None,
+            reverse_index: false,
+            memory_budget: None,
+            load_levels: None,
+            access_pattern: None,
+            merge_access_pattern: None,
+            hooks: Hooks::default(),
         }
     }
 
+    /// Opens (or creates) the database, taking an exclusive lock on `<prefix>.lock` for as long
+    /// as it is open.
+    ///
+    /// Fails with [`Error::Locked`] if the database is already open, whether in this process or
+    /// another. Files must still not be modified by anything other than phobos while the database
+    /// is open.
+    pub fn open(self) -> Result<Database, Error> {
+        // SAFETY: The lock stops any other instance from modifying the files
+        unsafe { self.open_with(true) }
+    }
+
+    /// Opens the database without taking its lock, _e.g._ on filesystems that do not support
+    /// locking.
+    ///
     /// # Safety
     ///
     /// From calling this function to closing the returned Database, the relevant files within
@@ -49,28 +161,68 @@ impl DatabaseOptions {
     /// Modifying any such file will likely result in a panic, but may result in incorrect results
     /// being returned instead. The `fst` crate guarantees that modifying the underlying files will
     /// not cause memory safety.
-    pub unsafe fn open(self) -> anyhow::Result<Database> {
-        let paths = Pather::new(self.at, self.prefix.clone())?;
-        let mut s = if paths.index.exists() {
+    pub unsafe fn open_unlocked(self) -> Result<Database, Error> {
+        unsafe { self.open_with(false) }
+    }
+
+    unsafe fn open_with(self, lock: bool) -> Result<Database, Error> {
+        let paths = Pather::new(self.at, self.prefix.clone());
+        let exists = paths.index.exists();
+        if !exists {
+            if !self.create {
+                return Err(Error::NotFound(paths.base));
+            }
+            fs_err::create_dir_all(&paths.base)?;
+        }
+        let lock = if lock { Some(Lock::new(&paths.lock)?) } else { None };
+        let expiry = if self.expiry {
+            let options = DatabaseOptions::new(paths.base.clone(), format!("{}.ttl", self.prefix))
+                .durability(self.durability)
+                .wal_recovery(self.wal_recovery);
+            Some(Box::new(unsafe { options.open_with(lock.is_some()) }?))
+        } else {
+            None
+        };
+        let versions = match self.versions {
+            Some(horizon) => {
+                let options = DatabaseOptions::new(paths.base.clone(), format!("{}.versions", self.prefix))
+                    .durability(self.durability)
+                    .wal_recovery(self.wal_recovery);
+                Some(Versions::new(unsafe { options.open_with(lock.is_some()) }?, horizon))
+            }
+            None => None,
+        };
+
+        let mut s = if exists {
             let mut index_file = OpenOptions::new().read(true).write(true).create(false).open(&paths.index)?;
-            let index = Index::read(&mut index_file)?;
+            let index = Index::read(&mut index_file).map_err(|source| Error::CorruptIndex {
+                path: paths.index.clone(),
+                source,
+            })?;
             let log_file = OpenOptions::new().read(true).write(true).create(false).open(&paths.log)?;
-            let fst_count = index.fsts.iter().map(|f| f.id as usize).max().unwrap_or(0);
+            let log_sync = LogSync::new(self.durability, log_file.file())?;
+            let fst_count = index.next_fst_id as usize;
+            let value_log = if self.value_log {
+                let generation = index.value_log_generation;
+                // A database that has never compacted may have been created without a value log
+                Some(ValueLog::open(&paths.value_log(generation), generation, generation == 0)?)
+            } else {
+                None
+            };
             let fsts = index
                 .fsts
                 .into_iter()
                 .map(|fs| {
-                    let fst_file = File::open(paths.fst(fs.id, fs.level))?;
-                    let map = unsafe { Mmap::map(&fst_file) }?;
-                    let fst = fst::Map::new(map)?;
-                    Ok(LevelFst {
-                        count: fs.count,
-                        id: fs.id,
-                        level: fs.level,
-                        fst,
-                    })
+                    open_fst(
+                        &paths,
+                        fs,
+                        self.verify_on_open,
+                        self.bloom_bits_per_key,
+                        self.load_levels,
+                        self.access_pattern,
+                    )
                 })
-                .collect::<Result<Vec<_>, anyhow::Error>>()?;
+                .collect::<Result<Vec<_>, Error>>()?;
 
             let mut s = Database {
                 index_file,
@@ -79,21 +231,55 @@ impl DatabaseOptions {
                 fst_count,
                 fsts,
                 held: Default::default(),
+                held_bytes: 0,
                 paths,
                 fanout: self.fanout,
                 memory_threshold: self.memory_threshold,
+                memory_threshold_bytes: self.memory_threshold_bytes,
+                value_log,
+                compact_value_log: self.compact_value_log,
+                bloom_bits_per_key: self.bloom_bits_per_key,
+                compression_level: self.compression_level,
+                log_sync,
+                durability: self.durability,
+                verify_on_open: self.verify_on_open,
+                wal_bytes: 0,
+                max_wal_bytes: self.max_wal_bytes,
+                merge_rate_limit: self.merge_rate_limit,
+                wal_recovery: self.wal_recovery,
+                expiry,
+                versions,
+                reverse_index: self.reverse_index,
+                reverse: None,
+                memory_budget: self.memory_budget.map(MemoryBudget::new),
+                load_levels: self.load_levels,
+                access_pattern: self.access_pattern,
+                merge_access_pattern: self.merge_access_pattern,
+                hooks: self.hooks,
+                _lock: lock,
+                _temp_dir: None,
             };
+            if s.reverse_index {
+                s.reverse = ReverseIndex::open(&s.paths);
+            }
+            // Without the lock, another instance may be about to add them to the index
+            if s._lock.is_some() {
+                check::remove_orphaned_fsts(&s.paths, &s.fsts)?;
+            }
+            debug!("opened {} with {} FSTs", s.paths.index.display(), s.fsts.len());
             s.restore_log()?;
 
             s
         } else {
-            if !self.create {
-                return Err(anyhow!("directory does not exist"));
-            }
-            fs_err::create_dir_all(&paths.base)?;
             let index_file = File::create(&paths.index)?;
             let log_file = File::create(&paths.log)?;
+            let log_sync = LogSync::new(self.durability, log_file.file())?;
             let fsts = vec![];
+            let value_log = if self.value_log {
+                Some(ValueLog::create(&paths.value_log(0), 0)?)
+            } else {
+                None
+            };
 
             let mut s = Database {
                 index_file,
@@ -102,9 +288,33 @@ impl DatabaseOptions {
                 fst_count: 0,
                 fsts,
                 held: Default::default(),
+                held_bytes: 0,
                 paths,
                 fanout: self.fanout,
                 memory_threshold: self.memory_threshold,
+                memory_threshold_bytes: self.memory_threshold_bytes,
+                value_log,
+                compact_value_log: self.compact_value_log,
+                bloom_bits_per_key: self.bloom_bits_per_key,
+                compression_level: self.compression_level,
+                log_sync,
+                durability: self.durability,
+                verify_on_open: self.verify_on_open,
+                wal_bytes: 0,
+                max_wal_bytes: self.max_wal_bytes,
+                merge_rate_limit: self.merge_rate_limit,
+                wal_recovery: self.wal_recovery,
+                expiry,
+                versions,
+                reverse_index: self.reverse_index,
+                reverse: None,
+                memory_budget: self.memory_budget.map(MemoryBudget::new),
+                load_levels: self.load_levels,
+                access_pattern: self.access_pattern,
+                merge_access_pattern: self.merge_access_pattern,
+                hooks: self.hooks,
+                _lock: lock,
+                _temp_dir: None,
             };
 
             s.write_index()?;
@@ -113,7 +323,7 @@ impl DatabaseOptions {
         };
 
         if self.merge_on_open {
-            s.merge(|_, _| Ok(()))?;
+            s.merge(empty_callback)?;
         }
 
         Ok(s)
@@ -162,6 +372,298 @@ impl DatabaseOptions {
             ..self
         }
     }
+
+    /// Also writes in-memory items to a level-`0` FST once their keys and values take up roughly
+    /// this many bytes, regardless of the [`write_threshold`][Self::write_threshold].
+    ///
+    /// This keeps memory use predictable when key sizes vary widely.
+    ///
+    /// Defaults to no limit.
+    pub fn memory_threshold_bytes(self, bytes: usize) -> Self {
+        Self {
+            memory_threshold_bytes: Some(bytes),
+            ..self
+        }
+    }
+
+    /// Sets the size of the Bloom filter written alongside each new FST, in bits per key. Lookups
+    /// skip any FST whose filter shows that it cannot contain the key, which makes lookups of
+    /// missing keys much cheaper. `10` gives a false positive rate of roughly 1%.
+    ///
+    /// FSTs written without a filter (_e.g._ before this was enabled) are always probed. Use `0`
+    /// to neither write nor load filters.
+    ///
+    /// Defaults to `0`.
+    pub fn bloom_filter(self, bits_per_key: usize) -> Self {
+        Self {
+            bloom_bits_per_key: bits_per_key,
+            ..self
+        }
+    }
+
+    /// Also writes in-memory items to a level-`0` FST once the write-ahead log reaches this many
+    /// bytes, regardless of the [`write_threshold`][Self::write_threshold].
+    ///
+    /// This bounds the size of the log (and so the time taken to replay it) when keys or
+    /// [values][Self::value_log] are large.
+    ///
+    /// Defaults to no limit.
+    pub fn max_wal_bytes(self, bytes: u64) -> Self {
+        Self {
+            max_wal_bytes: Some(bytes),
+            ..self
+        }
+    }
+
+    /// Writes the FSTs produced by merges at no more than `bytes_per_sec`, so that merging large
+    /// FSTs does not take the disk away from lookups for as long.
+    ///
+    /// Merges take correspondingly longer, and a merge started by [`set`][Database::set] (or
+    /// similar) holds up that write until it completes. Flushes that do not merge any existing
+    /// FSTs are never limited.
+    ///
+    /// Defaults to no limit.
+    pub fn merge_rate_limit(self, bytes_per_sec: u64) -> Self {
+        Self {
+            merge_rate_limit: Some(bytes_per_sec),
+            ..self
+        }
+    }
+
+    /// Whether to check the full contents of every FST against its checksum on open, detecting
+    /// any corruption up front at the cost of reading every FST once.
+    ///
+    /// The index is always checked, as is that each FST is the one the index expects. See also
+    /// [`Database::verify`].
+    ///
+    /// Defaults to `true`.
+    pub fn verify_on_open(self, verify: bool) -> Self {
+        Self {
+            verify_on_open: verify,
+            ..self
+        }
+    }
+
+    /// Compresses each new FST with zstd at `level` (`1` to `22`, higher being smaller but slower
+    /// to write), trading open time and memory for disk space. Hashes and other long keys often
+    /// compress well.
+    ///
+    /// Compressed FSTs are stored as `<prefix>_<id>.<level>.fst.zst`, and are decompressed into
+    /// anonymous memory on open rather than being mapped directly, so they always take up memory.
+    /// Existing FSTs keep their current form until they are merged, so this can be enabled or
+    /// disabled at any time, but a database with compressed FSTs can only be opened with the
+    /// `zstd` feature.
+    ///
+    /// Defaults to no compression.
+    #[cfg(feature = "zstd")]
+    pub fn compress_fsts(self, level: i32) -> Self {
+        Self {
+            compression_level: Some(level),
+            ..self
+        }
+    }
+
+    /// Calls `hook` after every flush of in-memory items to an FST, whether automatic or through
+    /// [`Database::flush`].
+    ///
+    /// Hooks are called on the thread doing the writing, which is blocked until they return.
+    pub fn on_flush(mut self, hook: impl Fn(&FlushInfo) + Send + Sync + 'static) -> Self {
+        self.hooks.on_flush = Some(Box::new(hook));
+        self
+    }
+
+    /// Calls `hook` before existing FSTs are merged, whether as part of a flush or through
+    /// [`Database::merge`] and similar.
+    pub fn on_merge_start(mut self, hook: impl Fn(&MergeStartInfo) + Send + Sync + 'static) -> Self {
+        self.hooks.on_merge_start = Some(Box::new(hook));
+        self
+    }
+
+    /// Calls `hook` once a merge announced to [`on_merge_start`][Self::on_merge_start] has
+    /// completed successfully.
+    pub fn on_merge_complete(mut self, hook: impl Fn(&MergeInfo) + Send + Sync + 'static) -> Self {
+        self.hooks.on_merge_complete = Some(Box::new(hook));
+        self
+    }
+
+    /// Passes every key through `codec` before it is stored or looked up, _e.g._ [`AsciiLowercase`]
+    /// for case-insensitive keys.
+    ///
+    /// This applies to writes, point lookups such as [`get`][Database::get] and
+    /// [`contains_key`][Database::contains_key], and [`bulk_load`][Database::bulk_load] (where the
+    /// encoded keys must still be in ascending order). Keys returned by iteration, searches and
+    /// merge callbacks are the stored, encoded keys. The same codec must be used every time a
+    /// database is opened, as existing keys are not re-encoded.
+    ///
+    /// Defaults to storing keys as they are.
+    pub fn key_codec(mut self, codec: impl KeyCodec) -> Self {
+        self.hooks.key_codec = Some(Box::new(codec));
+        self
+    }
+
+    /// Calls `hook` at every [`Failpoint`] reached, simulating a crash there whenever it returns
+    /// `true`, so that recovery from a crash at each point can be tested deterministically.
+    ///
+    /// This is only meant for tests. Databases opened for [expiry][Self::expiry] or
+    /// [versions][Self::versions] alongside this one do not call it.
+    #[cfg(feature = "failpoints")]
+    pub fn failpoint(mut self, hook: impl Fn(Failpoint) -> bool + Send + Sync + 'static) -> Self {
+        self.hooks.failpoint = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets how durably writes are persisted before returning.
+    ///
+    /// Defaults to [`Durability::Sync`].
+    pub fn durability(self, durability: Durability) -> Self {
+        Self { durability, ..self }
+    }
+
+    /// Sets what to do if the write-ahead log cannot be read to the end on open, as happens when
+    /// a crash interrupts a write partway through.
+    ///
+    /// Defaults to [`WalRecovery::Error`].
+    pub fn wal_recovery(self, recovery: WalRecovery) -> Self {
+        Self {
+            wal_recovery: recovery,
+            ..self
+        }
+    }
+
+    /// Whether to store arbitrary byte values in a separate value log, using
+    /// [`set_bytes`][Database::set_bytes] and [`get_bytes`][Database::get_bytes].
+    ///
+    /// In this mode the `u64` stored for each key is the value's offset in the value log, so
+    /// [`set`][Database::set] cannot be used. This must be the same every time a database is
+    /// opened.
+    ///
+    /// Defaults to `false`.
+    pub fn value_log(self, value_log: bool) -> Self {
+        Self { value_log, ..self }
+    }
+
+    /// Whether [`merge`][Database::merge] should also rewrite the value log, dropping any values
+    /// that have since been overwritten. Has no effect without a [value log][Self::value_log].
+    ///
+    /// Defaults to `false`.
+    pub fn compact_value_log(self, compact: bool) -> Self {
+        Self {
+            compact_value_log: compact,
+            ..self
+        }
+    }
+
+    /// Whether to allow keys to expire, using [`set_with_ttl`][Database::set_with_ttl].
+    ///
+    /// Expiry times are stored in a second database alongside this one, with the prefix
+    /// `<prefix>.ttl`, which every write of a key that has an expiry also updates.
+    ///
+    /// Defaults to `false`.
+    pub fn expiry(self, expiry: bool) -> Self {
+        Self { expiry, ..self }
+    }
+
+    /// Keeps a history of every value written, readable with [`get_at`][Database::get_at] and
+    /// [`snapshot_at`][Database::snapshot_at], covering at least the last `horizon` writes.
+    ///
+    /// Each write through [`set`][Database::set] and similar is given a sequence number one
+    /// higher than the last. Every full [`merge`][Database::merge] then drops versions that are
+    /// older than the horizon and have since been overwritten, so values from before the horizon
+    /// can no longer be read. The history is stored in a second database alongside this one, with
+    /// the prefix `<prefix>.versions`.
+    ///
+    /// Writes through [`bulk_load`][Database::bulk_load] or to a
+    /// [value log][Self::value_log] are not recorded.
+    ///
+    /// Defaults to no history.
+    pub fn versions(self, horizon: u64) -> Self {
+        Self {
+            versions: Some(horizon),
+            ..self
+        }
+    }
+
+    /// Whether to maintain an index from values to the keys holding them, for
+    /// [`keys_for`][Database::keys_for].
+    ///
+    /// The index is rebuilt by every full [`merge`][Database::merge], which then has to sort
+    /// every item in memory, and is stored in `<prefix>.rev`.
+    ///
+    /// Defaults to `false`.
+    pub fn reverse_index(self, reverse_index: bool) -> Self {
+        Self { reverse_index, ..self }
+    }
+
+    /// Limits how much of the FSTs is kept in memory by lookups, roughly, to `bytes`.
+    ///
+    /// FSTs are memory-mapped, so every FST that lookups read from stays in memory until the OS
+    /// needs it back. With a budget, once the FSTs read since they were last released add up to
+    /// more than `bytes`, the least recently read ones are released, to be read back in from disk
+    /// if they are needed again. Only lookups through [`get`][Database::get] and similar count
+    /// towards the budget, and compressed FSTs (or those [loaded][Self::load_levels]) are always
+    /// kept in memory.
+    ///
+    /// Defaults to no limit.
+    pub fn memory_budget(self, bytes: u64) -> Self {
+        Self {
+            memory_budget: Some(bytes),
+            ..self
+        }
+    }
+
+    /// Reads FSTs of level `max_level` and below into memory rather than mapping their files, so
+    /// that lookups in them never wait on the disk.
+    ///
+    /// Low levels hold few items each, so this bounds the memory used, but note that there may
+    /// be up to [`fanout`][Self::fanout] FSTs of each level. `0` keeps only the newest, smallest
+    /// FSTs in memory.
+    ///
+    /// Defaults to mapping every FST.
+    pub fn load_levels(self, max_level: u8) -> Self {
+        Self {
+            load_levels: Some(max_level),
+            ..self
+        }
+    }
+
+    /// Tells the OS how FSTs will be read while serving lookups, so that it reads ahead of each
+    /// access by the right amount. [`AccessPattern::Random`] suits point lookups in FSTs much
+    /// larger than memory, where read-ahead only evicts pages that are still needed.
+    ///
+    /// Defaults to leaving the OS's default in place.
+    pub fn access_pattern(self, pattern: AccessPattern) -> Self {
+        Self {
+            access_pattern: Some(pattern),
+            ..self
+        }
+    }
+
+    /// Tells the OS how FSTs will be read while they are being merged, which reads every FST being
+    /// merged from start to end, so [`AccessPattern::Sequential`] usually suits it best.
+    ///
+    /// The FSTs are switched back to the [`access_pattern`][Self::access_pattern] if the merge
+    /// fails. Otherwise they are removed once it completes, although any [`Snapshot`] still
+    /// reading them keeps this pattern.
+    ///
+    /// Defaults to leaving each FST's pattern as it is.
+    pub fn merge_access_pattern(self, pattern: AccessPattern) -> Self {
+        Self {
+            merge_access_pattern: Some(pattern),
+            ..self
+        }
+    }
+}
+
+/// What to do with an entry during [`merge_matching`][Database::merge_matching] or
+/// [`merge_range`][Database::merge_range].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MergeAction {
+    /// Keep the entry's value.
+    Keep,
+    /// Keep the entry, with a new value.
+    Replace(u64),
+    /// Remove the entry entirely.
+    Drop,
 }
 
 /// An [`fst`][fst::Map]-backed map that uses byte sequences as keys and [`u64`]s as values.
@@ -173,11 +675,47 @@ pub struct Database {
     index_file: File,
     log_file: File, // This cannot be a BufWriter, as we also need to read from it
     count: usize,
+    /// The id of the next FST to be written. This is stored in the index, so that ids are never
+    /// reused and any FST file that the index does not reference can safely be removed.
     fst_count: usize,
-    fsts: Vec<LevelFst>,
+    /// Shared with any [`Snapshot`]s, so that they can outlive a merge.
+    ///
+    /// Ordered from oldest to newest, as lookups stop at the newest FST holding a key.
+    fsts: Vec<Arc<LevelFst>>,
     held: HashMap<Bytes, u64>,
+    /// The approximate memory used by `held`, see [`Stats::memory_bytes`].
+    held_bytes: usize,
     fanout: usize,
     memory_threshold: usize,
+    memory_threshold_bytes: Option<usize>,
+    value_log: Option<ValueLog>,
+    compact_value_log: bool,
+    bloom_bits_per_key: usize,
+    compression_level: Option<i32>,
+    log_sync: LogSync,
+    durability: Durability,
+    verify_on_open: bool,
+    /// The current size of the write-ahead log.
+    wal_bytes: u64,
+    max_wal_bytes: Option<u64>,
+    wal_recovery: WalRecovery,
+    merge_rate_limit: Option<u64>,
+    /// Maps keys set with a TTL to when they expire, if [enabled][DatabaseOptions::expiry].
+    expiry: Option<Box<Database>>,
+    versions: Option<Versions>,
+    reverse_index: bool,
+    /// The index as of the last full merge, if [enabled][DatabaseOptions::reverse_index] and
+    /// readable.
+    reverse: Option<ReverseIndex>,
+    memory_budget: Option<MemoryBudget>,
+    load_levels: Option<u8>,
+    access_pattern: Option<AccessPattern>,
+    merge_access_pattern: Option<AccessPattern>,
+    hooks: Hooks,
+    /// Released on drop, after everything else has been closed.
+    _lock: Option<Lock>,
+    /// For [`in_memory`][Self::in_memory] databases, removed once everything else is closed.
+    _temp_dir: Option<TempDir>,
 }
 
 impl Database {
@@ -186,64 +724,109 @@ impl Database {
         DatabaseOptions::new(at, prefix)
     }
 
-    fn restore_log(&mut self) -> anyhow::Result<()> {
-        let end = self.log_file.seek(SeekFrom::End(0))?;
+    /// Creates an empty, throwaway database with the default options, _e.g._ for tests.
+    ///
+    /// Despite the name, this is backed by a new temporary directory (as FSTs are always
+    /// memory-mapped files), which is removed when the database is dropped. Writes are never
+    /// synced, as there is nothing to recover after a crash.
+    pub fn in_memory() -> Result<Self, Error> {
+        let dir = TempDir::new()?;
+        let mut db = Self::builder(dir.path().to_owned(), "db".to_owned())
+            .durability(Durability::None)
+            .open()?;
+        db._temp_dir = Some(dir);
+        Ok(db)
+    }
+
+    fn restore_log(&mut self) -> Result<(), Error> {
         self.log_file.rewind()?;
 
-        fn extract(f: &mut impl Read, end: u64) -> impl Iterator<Item = anyhow::Result<LogItem>> {
+        /// Reads every entry in `f`, handling any that cannot be read according to `recovery`.
+        fn extract(f: &mut File, recovery: WalRecovery) -> Result<Vec<LogItem>, Error> {
             let mut data = Vec::new();
-            let mut e = f.read_to_end(&mut data).err().map(Into::into);
-            let err = e.is_some();
+            f.read_to_end(&mut data)?;
+            let end = data.len() as u64;
             let mut reader = Cursor::new(data);
-            std::iter::from_fn(move || {
-                if err {
-                    return e.take().map(Err);
-                }
-                match reader.stream_position() {
-                    Ok(p) if p < end => LogItem::read(&mut reader).map(Some).map_err(Into::into).transpose(),
-                    Ok(_) => None,
-                    Err(e) => Some(Err(e.into())),
+            let mut items = Vec::new();
+            while reader.position() < end {
+                let offset = reader.position();
+                match LogItem::read(&mut reader) {
+                    Ok(item) => items.push(item),
+                    Err(source) if recovery == WalRecovery::TruncateAtLastValidRecord => {
+                        warn!("discarding the last {} bytes of {}, from offset {offset}: {source}", end - offset, f.path().display());
+                        break;
+                    }
+                    Err(source) => return Err(Error::CorruptLog { offset, source }),
                 }
-            })
-            .fuse()
+            }
+            Ok(items)
         }
 
         let using_backup = self.paths.log_backup.exists();
-        let mut log_backup = if using_backup { Some(File::open(&self.paths.log_backup)?) } else { None };
-        let base = log_backup.as_mut().map(|lb| extract(lb, end));
-        let items = base.into_iter().flatten().chain(extract(&mut self.log_file, end));
+        if using_backup {
+            warn!("a previous restore of {} was interrupted, restoring from its backup", self.paths.log.display());
+        }
+        let base = if using_backup {
+            extract(&mut File::open(&self.paths.log_backup)?, self.wal_recovery)?
+        } else {
+            Vec::new()
+        };
+        let items = base.into_iter().chain(extract(&mut self.log_file, self.wal_recovery)?);
 
         if !using_backup {
             // Standard restore
             fs_err::copy(&self.paths.log, &self.paths.log_backup)?;
         }
         self.log_file.set_len(0)?;
+        self.wal_bytes = 0;
         self.log_file.rewind()?;
 
+        enum Restored {
+            Value(u64),
+            Bytes(Bytes),
+        }
         let mut to_add = HashMap::new();
 
         for item in items {
-            match item? {
+            match item {
                 LogItem::Insert { key, value } => {
-                    to_add.insert(key, value);
+                    to_add.insert(key, Restored::Value(value));
+                }
+                LogItem::InsertValue { key, value } => {
+                    to_add.insert(key, Restored::Bytes(value));
+                }
+                LogItem::Batch { items } => {
+                    for (key, value) in items {
+                        to_add.insert(key, Restored::Value(value));
+                    }
                 }
                 LogItem::Flushed => {}
             }
         }
 
+        debug!("restoring {} items from the write-ahead log", to_add.len());
         for (key, value) in to_add {
-            self.set(key, value)?;
+            match value {
+                Restored::Value(value) => {
+                    self.log(LogItem::Insert { key: key.clone(), value })?;
+                    self.hold(key, value)?;
+                }
+                // The offset may be from a value log that has since been compacted, so the value
+                // is always appended again
+                Restored::Bytes(value) => self.set_bytes(key, &value)?,
+            }
         }
         self.flush()?;
 
         self.log_file.set_len(0)?;
+        self.wal_bytes = 0;
         self.log_file.rewind()?;
 
         let _ = fs_err::remove_file(&self.paths.log_backup);
         Ok(())
     }
 
-    fn write_index(&mut self) -> anyhow::Result<()> {
+    fn write_index(&mut self) -> Result<(), Error> {
         let mut wtr = BufWriter::new(File::create(&self.paths.index_write)?);
         Index {
             fsts: self
@@ -253,22 +836,37 @@ impl Database {
                     id: fs.id,
                     level: fs.level,
                     count: fs.count,
+                    checksum: Some(checksum::fst_checksum(fs.fst.as_fst().as_bytes())),
+                    bounds: Some((fs.first_key.clone(), fs.last_key.clone())),
                 })
                 .collect(),
+            value_log_generation: self.value_log.as_ref().map_or(0, |v| v.generation),
+            next_fst_id: self.fst_count as u64,
         }
         .write(&mut wtr)?;
         wtr.flush()?;
         drop(wtr);
-        fs_err::rename(&self.paths.index_write, &self.paths.index)?;
+        self.failpoint(Failpoint::BeforeIndexRename)?;
+        replace::replace(&self.paths.index_write, &self.paths.index)?;
         self.index_file = File::open(&self.paths.index)?;
+        self.failpoint(Failpoint::AfterIndexRename)?;
 
         Ok(())
     }
 
-    fn log(&mut self, item: LogItem) -> anyhow::Result<()> {
+    fn log(&mut self, item: LogItem) -> Result<(), Error> {
         item.write(&mut self.log_file)?;
         self.log_file.flush()?;
-        Ok(())
+        self.wal_bytes = self.log_file.stream_position()?;
+        self.log_sync.written(self.log_file.file())?;
+        self.failpoint(Failpoint::AfterWalAppend)
+    }
+
+    /// Whether the in-memory items should be written to an FST.
+    fn should_flush(&self) -> bool {
+        self.held.len() >= self.memory_threshold
+            || self.memory_threshold_bytes.is_some_and(|max| self.held_bytes >= max)
+            || self.max_wal_bytes.is_some_and(|max| self.wal_bytes >= max)
     }
 
     /// Stores `key`->`value`. All subsequent calls to `get(key)` before another `set(key, ..)` are
@@ -277,19 +875,154 @@ impl Database {
     ///
     /// This method is guaranteed to be durable, i.e. when this method returns, it is guaranteed
     /// that the data can be read correctly, even should the program immediately terminate.[^1]
+    /// Whether this also holds should the OS crash or power be lost depends on the configured
+    /// [`Durability`].
     ///
     /// [^1]: Note that some storage devices maintain a caching layer of their own that we cannot
     /// flush. Theoretically, it is possible for an immediate loss of power after this
     /// method returns to cause written data to _not_ be persisted. I am not aware of any way to
     /// mitigate this, but it is not a situation that will arise often.
-    pub fn set(&mut self, key: Bytes, value: u64) -> anyhow::Result<()> {
+    ///
+    /// This cannot be used with a [value log][DatabaseOptions::value_log].
+    pub fn set(&mut self, key: Bytes, value: u64) -> Result<(), Error> {
+        if self.value_log.is_some() {
+            return Err(Error::HasValueLog);
+        }
+        let key = self.encode_owned_key(key);
+        self.record_versions(&[(key.clone(), value)])?;
         self.log(LogItem::Insert { key: key.clone(), value })?;
+        self.hold(key.clone(), value)?;
+        self.clear_expiry([&key])
+    }
+
+    /// Returns the latest value for `key`, or if there is none, stores and returns the value
+    /// computed by `f`.
+    ///
+    /// As this borrows the database mutably, nothing else can set `key` between the lookup and
+    /// the insert. `f` is only called if `key` is missing.
+    pub fn get_or_insert_with(&mut self, key: Bytes, f: impl FnOnce() -> u64) -> Result<u64, Error> {
+        if let Some(value) = self.get(&key) {
+            return Ok(value);
+        }
+        let value = f();
+        self.set(key, value)?;
+        Ok(value)
+    }
+
+    /// Stores every `key`->`value` in `items`, as if by calling [`set`][Self::set] for each in
+    /// order.
+    ///
+    /// The whole batch is written to the write-ahead log as a single record and synced to disk
+    /// at most once, which is much faster than setting each item separately. If the program terminates
+    /// partway through, either all or none of the batch is restored.
+    ///
+    /// This cannot be used with a [value log][DatabaseOptions::value_log].
+    pub fn set_batch(&mut self, items: impl IntoIterator<Item = (Bytes, u64)>) -> Result<(), Error> {
+        if self.value_log.is_some() {
+            return Err(Error::HasValueLog);
+        }
+        let items = items.into_iter().map(|(key, value)| (self.encode_owned_key(key), value)).collect::<Vec<_>>();
+        if items.is_empty() {
+            return Ok(());
+        }
+        self.record_versions(&items)?;
+
+        // A single write, so that a torn record can never be mistaken for a smaller batch
+        let batch = LogItem::Batch { items };
+        let mut record = Vec::new();
+        batch.write(&mut record)?;
+        self.log_file.write_all(&record)?;
+        self.wal_bytes = self.log_file.stream_position()?;
+        self.log_sync.written(self.log_file.file())?;
+        self.failpoint(Failpoint::AfterWalAppend)?;
+        let LogItem::Batch { items } = batch else {
+            unreachable!();
+        };
+
+        // Everything must be held before flushing, as flushing clears the log
+        for (key, value) in &items {
+            self.insert_held(key.clone(), *value);
+        }
+        if self.should_flush() {
+            self.flush()?;
+        }
+        self.clear_expiry(items.iter().map(|(key, _)| key))
+    }
+
+    /// Starts a [`Transaction`], which collects writes and then applies them all at once.
+    ///
+    /// As the transaction borrows the database, no other writes can happen until it is committed
+    /// or dropped. This has the same restrictions as [`set_batch`][Self::set_batch].
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
+
+    /// Stores `key`->`value` in a database with a [value log][DatabaseOptions::value_log].
+    ///
+    /// This has the same durability guarantees as [`set`][Self::set]. Note that the value is
+    /// written to both the value log and the write-ahead log.
+    pub fn set_bytes(&mut self, key: Bytes, value: &[u8]) -> Result<(), Error> {
+        let key = self.encode_owned_key(key);
+        let Some(value_log) = &mut self.value_log else {
+            return Err(Error::NoValueLog);
+        };
+        let offset = value_log.append(value)?;
+        self.log(LogItem::InsertValue {
+            key: key.clone(),
+            value: Bytes::copy_from_slice(value),
+        })?;
+        self.hold(key.clone(), offset)?;
+        self.clear_expiry([&key])
+    }
+
+    /// Retrieves the latest value set for `key` with [`set_bytes`][Self::set_bytes].
+    pub fn get_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let Some(value_log) = &self.value_log else {
+            return Err(Error::NoValueLog);
+        };
+        match self.get(key) {
+            Some(offset) => Ok(Some(value_log.read(offset)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores a 128-bit `value` for `key` in a database with a
+    /// [value log][DatabaseOptions::value_log], as the value log's offsets leave no room for it in
+    /// the FSTs themselves.
+    ///
+    /// This is [`set_bytes`][Self::set_bytes] with the value's little-endian bytes.
+    pub fn set_u128(&mut self, key: Bytes, value: u128) -> Result<(), Error> {
+        self.set_bytes(key, &value.to_le_bytes())
+    }
+
+    /// Retrieves the latest value set for `key` with [`set_u128`][Self::set_u128].
+    ///
+    /// Fails with [`Error::ValueWidth`] if the value was set by [`set_bytes`][Self::set_bytes]
+    /// with any other length.
+    pub fn get_u128(&self, key: &[u8]) -> Result<Option<u128>, Error> {
+        let Some(bytes) = self.get_bytes(key)? else {
+            return Ok(None);
+        };
+        let bytes = <[u8; 16]>::try_from(bytes.as_slice()).map_err(|_| Error::ValueWidth {
+            expected: 16,
+            found: bytes.len(),
+        })?;
+        Ok(Some(u128::from_le_bytes(bytes)))
+    }
 
+    fn insert_held(&mut self, key: Bytes, value: u64) {
+        let len = key.len();
         if self.held.insert(key, value).is_none() {
             self.count += 1;
+            self.held_bytes += len + size_of::<u64>();
         }
+    }
+
+    /// Holds an already-logged item in memory until the next flush.
+    fn hold(&mut self, key: Bytes, value: u64) -> Result<(), Error> {
+        self.insert_held(key, value);
 
-        if self.held.len() >= self.memory_threshold {
+        if self.should_flush() {
             self.flush()?;
         }
 
@@ -297,42 +1030,191 @@ impl Database {
     }
 
     /// Retrieves the value associated with `key` from the map. This method will always return the
-    /// latest value set for `key`.
+    /// latest value set for `key`, unless it has [expired][Self::set_with_ttl].
     pub fn get(&self, key: &[u8]) -> Option<u64> {
-        if let Some(id) = self.held.get(key) {
-            return Some(*id);
+        let key = self.encode_key(key);
+        get(&self.held, &self.fsts, &key, self.memory_budget.as_ref()).filter(|_| !self.is_expired(&key))
+    }
+
+    /// The number of entries stored, counting a key once for each FST (and the in-memory items)
+    /// holding a value for it.
+    ///
+    /// This is an upper bound on the number of distinct keys, and is exact after a
+    /// [`merge`][Self::merge]. See [`distinct_len`][Self::distinct_len] for an exact count.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The exact number of distinct keys.
+    ///
+    /// Unlike [`len`][Self::len], this has to read every key in every FST to remove duplicates
+    /// between them, though it does not write anything.
+    pub fn distinct_len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Summarises the FSTs in each level, the items held in memory and the sizes of the logs.
+    pub fn stats(&self) -> Result<Stats, Error> {
+        let mut levels = BTreeMap::<u8, LevelStats>::new();
+        for fst in &self.fsts {
+            let level = levels.entry(fst.level).or_insert_with(|| LevelStats {
+                level: fst.level,
+                ..LevelStats::default()
+            });
+            level.fsts += 1;
+            level.items += fst.count;
+            level.bytes += fst.fst.as_fst().as_bytes().len() as u64;
+        }
+        Ok(Stats {
+            levels: levels.into_values().collect(),
+            memory_items: self.held.len(),
+            memory_bytes: self.held_bytes,
+            wal_bytes: self.wal_bytes,
+            value_log_bytes: self.value_log.as_ref().map(ValueLog::len).transpose()?,
+        })
+    }
+
+    /// Checks the index and the full contents of every FST against their checksums.
+    ///
+    /// This reads every FST, so is as expensive as [opening][DatabaseOptions::verify_on_open] with
+    /// verification, but can be used to detect corruption that has happened since.
+    pub fn verify(&self) -> Result<(), Error> {
+        let index = Index::read(&mut File::open(&self.paths.index)?).map_err(|source| Error::CorruptIndex {
+            path: self.paths.index.clone(),
+            source,
+        })?;
+        let expected = index.fsts.iter().map(|f| ((f.id, f.level), f.checksum)).collect::<HashMap<_, _>>();
+        for fst in &self.fsts {
+            let valid = match expected.get(&(fst.id, fst.level)) {
+                Some(&Some(checksum)) => checksum::verify_fst(fst.fst.as_fst(), checksum),
+                // Only possible if the index was replaced since it was opened
+                Some(None) | None => false,
+            };
+            if !valid {
+                return Err(Error::ChecksumMismatch {
+                    path: self.paths.fst(fst.id, fst.level),
+                });
+            }
         }
+        Ok(())
+    }
+
+    /// Whether any value has been set for `key`.
+    ///
+    /// This is faster than checking [`get`][Self::get], as it stops as soon as the key is found
+    /// rather than finding which FST holds its latest value.
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        let key = self.encode_key(key);
+        contains_key(&self.held, &self.fsts, &key, self.memory_budget.as_ref()) && !self.is_expired(&key)
+    }
 
-        let mut found = vec![];
-        for f in &self.fsts {
-            if let Some(iid) = f.fst.get(key) {
-                found.push((f, iid));
+    /// Retrieves the values associated with each of `keys`, in the same order.
+    ///
+    /// This is equivalent to calling [`get`][Self::get] for each key, but is faster for large
+    /// numbers of keys, as each FST is only visited once and keys are looked up in sorted order.
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Vec<Option<u64>> {
+        let encoded = keys.iter().map(|key| self.encode_key(key)).collect::<Vec<_>>();
+        let keys = encoded.iter().map(|key| key.as_ref()).collect::<Vec<_>>();
+        let mut values = multi_get(&self.held, &self.fsts, &keys, self.memory_budget.as_ref());
+        if self.expiry.is_some() {
+            for (value, key) in values.iter_mut().zip(keys) {
+                if self.is_expired(key) {
+                    *value = None;
+                }
             }
         }
+        values
+    }
+
+    /// Iterates over every key and its latest value, in ascending key order.
+    ///
+    /// This reads directly from the in-memory data and the existing FSTs, so unlike
+    /// [`merge`][Self::merge] it does not write anything.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter::new(&self.held, &self.fsts)
+    }
+
+    /// Iterates over every key matched by `automaton` and its latest value, in ascending key
+    /// order.
+    ///
+    /// Only the parts of each FST that `automaton` can match are read, which makes this much
+    /// cheaper than filtering [`iter`][Self::iter] for selective searches. See also
+    /// `fst::automaton` for simple automata, and `Regex` (with the `regex` feature) for regular
+    /// expressions.
+    pub fn search<'a, A: Automaton>(&'a self, automaton: &'a A) -> Iter<'a> {
+        Iter::search(&self.held, &self.fsts, automaton)
+    }
+
+    /// Finds every key within `max_distance` edits (insertions, deletions or substitutions of a
+    /// character) of `key`, along with its latest value, in ascending key order.
+    ///
+    /// Distances are counted in Unicode characters, so only UTF-8 keys can match. Building the
+    /// automaton is exponential in `max_distance`, so it should be kept small (_e.g._ `1` or `2`).
+    #[cfg(feature = "fuzzy")]
+    pub fn fuzzy(&self, key: &str, max_distance: u32) -> Result<Vec<(Bytes, u64)>, Error> {
+        fuzzy(&self.held, &self.fsts, key, max_distance)
+    }
 
-        found.into_iter().max_by_key(|(f, _)| f.id).map(|(_, v)| v)
+    /// Creates a read-only view of the database as it is now, which can be sent to and shared
+    /// between other threads.
+    ///
+    /// This is cheap, as the FSTs are shared rather than copied, and only the in-memory data
+    /// (at most [`write_threshold`][DatabaseOptions::write_threshold] items) is cloned. Changes
+    /// made through this handle after the snapshot is taken are not visible through it.
+    ///
+    /// FSTs that are merged away while a snapshot still refers to them have their files removed
+    /// as usual, but remain readable through the snapshot until it is dropped. This relies on
+    /// the platform allowing mapped files to be removed, so on Windows, merging fails while such
+    /// a snapshot is alive.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            fsts: self.fsts.clone(),
+            held: Arc::new(self.held.clone()),
+        }
     }
 
-    fn merge_fsts(&mut self, filter: impl Fn(&LevelFst) -> bool, mut callback: impl FnMut(Bytes, u64) -> anyhow::Result<()>) -> anyhow::Result<()> {
+    /// `compact_values` must only be set when merging every FST, as any value not merged is
+    /// dropped from the value log.
+    fn merge_fsts(
+        &mut self,
+        filter: impl Fn(&LevelFst) -> bool,
+        compact_values: bool,
+        mut callback: impl FnMut(Bytes, u64) -> Result<MergeAction, Error>,
+    ) -> Result<(), Error> {
+        let started = Instant::now();
         let mut items = self.held.drain().collect::<Vec<_>>();
+        self.held_bytes = 0;
         items.sort_by(|(a, _), (b, _)| a.cmp(b).reverse());
+        let flushed = items.len();
 
         let to_merge = self.fsts.iter().filter(|f| filter(f)).collect::<Vec<_>>();
         if to_merge.is_empty() && items.is_empty() {
             return Ok(());
         }
-        let target_level = if to_merge.is_empty() {
-            self.calculate_level(items.len())
-        } else {
-            self.calculate_level(items.len() + to_merge.iter().map(|fs| fs.count as usize).sum::<usize>())
-        };
-
+        let merged_levels = to_merge.iter().map(|f| f.level).collect::<Vec<_>>();
+        debug!(
+            "writing FST {} from {flushed} in-memory items and {} FSTs (levels {merged_levels:?})",
+            self.fst_count,
+            to_merge.len()
+        );
+        if let (Some(hook), false) = (&self.hooks.on_merge_start, to_merge.is_empty()) {
+            hook(&MergeStartInfo {
+                levels: merged_levels.clone(),
+                items: to_merge.iter().map(|f| f.count).sum::<u64>() + flushed as u64,
+            });
+        }
         let new_id = self.fst_count as u64;
         self.fst_count += 1;
+        let advice = MergeAdvice::new(&to_merge, self.merge_access_pattern, self.access_pattern);
 
         // Build new FST
         let file = OpenOptions::new().create(true).write(true).read(true).open(&self.paths.write_fst)?;
-        let mut wtr = BufWriter::new(file);
+        let rate_limit = self.merge_rate_limit.filter(|_| !to_merge.is_empty());
+        let mut wtr = BufWriter::new(Throttled::new(file, rate_limit));
 
         let mut builder = MapBuilder::new(&mut wtr)?;
         let mut stream = OpBuilder::new();
@@ -341,18 +1223,43 @@ impl Database {
         }
         let mut stream = stream.union();
 
+        // Compaction copies every live value into a new value log, which only takes the place of
+        // the old one once the index referring to it has been written
+        let old_values = self.value_log.as_ref();
+        let mut new_values = match old_values {
+            Some(old) if compact_values => {
+                let generation = old.generation + 1;
+                Some(ValueLog::create(&self.paths.value_log(generation), generation)?)
+            }
+            _ => None,
+        };
+
         let mut count = 0;
+        let mut key_hashes = Vec::new();
+        let bloom = self.bloom_bits_per_key > 0;
         let mut previous: Option<Bytes> = None;
-        let mut add = |key: Bytes, value| -> anyhow::Result<()> {
+        let mut add = |key: Bytes, value| -> Result<(), Error> {
             if previous.as_ref().is_some_and(|p| *p == key) {
                 return Ok(());
             }
-            count += 1;
             previous = Some(key.clone());
-            callback(key.clone(), value)?;
-            builder.insert(key, value).map_err(Into::into)
-        };
-
+            let value = match callback(key.clone(), value)? {
+                MergeAction::Keep => value,
+                MergeAction::Replace(value) => value,
+                MergeAction::Drop => return Ok(()),
+            };
+            // Only values that are kept are copied, so that dropped ones are reclaimed too
+            let value = match (&mut new_values, old_values) {
+                (Some(new), Some(old)) => new.append(&old.read(value)?)?,
+                _ => value,
+            };
+            count += 1;
+            if bloom {
+                key_hashes.push(bloom::hash(&key));
+            }
+            builder.insert(key, value).map_err(Into::into)
+        };
+
         while let Some((key, idxs)) = stream.next() {
             let max = idxs.iter().max_by_key(|id| to_merge[id.index].id).expect("non-empty");
             while items.last().is_some_and(|(d, _)| d < key) {
@@ -380,51 +1287,187 @@ impl Database {
             None
         } else {
             drop(wtr);
-            let target = self.paths.fst(new_id, self.calculate_level(count as usize));
-            fs_err::rename(&self.paths.write_fst, &target)?;
-            let file = File::open(&target)?;
-            let mmap = unsafe { Mmap::map(&file) }?;
-            let new = LevelFst {
-                count,
-                id: new_id,
-                level: target_level,
-                fst: fst::Map::new(mmap)?,
-            };
-
-            Some(new)
+            Some(self.install_fst(new_id, count, &key_hashes)?)
         };
+        self.failpoint(Failpoint::MidMerge)?;
 
         let merged = to_merge.iter().map(|r| r.id).collect::<HashSet<_>>();
         let to_remove = to_merge.iter().map(|f| (f.id, f.level)).collect::<Vec<_>>();
 
         self.fsts.retain(|it| !merged.contains(&it.id));
+        advice.merged();
+        let new_level = new.as_ref().map(|n| n.level);
         if let Some(new) = new {
             self.fsts.push(new);
         }
+        // Duplicates between the merged FSTs and held items have now been removed
+        self.count = self.fsts.iter().map(|f| f.count as usize).sum();
+        if let Some(budget) = &self.memory_budget {
+            budget.recount(&self.fsts);
+        }
+        let old_values = match new_values {
+            Some(new_values) => {
+                new_values.sync()?;
+                self.value_log.replace(new_values)
+            }
+            None => None,
+        };
         self.write_index()?;
 
+        if let Some(old_values) = old_values {
+            let path = self.paths.value_log(old_values.generation);
+            drop(old_values);
+            fs_err::remove_file(path)?;
+        }
+
         for (merged_id, merged_level) in to_remove {
             let origin = self.paths.fst(merged_id, merged_level);
-            if origin.exists() {
-                fs_err::remove_file(&origin)?;
-            } else {
-                println!("cannot remove {}", origin.display()); // TODO: Proper logging
+            if !compression::remove(&origin)? {
+                warn!("cannot remove merged FST {}, as it does not exist", origin.display());
             }
+            // Filters are optional, so may not exist
+            let _ = fs_err::remove_file(self.paths.bloom(merged_id, merged_level));
         }
 
         self.log(LogItem::Flushed)?;
         self.log_file.rewind()?;
         self.log_file.set_len(0)?;
+        self.wal_bytes = 0;
+
+        let duration = started.elapsed();
+        match new_level {
+            Some(level) => debug!("wrote FST {new_id} at level {level} with {count} items in {duration:?}"),
+            None => debug!("merge left no items, so no FST was written ({duration:?})"),
+        }
+        if let (Some(hook), false) = (&self.hooks.on_merge_complete, merged_levels.is_empty()) {
+            hook(&MergeInfo {
+                levels: merged_levels,
+                level: new_level,
+                items: count,
+                duration,
+            });
+        }
+        if let (Some(hook), Some(level)) = (&self.hooks.on_flush, new_level.filter(|_| flushed > 0)) {
+            hook(&FlushInfo {
+                items: flushed,
+                level,
+                duration,
+            });
+        }
 
         // dbg!(&self.fsts);
 
         Ok(())
     }
 
+    /// Adds every `key`->`value` in `items` as a single new FST, without holding them in memory or
+    /// writing them to the write-ahead log. This is much faster than [`set`][Self::set] for large
+    /// initial imports.
+    ///
+    /// `items` must be in strictly ascending key order, otherwise this fails with
+    /// [`Error::Fst`] and nothing is added. Any in-memory items are [flushed][Self::flush] first,
+    /// so that the loaded items take precedence over everything already in the database. Nothing
+    /// is visible until the whole stream has been written, and as the write-ahead log is bypassed,
+    /// a crash partway through loses the whole load (but nothing else).
+    ///
+    /// As the loaded FST is newer than any lower-level FSTs already in the database, it is merged
+    /// with them the next time they are, however many items it holds.
+    ///
+    /// This cannot be used with a [value log][DatabaseOptions::value_log].
+    pub fn bulk_load(&mut self, items: impl IntoIterator<Item = (Bytes, u64)>) -> Result<(), Error> {
+        if self.value_log.is_some() {
+            return Err(Error::HasValueLog);
+        }
+        self.flush()?;
+
+        let mut wtr = BufWriter::new(File::create(&self.paths.write_fst)?);
+        let mut builder = MapBuilder::new(&mut wtr)?;
+        let mut count = 0;
+        let mut key_hashes = Vec::new();
+        let mut expiring = Vec::new();
+        let built = items
+            .into_iter()
+            .try_for_each(|(key, value)| {
+                let key = self.encode_owned_key(key);
+                count += 1;
+                if self.bloom_bits_per_key > 0 {
+                    key_hashes.push(bloom::hash(&key));
+                }
+                if self.expires_at(&key).is_some() {
+                    expiring.push(key.clone());
+                }
+                builder.insert(key, value)
+            })
+            .and_then(|()| builder.finish());
+        if let Err(e) = built {
+            drop(wtr);
+            fs_err::remove_file(&self.paths.write_fst)?;
+            return Err(e.into());
+        }
+        wtr.flush()?;
+        drop(wtr);
+        if count == 0 {
+            fs_err::remove_file(&self.paths.write_fst)?;
+            return Ok(());
+        }
+
+        let id = self.fst_count as u64;
+        self.fst_count += 1;
+        let new = self.install_fst(id, count, &key_hashes)?;
+        debug!("bulk loaded {count} items into FST {id} at level {}", new.level);
+        self.fsts.push(new);
+        self.count += count as usize;
+        self.write_index()?;
+        self.clear_expiry(&expiring)
+    }
+
+    /// Moves the FST just written to `write_fst` into place as FST `id` (compressing it, if
+    /// enabled), writing its Bloom filter (if enabled) from `key_hashes`. This does not add it to
+    /// the index.
+    fn install_fst(&self, id: u64, count: u64, key_hashes: &[u64]) -> Result<Arc<LevelFst>, Error> {
+        let level = self.calculate_level(count as usize);
+        let target = self.paths.fst(id, level);
+        let mut mmap = compression::install(&self.paths.write_fst, &target, self.compression_level)?;
+        let mut file_backed = self.compression_level.is_none();
+        if file_backed && self.load_levels.is_some_and(|max| level <= max) {
+            mmap = compression::copy_to_anon(&mmap)?;
+            file_backed = false;
+        }
+
+        let bloom = if self.bloom_bits_per_key > 0 {
+            let bloom = Bloom::new(key_hashes, self.bloom_bits_per_key);
+            let mut wtr = BufWriter::new(File::create(&self.paths.write_bloom)?);
+            bloom.write(&mut wtr)?;
+            wtr.flush()?;
+            drop(wtr);
+            replace::replace(&self.paths.write_bloom, &self.paths.bloom(id, level))?;
+            Some(bloom)
+        } else {
+            None
+        };
+
+        let fst = fst::Map::new(mmap)?;
+        let (first_key, last_key) = key_bounds(fst.as_fst());
+        let fst = LevelFst {
+            count,
+            id,
+            level,
+            fst,
+            bloom,
+            residency: Residency::new(file_backed),
+            first_key,
+            last_key,
+        };
+        if let Some(pattern) = self.access_pattern {
+            advice::apply(&fst, pattern);
+        }
+        Ok(Arc::new(fst))
+    }
+
     /// Flushes all in-memory data to the filesystem, potentially merging some existing FSTs.
     ///
     /// To merge _all_ FSTs, use [`merge`][`Self::merge`].
-    pub fn flush(&mut self) -> anyhow::Result<()> {
+    pub fn flush(&mut self) -> Result<(), Error> {
         if self.held.is_empty() {
             return Ok(());
         }
@@ -450,18 +1493,224 @@ impl Database {
         }
 
         if let Some(max) = maximum_level {
-            self.merge_fsts(|f| f.level <= max, empty_callback)?;
+            // Usually every FST newer than the oldest selected one is selected as well, but an FST
+            // from a bulk load can be newer than lower-level ones. It must then be merged too, so
+            // that their older values do not take precedence over its own.
+            let oldest = self.fsts.iter().filter(|f| f.level <= max).map(|f| f.id).min().expect("at least fanout FSTs");
+            self.merge_fsts(|f| f.id >= oldest, false, keep_all)?;
         } else {
-            self.merge_fsts(|_| false, empty_callback)?;
+            self.merge_fsts(|_| false, false, keep_all)?;
         }
 
         Ok(())
     }
 
+    /// Reads the index, FSTs and write-ahead log again, _e.g._ after they have been replaced by a
+    /// restore or a copy from a replica, without having to drop the database first.
+    ///
+    /// In-memory items are discarded, then those in the write-ahead log on disk are restored, just
+    /// as when opening the database. The lock is held throughout, so the files must not be
+    /// replaced while any method is running. [`Snapshot`]s already taken keep reading the old
+    /// FSTs. If this fails, the database should be dropped and opened again.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        if let Some(expiry) = &mut self.expiry {
+            expiry.reload()?;
+        }
+        if let Some(versions) = &mut self.versions {
+            versions.reload()?;
+        }
+
+        let mut index_file = OpenOptions::new().read(true).write(true).create(false).open(&self.paths.index)?;
+        let index = Index::read(&mut index_file).map_err(|source| Error::CorruptIndex {
+            path: self.paths.index.clone(),
+            source,
+        })?;
+        let fsts = index
+            .fsts
+            .into_iter()
+            .map(|fs| {
+                open_fst(
+                    &self.paths,
+                    fs,
+                    self.verify_on_open,
+                    self.bloom_bits_per_key,
+                    self.load_levels,
+                    self.access_pattern,
+                )
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let log_file = OpenOptions::new().read(true).write(true).create(false).open(&self.paths.log)?;
+        let log_sync = LogSync::new(self.durability, log_file.file())?;
+        let value_log = match self.value_log {
+            Some(_) => {
+                let generation = index.value_log_generation;
+                Some(ValueLog::open(&self.paths.value_log(generation), generation, generation == 0)?)
+            }
+            None => None,
+        };
+
+        self.index_file = index_file;
+        self.log_file = log_file;
+        self.log_sync = log_sync;
+        self.wal_bytes = 0;
+        self.value_log = value_log;
+        self.count = fsts.iter().map(|f| f.count as usize).sum();
+        self.fst_count = index.next_fst_id as usize;
+        self.fsts = fsts;
+        self.held.clear();
+        self.held_bytes = 0;
+        if let Some(budget) = &self.memory_budget {
+            budget.recount(&self.fsts);
+        }
+        if self.reverse_index {
+            self.reverse = ReverseIndex::open(&self.paths);
+        }
+        debug!("reloaded {} with {} FSTs", self.paths.index.display(), self.fsts.len());
+        self.restore_log()
+    }
+
+    /// Flushes all in-memory data, syncs every file to disk and releases the lock.
+    ///
+    /// Dropping a database does none of this, as nothing is lost that the write-ahead log cannot
+    /// restore on the next open, and errors could only be ignored. Closing instead leaves nothing
+    /// to restore, and reports anything that fails.
+    pub fn close(mut self) -> Result<(), Error> {
+        self.flush()?;
+        if let Some(expiry) = self.expiry.take() {
+            expiry.close()?;
+        }
+        if let Some(versions) = self.versions.take() {
+            versions.close()?;
+        }
+        if let Some(value_log) = &self.value_log {
+            value_log.sync()?;
+        }
+        // The index and FSTs were synced as they were written
+        self.log_file.sync_all()?;
+
+        debug!("closed {}", self.paths.index.display());
+        let lock = self._lock.take();
+        drop(self);
+        match lock {
+            Some(lock) => lock.release(),
+            None => Ok(()),
+        }
+    }
+
     /// Merges all in-memory and on-disk data into a single FST.
-    pub fn merge(&mut self, callback: impl FnMut(Bytes, u64) -> anyhow::Result<()>) -> anyhow::Result<()> {
-        self.fst_count = 0;
-        self.merge_fsts(|_| true, callback)
+    ///
+    /// `callback` is called with every resulting key and value, in ascending key order. Any error
+    /// it returns aborts the merge and is returned as [`Error::Callback`].
+    pub fn merge<E>(&mut self, mut callback: impl FnMut(Bytes, u64) -> Result<(), E>) -> Result<(), Error>
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        self.merge_matching(|_| true, |key, value| callback(key, value).map(|()| MergeAction::Keep))
+    }
+
+    /// Like [`merge`][Self::merge], but only calls `callback` for keys within `range`, and lets it
+    /// replace or drop each entry. Keys outside `range` are kept as they are, except that
+    /// [expired][Self::set_with_ttl] keys are always dropped, wherever they are.
+    pub fn merge_range<'r, E>(
+        &mut self,
+        range: impl RangeBounds<&'r [u8]>,
+        callback: impl FnMut(Bytes, u64) -> Result<MergeAction, E>,
+    ) -> Result<(), Error>
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        self.merge_matching(|key| range.contains(&key), callback)
+    }
+
+    /// Like [`merge`][Self::merge], but only calls `callback` for keys matching `predicate`, and
+    /// lets it replace or drop each entry. Other keys are kept as they are, except that
+    /// [expired][Self::set_with_ttl] keys are always dropped, whether they match or not.
+    ///
+    /// This allows garbage collection policies to be implemented on top of merging. With a
+    /// [value log][DatabaseOptions::value_log], values are offsets into it as it was before the
+    /// merge, so [`MergeAction::Replace`] should only be used with offsets passed to `callback`.
+    pub fn merge_matching<E>(
+        &mut self,
+        predicate: impl FnMut(&[u8]) -> bool,
+        callback: impl FnMut(Bytes, u64) -> Result<MergeAction, E>,
+    ) -> Result<(), Error>
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        self.full_merge(self.compact_value_log, predicate, callback)
+    }
+
+    /// Merges everything into a single FST like [`merge`][Self::merge], then reports how much
+    /// space that reclaimed.
+    ///
+    /// Every merge drops entries that have been superseded by newer values for the same key (and
+    /// full merges drop [expired][Self::set_with_ttl] keys), but this also always compacts the
+    /// [value log][DatabaseOptions::value_log], regardless of
+    /// [`compact_value_log`][DatabaseOptions::compact_value_log]. Use this to bound space usage
+    /// after heavy update workloads.
+    pub fn collect_garbage(&mut self) -> Result<GcReport, Error> {
+        let before = self.stats()?;
+        let entries = self.count;
+        self.full_merge(true, |_| true, keep_all)?;
+        let after = self.stats()?;
+        let report = GcReport {
+            entries_dropped: entries.saturating_sub(self.count) as u64,
+            bytes_reclaimed: before.total_bytes().saturating_sub(after.total_bytes()),
+        };
+        debug!("collected garbage: {report:?}");
+        Ok(report)
+    }
+
+    fn full_merge<E>(
+        &mut self,
+        compact_values: bool,
+        mut predicate: impl FnMut(&[u8]) -> bool,
+        mut callback: impl FnMut(Bytes, u64) -> Result<MergeAction, E>,
+    ) -> Result<(), Error>
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        // Only a full merge can drop expired keys, as otherwise older values in the FSTs left
+        // out would take their place. They are dropped before `predicate` is checked, as their
+        // expiries are pruned afterwards and keeping them would bring them back.
+        let now = expiry::now_millis();
+        let expiry = self.expiry.take();
+        let merged = self.merge_fsts(|_| true, compact_values, |key, value| {
+            if expiry.as_ref().is_some_and(|expiry| expiry::expired(expiry.get(&key), now)) {
+                return Ok(MergeAction::Drop);
+            }
+            if !predicate(&key) {
+                return Ok(MergeAction::Keep);
+            }
+            callback(key, value).map_err(|e| Error::Callback(e.into()))
+        });
+        self.expiry = expiry;
+        merged?;
+        self.prune_expiry(now)?;
+        self.prune_versions()?;
+        self.rebuild_reverse_index()
+    }
+
+    /// Merges every FST of `level` into a single FST, along with any in-memory data.
+    ///
+    /// See [`compact_range`][Self::compact_range].
+    pub fn compact_level(&mut self, level: u8) -> Result<(), Error> {
+        self.compact_range(level..=level)
+    }
+
+    /// Merges every FST with a level in `levels` into a single FST, along with any in-memory
+    /// data. This is much cheaper than a full [`merge`][Self::merge] when the selected levels
+    /// only hold a small part of the database, _e.g._ for compacting level-`0` FSTs during quiet
+    /// periods.
+    ///
+    /// As the resulting FST takes precedence over everything it replaces, any FSTs newer than
+    /// the oldest selected FST are included as well, regardless of their level.
+    pub fn compact_range(&mut self, levels: impl RangeBounds<u8>) -> Result<(), Error> {
+        let oldest = self.fsts.iter().filter(|f| levels.contains(&f.level)).map(|f| f.id).min();
+        match oldest {
+            Some(oldest) => self.merge_fsts(|f| f.id >= oldest, false, keep_all),
+            None => self.merge_fsts(|_| false, false, keep_all),
+        }
     }
 
     fn calculate_level(&self, count: usize) -> u8 {
@@ -472,6 +1721,197 @@ impl Database {
     }
 }
 
+#[cfg(feature = "fuzzy")]
+fn fuzzy(held: &HashMap<Bytes, u64>, fsts: &[Arc<LevelFst>], key: &str, max_distance: u32) -> Result<Vec<(Bytes, u64)>, Error> {
+    let automaton = fst::automaton::Levenshtein::new(key, max_distance)?;
+    Ok(Iter::search(held, fsts, &automaton).collect())
+}
+
+/// Whether `automaton` matches all of `key`, for searching keys that are not in an FST.
+fn matches<A: Automaton>(automaton: &A, key: &[u8]) -> bool {
+    let mut state = automaton.start();
+    for &b in key {
+        if !automaton.can_match(&state) {
+            return false;
+        }
+        state = automaton.accept(&state, b);
+    }
+    automaton.is_match(&state)
+}
+
+fn get(held: &HashMap<Bytes, u64>, fsts: &[Arc<LevelFst>], key: &[u8], budget: Option<&MemoryBudget>) -> Option<u64> {
+    if let Some(id) = held.get(key) {
+        return Some(*id);
+    }
+
+    // The first FST found holding the key, probing newest first, holds its latest value
+    fsts.iter().rev().filter(|f| f.may_contain(key)).find_map(|f| {
+        if let Some(budget) = budget {
+            budget.touch(f, fsts);
+        }
+        f.fst.get(key)
+    })
+}
+
+fn contains_key(held: &HashMap<Bytes, u64>, fsts: &[Arc<LevelFst>], key: &[u8], budget: Option<&MemoryBudget>) -> bool {
+    held.contains_key(key)
+        || fsts.iter().filter(|f| f.may_contain(key)).any(|f| {
+            if let Some(budget) = budget {
+                budget.touch(f, fsts);
+            }
+            f.fst.contains_key(key)
+        })
+}
+
+fn multi_get(held: &HashMap<Bytes, u64>, fsts: &[Arc<LevelFst>], keys: &[&[u8]], budget: Option<&MemoryBudget>) -> Vec<Option<u64>> {
+    let mut found = keys.iter().map(|key| held.get(*key).copied()).collect::<Vec<_>>();
+    let mut unresolved = (0..keys.len()).filter(|&i| found[i].is_none()).collect::<Vec<_>>();
+    unresolved.sort_by_key(|&i| keys[i]);
+
+    // Visiting the newest FST first means that the first value found for a key is its latest
+    for f in fsts.iter().rev() {
+        if unresolved.is_empty() {
+            break;
+        }
+        if let Some(budget) = budget {
+            budget.touch(f, fsts);
+        }
+        unresolved.retain(|&i| match f.may_contain(keys[i]).then(|| f.fst.get(keys[i])).flatten() {
+            Some(value) => {
+                found[i] = Some(value);
+                false
+            }
+            None => true,
+        });
+    }
+    found
+}
+
+/// A read-only view of a [`Database`] at a point in time.
+///
+/// Cheaply cloneable, and can be shared between threads. See [`Database::snapshot`].
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    fsts: Vec<Arc<LevelFst>>,
+    held: Arc<HashMap<Bytes, u64>>,
+}
+
+impl Snapshot {
+    /// Retrieves the value associated with `key` when this snapshot was taken.
+    pub fn get(&self, key: &[u8]) -> Option<u64> {
+        get(&self.held, &self.fsts, key, None)
+    }
+
+    /// See [`Database::multi_get`].
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Vec<Option<u64>> {
+        multi_get(&self.held, &self.fsts, keys, None)
+    }
+
+    /// See [`Database::contains_key`].
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        contains_key(&self.held, &self.fsts, key, None)
+    }
+
+    /// Iterates over every key and its value when this snapshot was taken, in ascending key
+    /// order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter::new(&self.held, &self.fsts)
+    }
+
+    /// See [`Database::search`].
+    pub fn search<'a, A: Automaton>(&'a self, automaton: &'a A) -> Iter<'a> {
+        Iter::search(&self.held, &self.fsts, automaton)
+    }
+
+    /// See [`Database::fuzzy`].
+    #[cfg(feature = "fuzzy")]
+    pub fn fuzzy(&self, key: &str, max_distance: u32) -> Result<Vec<(Bytes, u64)>, Error> {
+        fuzzy(&self.held, &self.fsts, key, max_distance)
+    }
+}
+
+/// An iterator over the entries in a [`Database`] or [`Snapshot`].
+///
+/// See [`Database::iter`] and [`Database::search`].
+pub struct Iter<'a> {
+    stream: fst::map::Union<'a>,
+    fsts: Vec<&'a LevelFst>,
+    held: std::iter::Peekable<std::vec::IntoIter<(&'a Bytes, u64)>>,
+    /// The next item from the FSTs, which has already been taken from `stream`.
+    next_stored: Option<(Bytes, u64)>,
+}
+
+impl<'a> Iter<'a> {
+    fn new(held: &'a HashMap<Bytes, u64>, fsts: &'a [Arc<LevelFst>]) -> Self {
+        let held = held.iter().map(|(k, v)| (k, *v)).collect();
+        let mut union = OpBuilder::new();
+        for f in fsts {
+            union.push(&f.fst);
+        }
+        Self::from_union(held, fsts, union)
+    }
+
+    fn search<A: Automaton>(held: &'a HashMap<Bytes, u64>, fsts: &'a [Arc<LevelFst>], automaton: &'a A) -> Self {
+        let held = held.iter().filter(|(k, _)| matches(automaton, k)).map(|(k, v)| (k, *v)).collect();
+        let mut union = OpBuilder::new();
+        for f in fsts {
+            union.push(f.fst.search(automaton));
+        }
+        Self::from_union(held, fsts, union)
+    }
+
+    /// `union` must contain a stream from each of `fsts`, in order.
+    fn from_union(mut held: Vec<(&'a Bytes, u64)>, fsts: &'a [Arc<LevelFst>], union: OpBuilder<'a>) -> Self {
+        held.sort_by_key(|(k, _)| *k);
+        Self {
+            stream: union.union(),
+            fsts: fsts.iter().map(AsRef::as_ref).collect(),
+            held: held.into_iter().peekable(),
+            next_stored: None,
+        }
+    }
+
+    fn peek_stored(&mut self) -> Option<&(Bytes, u64)> {
+        if self.next_stored.is_none() {
+            self.next_stored = self.stream.next().map(|(key, idxs)| {
+                let newest = idxs.iter().max_by_key(|i| self.fsts[i.index].id).expect("non-empty");
+                (Bytes::copy_from_slice(key), newest.value)
+            });
+        }
+        self.next_stored.as_ref()
+    }
+}
+
+impl Iterator for Iter<'_> {
+    type Item = (Bytes, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let stored = self.peek_stored().map(|(key, _)| key.clone());
+        match (self.held.peek(), stored) {
+            (None, None) => None,
+            (Some(_), None) => self.held.next().map(|(k, v)| (k.clone(), v)),
+            (None, Some(_)) => self.next_stored.take(),
+            (Some((held, _)), Some(stored)) => {
+                if **held <= stored {
+                    // Held items are always newer than anything in the FSTs
+                    if **held == stored {
+                        self.next_stored = None;
+                    }
+                    self.held.next().map(|(k, v)| (k.clone(), v))
+                } else {
+                    self.next_stored.take()
+                }
+            }
+        }
+    }
+}
+
+impl Debug for Iter<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Iter").finish_non_exhaustive()
+    }
+}
+
 #[derive(Debug)]
 struct Pather {
     prefix: String,
@@ -479,33 +1919,54 @@ struct Pather {
     index: PathBuf,
     index_write: PathBuf,
     write_fst: PathBuf,
+    write_bloom: PathBuf,
+    reverse: PathBuf,
+    write_reverse: PathBuf,
     log: PathBuf,
     log_backup: PathBuf,
+    lock: PathBuf,
 }
 
 impl Pather {
-    fn new(base: PathBuf, prefix: String) -> anyhow::Result<Self> {
-        Ok(Self {
+    fn new(base: PathBuf, prefix: String) -> Self {
+        Self {
             index: base.join(format!("{prefix}.idx")),
             index_write: base.join(format!(".{prefix}.idx~")),
             log: base.join(format!("{prefix}.log")),
             log_backup: base.join(format!(".{prefix}.log~")),
+            lock: base.join(format!("{prefix}.lock")),
             write_fst: base.join(format!(".{prefix}._.fst~")),
+            write_bloom: base.join(format!(".{prefix}._.bloom~")),
+            reverse: base.join(format!("{prefix}.rev")),
+            write_reverse: base.join(format!(".{prefix}.rev~")),
 
             prefix,
             base,
-        })
+        }
     }
 
     fn fst(&self, id: u64, level: u8) -> PathBuf {
         self.base.join(format!("{}_{id}.{level}.fst", self.prefix))
     }
+
+    fn bloom(&self, id: u64, level: u8) -> PathBuf {
+        self.base.join(format!("{}_{id}.{level}.bloom", self.prefix))
+    }
+
+    fn value_log(&self, generation: u64) -> PathBuf {
+        self.base.join(format!("{}_{generation}.vlog", self.prefix))
+    }
 }
 
 #[derive(Debug)]
 enum LogItem {
     Insert { key: Bytes, value: u64 },
     Flushed,
+    /// An insert into a database with a value log. The value itself is logged rather than its
+    /// offset, as the value log may be compacted before the log is replayed.
+    InsertValue { key: Bytes, value: Bytes },
+    /// Several inserts written at once by [`Database::set_batch`].
+    Batch { items: Vec<(Bytes, u64)> },
 }
 
 impl LogItem {
@@ -522,6 +1983,24 @@ impl LogItem {
                 w.write_all(&[1])?;
                 Ok(())
             }
+            LogItem::InsertValue { key, value } => {
+                w.write_all(&[2])?;
+                w.write_varint(key.len() as u64)?;
+                w.write_all(key)?;
+                w.write_varint(value.len() as u64)?;
+                w.write_all(value)?;
+                Ok(())
+            }
+            LogItem::Batch { items } => {
+                w.write_all(&[3])?;
+                w.write_varint(items.len() as u64)?;
+                for (key, value) in items {
+                    w.write_varint(key.len() as u64)?;
+                    w.write_all(key)?;
+                    w.write_varint(*value)?;
+                }
+                Ok(())
+            }
         }
     }
 
@@ -540,6 +2019,30 @@ impl LogItem {
                 })
             }
             1 => Ok(Self::Flushed),
+            2 => {
+                let mut read_bytes = || -> std::io::Result<Bytes> {
+                    let len = <_ as ReadVarint<u64>>::read_varint(&mut r)? as usize;
+                    let mut buf = vec![0; len];
+                    r.read_exact(&mut buf)?;
+                    Ok(Bytes::from(buf))
+                };
+                let key = read_bytes()?;
+                let value = read_bytes()?;
+                Ok(Self::InsertValue { key, value })
+            }
+            3 => {
+                let len = <_ as ReadVarint<u64>>::read_varint(&mut r)? as usize;
+                // The length is untrusted, so don't preallocate for it
+                let mut items = Vec::new();
+                for _ in 0..len {
+                    let key_len = <_ as ReadVarint<u64>>::read_varint(&mut r)? as usize;
+                    let mut key = vec![0; key_len];
+                    r.read_exact(&mut key)?;
+                    let value = <_ as ReadVarint<u64>>::read_varint(&mut r)?;
+                    items.push((Bytes::from(key), value));
+                }
+                Ok(Self::Batch { items })
+            }
             _ => Err(std::io::Error::from(std::io::ErrorKind::InvalidData)),
         }
     }
@@ -550,6 +2053,40 @@ struct LevelFst {
     id: u64,
     level: u8,
     fst: fst::Map<Mmap>,
+    bloom: Option<Bloom>,
+    residency: Residency,
+    /// The smallest and largest keys in the FST, so that lookups can skip FSTs that cannot hold
+    /// a key when keys are clustered.
+    first_key: Bytes,
+    last_key: Bytes,
+}
+
+impl LevelFst {
+    /// Whether this FST may contain `key`, without reading the FST itself if possible.
+    fn may_contain(&self, key: &[u8]) -> bool {
+        *self.first_key <= *key && *key <= *self.last_key && self.bloom.as_ref().is_none_or(|b| b.may_contain(key))
+    }
+}
+
+/// The smallest and largest keys in `fst`, found by following its first and last transitions.
+/// Both are empty if it is empty.
+fn key_bounds(fst: &fst::raw::Fst<impl AsRef<[u8]>>) -> (Bytes, Bytes) {
+    let mut first = Vec::new();
+    let mut node = fst.root();
+    while !node.is_final() && !node.is_empty() {
+        let t = node.transition(0);
+        first.push(t.inp);
+        node = fst.node(t.addr);
+    }
+    // The largest key cannot have any keys below it
+    let mut last = Vec::new();
+    let mut node = fst.root();
+    while !node.is_empty() {
+        let t = node.transition(node.len() - 1);
+        last.push(t.inp);
+        node = fst.node(t.addr);
+    }
+    (first.into(), last.into())
 }
 
 impl Debug for LevelFst {
@@ -558,6 +2095,7 @@ impl Debug for LevelFst {
             .field("count", &self.count)
             .field("id", &self.id)
             .field("level", &self.level)
+            .field("bloom", &self.bloom.is_some())
             .finish_non_exhaustive()
     }
 }
@@ -567,37 +2105,81 @@ struct IndexFst {
     id: u64,
     level: u8,
     count: u64,
+    /// `None` if read from an index written before checksums existed.
+    checksum: Option<u32>,
+    /// The first and last keys, or `None` if read from an index written before they were stored.
+    bounds: Option<(Bytes, Bytes)>,
 }
 
 #[derive(Debug)]
 struct Index {
     fsts: Vec<IndexFst>,
+    value_log_generation: u64,
+    /// The id that the next FST written will be given, as ids are never reused.
+    next_fst_id: u64,
 }
 
 impl Index {
-    const MAGIC: &'static [u8] = b"\xFEruFSTg\xAA";
-
+    /// Indexes without checksums, which are still read.
+    const MAGIC_V1: &'static [u8] = b"\xFEruFSTg\xAA";
+    /// Indexes without key bounds, which are still read.
+    const MAGIC_V2: &'static [u8] = b"\xFEruFSTg\xAB";
+    const MAGIC: &'static [u8] = b"\xFEruFSTg\xAC";
+
+    /// Written as the magic bytes, the FSTs (each with the checksum of its file and its first and
+    /// last keys), the value log generation, the next FST id, then a CRC-32C of everything before
+    /// it.
     fn write(&self, w: &mut impl Write) -> std::io::Result<()> {
-        w.write_all(Self::MAGIC)?;
-
-        w.write_varint(self.fsts.len() as u64)?;
-        for &IndexFst { id, level, count } in &self.fsts {
-            w.write_varint(id)?;
-            w.write_all(&[level])?;
-            w.write_varint(count)?;
+        let mut buf = Vec::from(Self::MAGIC);
+
+        buf.write_varint(self.fsts.len() as u64)?;
+        for IndexFst { id, level, count, checksum, bounds } in &self.fsts {
+            buf.write_varint(*id)?;
+            buf.write_all(&[*level])?;
+            buf.write_varint(*count)?;
+            buf.write_all(&checksum.unwrap_or(0).to_le_bytes())?;
+            let (first, last) = bounds.as_ref().map_or((&[][..], &[][..]), |(first, last)| (first, last));
+            for key in [first, last] {
+                buf.write_varint(key.len() as u64)?;
+                buf.write_all(key)?;
+            }
         }
+        buf.write_varint(self.value_log_generation)?;
+        buf.write_varint(self.next_fst_id)?;
 
-        Ok(())
+        let crc = checksum::crc32c(&buf);
+        buf.extend_from_slice(&crc.to_le_bytes());
+        w.write_all(&buf)
     }
 
     fn read(r: &mut impl Read) -> std::io::Result<Self> {
-        let mut buf = [0; Self::MAGIC.len()];
-        r.read_exact(&mut buf)?;
-        if buf != Self::MAGIC {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+
+        let (rest, version) = if let Some(rest) = data.strip_prefix(Self::MAGIC) {
+            (rest, 3)
+        } else if let Some(rest) = data.strip_prefix(Self::MAGIC_V2) {
+            (rest, 2)
+        } else if let Some(rest) = data.strip_prefix(Self::MAGIC_V1) {
+            (rest, 1)
+        } else {
             return Err(std::io::Error::from(std::io::ErrorKind::InvalidData));
-        }
+        };
+        let body = if version >= 2 {
+            let Some((body, crc)) = rest.split_last_chunk::<4>() else {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+            };
+            if checksum::crc32c(&data[..data.len() - 4]) != u32::from_le_bytes(*crc) {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "index checksum mismatch"));
+            }
+            body
+        } else {
+            rest
+        };
+        let end = body.len() as u64;
+        let mut r = Cursor::new(body);
 
-        let len = <_ as ReadVarint<u64>>::read_varint(r)? as usize;
+        let len = <_ as ReadVarint<u64>>::read_varint(&mut r)? as usize;
         let mut fsts = Vec::with_capacity(len);
         for _ in 0..len {
             let id = r.read_varint()?;
@@ -605,14 +2187,607 @@ impl Index {
             r.read_exact(&mut buf)?;
             let level = buf[0];
             let count = r.read_varint()?;
-            fsts.push(IndexFst { id, level, count })
+            let checksum = if version >= 2 {
+                let mut buf = [0; 4];
+                r.read_exact(&mut buf)?;
+                Some(u32::from_le_bytes(buf))
+            } else {
+                None
+            };
+            let bounds = if version >= 3 {
+                let mut read_key = || -> std::io::Result<Bytes> {
+                    let mut key = vec![0; <_ as ReadVarint<u64>>::read_varint(&mut r)? as usize];
+                    r.read_exact(&mut key)?;
+                    Ok(key.into())
+                };
+                Some((read_key()?, read_key()?))
+            } else {
+                None
+            };
+            fsts.push(IndexFst {
+                id,
+                level,
+                count,
+                checksum,
+                bounds,
+            })
         }
 
-        Ok(Self { fsts })
+        // Indexes written before value logs existed end here
+        let value_log_generation = if r.position() == end { 0 } else { r.read_varint()? };
+        // As do those written before the next FST id was, which was then one past the highest id
+        let next_fst_id = if r.position() == end {
+            fsts.iter().map(|f| f.id + 1).max().unwrap_or(0)
+        } else {
+            r.read_varint()?
+        };
+
+        Ok(Self {
+            fsts,
+            value_log_generation,
+            next_fst_id,
+        })
+    }
+}
+
+/// Maps the FST described by `fs`, checking it against its checksum (in full if `verify`).
+fn open_fst(
+    paths: &Pather,
+    fs: IndexFst,
+    verify: bool,
+    bloom_bits_per_key: usize,
+    load_levels: Option<u8>,
+    access_pattern: Option<AccessPattern>,
+) -> Result<Arc<LevelFst>, Error> {
+    let path = paths.fst(fs.id, fs.level);
+    // Otherwise it is compressed, so is decompressed into anonymous memory
+    let mut file_backed = path.exists();
+    let mut map = compression::map_fst(&path)?;
+    if file_backed && load_levels.is_some_and(|max| fs.level <= max) {
+        map = compression::copy_to_anon(&map)?;
+        file_backed = false;
+    }
+    let fst = fst::Map::new(map)?;
+    // Indexes written before checksums existed have none to compare against
+    if let Some(expected) = fs.checksum {
+        let valid = if verify {
+            checksum::verify_fst(fst.as_fst(), expected)
+        } else {
+            checksum::fst_checksum(fst.as_fst().as_bytes()) == expected
+        };
+        if !valid {
+            warn!("{} does not match its checksum", path.display());
+            return Err(Error::ChecksumMismatch { path });
+        }
+    }
+    let bloom = (bloom_bits_per_key > 0).then(|| Bloom::read(&paths.bloom(fs.id, fs.level))).flatten();
+    let (first_key, last_key) = fs.bounds.unwrap_or_else(|| key_bounds(fst.as_fst()));
+    let fst = LevelFst {
+        count: fs.count,
+        id: fs.id,
+        level: fs.level,
+        fst,
+        bloom,
+        residency: Residency::new(file_backed),
+        first_key,
+        last_key,
+    };
+    if let Some(pattern) = access_pattern {
+        advice::apply(&fst, pattern);
     }
+    Ok(Arc::new(fst))
 }
 
 #[inline(always)]
-fn empty_callback(_: Bytes, _: u64) -> anyhow::Result<()> {
+fn empty_callback(_: Bytes, _: u64) -> Result<(), Error> {
     Ok(())
 }
+
+#[inline(always)]
+fn keep_all(_: Bytes, _: u64) -> Result<MergeAction, Error> {
+    Ok(MergeAction::Keep)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn open(dir: &TempDir) -> Database {
+        Database::builder(dir.path().to_owned(), "db".to_owned()).open().unwrap()
+    }
+
+    #[test]
+    fn set_batch() {
+        let dir = TempDir::new().unwrap();
+        let mut db = open(&dir);
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        db.set_batch([(Bytes::from_static(b"a"), 2), (Bytes::from_static(b"b"), 3)]).unwrap();
+        assert_eq!(db.get(b"a"), Some(2));
+        assert_eq!(db.get(b"b"), Some(3));
+
+        // Restored from the write-ahead log
+        drop(db);
+        let db = open(&dir);
+        assert_eq!(db.get(b"a"), Some(2));
+        assert_eq!(db.get(b"b"), Some(3));
+        assert_eq!(db.len(), 2);
+    }
+
+    #[test]
+    fn bulk_load_then_flush() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned()).fanout(2).open().unwrap();
+        db.set(Bytes::from_static(b"k"), 1).unwrap();
+        db.flush().unwrap();
+        // Enough items for the loaded FST to be above level 0
+        let loaded = (0..1000_u64).map(|i| (Bytes::from(format!("k{i:03}")), i)).chain([(Bytes::from_static(b"k"), 2)]);
+        let mut loaded = loaded.collect::<Vec<_>>();
+        loaded.sort();
+        db.bulk_load(loaded).unwrap();
+        assert_eq!(db.get(b"k"), Some(2));
+
+        // Once level 0 merges, the FST from before the load must not bring back its older value
+        db.set(Bytes::from_static(b"x"), 3).unwrap();
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"y"), 4).unwrap();
+        db.flush().unwrap();
+        assert_eq!(db.get(b"k"), Some(2));
+        assert_eq!(db.get(b"x"), Some(3));
+        drop(db);
+        let db = open(&dir);
+        assert_eq!(db.get(b"k"), Some(2));
+    }
+    #[test]
+    fn compact_dropped_values() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned()).value_log(true).compact_value_log(true).open().unwrap();
+        db.set_bytes(Bytes::from_static(b"a"), b"kept").unwrap();
+        db.set_bytes(Bytes::from_static(b"b"), &[0; 1000]).unwrap();
+        db.set_bytes(Bytes::from_static(b"c"), b"replaced").unwrap();
+        let mut kept = None;
+        db.merge_matching(|_| true, |key, value| {
+            Ok::<_, Error>(match &key[..] {
+                b"a" => {
+                    kept = Some(value);
+                    MergeAction::Keep
+                }
+                b"b" => MergeAction::Drop,
+                _ => MergeAction::Replace(kept.unwrap()),
+            })
+        })
+        .unwrap();
+        assert_eq!(db.get_bytes(b"a").unwrap().as_deref(), Some(&b"kept"[..]));
+        assert_eq!(db.get_bytes(b"b").unwrap(), None);
+        assert_eq!(db.get_bytes(b"c").unwrap().as_deref(), Some(&b"kept"[..]));
+        // Only the values that were kept are copied into the compacted log
+        let expected = 2 * ValueLog::encoded_len(4);
+        assert_eq!(db.stats().unwrap().value_log_bytes, Some(expected));
+    }
+    #[test]
+    fn merge_range() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned()).expiry(true).open().unwrap();
+        for (i, key) in [&b"a"[..], b"b", b"c", b"d"].into_iter().enumerate() {
+            db.set(Bytes::copy_from_slice(key), i as u64).unwrap();
+        }
+        db.set_with_ttl(Bytes::from_static(b"expired"), 4, std::time::Duration::ZERO).unwrap();
+        db.flush().unwrap();
+
+        let mut seen = Vec::new();
+        db.merge_range(&b"b"[..]..=&b"c"[..], |key, value| {
+            seen.push(key.clone());
+            Ok::<_, Error>(if &key[..] == b"b" { MergeAction::Replace(value + 10) } else { MergeAction::Drop })
+        })
+        .unwrap();
+        assert_eq!(seen, [&b"b"[..], b"c"]);
+        // Expired keys are dropped even though they are outside the range
+        let entries = db.iter().collect::<Vec<_>>();
+        assert_eq!(entries, [(Bytes::from_static(b"a"), 0), (Bytes::from_static(b"b"), 11), (Bytes::from_static(b"d"), 3)]);
+
+        drop(db);
+        let db = Database::builder(dir.path().to_owned(), "db".to_owned()).expiry(true).open().unwrap();
+        assert_eq!(db.get(b"b"), Some(11));
+        assert_eq!(db.get(b"c"), None);
+        assert_eq!(db.len(), 3);
+    }
+
+    #[test]
+    fn merge_matching() {
+        let mut db = Database::in_memory().unwrap();
+        for i in 0..10_u64 {
+            db.set(Bytes::from(i.to_string()), i).unwrap();
+        }
+        db.merge_matching(
+            |key| key[0] % 2 == 0,
+            |_, value| Ok::<_, Error>(if value < 5 { MergeAction::Drop } else { MergeAction::Replace(value * 2) }),
+        )
+        .unwrap();
+        let entries = db.iter().map(|(key, value)| (String::from_utf8(key.to_vec()).unwrap(), value)).collect::<Vec<_>>();
+        let expected = [("1", 1), ("3", 3), ("5", 5), ("6", 12), ("7", 7), ("8", 16), ("9", 9)];
+        assert_eq!(entries, expected.map(|(key, value)| (key.to_owned(), value)));
+    }
+    #[test]
+    fn compact_level() {
+        let dir = TempDir::new().unwrap();
+        let mut db = open(&dir);
+        db.set(Bytes::from_static(b"k"), 1).unwrap();
+        db.flush().unwrap();
+        // Loaded as a single FST above level 0, which is newer than the level-0 one
+        let loaded = (0..1000_u64).map(|i| (Bytes::from(format!("k{i:03}")), i)).chain([(Bytes::from_static(b"k"), 2)]);
+        let mut loaded = loaded.collect::<Vec<_>>();
+        loaded.sort();
+        db.bulk_load(loaded).unwrap();
+        db.set(Bytes::from_static(b"x"), 3).unwrap();
+        db.flush().unwrap();
+        let levels = db.fsts.iter().map(|f| f.level).collect::<Vec<_>>();
+        assert_eq!(levels, [0, 1, 0]);
+
+        // The loaded FST is newer than the oldest level-0 one, so is merged with it
+        db.compact_level(0).unwrap();
+        assert_eq!(db.fsts.len(), 1);
+        assert_eq!(db.get(b"k"), Some(2));
+        assert_eq!(db.get(b"x"), Some(3));
+
+        // Only FSTs at or newer than the selected levels are merged
+        db.set(Bytes::from_static(b"y"), 4).unwrap();
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"k"), 5).unwrap();
+        db.compact_range(0..1).unwrap();
+        assert_eq!(db.fsts.len(), 2);
+        drop(db);
+        let db = open(&dir);
+        assert_eq!(db.get(b"k"), Some(5));
+        assert_eq!(db.get(b"k999"), Some(999));
+        assert_eq!(db.get(b"y"), Some(4));
+        assert_eq!(db.distinct_len(), 1003);
+    }
+    #[test]
+    fn next_fst_id() {
+        let dir = TempDir::new().unwrap();
+        let mut db = open(&dir);
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        db.flush().unwrap();
+        // Drops everything, so the merge's FST id is used up without any FST left to show it
+        db.merge_matching(|_| true, |_, _| Ok::<_, Error>(MergeAction::Drop)).unwrap();
+        assert!(db.fsts.is_empty());
+        assert_eq!(db.fst_count, 2);
+        drop(db);
+
+        let mut db = open(&dir);
+        assert_eq!(db.fst_count, 2);
+        db.set(Bytes::from_static(b"b"), 2).unwrap();
+        db.flush().unwrap();
+        assert_eq!(db.fsts.iter().map(|f| f.id).collect::<Vec<_>>(), [2]);
+        drop(db);
+        let db = open(&dir);
+        assert_eq!(db.fst_count, 3);
+        assert_eq!(db.get(b"b"), Some(2));
+    }
+    #[test]
+    fn reload() {
+        let copy = |from: &Path, to: &Path| {
+            for entry in fs_err::read_dir(from).unwrap() {
+                let entry = entry.unwrap();
+                if entry.file_name() != "db.lock" {
+                    fs_err::copy(entry.path(), to.join(entry.file_name())).unwrap();
+                }
+            }
+        };
+        let dir = TempDir::new().unwrap();
+        let backup = TempDir::new().unwrap();
+        let mut db = open(&dir);
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"b"), 2).unwrap();
+        copy(dir.path(), backup.path());
+
+        db.set(Bytes::from_static(b"a"), 3).unwrap();
+        db.merge(empty_callback).unwrap();
+        db.set(Bytes::from_static(b"c"), 4).unwrap();
+        let snapshot = db.snapshot();
+
+        // Restores the backup's FSTs and write-ahead log, discarding what was held in memory
+        copy(backup.path(), dir.path());
+        db.reload().unwrap();
+        assert_eq!(db.get(b"a"), Some(1));
+        assert_eq!(db.get(b"b"), Some(2));
+        assert_eq!(db.get(b"c"), None);
+        assert_eq!(snapshot.get(b"a"), Some(3));
+        assert_eq!(snapshot.get(b"c"), Some(4));
+
+        // Carries on from the reloaded files
+        db.set(Bytes::from_static(b"d"), 5).unwrap();
+        db.flush().unwrap();
+        drop(db);
+        let db = open(&dir);
+        assert_eq!(db.get(b"a"), Some(1));
+        assert_eq!(db.get(b"b"), Some(2));
+        assert_eq!(db.get(b"d"), Some(5));
+        assert_eq!(db.distinct_len(), 3);
+    }
+    #[test]
+    fn key_bounds() {
+        let dir = TempDir::new().unwrap();
+        let mut db = open(&dir);
+        for key in [&b"b"[..], b"ba", b"c"] {
+            db.set(Bytes::copy_from_slice(key), 1).unwrap();
+        }
+        db.flush().unwrap();
+        for key in [&b"x"[..], b"xy", b"xyz"] {
+            db.set(Bytes::copy_from_slice(key), 2).unwrap();
+        }
+        db.flush().unwrap();
+        let bounds = |db: &Database| db.fsts.iter().map(|f| (f.first_key.clone(), f.last_key.clone())).collect::<Vec<_>>();
+        let expected = [(Bytes::from_static(b"b"), Bytes::from_static(b"c")), (Bytes::from_static(b"x"), Bytes::from_static(b"xyz"))];
+        assert_eq!(bounds(&db), expected);
+        for fst in &db.fsts {
+            assert_eq!(super::key_bounds(fst.fst.as_fst()), (fst.first_key.clone(), fst.last_key.clone()));
+        }
+
+        // Read back from the index rather than the FSTs
+        drop(db);
+        let db = open(&dir);
+        let index = Index::read(&mut File::open(&db.paths.index).unwrap()).unwrap();
+        assert!(index.fsts.iter().all(|f| f.bounds.is_some()));
+        assert_eq!(bounds(&db), expected);
+        assert_eq!(db.multi_get(&[b"a", b"b", b"bb", b"c", b"d", b"x", b"xyz", b"z"]), [None, Some(1), None, Some(1), None, Some(2), Some(2), None]);
+        assert!(db.contains_key(b"ba"));
+        assert!(!db.contains_key(b"xz"));
+    }
+    #[test]
+    fn newest_first() {
+        let dir = TempDir::new().unwrap();
+        let mut db = open(&dir);
+        // Each FST overwrites some of the keys of the ones before it
+        for (i, keys) in [&[&b"a"[..], b"b", b"c"][..], &[b"b", b"c"], &[b"c"]].into_iter().enumerate() {
+            for key in keys {
+                db.set(Bytes::copy_from_slice(key), i as u64).unwrap();
+            }
+            db.flush().unwrap();
+        }
+        assert_eq!(db.fsts.len(), 3);
+
+        drop(db);
+        let db = open(&dir);
+        assert_eq!(db.get(b"a"), Some(0));
+        assert_eq!(db.get(b"b"), Some(1));
+        assert_eq!(db.get(b"c"), Some(2));
+        assert_eq!(db.multi_get(&[b"c", b"a", b"d", b"b"]), [Some(2), Some(0), None, Some(1)]);
+        assert_eq!(db.snapshot().multi_get(&[b"b", b"c"]), [Some(1), Some(2)]);
+    }
+    #[test]
+    fn iter() {
+        let dir = TempDir::new().unwrap();
+        let mut db = open(&dir);
+        db.set(Bytes::from_static(b"b"), 1).unwrap();
+        db.set(Bytes::from_static(b"d"), 2).unwrap();
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"a"), 3).unwrap();
+        db.set(Bytes::from_static(b"d"), 4).unwrap();
+        db.flush().unwrap();
+        // Held in memory, overriding the FSTs
+        db.set(Bytes::from_static(b"c"), 5).unwrap();
+        db.set(Bytes::from_static(b"b"), 6).unwrap();
+
+        let expected = [(Bytes::from_static(b"a"), 3), (Bytes::from_static(b"b"), 6), (Bytes::from_static(b"c"), 5), (Bytes::from_static(b"d"), 4)];
+        assert_eq!(db.iter().collect::<Vec<_>>(), expected);
+        drop(db);
+        let db = open(&dir);
+        assert_eq!(db.iter().collect::<Vec<_>>(), expected);
+        assert_eq!(Database::in_memory().unwrap().iter().next(), None);
+    }
+    #[test]
+    fn snapshot() {
+        let mut db = Database::in_memory().unwrap();
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"b"), 2).unwrap();
+        let snapshot = db.snapshot();
+
+        // Neither later writes nor merging away the FSTs it reads are visible through it
+        db.set(Bytes::from_static(b"a"), 3).unwrap();
+        db.set(Bytes::from_static(b"c"), 4).unwrap();
+        db.merge(empty_callback).unwrap();
+        let reader = std::thread::spawn(move || (snapshot.get(b"a"), snapshot.get(b"b"), snapshot.contains_key(b"c"), snapshot.iter().count()));
+        assert_eq!(reader.join().unwrap(), (Some(1), Some(2), false, 2));
+        assert_eq!(db.get(b"a"), Some(3));
+    }
+    #[test]
+    fn multi_get() {
+        let mut db = Database::in_memory().unwrap();
+        for i in 0..300_u64 {
+            db.set(Bytes::from(format!("k{i:03}")), i).unwrap();
+        }
+        db.set(Bytes::from_static(b"k005"), 1000).unwrap();
+        assert!(!db.fsts.is_empty());
+
+        // Unsorted and repeated keys are answered in the order given
+        let keys = [&b"k299"[..], b"missing", b"k005", b"k000", b"k005"];
+        assert_eq!(db.multi_get(&keys), [Some(299), None, Some(1000), Some(0), Some(1000)]);
+        assert_eq!(db.multi_get(&keys), keys.iter().map(|key| db.get(key)).collect::<Vec<_>>());
+        assert!(db.multi_get(&[]).is_empty());
+    }
+    #[test]
+    fn contains_key() {
+        let mut db = Database::in_memory().unwrap();
+        db.set(Bytes::from_static(b"flushed"), 0).unwrap();
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"held"), 0).unwrap();
+        let snapshot = db.snapshot();
+        for key in [&b"flushed"[..], b"held"] {
+            assert!(db.contains_key(key));
+            assert!(snapshot.contains_key(key));
+        }
+        assert!(!db.contains_key(b"missing"));
+        assert!(!snapshot.contains_key(b"missing"));
+    }
+    #[test]
+    fn len() {
+        let dir = TempDir::new().unwrap();
+        let mut db = open(&dir);
+        assert!(db.is_empty());
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        db.set(Bytes::from_static(b"a"), 2).unwrap();
+        assert_eq!((db.len(), db.distinct_len()), (1, 1));
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"a"), 3).unwrap();
+        db.set(Bytes::from_static(b"b"), 4).unwrap();
+        // `a` is counted once for the FST and once for the in-memory items
+        assert_eq!((db.len(), db.distinct_len()), (3, 2));
+        assert!(!db.is_empty());
+
+        drop(db);
+        let mut db = open(&dir);
+        assert_eq!((db.len(), db.distinct_len()), (3, 2));
+        db.merge(empty_callback).unwrap();
+        assert_eq!((db.len(), db.distinct_len()), (2, 2));
+    }
+    #[test]
+    fn max_wal_bytes() {
+        let dir = TempDir::new().unwrap();
+        let open = || Database::builder(dir.path().to_owned(), "db".to_owned()).value_log(true).max_wal_bytes(10_000).open().unwrap();
+        let mut db = open();
+        db.set_bytes(Bytes::from_static(b"small"), b"x").unwrap();
+        assert!(db.fsts.is_empty());
+        // Far fewer items than the write threshold, but each logs its whole value
+        for i in 0..10_u8 {
+            db.set_bytes(Bytes::from(vec![i]), &[i; 3000]).unwrap();
+            assert!(db.stats().unwrap().wal_bytes < 10_000);
+        }
+        assert!(!db.fsts.is_empty());
+        drop(db);
+        let db = open();
+        assert_eq!(db.get_bytes(&[9]).unwrap(), Some(vec![9; 3000]));
+        assert_eq!(db.get_bytes(b"small").unwrap(), Some(b"x".to_vec()));
+    }
+    #[test]
+    fn search() {
+        use fst::automaton::Str;
+
+        let mut db = Database::in_memory().unwrap();
+        for (key, value) in [(&b"apple"[..], 1), (b"apricot", 2), (b"banana", 3)] {
+            db.set(Bytes::copy_from_slice(key), value).unwrap();
+        }
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"apple"), 4).unwrap();
+        db.set(Bytes::from_static(b"april"), 5).unwrap();
+        db.set(Bytes::from_static(b"cherry"), 6).unwrap();
+
+        let prefix = Str::new("ap").starts_with();
+        let found = db.search(&prefix).collect::<Vec<_>>();
+        let expected = [(Bytes::from_static(b"apple"), 4), (Bytes::from_static(b"apricot"), 2), (Bytes::from_static(b"april"), 5)];
+        assert_eq!(found, expected);
+        assert_eq!(db.snapshot().search(&prefix).collect::<Vec<_>>(), expected);
+        assert_eq!(db.search(&Str::new("banana")).collect::<Vec<_>>(), [(Bytes::from_static(b"banana"), 3)]);
+    }
+    #[cfg(feature = "fuzzy")]
+    #[test]
+    fn fuzzy() {
+        let mut db = Database::in_memory().unwrap();
+        for key in ["cat", "cart", "dog", "cot"] {
+            db.set(Bytes::from(key), key.len() as u64).unwrap();
+        }
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"cat"), 10).unwrap();
+        db.set(Bytes::from_static(b"\xFFcat"), 11).unwrap();
+
+        let found = db.fuzzy("cat", 1).unwrap();
+        let expected = [(Bytes::from_static(b"cart"), 4), (Bytes::from_static(b"cat"), 10), (Bytes::from_static(b"cot"), 3)];
+        assert_eq!(found, expected);
+        assert_eq!(db.snapshot().fuzzy("cat", 1).unwrap(), expected);
+        assert_eq!(db.fuzzy("cat", 0).unwrap(), [(Bytes::from_static(b"cat"), 10)]);
+    }
+    #[test]
+    fn get_or_insert_with() {
+        let mut db = Database::in_memory().unwrap();
+        assert_eq!(db.get_or_insert_with(Bytes::from_static(b"a"), || 1).unwrap(), 1);
+        assert_eq!(db.get_or_insert_with(Bytes::from_static(b"a"), || unreachable!()).unwrap(), 1);
+        db.flush().unwrap();
+        assert_eq!(db.get_or_insert_with(Bytes::from_static(b"a"), || unreachable!()).unwrap(), 1);
+        assert_eq!(db.get(b"a"), Some(1));
+        assert_eq!(db.len(), 1);
+    }
+
+    #[test]
+    fn memory_threshold_bytes() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned()).memory_threshold_bytes(1000).open().unwrap();
+        // Each item takes up 108 bytes, so the tenth passes the limit long before the item count does
+        for i in 0..9_u8 {
+            db.set(Bytes::from(vec![i; 100]), i.into()).unwrap();
+        }
+        assert!(db.fsts.is_empty());
+        assert_eq!(db.stats().unwrap().memory_bytes, 9 * 108);
+        db.set(Bytes::from(vec![9; 100]), 9).unwrap();
+        assert_eq!(db.fsts.len(), 1);
+        assert_eq!(db.stats().unwrap().memory_bytes, 0);
+        assert_eq!(db.get(&[9; 100]), Some(9));
+
+        // Overwriting a held key doesn't count it twice
+        db.set(Bytes::from(vec![0; 100]), 1).unwrap();
+        db.set(Bytes::from(vec![0; 100]), 2).unwrap();
+        assert_eq!(db.stats().unwrap().memory_bytes, 108);
+    }
+
+    #[test]
+    fn in_memory() {
+        let mut db = Database::in_memory().unwrap();
+        assert!(matches!(db.durability, Durability::None));
+        let path = db._temp_dir.as_ref().unwrap().path().to_owned();
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        db.flush().unwrap();
+        assert_eq!(db.get(b"a"), Some(1));
+        assert!(path.read_dir().unwrap().next().is_some());
+
+        // Each database has its own directory, removed along with it
+        let other = Database::in_memory().unwrap();
+        assert_ne!(other._temp_dir.as_ref().unwrap().path(), path);
+        assert_eq!(other.get(b"a"), None);
+        drop(db);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn u128_values() {
+        let dir = TempDir::new().unwrap();
+        let options = || Database::builder(dir.path().to_owned(), "db".to_owned()).value_log(true);
+        let mut db = options().open().unwrap();
+        db.set_u128(Bytes::from_static(b"max"), u128::MAX).unwrap();
+        db.set_u128(Bytes::from_static(b"one"), 1).unwrap();
+        db.set_bytes(Bytes::from_static(b"short"), &[1; 8]).unwrap();
+        db.flush().unwrap();
+        assert_eq!(db.get_u128(b"max").unwrap(), Some(u128::MAX));
+        assert_eq!(db.get_u128(b"missing").unwrap(), None);
+        assert!(matches!(db.get_u128(b"short"), Err(Error::ValueWidth { expected: 16, found: 8 })));
+
+        drop(db);
+        let db = options().open().unwrap();
+        assert_eq!(db.get_u128(b"one").unwrap(), Some(1));
+        assert_eq!(db.get_u128(b"max").unwrap(), Some(u128::MAX));
+
+        let mut db = Database::in_memory().unwrap();
+        assert!(matches!(db.set_u128(Bytes::from_static(b"a"), 1), Err(Error::NoValueLog)));
+        assert!(matches!(db.get_u128(b"a"), Err(Error::NoValueLog)));
+    }
+
+    #[test]
+    fn close() {
+        let dir = TempDir::new().unwrap();
+        let options = || Database::builder(dir.path().to_owned(), "db".to_owned()).expiry(true);
+        let mut db = options().open().unwrap();
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        db.set_with_ttl(Bytes::from_static(b"b"), 2, std::time::Duration::from_secs(3600)).unwrap();
+        db.close().unwrap();
+
+        // Everything was flushed, so there is nothing left to restore, and the lock was released
+        let db = options().open().unwrap();
+        let stats = db.stats().unwrap();
+        assert_eq!((stats.memory_items, stats.wal_bytes, stats.fst_count()), (0, 0, 1));
+        assert_eq!(db.expiry.as_ref().unwrap().stats().unwrap().memory_items, 0);
+        assert_eq!(db.get(b"a"), Some(1));
+        assert_eq!(db.get(b"b"), Some(2));
+        db.close().unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+use std::path::{Path, PathBuf};
+
+use fs4::FileExt;
+
+use crate::Error;
+
+/// An exclusive advisory lock on a database, held for as long as it is open.
+#[derive(Debug)]
+pub(crate) struct Lock {
+    file: std::fs::File,
+}
+
+impl Lock {
+    pub(crate) fn new(at: &Path) -> Result<Self, Error> {
+        let file = fs_err::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(at)?.into_parts().0;
+        FileExt::try_lock_exclusive(&file).map_err(|source| Error::Locked {
+            path: PathBuf::from(at),
+            source,
+        })?;
+        Ok(Self { file })
+    }
+
+    /// Releases the lock, reporting whether that failed, unlike dropping it.
+    pub(crate) fn release(self) -> Result<(), Error> {
+        // Dropping it afterwards unlocks it again, which does nothing
+        FileExt::unlock(&self.file)?;
+        Ok(())
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // Closing the file releases the lock anyway, so there is nothing to do if this fails
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{temp_dir::TempDir, Database, Error};
+
+    #[test]
+    fn already_open() {
+        let dir = TempDir::new().unwrap();
+        let open = || Database::builder(dir.path().to_owned(), "db".to_owned()).open();
+        let db = open().unwrap();
+        assert!(matches!(open(), Err(Error::Locked { .. })));
+        // Other prefixes in the same directory are separate databases
+        Database::builder(dir.path().to_owned(), "other".to_owned()).open().unwrap();
+
+        // Closing the database, or just dropping it, releases the lock
+        db.close().unwrap();
+        let db = open().unwrap();
+        drop(db);
+        open().unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{versions::Prefix, Database, Error, MergeAction};
+
+/// A map from byte sequences to sets of [`u64`]s, stored in a [`Database`].
+///
+/// Each pair is its own entry in the database, keyed by the key followed by the value (`u64` BE),
+/// so a key's values are found with a prefix search and come out in ascending order. As every
+/// entry is 8 bytes longer than its key, the length of an entry tells which key it belongs to.
+/// Every change is a single [`set`][Database::set] or [`set_batch`][Database::set_batch], so
+/// changes are exactly as durable as the database's own.
+///
+/// Removed pairs are overwritten with a marker until the next [`compact`][Self::compact].
+#[derive(Debug)]
+pub struct MultiMap {
+    db: Database,
+}
+
+/// The database value of a pair that is in the map.
+const PRESENT: u64 = 1;
+/// The database value of a pair that has been removed, but not yet compacted away.
+const REMOVED: u64 = 0;
+
+impl MultiMap {
+    /// Wraps `db`, which must only ever be written through a `MultiMap` from now on.
+    ///
+    /// `db` must not have a [value log][crate::DatabaseOptions::value_log] (every change fails
+    /// with [`Error::HasValueLog`]) or a [key codec][crate::DatabaseOptions::key_codec], which
+    /// would also be applied to the values.
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Adds `value` to the values of `key`, if it is not already one of them.
+    pub fn insert(&mut self, key: &[u8], value: u64) -> Result<(), Error> {
+        self.db.set(entry(key, value), PRESENT)
+    }
+
+    /// Adds every value in `values` to the values of `key` at once, as a single
+    /// [batch][Database::set_batch].
+    pub fn insert_all(&mut self, key: &[u8], values: impl IntoIterator<Item = u64>) -> Result<(), Error> {
+        self.db.set_batch(values.into_iter().map(|value| (entry(key, value), PRESENT)))
+    }
+
+    /// Removes `value` from the values of `key`. Returns whether it was one of them.
+    pub fn remove(&mut self, key: &[u8], value: u64) -> Result<bool, Error> {
+        if !self.contains(key, value) {
+            return Ok(false);
+        }
+        self.db.set(entry(key, value), REMOVED)?;
+        Ok(true)
+    }
+
+    /// Removes every value of `key` at once, as a single [batch][Database::set_batch]. Returns
+    /// how many there were.
+    pub fn remove_all(&mut self, key: &[u8]) -> Result<usize, Error> {
+        let values = self.get(key);
+        self.db.set_batch(values.iter().map(|&value| (entry(key, value), REMOVED)))?;
+        Ok(values.len())
+    }
+
+    /// Every value of `key`, in ascending order.
+    pub fn get(&self, key: &[u8]) -> Vec<u64> {
+        let prefix = Prefix(key);
+        self.db
+            .search(&prefix)
+            .filter(|(k, v)| k.len() == key.len() + 8 && *v == PRESENT)
+            .map(|(k, _)| u64::from_be_bytes(k[key.len()..].try_into().expect("8 bytes")))
+            .collect()
+    }
+
+    /// Whether `value` is one of the values of `key`.
+    pub fn contains(&self, key: &[u8], value: u64) -> bool {
+        self.db.get(&entry(key, value)) == Some(PRESENT)
+    }
+
+    /// Flushes all in-memory changes to the filesystem, as [`Database::flush`].
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.db.flush()
+    }
+
+    /// Merges everything into a single FST, as [`Database::merge`], dropping the entries of
+    /// removed pairs.
+    pub fn compact(&mut self) -> Result<(), Error> {
+        self.db.merge_matching(
+            |_| true,
+            |_, value| {
+                Ok::<_, Error>(match value {
+                    REMOVED => MergeAction::Drop,
+                    _ => MergeAction::Keep,
+                })
+            },
+        )
+    }
+
+    /// The wrapped database, _e.g._ for [`stats`][Database::stats].
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    pub fn into_inner(self) -> Database {
+        self.db
+    }
+}
+
+fn entry(key: &[u8], value: u64) -> Bytes {
+    let mut entry = BytesMut::with_capacity(key.len() + 8);
+    entry.put_slice(key);
+    entry.put_u64(value);
+    entry.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_remove() {
+        let mut map = MultiMap::new(Database::in_memory().unwrap());
+        map.insert(b"a", 2).unwrap();
+        map.insert_all(b"a", [3, 1, 2]).unwrap();
+        // A key that is a prefix of another must not see its values
+        map.insert(b"ab", 4).unwrap();
+        assert_eq!(map.get(b"a"), [1, 2, 3]);
+        assert_eq!(map.get(b"ab"), [4]);
+        assert!(map.contains(b"a", 3));
+
+        assert!(map.remove(b"a", 3).unwrap());
+        assert!(!map.remove(b"a", 3).unwrap());
+        assert_eq!(map.get(b"a"), [1, 2]);
+        assert_eq!(map.remove_all(b"ab").unwrap(), 1);
+        assert!(map.get(b"ab").is_empty());
+
+        map.compact().unwrap();
+        assert_eq!(map.get(b"a"), [1, 2]);
+        assert_eq!(map.database().len(), 2);
+    }
+}
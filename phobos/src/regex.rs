@@ -0,0 +1,62 @@
+use fst::Automaton;
+use regex_automata::{dense, DenseDFA};
+
+use crate::Error;
+
+/// A regular expression over whole keys, for use with [`Database::search`][crate::Database::search].
+///
+/// Matching is anchored at both ends, so `ab+` matches `abb` but not `xabb` or `abbx`. Keys do not
+/// have to be UTF-8, and `(?-u)` can be used to match arbitrary bytes.
+#[derive(Debug, Clone)]
+pub struct Regex(DenseDFA<Vec<usize>, usize>);
+
+impl Regex {
+    /// Compiles `pattern`. This builds a full DFA, so can be slow (and large) for complex patterns.
+    pub fn new(pattern: &str) -> Result<Self, Error> {
+        let dfa = dense::Builder::new().anchored(true).build(pattern)?;
+        Ok(Self(dfa))
+    }
+}
+
+impl Automaton for Regex {
+    type State = usize;
+
+    fn start(&self) -> usize {
+        self.0.start()
+    }
+
+    fn is_match(&self, state: &usize) -> bool {
+        self.0.is_match(state)
+    }
+
+    fn can_match(&self, state: &usize) -> bool {
+        self.0.can_match(state)
+    }
+
+    fn accept(&self, state: &usize, byte: u8) -> usize {
+        self.0.accept(state, byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::Database;
+
+    #[test]
+    fn search() {
+        let mut db = Database::in_memory().unwrap();
+        for key in ["ab", "abb", "xabb", "abbx", "ac"] {
+            db.set(Bytes::from(key), key.len() as u64).unwrap();
+        }
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"abbb"), 4).unwrap();
+
+        let regex = Regex::new("ab+").unwrap();
+        let found = db.search(&regex).map(|(key, _)| key).collect::<Vec<_>>();
+        assert_eq!(found, [&b"ab"[..], b"abb", b"abbb"]);
+        assert!(Regex::new("(").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::path::Path;
+
+use fs_err::File;
+
+use crate::Error;
+
+/// Moves the file just written to `from` into place at `to`, replacing any file there.
+///
+/// This is atomic and durable: once it returns, `to` has the new contents even after a crash or
+/// power loss, and until then it has either the old or the new contents, never a mix of both.
+pub(crate) fn replace(from: &Path, to: &Path) -> Result<(), Error> {
+    // Otherwise the rename could reach the disk before the contents do, leaving an empty or
+    // partial file at `to` after a crash
+    File::open(from)?.sync_all()?;
+    rename(from, to)?;
+    sync_parent(to)
+}
+
+#[cfg(not(windows))]
+fn rename(from: &Path, to: &Path) -> Result<(), Error> {
+    fs_err::rename(from, to)?;
+    Ok(())
+}
+
+/// Renames `from` over `to`, retrying for a while if `to` is in use.
+///
+/// Unlike unix, Windows refuses to replace a file that is open without shared delete access,
+/// which other processes (_e.g._ virus scanners and search indexers) often do briefly.
+#[cfg(windows)]
+fn rename(from: &Path, to: &Path) -> Result<(), Error> {
+    const ATTEMPTS: u32 = 8;
+    let mut delay = std::time::Duration::from_millis(1);
+    for _ in 1..ATTEMPTS {
+        match fs_err::rename(from, to) {
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                log::debug!("{} is in use, retrying the rename in {delay:?}", to.display());
+                std::thread::sleep(delay);
+                delay *= 4;
+            }
+            result => return Ok(result?),
+        }
+    }
+    Ok(fs_err::rename(from, to)?)
+}
+
+/// Syncs the directory holding `path`, which on unix is what makes a rename into it durable.
+/// Windows has no equivalent, as its file systems journal renames themselves.
+fn sync_parent(path: &Path) -> Result<(), Error> {
+    #[cfg(unix)]
+    {
+        let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn replaces() {
+        let dir = TempDir::new().unwrap();
+        let (from, to) = (dir.path().join("from~"), dir.path().join("to"));
+        fs_err::write(&to, b"old").unwrap();
+        fs_err::write(&from, b"new").unwrap();
+        replace(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs_err::read(&to).unwrap(), b"new");
+    }
+
+    #[cfg(feature = "failpoints")]
+    #[test]
+    fn crash_around_index_rename() {
+        use std::sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        };
+
+        use bytes::Bytes;
+
+        use crate::{Database, Failpoint};
+
+        for at in [Failpoint::BeforeIndexRename, Failpoint::AfterIndexRename] {
+            let dir = TempDir::new().unwrap();
+            let crash = Arc::new(AtomicBool::new(false));
+            let hook = crash.clone();
+            let mut db = Database::builder(dir.path().to_owned(), "db".to_owned())
+                .failpoint(move |point| point == at && hook.load(Ordering::Relaxed))
+                .open()
+                .unwrap();
+            db.set(Bytes::from_static(b"a"), 1).unwrap();
+            db.flush().unwrap();
+            db.set(Bytes::from_static(b"a"), 2).unwrap();
+            db.set(Bytes::from_static(b"b"), 3).unwrap();
+            crash.store(true, Ordering::Relaxed);
+            assert!(matches!(db.flush(), Err(Error::Failpoint(point)) if point == at));
+            drop(db);
+
+            // Either the old index is still in place and the log restores the rest, or the new one
+            // is, but never a mix of the two
+            let db = Database::builder(dir.path().to_owned(), "db".to_owned()).open().unwrap();
+            assert_eq!(db.get(b"a"), Some(2), "{at:?}");
+            assert_eq!(db.get(b"b"), Some(3), "{at:?}");
+            assert_eq!(db.distinct_len(), 2, "{at:?}");
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
+
+use log::debug;
+
+use crate::LevelFst;
+
+/// How recently an FST was read, for a [`MemoryBudget`].
+#[derive(Debug)]
+pub(crate) struct Residency {
+    last_access: AtomicU64,
+    /// Whether the FST's pages may be in memory, _i.e._ it has been read since it was last
+    /// released.
+    resident: AtomicBool,
+    /// Whether the FST is mapped from its file, so that its pages can be released and read back
+    /// in. Decompressed or loaded FSTs live in anonymous memory, which would be lost.
+    evictable: bool,
+}
+
+impl Residency {
+    pub(crate) fn new(evictable: bool) -> Self {
+        Self {
+            last_access: AtomicU64::new(0),
+            resident: AtomicBool::new(false),
+            evictable,
+        }
+    }
+}
+
+/// Releases the pages of the least recently read FSTs once those read since their last release
+/// add up to more than a budget, see
+/// [`DatabaseOptions::memory_budget`][crate::DatabaseOptions::memory_budget].
+#[derive(Debug)]
+pub(crate) struct MemoryBudget {
+    max_bytes: u64,
+    clock: AtomicU64,
+    resident_bytes: AtomicU64,
+}
+
+impl MemoryBudget {
+    pub(crate) fn new(max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            clock: AtomicU64::new(0),
+            resident_bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Records that `fst`, one of `fsts`, is about to be read, releasing others if that goes over
+    /// budget.
+    pub(crate) fn touch(&self, fst: &LevelFst, fsts: &[Arc<LevelFst>]) {
+        let residency = &fst.residency;
+        residency.last_access.store(self.clock.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+        if !residency.evictable || residency.resident.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let len = fst_len(fst);
+        if self.resident_bytes.fetch_add(len, Ordering::Relaxed) + len > self.max_bytes {
+            self.trim(fsts);
+        }
+    }
+
+    /// Releases the least recently read FSTs until back within budget, always keeping the most
+    /// recently read.
+    fn trim(&self, fsts: &[Arc<LevelFst>]) {
+        let mut resident = fsts
+            .iter()
+            .filter(|f| f.residency.resident.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        resident.sort_by_key(|f| f.residency.last_access.load(Ordering::Relaxed));
+        resident.pop();
+        for fst in resident {
+            if self.resident_bytes.load(Ordering::Relaxed) <= self.max_bytes {
+                break;
+            }
+            // Another thread may have released it already
+            if fst.residency.resident.swap(false, Ordering::Relaxed) {
+                release(fst);
+                self.resident_bytes.fetch_sub(fst_len(fst), Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Recounts the resident bytes after FSTs have been added or removed.
+    pub(crate) fn recount(&self, fsts: &[Arc<LevelFst>]) {
+        let bytes = fsts
+            .iter()
+            .filter(|f| f.residency.resident.load(Ordering::Relaxed))
+            .map(|f| fst_len(f))
+            .sum();
+        self.resident_bytes.store(bytes, Ordering::Relaxed);
+    }
+}
+
+fn fst_len(fst: &LevelFst) -> u64 {
+    fst.fst.as_fst().as_bytes().len() as u64
+}
+
+fn release(fst: &LevelFst) {
+    debug!("releasing the pages of FST {}", fst.id);
+    #[cfg(unix)]
+    {
+        // SAFETY: The map is a read-only view of the FST's file, so the pages are read back in
+        // from the file the next time they are accessed
+        let released = unsafe { fst.fst.as_fst().as_inner().unchecked_advise(memmap2::UncheckedAdvice::DontNeed) };
+        if let Err(e) = released {
+            log::warn!("failed to release the pages of FST {}: {e}", fst.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use bytes::Bytes;
+
+    use crate::{temp_dir::TempDir, Database};
+
+    fn resident(db: &Database) -> Vec<bool> {
+        db.fsts.iter().map(|f| f.residency.resident.load(Ordering::Relaxed)).collect()
+    }
+
+    #[test]
+    fn memory_budget() {
+        let dir = TempDir::new().unwrap();
+        // Too small for any FST, so only the most recently read one is kept
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned()).memory_budget(1).open().unwrap();
+        for key in [b"a", b"b", b"c"] {
+            db.set(Bytes::from_static(key), key[0].into()).unwrap();
+            db.flush().unwrap();
+        }
+        assert_eq!(resident(&db), [false, false, false]);
+
+        assert_eq!(db.get(b"a"), Some(b'a'.into()));
+        assert_eq!(resident(&db), [true, false, false]);
+        assert_eq!(db.get(b"c"), Some(b'c'.into()));
+        assert_eq!(resident(&db), [false, false, true]);
+        // Released FSTs are read back in from their files
+        assert_eq!(db.get(b"a"), Some(b'a'.into()));
+        assert_eq!(resident(&db), [true, false, false]);
+        let budget = db.memory_budget.as_ref().unwrap();
+        assert_eq!(budget.resident_bytes.load(Ordering::Relaxed), db.fsts[0].fst.as_fst().as_bytes().len() as u64);
+
+        // Merged FSTs start out released
+        db.merge(|_, _| Ok::<_, crate::Error>(())).unwrap();
+        assert_eq!(resident(&db), [false]);
+        assert_eq!(db.memory_budget.as_ref().unwrap().resident_bytes.load(Ordering::Relaxed), 0);
+        assert_eq!(db.get(b"b"), Some(b'b'.into()));
+    }
+
+    #[test]
+    fn load_levels() {
+        let dir = TempDir::new().unwrap();
+        let options = || {
+            Database::builder(dir.path().to_owned(), "db".to_owned())
+                .fanout(2)
+                .load_levels(0)
+                .memory_budget(1)
+        };
+        let mut db = options().open().unwrap();
+        // Enough items for the loaded FST to be above level 0
+        db.bulk_load((0..1000_u64).map(|i| (Bytes::from(format!("k{i:04}")), i))).unwrap();
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        db.flush().unwrap();
+        let check = |db: &Database| {
+            let levels = db.fsts.iter().map(|f| (f.level > 0, f.residency.evictable)).collect::<Vec<_>>();
+            assert_eq!(levels, [(true, true), (false, false)]);
+            assert_eq!(db.get(b"a"), Some(1));
+            assert_eq!(db.get(b"k0999"), Some(999));
+            // Loaded FSTs never count towards the budget
+            assert_eq!(resident(db), [true, false]);
+        };
+        check(&db);
+        drop(db);
+        check(&options().open().unwrap());
+    }
+}
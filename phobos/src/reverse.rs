@@ -0,0 +1,155 @@
+use std::{
+    collections::BTreeSet,
+    io::{BufWriter, Write},
+};
+
+use bytes::Bytes;
+use fs_err::File;
+use fst::{IntoStreamer, MapBuilder, Streamer};
+use log::{debug, warn};
+use memmap2::Mmap;
+
+use crate::{checksum, replace, Database, Error, Pather};
+
+/// A map from values to the keys that held them as of the last full merge, written to
+/// `<prefix>.rev` if [enabled][crate::DatabaseOptions::reverse_index].
+///
+/// Each entry's key is a value (`u64` BE) followed by a key holding it. The empty key instead maps
+/// to the [checksum][checksum::fst_checksum] of the FST that the index was built from, so that
+/// only FSTs written since then need to be scanned.
+#[derive(Debug)]
+pub(crate) struct ReverseIndex {
+    map: fst::Map<Mmap>,
+    covers: u64,
+}
+
+impl ReverseIndex {
+    /// Returns `None` if the index is missing or unreadable, in which case every FST is scanned.
+    pub(crate) fn open(paths: &Pather) -> Option<Self> {
+        let file = File::open(&paths.reverse).ok()?;
+        let map = unsafe { Mmap::map(&file) }.ok().and_then(|map| fst::Map::new(map).ok());
+        let Some(map) = map else {
+            warn!("ignoring unreadable reverse index {}", paths.reverse.display());
+            return None;
+        };
+        let covers = map.get(b"")?;
+        Some(Self { map, covers })
+    }
+}
+
+impl Database {
+    /// Every key whose latest value is `value`, in ascending order.
+    ///
+    /// This uses the reverse index written by the last full [`merge`][Self::merge], so is only
+    /// cheap if few items have been written since. Items written since are found by scanning the
+    /// newer FSTs and the in-memory items in full. Fails with [`Error::NoReverseIndex`] unless
+    /// [`DatabaseOptions::reverse_index`][crate::DatabaseOptions::reverse_index] is enabled.
+    pub fn keys_for(&self, value: u64) -> Result<Vec<Bytes>, Error> {
+        if !self.reverse_index {
+            return Err(Error::NoReverseIndex);
+        }
+        let mut candidates = BTreeSet::new();
+
+        let covered = self.reverse.as_ref().map(|reverse| {
+            let start = value.to_be_bytes();
+            let range = reverse.map.range().ge(start);
+            let mut stream = match value.checked_add(1) {
+                Some(end) => range.lt(end.to_be_bytes()).into_stream(),
+                None => range.into_stream(),
+            };
+            while let Some((k, _)) = stream.next() {
+                candidates.insert(Bytes::copy_from_slice(&k[8..]));
+            }
+            reverse.covers
+        });
+        for f in &self.fsts {
+            if covered.is_some_and(|covers| u64::from(checksum::fst_checksum(f.fst.as_fst().as_bytes())) == covers) {
+                continue;
+            }
+            let mut stream = f.fst.stream();
+            while let Some((k, v)) = stream.next() {
+                if v == value {
+                    candidates.insert(Bytes::copy_from_slice(k));
+                }
+            }
+        }
+        candidates.extend(self.held.iter().filter(|&(_, &v)| v == value).map(|(k, _)| k.clone()));
+
+        // Candidates may have been overwritten since
+        Ok(candidates.into_iter().filter(|key| self.get(key) == Some(value)).collect())
+    }
+
+    /// Rebuilds the reverse index from the single FST left by a full merge.
+    pub(crate) fn rebuild_reverse_index(&mut self) -> Result<(), Error> {
+        if !self.reverse_index {
+            return Ok(());
+        }
+        self.reverse = None;
+        let [fst] = self.fsts.as_slice() else {
+            // Nothing is left, or the merge was not a full one
+            let _ = fs_err::remove_file(&self.paths.reverse);
+            return Ok(());
+        };
+
+        let mut entries = Vec::with_capacity(fst.count as usize);
+        let mut stream = fst.fst.stream();
+        while let Some((k, v)) = stream.next() {
+            let mut entry = Vec::with_capacity(8 + k.len());
+            entry.extend_from_slice(&v.to_be_bytes());
+            entry.extend_from_slice(k);
+            entries.push(entry);
+        }
+        entries.sort_unstable();
+
+        let mut wtr = BufWriter::new(File::create(&self.paths.write_reverse)?);
+        let mut builder = MapBuilder::new(&mut wtr)?;
+        builder.insert(b"", u64::from(checksum::fst_checksum(fst.fst.as_fst().as_bytes())))?;
+        for entry in &entries {
+            builder.insert(entry, 0)?;
+        }
+        builder.finish()?;
+        wtr.flush()?;
+        drop(wtr);
+        replace::replace(&self.paths.write_reverse, &self.paths.reverse)?;
+        debug!("rebuilt {} with {} entries", self.paths.reverse.display(), entries.len());
+
+        self.reverse = ReverseIndex::open(&self.paths);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn keys_for() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned()).reverse_index(true).open().unwrap();
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        db.set(Bytes::from_static(b"b"), 1).unwrap();
+        db.set(Bytes::from_static(b"c"), 2).unwrap();
+        db.merge(|_, _| Ok::<_, Error>(())).unwrap();
+        assert!(db.reverse.is_some());
+
+        // Writes since the merge are found by scanning, including ones that replace indexed values
+        db.set(Bytes::from_static(b"d"), 1).unwrap();
+        db.set(Bytes::from_static(b"b"), 3).unwrap();
+        assert_eq!(db.keys_for(1).unwrap(), [&b"a"[..], b"d"]);
+        assert_eq!(db.keys_for(3).unwrap(), [&b"b"[..]]);
+        assert!(db.keys_for(4).unwrap().is_empty());
+
+        drop(db);
+        let db = Database::builder(dir.path().to_owned(), "db".to_owned()).reverse_index(true).open().unwrap();
+        assert_eq!(db.keys_for(1).unwrap(), [&b"a"[..], b"d"]);
+    }
+
+    #[test]
+    fn not_enabled() {
+        let db = Database::in_memory().unwrap();
+        assert!(matches!(db.keys_for(1), Err(Error::NoReverseIndex)));
+    }
+}
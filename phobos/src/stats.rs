@@ -0,0 +1,113 @@
+/// A summary of a [`Database`][crate::Database]'s FSTs and files, from
+/// [`Database::stats`][crate::Database::stats].
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    /// Each level that has at least one FST, in ascending order.
+    pub levels: Vec<LevelStats>,
+    /// The number of items held in memory, waiting to be written to a level-`0` FST.
+    pub memory_items: usize,
+    /// The approximate memory used by those items' keys and values.
+    pub memory_bytes: usize,
+    /// The current size of the write-ahead log.
+    pub wal_bytes: u64,
+    /// The size of the [value log][crate::DatabaseOptions::value_log], if there is one.
+    pub value_log_bytes: Option<u64>,
+}
+
+/// The FSTs of a single level.
+#[derive(Debug, Clone, Default)]
+pub struct LevelStats {
+    pub level: u8,
+    pub fsts: usize,
+    /// The total number of items in this level's FSTs, including any that are shadowed by newer
+    /// FSTs.
+    pub items: u64,
+    /// The total size of this level's FSTs, before any compression.
+    pub bytes: u64,
+}
+
+impl Stats {
+    /// The total number of FSTs across all levels.
+    pub fn fst_count(&self) -> usize {
+        self.levels.iter().map(|l| l.fsts).sum()
+    }
+
+    /// The total size of all FST files.
+    pub fn fst_bytes(&self) -> u64 {
+        self.levels.iter().map(|l| l.bytes).sum()
+    }
+
+    /// The total size of the FSTs and logs.
+    pub fn total_bytes(&self) -> u64 {
+        self.fst_bytes() + self.wal_bytes + self.value_log_bytes.unwrap_or(0)
+    }
+}
+
+/// What was reclaimed by [`Database::collect_garbage`][crate::Database::collect_garbage].
+#[derive(Debug, Clone, Default)]
+pub struct GcReport {
+    /// The number of superseded or expired entries that were dropped.
+    pub entries_dropped: u64,
+    /// How much smaller the database's [files][Stats::total_bytes] are afterwards.
+    pub bytes_reclaimed: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::{temp_dir::TempDir, Database};
+
+    #[test]
+    fn stats() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned()).open().unwrap();
+        let stats = db.stats().unwrap();
+        assert_eq!((stats.fst_count(), stats.memory_items, stats.wal_bytes, stats.value_log_bytes), (0, 0, 0, None));
+
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        let stats = db.stats().unwrap();
+        assert_eq!((stats.memory_items, stats.memory_bytes), (1, 1 + 8));
+        assert!(stats.wal_bytes > 0);
+
+        db.flush().unwrap();
+        db.set(Bytes::from_static(b"a"), 2).unwrap();
+        db.set(Bytes::from_static(b"b"), 2).unwrap();
+        db.flush().unwrap();
+        // Shadowed items are still counted until a merge
+        let stats = db.stats().unwrap();
+        let levels = stats.levels.iter().map(|l| (l.level, l.fsts, l.items)).collect::<Vec<_>>();
+        assert_eq!(levels, [(0, 2, 3)]);
+        assert_eq!(stats.fst_count(), 2);
+        assert_eq!(stats.fst_bytes(), stats.levels.iter().map(|l| l.bytes).sum::<u64>());
+        assert_eq!(stats.total_bytes(), stats.fst_bytes() + stats.wal_bytes);
+        assert_eq!(stats.memory_items, 0);
+
+        db.merge(|_, _| Ok::<_, crate::Error>(())).unwrap();
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.levels.iter().map(|l| (l.level, l.fsts, l.items)).collect::<Vec<_>>(), [(0, 1, 2)]);
+    }
+
+    #[test]
+    fn collect_garbage() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned()).value_log(true).open().unwrap();
+        for i in 0..4_u8 {
+            db.set_bytes(Bytes::from_static(b"a"), &[i; 1000]).unwrap();
+            db.flush().unwrap();
+        }
+        db.set_bytes(Bytes::from_static(b"b"), &[9; 1000]).unwrap();
+        let before = db.stats().unwrap();
+        let report = db.collect_garbage().unwrap();
+        // Three older values of `a`, all still in the value log until now
+        assert_eq!(report.entries_dropped, 3);
+        assert!(report.bytes_reclaimed >= 3000);
+        assert_eq!(before.total_bytes() - report.bytes_reclaimed, db.stats().unwrap().total_bytes());
+        assert_eq!(db.get_bytes(b"a").unwrap(), Some(vec![3; 1000]));
+        assert_eq!(db.get_bytes(b"b").unwrap(), Some(vec![9; 1000]));
+
+        // Nothing left to collect
+        let report = db.collect_garbage().unwrap();
+        assert_eq!((report.entries_dropped, report.bytes_reclaimed), (0, 0));
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+/// Limits how fast bytes are written through it, sleeping whenever it gets ahead, see
+/// [`DatabaseOptions::merge_rate_limit`][crate::DatabaseOptions::merge_rate_limit].
+pub(crate) struct Throttled<W> {
+    inner: W,
+    /// `None` if not limited.
+    bytes_per_sec: Option<u64>,
+    started: Instant,
+    written: u64,
+}
+
+impl<W> Throttled<W> {
+    pub(crate) fn new(inner: W, bytes_per_sec: Option<u64>) -> Self {
+        Self {
+            inner,
+            bytes_per_sec: bytes_per_sec.map(|rate| rate.max(1)),
+            started: Instant::now(),
+            written: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for Throttled<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(rate) = self.bytes_per_sec {
+            self.written += written as u64;
+            let due = Duration::from_secs_f64(self.written as f64 / rate as f64);
+            if let Some(ahead) = due.checked_sub(self.started.elapsed()) {
+                std::thread::sleep(ahead);
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        time::{Duration, Instant},
+    };
+
+    use bytes::Bytes;
+
+    use super::Throttled;
+    use crate::{temp_dir::TempDir, Database};
+
+    #[test]
+    fn limits_rate() {
+        let started = Instant::now();
+        let mut wtr = Throttled::new(Vec::new(), Some(100_000));
+        for _ in 0..20 {
+            wtr.write_all(&[0; 1000]).unwrap();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(wtr.inner.len(), 20_000);
+
+        let started = Instant::now();
+        let mut wtr = Throttled::new(Vec::new(), None);
+        wtr.write_all(&[0; 1_000_000]).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(200));
+    }
+
+    #[test]
+    fn rate_limited_merge() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned()).merge_rate_limit(10_000).open().unwrap();
+        for i in 0..4_u64 {
+            db.set(Bytes::from(format!("k{i}")), i).unwrap();
+            db.flush().unwrap();
+        }
+        // Flushes are never limited, only merges
+        let started = Instant::now();
+        db.merge(|_, _| Ok::<_, crate::Error>(())).unwrap();
+        let bytes = db.stats().unwrap().fst_bytes();
+        assert!(started.elapsed() >= Duration::from_secs_f64(bytes as f64 / 10_000.0));
+        assert_eq!(db.fsts.len(), 1);
+        assert_eq!(db.get(b"k2"), Some(2));
+    }
+}
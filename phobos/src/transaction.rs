@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+use crate::{Database, Error};
+
+/// A group of writes that are applied to a [`Database`] all at once, from
+/// [`Database::transaction`].
+///
+/// Nothing is written until [`commit`][Self::commit]; dropping a transaction discards its writes.
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    db: &'a mut Database,
+    writes: HashMap<Bytes, u64>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(db: &'a mut Database) -> Self {
+        Self {
+            db,
+            writes: HashMap::new(),
+        }
+    }
+
+    /// Stores `key`->`value` once committed. Later writes to the same key replace earlier ones.
+    pub fn set(&mut self, key: Bytes, value: u64) -> &mut Self {
+        self.writes.insert(self.db.encode_owned_key(key), value);
+        self
+    }
+
+    /// Retrieves the latest value for `key`, including writes made in this transaction.
+    pub fn get(&self, key: &[u8]) -> Option<u64> {
+        self.writes.get(self.db.encode_key(key).as_ref()).copied().or_else(|| self.db.get(key))
+    }
+
+    /// The number of distinct keys written in this transaction.
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Applies every write as a single [batch][Database::set_batch], so that after a crash either
+    /// all or none of them are restored.
+    pub fn commit(self) -> Result<(), Error> {
+        self.db.set_batch(self.writes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::{temp_dir::TempDir, Database};
+
+    #[test]
+    fn commit() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::builder(dir.path().to_owned(), "db".to_owned()).open().unwrap();
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+
+        let mut tx = db.transaction();
+        tx.set(Bytes::from_static(b"a"), 2).set(Bytes::from_static(b"b"), 3);
+        assert_eq!(tx.get(b"a"), Some(2));
+        assert_eq!(tx.len(), 2);
+        drop(tx);
+        assert_eq!(db.get(b"a"), Some(1));
+        assert_eq!(db.get(b"b"), None);
+
+        let mut tx = db.transaction();
+        tx.set(Bytes::from_static(b"a"), 2).set(Bytes::from_static(b"b"), 3);
+        tx.commit().unwrap();
+        assert_eq!(db.get(b"a"), Some(2));
+        assert_eq!(db.get(b"b"), Some(3));
+
+        drop(db);
+        let db = Database::builder(dir.path().to_owned(), "db".to_owned()).open().unwrap();
+        assert_eq!(db.get(b"a"), Some(2));
+        assert_eq!(db.get(b"b"), Some(3));
+    }
+}
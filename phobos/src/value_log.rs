@@ -0,0 +1,110 @@
+use std::{
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use fs_err::{File, OpenOptions};
+
+/// An append-only file of byte values, referred to by their offset.
+///
+/// Each value is stored as its length (`u64` LE) followed by its bytes. A partially-written value
+/// at the end of the file (_e.g._ after a crash) is harmless, as nothing refers to it and it is
+/// simply appended after.
+#[derive(Debug)]
+pub(crate) struct ValueLog {
+    file: File,
+    /// Which file this is, so that compaction can write a new one without touching the old.
+    pub(crate) generation: u64,
+}
+
+impl ValueLog {
+    const HEADER_LEN: usize = 8;
+
+    pub(crate) fn open(at: &Path, generation: u64, create: bool) -> std::io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(create).open(at)?;
+        Ok(Self { file, generation })
+    }
+
+    /// Creates a new, empty value log, replacing anything already at `at`.
+    pub(crate) fn create(at: &Path, generation: u64) -> std::io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(at)?;
+        Ok(Self { file, generation })
+    }
+
+    /// Appends `value`, returning the offset to [`read`][Self::read] it from.
+    pub(crate) fn append(&mut self, value: &[u8]) -> std::io::Result<u64> {
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        Self::encode(&mut self.file, value)?;
+        self.file.flush()?;
+        Ok(offset)
+    }
+
+    /// Writes `value` to `w` in the same format as the value log itself.
+    pub(crate) fn encode(w: &mut impl Write, value: &[u8]) -> std::io::Result<()> {
+        w.write_all(&(value.len() as u64).to_le_bytes())?;
+        w.write_all(value)
+    }
+
+    /// The number of bytes that [`encode`][Self::encode] writes for a value of `len` bytes.
+    pub(crate) fn encoded_len(len: u64) -> u64 {
+        Self::HEADER_LEN as u64 + len
+    }
+
+    pub(crate) fn read(&self, offset: u64) -> std::io::Result<Vec<u8>> {
+        let mut header = [0; Self::HEADER_LEN];
+        read_exact_at(self.file.file(), &mut header, offset)?;
+        let mut value = vec![0; u64::from_le_bytes(header) as usize];
+        read_exact_at(self.file.file(), &mut value, offset + Self::HEADER_LEN as u64)?;
+        Ok(value)
+    }
+
+    /// The length of the value at `offset`, without reading the value itself.
+    pub(crate) fn value_len(&self, offset: u64) -> std::io::Result<u64> {
+        let mut header = [0; Self::HEADER_LEN];
+        read_exact_at(self.file.file(), &mut header, offset)?;
+        Ok(u64::from_le_bytes(header))
+    }
+
+    /// Appends exactly `len` bytes of already-encoded values from `r`.
+    pub(crate) fn append_raw(&mut self, r: &mut impl Read, len: u64) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::End(0))?;
+        if std::io::copy(&mut r.take(len), &mut self.file)? != len {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        self.file.flush()
+    }
+
+    pub(crate) fn len(&self) -> std::io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /// Removes every value.
+    pub(crate) fn truncate(&mut self) -> std::io::Result<()> {
+        self.file.set_len(0)
+    }
+
+    pub(crate) fn sync(&self) -> std::io::Result<()> {
+        self.file.sync_all()
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &std::fs::File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    while !buf.is_empty() {
+        match std::os::windows::fs::FileExt::seek_read(file, buf, offset) {
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,245 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use fst::Automaton;
+
+use crate::{Database, Error, MergeAction, Snapshot};
+
+/// The history of a database with [versioning][crate::DatabaseOptions::versions] enabled.
+///
+/// The history is itself a database, mapping each key followed by the bitwise NOT of a sequence
+/// number (`u64` BE) to the value written at that sequence number, so that a key's versions sort
+/// newest first. As these are always at least 8 bytes long, shorter keys hold metadata.
+#[derive(Debug)]
+pub(crate) struct Versions {
+    history: Box<Database>,
+    sequence: u64,
+    oldest: u64,
+    horizon: u64,
+}
+
+/// The sequence number of the latest write.
+const LATEST: &[u8] = &[0];
+/// The oldest sequence number that can still be read.
+const OLDEST: &[u8] = &[1];
+
+impl Versions {
+    pub(crate) fn new(history: Database, horizon: u64) -> Self {
+        Self {
+            sequence: history.get(LATEST).unwrap_or(0),
+            oldest: history.get(OLDEST).unwrap_or(0),
+            history: Box::new(history),
+            horizon,
+        }
+    }
+
+    pub(crate) fn reload(&mut self) -> Result<(), Error> {
+        self.history.reload()?;
+        self.sequence = self.history.get(LATEST).unwrap_or(0);
+        self.oldest = self.history.get(OLDEST).unwrap_or(0);
+        Ok(())
+    }
+
+    pub(crate) fn close(self) -> Result<(), Error> {
+        self.history.close()
+    }
+}
+
+impl Database {
+    /// The sequence number of the latest write, or `0` if there has been none since
+    /// [versioning][crate::DatabaseOptions::versions] was enabled (or it is not enabled).
+    pub fn sequence(&self) -> u64 {
+        self.versions.as_ref().map_or(0, |v| v.sequence)
+    }
+
+    /// Retrieves the value `key` had just after the write with sequence number `sequence`.
+    ///
+    /// Only writes made while versioning was enabled are visible. Fails with
+    /// [`Error::NoVersions`] if it is not enabled, and with [`Error::VersionCompacted`] if
+    /// `sequence` is older than the [horizon][crate::DatabaseOptions::versions] as of the last
+    /// full merge.
+    pub fn get_at(&self, key: &[u8], sequence: u64) -> Result<Option<u64>, Error> {
+        let versions = self.readable_versions(sequence)?;
+        let key = self.encode_key(key);
+        let key = key.as_ref();
+        let prefix = Prefix(key);
+        let mut entries = versions.history.search(&prefix);
+        Ok(entries.find_map(|(k, value)| match split_versioned(&k) {
+            Some((base, at)) if base == key && at <= sequence => Some(value),
+            _ => None,
+        }))
+    }
+
+    /// A [`Snapshot`] of every key as it was just after the write with sequence number
+    /// `sequence`, with the same restrictions as [`get_at`][Self::get_at].
+    ///
+    /// Unlike [`snapshot`][Self::snapshot], this reads the whole history and holds the result in
+    /// memory.
+    pub fn snapshot_at(&self, sequence: u64) -> Result<Snapshot, Error> {
+        let versions = self.readable_versions(sequence)?;
+        let mut held = HashMap::new();
+        for (k, value) in versions.history.iter() {
+            if let Some((base, at)) = split_versioned(&k) {
+                // The first version seen of each key that is old enough is its newest
+                if at <= sequence && !held.contains_key(base) {
+                    held.insert(k.slice(..base.len()), value);
+                }
+            }
+        }
+        Ok(Snapshot {
+            fsts: Vec::new(),
+            held: Arc::new(held),
+        })
+    }
+
+    fn readable_versions(&self, sequence: u64) -> Result<&Versions, Error> {
+        let versions = self.versions.as_ref().ok_or(Error::NoVersions)?;
+        if sequence < versions.oldest {
+            return Err(Error::VersionCompacted(sequence));
+        }
+        Ok(versions)
+    }
+
+    /// Records `items` in the history, each with the next sequence number, before they are
+    /// written.
+    pub(crate) fn record_versions(&mut self, items: &[(Bytes, u64)]) -> Result<(), Error> {
+        let Some(versions) = &mut self.versions else {
+            return Ok(());
+        };
+        let mut records = Vec::with_capacity(items.len() + 1);
+        for (key, value) in items {
+            versions.sequence += 1;
+            records.push((versioned_key(key, versions.sequence), *value));
+        }
+        records.push((Bytes::from_static(LATEST), versions.sequence));
+        versions.history.set_batch(records)
+    }
+
+    /// Drops every version that is older than the horizon and has been overwritten since.
+    pub(crate) fn prune_versions(&mut self) -> Result<(), Error> {
+        let Some(versions) = &mut self.versions else {
+            return Ok(());
+        };
+        let cutoff = versions.sequence.saturating_sub(versions.horizon);
+        if cutoff <= versions.oldest {
+            return Ok(());
+        }
+        // Raised first, so that versions are never readable once they may be gone
+        versions.history.set(Bytes::from_static(OLDEST), cutoff)?;
+        versions.oldest = cutoff;
+
+        // The newest version of each key at or before the cutoff is kept, as it is still the
+        // value at the cutoff
+        let mut previous = Bytes::new();
+        let mut kept_old = false;
+        versions.history.merge_matching(
+            |k| k.len() >= 8,
+            |k, _| {
+                let Some((base, at)) = split_versioned(&k) else {
+                    return Ok::<_, Error>(MergeAction::Keep);
+                };
+                if base != previous {
+                    previous = k.slice(..base.len());
+                    kept_old = false;
+                }
+                if at > cutoff {
+                    Ok(MergeAction::Keep)
+                } else if !kept_old {
+                    kept_old = true;
+                    Ok(MergeAction::Keep)
+                } else {
+                    Ok(MergeAction::Drop)
+                }
+            },
+        )
+    }
+}
+
+fn versioned_key(key: &[u8], sequence: u64) -> Bytes {
+    let mut versioned = BytesMut::with_capacity(key.len() + 8);
+    versioned.put_slice(key);
+    versioned.put_u64(!sequence);
+    versioned.freeze()
+}
+
+fn split_versioned(versioned: &[u8]) -> Option<(&[u8], u64)> {
+    let (key, sequence) = versioned.split_at_checked(versioned.len().checked_sub(8)?)?;
+    Some((key, !u64::from_be_bytes(sequence.try_into().ok()?)))
+}
+
+/// Matches every key starting with the given bytes.
+pub(crate) struct Prefix<'a>(pub(crate) &'a [u8]);
+
+impl Automaton for Prefix<'_> {
+    /// How much of the prefix has been matched, or `None` once it cannot be.
+    type State = Option<usize>;
+
+    fn start(&self) -> Self::State {
+        Some(0)
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        *state == Some(self.0.len())
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        state.is_some()
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        self.is_match(state)
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        match *state {
+            Some(i) if i == self.0.len() => Some(i),
+            Some(i) if self.0[i] == byte => Some(i + 1),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::{temp_dir::TempDir, Database, Error};
+
+    fn open(dir: &TempDir) -> Database {
+        Database::builder(dir.path().to_owned(), "db".to_owned()).versions(1).open().unwrap()
+    }
+
+    #[test]
+    fn get_at() {
+        let dir = TempDir::new().unwrap();
+        let mut db = open(&dir);
+        db.set(Bytes::from_static(b"a"), 1).unwrap();
+        db.set_batch([(Bytes::from_static(b"a"), 2), (Bytes::from_static(b"b"), 3)]).unwrap();
+        assert_eq!(db.sequence(), 3);
+        assert_eq!(db.get_at(b"a", 1).unwrap(), Some(1));
+        assert_eq!(db.get_at(b"a", 2).unwrap(), Some(2));
+        assert_eq!(db.get_at(b"b", 2).unwrap(), None);
+        let snapshot = db.snapshot_at(1).unwrap();
+        assert_eq!(snapshot.get(b"a"), Some(1));
+        assert_eq!(snapshot.get(b"b"), None);
+
+        // The history survives a reopen
+        drop(db);
+        let mut db = open(&dir);
+        assert_eq!(db.sequence(), 3);
+        assert_eq!(db.get_at(b"a", 1).unwrap(), Some(1));
+
+        // A full merge drops versions older than the horizon
+        db.set(Bytes::from_static(b"a"), 4).unwrap();
+        db.merge(|_, _| Ok::<_, Error>(())).unwrap();
+        assert!(matches!(db.get_at(b"a", 1), Err(Error::VersionCompacted(1))));
+        assert_eq!(db.get_at(b"a", 3).unwrap(), Some(2));
+        assert_eq!(db.get_at(b"a", 4).unwrap(), Some(4));
+    }
+
+    #[test]
+    fn not_enabled() {
+        let db = Database::in_memory().unwrap();
+        assert!(matches!(db.get_at(b"a", 0), Err(Error::NoVersions)));
+    }
+}
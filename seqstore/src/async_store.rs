@@ -0,0 +1,216 @@
+use std::{
+    fmt::{Debug, Formatter},
+    mem,
+    sync::{Arc, Mutex, PoisonError, RwLock},
+};
+
+use tokio::sync::oneshot;
+
+use crate::{error::Error, raw_store::RawStore, Id};
+
+/// An async wrapper around a [`RawStore`], which runs every operation on tokio's blocking thread
+/// pool.
+///
+/// Concurrent [`add`][Self::add]s are written together as a single [batch][RawStore::batch], so
+/// that they share a round of flushes. Reads share the store, but wait for any change in progress.
+#[derive(Clone)]
+pub struct AsyncRawStore {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    store: RwLock<RawStore>,
+    pending: Mutex<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+    adds: Vec<PendingAdd>,
+    /// Whether a blocking task is currently writing batches.
+    writing: bool,
+}
+
+struct PendingAdd {
+    bytes: Vec<u8>,
+    done: oneshot::Sender<Result<Id, Error>>,
+}
+
+impl AsyncRawStore {
+    pub fn new(store: RawStore) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                store: RwLock::new(store),
+                pending: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Stores `bytes`, as [`RawStore::add`].
+    ///
+    /// This returns once the item has been written, which may be together with items added by
+    /// other tasks.
+    pub async fn add(&self, bytes: Vec<u8>) -> Result<Id, Error> {
+        let (done, receiver) = oneshot::channel();
+        let start_writing = {
+            let mut pending = self.inner.pending.lock().unwrap_or_else(PoisonError::into_inner);
+            pending.adds.push(PendingAdd { bytes, done });
+            !mem::replace(&mut pending.writing, true)
+        };
+        if start_writing {
+            let inner = Arc::clone(&self.inner);
+            tokio::task::spawn_blocking(move || inner.write_pending());
+        }
+        receiver.await.expect("seqstore writer task panicked")
+    }
+
+    /// Gets a copy of the data stored at `at`, as [`RawStore::get`].
+    pub async fn get(&self, at: Id) -> Result<Vec<u8>, Error> {
+        self.read(move |store| store.get(at, <[u8]>::to_vec)).await
+    }
+
+    /// Removes the data at `at`, as [`RawStore::remove`].
+    pub async fn remove(&self, at: Id) -> Result<(), Error> {
+        self.write(move |store| store.remove(at, |_| ())).await
+    }
+
+    /// Flushes every change not yet flushed, as [`RawStore::flush`].
+    pub async fn flush(&self) -> Result<(), Error> {
+        self.write(RawStore::flush).await
+    }
+
+    /// Runs `f` with shared access to the store on the blocking thread pool, for anything not
+    /// covered by the other methods.
+    pub async fn read<T: Send + 'static>(&self, f: impl FnOnce(&RawStore) -> T + Send + 'static) -> T {
+        let inner = Arc::clone(&self.inner);
+        blocking(move || f(&inner.store.read().unwrap_or_else(PoisonError::into_inner))).await
+    }
+
+    /// Runs `f` with exclusive access to the store on the blocking thread pool, for anything not
+    /// covered by the other methods.
+    pub async fn write<T: Send + 'static>(&self, f: impl FnOnce(&mut RawStore) -> T + Send + 'static) -> T {
+        let inner = Arc::clone(&self.inner);
+        blocking(move || f(&mut inner.store.write().unwrap_or_else(PoisonError::into_inner))).await
+    }
+}
+
+impl Inner {
+    /// Writes batches of pending adds until there are none left.
+    fn write_pending(&self) {
+        let _guard = WritingGuard(&self.pending);
+        loop {
+            let adds = {
+                let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+                if pending.adds.is_empty() {
+                    pending.writing = false;
+                    return;
+                }
+                mem::take(&mut pending.adds)
+            };
+
+            let mut store = self.store.write().unwrap_or_else(PoisonError::into_inner);
+            let mut batch = store.batch();
+            for add in &adds {
+                batch.add(&add.bytes);
+            }
+            match batch.commit() {
+                Ok(ids) => {
+                    for (add, id) in adds.into_iter().zip(ids) {
+                        // The task waiting for this may have been cancelled
+                        let _ = add.done.send(Ok(id));
+                    }
+                }
+                Err(_) => {
+                    // A failed batch removes the items it had already added, so each add is
+                    // retried on its own, and every caller gets its own result without anything
+                    // being stored twice
+                    for add in adds {
+                        let _ = add.done.send(store.add(&add.bytes));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Lets a later add start a new writer task if this one panics.
+struct WritingGuard<'a>(&'a Mutex<Pending>);
+
+impl Drop for WritingGuard<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.0.lock().unwrap_or_else(PoisonError::into_inner).writing = false;
+        }
+    }
+}
+
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => panic!("seqstore blocking task failed: {e}"),
+    }
+}
+
+impl Debug for AsyncRawStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncRawStore").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Backing;
+
+    #[test]
+    fn concurrent_adds() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let store = AsyncRawStore::new(RawStore::options().new(Backing::new_anon().unwrap()).unwrap());
+        runtime.block_on(async {
+            let adds = (0..20_u8).map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move { (i, store.add(vec![i; 10]).await.unwrap()) })
+            });
+            let mut ids = Vec::new();
+            for add in adds.collect::<Vec<_>>() {
+                ids.push(add.await.unwrap());
+            }
+            for (i, id) in &ids {
+                assert_eq!(store.get(*id).await.unwrap(), [*i; 10]);
+            }
+            store.remove(ids[0].1).await.unwrap();
+            assert!(matches!(store.remove(ids[0].1).await, Err(Error::AlreadyDeleted { .. })));
+            store.flush().await.unwrap();
+            assert_eq!(store.read(|s| s.stats().entries).await, 19);
+        });
+    }
+
+    #[cfg(feature = "failpoints")]
+    #[test]
+    fn failed_batch() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let failpoints = crate::Failpoints::new();
+        let backing = Backing::new_anon().unwrap().failpoints(&failpoints);
+        let store = AsyncRawStore::new(RawStore::options().new(backing).unwrap());
+        failpoints.fail_resize(0);
+        runtime.block_on(async {
+            // Only the largest add needs the backing to grow, so fails if it is on its own, and
+            // fails the whole batch otherwise
+            let adds = (0..6_usize).map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move { store.add(vec![1; if i == 3 { 10_000 } else { 10 }]).await })
+            });
+            let mut added = 0;
+            for add in adds.collect::<Vec<_>>() {
+                if let Ok(id) = add.await.unwrap() {
+                    assert_eq!(store.get(id).await.unwrap().len() % 10, 0);
+                    added += 1;
+                }
+            }
+            assert!(added >= 5);
+            // Nothing from a failed batch is left behind
+            assert_eq!(store.read(|s| s.stats().entries).await, added);
+            store.write(|s| s.verify()).await.unwrap();
+        });
+    }
+}
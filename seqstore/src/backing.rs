@@ -7,12 +7,109 @@ use crate::error::Error;
 
 /// The underlying storage used by [`RawStore`][crate::raw_store::RawStore].
 ///
-/// Can either be an anonymous map (just in memory), or a file-backed map.
+/// Can either be an anonymous map (just in memory), or a file-backed map. Stores can also be opened
+/// (but not changed) from a [read-only file][Self::open_file_readonly] or a
+/// [`'static` buffer][Self::from_slice].
 pub struct Backing(pub(crate) BackingInner);
 
-pub(crate) enum BackingInner {
+pub(crate) struct BackingInner {
+    map: Map,
+    pub(crate) growth: GrowthPolicy,
+    map_options: MapOptions,
+    /// Whether every flush also syncs the file, see
+    /// [`OpenStoreOptions::strict_durability`][crate::raw_store::OpenStoreOptions::strict_durability].
+    pub(crate) sync_file: bool,
+    /// See [`Backing::failpoints`].
+    #[cfg(feature = "failpoints")]
+    pub(crate) failpoints: Option<crate::Failpoints>,
+}
+
+enum Map {
     File { file: File, map: memmap2::MmapMut },
     Anon(memmap2::MmapMut),
+    ReadOnly(memmap2::Mmap),
+    Static(&'static [u8]),
+}
+
+/// How a [`Backing`] grows when it runs out of space, set with
+/// [`OpenStoreOptions::growth_policy`][crate::raw_store::OpenStoreOptions::growth_policy].
+///
+/// Every time it grows, a file-backed [`Backing`] has to resize the file and remap it, so growing
+/// in larger steps makes adding items to the end of a store cheaper, at the cost of space that is
+/// not used yet. The backing is only ever shrunk back down by
+/// [`truncate_to_end`][crate::raw_store::RawStore::truncate_to_end] (and so also on close).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum GrowthPolicy {
+    /// Grow to the next multiple of this many bytes.
+    Chunk(usize),
+    /// At least double in size, so that the number of times the backing grows is logarithmic in
+    /// its size.
+    Double,
+    /// As for [`Chunk`][Self::Chunk], but also reserve the space on disk when file-backed, so that
+    /// writing to it later does not fail (with a `SIGBUS`) if the disk fills up in the meantime.
+    ///
+    /// The space is reserved with `posix_fallocate` on Unix, and not at all elsewhere.
+    Allocate(usize),
+}
+
+impl Default for GrowthPolicy {
+    fn default() -> Self {
+        Self::Chunk(256)
+    }
+}
+
+/// How the OS should treat the memory map of a [`Backing`], set with [`Backing::map_options`].
+///
+/// These are applied again whenever the backing grows, so cover the whole of it. They are only
+/// supported on Unix, and ignored elsewhere (as well as for [`Backing::from_slice`]).
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct MapOptions {
+    advice: Option<MapAdvice>,
+    populate: bool,
+    lock: bool,
+}
+
+impl MapOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tells the OS how the backing is going to be read, so it can read ahead accordingly.
+    pub fn advice(self, advice: MapAdvice) -> Self {
+        Self {
+            advice: Some(advice),
+            ..self
+        }
+    }
+
+    /// Reads the whole backing in up front, rather than as each part of it is first used.
+    pub fn populate(self, populate: bool) -> Self {
+        Self { populate, ..self }
+    }
+
+    /// Keeps the whole backing in memory, rather than letting the OS page it out.
+    ///
+    /// This is limited by `RLIMIT_MEMLOCK`, past which applying this (or growing the backing)
+    /// fails with [`Error::MapOptions`].
+    pub fn lock(self, lock: bool) -> Self {
+        Self { lock, ..self }
+    }
+}
+
+/// Advice on how a [`Backing`] is going to be read, see [`MapOptions::advice`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum MapAdvice {
+    /// No particular pattern, the default.
+    Normal,
+    /// Items are read in no particular order, so reading ahead is wasted (_e.g._ for stores of
+    /// large items, read by [`Id`][crate::Id]).
+    Random,
+    /// The backing is read from start to end, _e.g._ by [`iter`][crate::raw_store::RawStore::iter].
+    Sequential,
+    /// The whole backing is going to be read soon, so should be read ahead of time.
+    WillNeed,
 }
 
 impl std::fmt::Debug for Backing {
@@ -23,9 +120,11 @@ impl std::fmt::Debug for Backing {
 
 impl std::fmt::Debug for BackingInner {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            BackingInner::File { .. } => f.debug_struct("BackingFile").finish_non_exhaustive(),
-            BackingInner::Anon(_) => f.debug_struct("BackingAnon").finish_non_exhaustive(),
+        match self.map {
+            Map::File { .. } => f.debug_struct("BackingFile").finish_non_exhaustive(),
+            Map::Anon(_) => f.debug_struct("BackingAnon").finish_non_exhaustive(),
+            Map::ReadOnly(_) => f.debug_struct("BackingReadOnly").finish_non_exhaustive(),
+            Map::Static(_) => f.debug_struct("BackingStatic").finish_non_exhaustive(),
         }
     }
 }
@@ -34,18 +133,25 @@ impl Deref for BackingInner {
     type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
-        match self {
-            BackingInner::File { map, .. } => map,
-            BackingInner::Anon(map) => map,
+        match &self.map {
+            Map::File { map, .. } => map,
+            Map::Anon(map) => map,
+            Map::ReadOnly(map) => map,
+            Map::Static(bytes) => bytes,
         }
     }
 }
 
 impl DerefMut for BackingInner {
+    /// # Panics
+    ///
+    /// If the backing is read-only, which [`RawStore`][crate::raw_store::RawStore] checks for
+    /// before making any changes.
     fn deref_mut(&mut self) -> &mut Self::Target {
-        match self {
-            BackingInner::File { map, .. } => map,
-            BackingInner::Anon(map) => map,
+        match &mut self.map {
+            Map::File { map, .. } => map,
+            Map::Anon(map) => map,
+            Map::ReadOnly(_) | Map::Static(_) => panic!("cannot write to a read-only backing"),
         }
     }
 }
@@ -79,7 +185,47 @@ impl Backing {
     /// interfaces) do not provide ways to hold onto the backing bytes.
     pub unsafe fn new_file(file: File) -> Result<Self, Error> {
         let map = unsafe { memmap2::MmapMut::map_mut(&file).map_err(Error::Map)? };
-        Ok(Self(BackingInner::File { map, file }))
+        Ok(Self::from_map(Map::File { map, file }))
+    }
+
+    /// Initializes a file-backed mapping, first growing the file to at least `capacity` bytes.
+    ///
+    /// This avoids growing the file bit by bit as a new store fills up, which means resizing and
+    /// remapping it each time (see [`GrowthPolicy`]). The extra space is not kept once the store is
+    /// [truncated][crate::raw_store::RawStore::truncate_to_end].
+    ///
+    /// # Safety
+    ///
+    /// See [`new_file`][Self::new_file].
+    pub unsafe fn new_file_with_capacity(file: File, capacity: usize) -> Result<Self, Error> {
+        if file.metadata().map_err(Error::Resize)?.len() < capacity as u64 {
+            file.set_len(capacity as u64).map_err(Error::Resize)?;
+        }
+        unsafe { Self::new_file(file) }
+    }
+
+    /// Initializes a read-only file-backed mapping, for opening a store without being able to
+    /// change it.
+    ///
+    /// The file only needs to have been opened for reading. Any attempt to change a store opened
+    /// from this returns [`Error::ReadOnly`], and the store is never
+    /// [recovered][crate::raw_store::RecoveryStrategy] or upgraded when opened.
+    ///
+    /// # Safety
+    ///
+    /// See [`new_file`][Self::new_file].
+    pub unsafe fn open_file_readonly(file: File) -> Result<Self, Error> {
+        let map = unsafe { memmap2::Mmap::map(&file).map_err(Error::Map)? };
+        Ok(Self::from_map(Map::ReadOnly(map)))
+    }
+
+    /// Uses `bytes` as a read-only backing without copying them, _e.g._ for a store embedded with
+    /// [`include_bytes!`].
+    ///
+    /// As with [`open_file_readonly`][Self::open_file_readonly], a store opened from this cannot be
+    /// changed.
+    pub fn from_slice(bytes: &'static [u8]) -> Self {
+        Self::from_map(Map::Static(bytes))
     }
 
     /// Initializes an in-memory mapping.
@@ -87,7 +233,7 @@ impl Backing {
     /// Note that this uses an [anonymous memory map][memmap2::MmapMut::map_anon] and not a [`Vec<u8>`][std::vec::Vec]
     /// or similar.
     pub fn new_anon() -> Result<Self, Error> {
-        Ok(Self(BackingInner::Anon(memmap2::MmapMut::map_anon(256).map_err(Error::Map)?)))
+        Ok(Self::from_map(Map::Anon(memmap2::MmapMut::map_anon(256).map_err(Error::Map)?)))
     }
 
     /// Initializes an in-memory mapping containing exactly the contents of `b`.
@@ -96,7 +242,25 @@ impl Backing {
     pub fn new_from_buffer(b: &[u8]) -> Result<Self, Error> {
         let mut m = memmap2::MmapMut::map_anon(b.len()).map_err(Error::Map)?;
         m[..b.len()].copy_from_slice(b);
-        Ok(Self(BackingInner::Anon(m)))
+        Ok(Self::from_map(Map::Anon(m)))
+    }
+
+    fn from_map(map: Map) -> Self {
+        Self(BackingInner {
+            map,
+            growth: GrowthPolicy::default(),
+            map_options: MapOptions::default(),
+            sync_file: false,
+            #[cfg(feature = "failpoints")]
+            failpoints: None,
+        })
+    }
+
+    /// Sets how the OS should treat the memory map, see [`MapOptions`].
+    pub fn map_options(mut self, options: MapOptions) -> Result<Self, Error> {
+        self.0.map_options = options;
+        self.0.apply_map_options()?;
+        Ok(self)
     }
 }
 
@@ -112,36 +276,121 @@ impl BackingInner {
 
     /// Ensures there is enough space. Will not truncate.
     pub(crate) fn resize_for(&mut self, len: usize) -> Result<(), Error> {
-        if self.len() <= len {
-            self.resize_to(((len / 256) + 1) * 256)?;
+        if self.len() > len {
+            return Ok(());
+        }
+        let chunk = |chunk: usize| ((len / chunk.max(1)) + 1) * chunk.max(1);
+        match self.growth {
+            GrowthPolicy::Chunk(n) => self.resize_to(chunk(n)),
+            GrowthPolicy::Double => self.resize_to(chunk(256).max(self.len() * 2)),
+            GrowthPolicy::Allocate(n) => {
+                let old = self.len();
+                let size = chunk(n);
+                self.allocate(old, size)?;
+                self.resize_to(size)
+            }
+        }
+    }
+
+    /// Reserves disk space for `start..end` of the file, if file-backed.
+    #[cfg(unix)]
+    fn allocate(&mut self, start: usize, end: usize) -> Result<(), Error> {
+        use std::os::fd::AsRawFd;
+
+        let Map::File { file, .. } = &self.map else {
+            return Ok(());
+        };
+        match unsafe { libc::posix_fallocate(file.as_raw_fd(), start as _, (end - start) as _) } {
+            0 => Ok(()),
+            e => Err(Error::Resize(std::io::Error::from_raw_os_error(e))),
         }
+    }
+
+    #[cfg(not(unix))]
+    fn allocate(&mut self, _start: usize, _end: usize) -> Result<(), Error> {
         Ok(())
     }
 
+    /// Whether this can be changed, see [`Backing::open_file_readonly`].
+    pub(crate) fn is_writable(&self) -> bool {
+        matches!(self.map, Map::File { .. } | Map::Anon(_))
+    }
+
     /// Sets the size. This will truncate.
-    fn resize_to(&mut self, size: usize) -> Result<(), Error> {
-        match self {
-            BackingInner::File { file, map } => {
+    pub(crate) fn resize_to(&mut self, size: usize) -> Result<(), Error> {
+        #[cfg(feature = "failpoints")]
+        if let Some(failpoints) = &self.failpoints {
+            failpoints.resize(size)?;
+        }
+        match &mut self.map {
+            Map::File { file, map } => {
                 file.set_len(size as u64).map_err(Error::Resize)?;
                 unsafe { map.remap(size, memmap2::RemapOptions::new().may_move(true)).map_err(Error::Resize)? };
             }
-            BackingInner::Anon(map) => {
+            Map::Anon(map) => {
                 unsafe { map.remap(size, memmap2::RemapOptions::new().may_move(true)).map_err(Error::Resize)? };
             }
+            Map::ReadOnly(_) | Map::Static(_) => return Err(Error::ReadOnly),
         }
+        self.apply_map_options()
+    }
+
+    #[cfg(unix)]
+    fn apply_map_options(&self) -> Result<(), Error> {
+        use memmap2::Advice;
+
+        fn apply(map: &impl MapExt, options: MapOptions) -> std::io::Result<()> {
+            if let Some(advice) = options.advice {
+                map.advise(match advice {
+                    MapAdvice::Normal => Advice::Normal,
+                    MapAdvice::Random => Advice::Random,
+                    MapAdvice::Sequential => Advice::Sequential,
+                    MapAdvice::WillNeed => Advice::WillNeed,
+                })?;
+            }
+            if options.lock {
+                map.lock()?;
+            }
+            Ok(())
+        }
+
+        let options = self.map_options;
+        let applied = match &self.map {
+            Map::File { map, .. } | Map::Anon(map) => apply(map, options),
+            Map::ReadOnly(map) => apply(map, options),
+            Map::Static(_) => return Ok(()),
+        };
+        applied.map_err(Error::MapOptions)?;
+        if options.populate {
+            // Touching every page faults it in, which works on every kind of map and every kernel
+            for page in self.chunks(4096) {
+                std::hint::black_box(page[0]);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply_map_options(&self) -> Result<(), Error> {
         Ok(())
     }
 
-    fn map(&self) -> &memmap2::MmapMut {
-        match self {
-            BackingInner::File { map, .. } => map,
-            BackingInner::Anon(map) => map,
+    /// The map to flush, if it can have been changed.
+    fn map(&self) -> Option<&memmap2::MmapMut> {
+        match &self.map {
+            Map::File { map, .. } => Some(map),
+            Map::Anon(map) => Some(map),
+            Map::ReadOnly(_) | Map::Static(_) => None,
         }
     }
 
     pub(crate) fn flush(&mut self) -> Result<(), Error> {
-        self.map().flush().map_err(Error::Flush)?;
-        Ok(())
+        #[cfg(feature = "failpoints")]
+        self.fail_flush(0, self.len())?;
+        if let Some(map) = self.map() {
+            map.flush().map_err(Error::Flush)?;
+        }
+        self.sync_file()
     }
 
     pub(crate) fn flush_start_end(&mut self, start: usize, end: usize) -> Result<(), Error> {
@@ -149,11 +398,56 @@ impl BackingInner {
         if start == end {
             return Ok(());
         }
-        self.map().flush_range(start, end - start).map_err(Error::Flush)?;
-        Ok(())
+        self.flush_range(start, end - start)
     }
 
     pub(crate) fn flush_range(&mut self, start: usize, length: usize) -> Result<(), Error> {
-        self.map().flush_range(start, length).map_err(Error::Flush)
+        #[cfg(feature = "failpoints")]
+        self.fail_flush(start, start + length)?;
+        if let Some(map) = self.map() {
+            map.flush_range(start, length).map_err(Error::Flush)?;
+        }
+        self.sync_file()
+    }
+
+    /// Syncs the file itself as well as the map, if asked to.
+    ///
+    /// Flushing the map only writes back its pages, which on some systems leaves the file's
+    /// metadata (such as its length, after growing) to be written at some later point.
+    fn sync_file(&self) -> Result<(), Error> {
+        match &self.map {
+            Map::File { file, .. } if self.sync_file => file.sync_data().map_err(Error::Flush),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The parts of [`memmap2::MmapMut`] and [`memmap2::Mmap`] used by [`MapOptions`].
+#[cfg(unix)]
+trait MapExt {
+    fn advise(&self, advice: memmap2::Advice) -> std::io::Result<()>;
+
+    fn lock(&self) -> std::io::Result<()>;
+}
+
+#[cfg(unix)]
+impl MapExt for memmap2::MmapMut {
+    fn advise(&self, advice: memmap2::Advice) -> std::io::Result<()> {
+        self.advise(advice)
+    }
+
+    fn lock(&self) -> std::io::Result<()> {
+        self.lock()
+    }
+}
+
+#[cfg(unix)]
+impl MapExt for memmap2::Mmap {
+    fn advise(&self, advice: memmap2::Advice) -> std::io::Result<()> {
+        self.advise(advice)
+    }
+
+    fn lock(&self) -> std::io::Result<()> {
+        self.lock()
     }
 }
@@ -0,0 +1,146 @@
+use std::fmt::{Debug, Formatter};
+
+use chacha20poly1305::{
+    aead::{AeadCore, AeadInPlace, KeyInit, OsRng},
+    Key, Tag, XChaCha20Poly1305, XNonce,
+};
+
+use crate::{error::Error, raw_store::RawStore, Id};
+
+// Every item is stored as a random 24-byte nonce, followed by the encrypted data and then its
+// 16-byte authentication tag. The nonce cannot be derived from the item's position and generation
+// instead: the position is only chosen once the item is being written, and the generation is only
+// 16 bits, so the same pair comes round again in a store that keeps reusing the same gaps - and
+// reusing a nonce with the same key gives away both items. Nonces this long can safely be random.
+
+/// The bytes each item takes up in the underlying store on top of its data.
+const OVERHEAD: usize = 24 + 16;
+
+/// A [`RawStore`] that encrypts every item it holds, using XChaCha20-Poly1305 with a single key for
+/// the whole store.
+///
+/// Each item is encrypted and authenticated on its own, so an item that has been changed, or was
+/// written with another key, fails to be read with [`Error::Decrypt`] rather than giving back the
+/// wrong data. Everything else about the store (which positions are used, and how long each item is)
+/// is left as it is, as is anything in its header.
+///
+/// Only items added through this wrapper are encrypted, so a store should be used either entirely
+/// through it, or not at all. Each item takes up 40 more bytes than it would otherwise.
+pub struct EncryptedStore {
+    store: RawStore,
+    cipher: XChaCha20Poly1305,
+}
+
+impl EncryptedStore {
+    /// Wraps `store`, encrypting its items with `key`.
+    ///
+    /// The key is not checked against the store, as nothing about it is recorded there, so a store
+    /// opened with the wrong key only shows up once its items are read.
+    pub fn new(store: RawStore, key: &[u8; 32]) -> Self {
+        Self {
+            store,
+            cipher: XChaCha20Poly1305::new(Key::from_slice(key)),
+        }
+    }
+
+    /// Encrypts and stores `bytes`, as [`RawStore::add`].
+    pub fn add(&mut self, bytes: &[u8]) -> Result<Id, Error> {
+        let buffer = self.encrypt(bytes);
+        self.store.add(&buffer)
+    }
+
+    /// Gets and decrypts the data stored at `at`, as [`RawStore::get`].
+    pub fn get(&self, at: Id) -> Result<Vec<u8>, Error> {
+        let mut buffer = Vec::new();
+        self.get_into(at, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Gets and decrypts the data stored at `at` into `buffer`, as [`RawStore::get_into`].
+    pub fn get_into(&self, at: Id, buffer: &mut Vec<u8>) -> Result<(), Error> {
+        self.store.get_into(at, buffer)?;
+        if buffer.len() < OVERHEAD {
+            return Err(Error::Decrypt(at));
+        }
+        let tag = Tag::clone_from_slice(&buffer[buffer.len() - 16..]);
+        let nonce = XNonce::clone_from_slice(&buffer[..24]);
+        let end = buffer.len() - 16;
+        self.cipher
+            .decrypt_in_place_detached(&nonce, &[], &mut buffer[24..end], &tag)
+            .map_err(|_| Error::Decrypt(at))?;
+        buffer.truncate(end);
+        buffer.drain(..24);
+        Ok(())
+    }
+
+    /// Removes the data at `at`, as [`RawStore::remove`].
+    pub fn remove(&mut self, at: Id) -> Result<(), Error> {
+        self.store.remove(at, |_| ())
+    }
+
+    /// Replaces the data at `at` with `bytes`, as [`RawStore::replace_resize`].
+    pub fn replace_resize(&mut self, at: Id, bytes: &[u8]) -> Result<Id, Error> {
+        let buffer = self.encrypt(bytes);
+        self.store.replace_resize(at, &buffer)
+    }
+
+    /// Flushes every change not yet flushed, as [`RawStore::flush`].
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.store.flush()
+    }
+
+    /// The underlying store, whose items are all encrypted.
+    pub fn store(&self) -> &RawStore {
+        &self.store
+    }
+
+    pub fn into_inner(self) -> RawStore {
+        self.store
+    }
+
+    fn encrypt(&self, bytes: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut buffer = Vec::with_capacity(bytes.len() + OVERHEAD);
+        buffer.extend_from_slice(&nonce);
+        buffer.extend_from_slice(bytes);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce, &[], &mut buffer[24..])
+            .expect("item too large to encrypt");
+        buffer.extend_from_slice(&tag);
+        buffer
+    }
+}
+
+impl Debug for EncryptedStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedStore").field("store", &self.store).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Backing;
+
+    #[test]
+    fn roundtrip() {
+        let store = RawStore::options().new(Backing::new_anon().unwrap()).unwrap();
+        let mut s = EncryptedStore::new(store, &[7; 32]);
+        let a = s.add(b"secret").unwrap();
+        let b = s.add(&[]).unwrap();
+        assert_eq!(s.get(a).unwrap(), b"secret");
+        assert_eq!(s.get(b).unwrap(), b"");
+        assert!(s.store().get(a, |d| !d.windows(6).any(|w| w == b"secret")).unwrap());
+        let a = s.replace_resize(a, &[1; 1000]).unwrap();
+        assert_eq!(s.get(a).unwrap(), [1; 1000]);
+
+        // Tampering and the wrong key are both caught
+        let mut store = s.into_inner();
+        store.update(b, |d| d[30] ^= 1).unwrap();
+        let s = EncryptedStore::new(store, &[7; 32]);
+        assert!(matches!(s.get(b), Err(Error::Decrypt(_))));
+        let s = EncryptedStore::new(s.into_inner(), &[8; 32]);
+        assert!(matches!(s.get(a), Err(Error::Decrypt(_))));
+    }
+}
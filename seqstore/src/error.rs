@@ -16,10 +16,14 @@ pub enum Error {
     Resize(#[source] std::io::Error),
     /// Failed to flush the underlying memory map to disk.
     Flush(#[source] std::io::Error),
+    /// Failed to apply the [`MapOptions`][crate::MapOptions] of the underlying memory map.
+    MapOptions(#[source] std::io::Error),
     /// Encountered an unknown tag.
     ///
     /// This almost certainly means that an incorrect or invalid [`Id`] was given as an argument.
-    UnknownTag { position: usize, byte: u8 },
+    /// `surrounding` holds the bytes from 3 before the tag to 3 after it (with zeroes past either
+    /// end of the backing), to help tell what was there instead.
+    UnknownTag { position: usize, byte: u8, surrounding: [u8; 7] },
     /// Encountered an invalid tag for the desired operation.
     ///
     /// This most likely means that an incorrect [`Id`] has been given as an argument.
@@ -43,6 +47,48 @@ pub enum Error {
     /// As varints are (currently) only used in the header, this likely means that the file has been
     /// externally modified.
     InvalidVarint { position: usize },
+    /// [`verify`][crate::raw_store::RawStore::verify] found that the store does not match the
+    /// summary it was opened with, starting at `position`.
+    ///
+    /// This is only possible if the file has been externally modified since it was closed.
+    Inconsistent { position: usize },
+    /// Failed to read the data to [add][crate::raw_store::RawStore::add_from_reader].
+    Read(#[source] std::io::Error),
+    /// Failed to create, sync or rename a file while
+    /// [compacting][crate::raw_store::RawStore::compact_to] a store.
+    File(#[source] std::io::Error),
+    /// The range given to [`with_bytes_mut`][crate::raw_store::RawStore::with_bytes_mut] did not
+    /// lie within the data of a single stored item.
+    InvalidRange { start: usize, end: usize },
+    /// Attempted to change a store opened from a read-only [`Backing`][crate::Backing], see
+    /// [`Backing::open_file_readonly`][crate::Backing::open_file_readonly].
+    ReadOnly,
+    /// The data at an [`Id`] could not be decrypted by an
+    /// [`EncryptedStore`][crate::EncryptedStore], either because it was written with a different key
+    /// (or without one), or because it has been changed since.
+    #[cfg(feature = "encryption")]
+    Decrypt(Id),
+    /// Failed to write or read a [dump][crate::raw_store::RawStore::dump] of a store.
+    #[cfg(feature = "serde")]
+    Dump(#[source] serde_json::Error),
+    /// An error from a store with the given [`label`][crate::raw_store::OpenStoreOptions::label],
+    /// see [`unlabelled`][Self::unlabelled].
+    Labelled {
+        label: String,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// The error itself, without the [label][Self::Labelled] of the store it came from (if any),
+    /// _e.g._ for matching on.
+    pub fn unlabelled(&self) -> &Error {
+        match self {
+            Self::Labelled { source, .. } => source,
+            other => other,
+        }
+    }
 }
 
 impl Display for Error {
@@ -51,7 +97,12 @@ impl Display for Error {
             Self::Resize(e) => write!(f, "could not resize backing: {e}"),
             Self::Flush(e) => write!(f, "could not flush data: {e}"),
             Self::Map(e) => write!(f, "could not create memory map: {e}"),
-            Self::UnknownTag { position, byte } => write!(f, "unknown tag {byte:08b} at position 0x{position:X}",),
+            Self::MapOptions(e) => write!(f, "could not apply memory map options: {e}"),
+            Self::UnknownTag {
+                position,
+                byte,
+                surrounding,
+            } => write!(f, "unknown tag {byte:08b} at position 0x{position:X} (surrounded by {surrounding:02X?})"),
             Self::IncorrectTag {
                 position,
                 found,
@@ -67,6 +118,16 @@ impl Display for Error {
             Self::InvalidVarint { position } => {
                 write!(f, "invalid packed integer or EOF at 0x{:X}", position)
             }
+            Self::Read(e) => write!(f, "could not read data to add: {e}"),
+            Self::Inconsistent { position } => write!(f, "store does not match its summary at 0x{position:X}"),
+            Self::File(e) => write!(f, "could not write compacted store: {e}"),
+            Self::InvalidRange { start, end } => write!(f, "0x{start:X}..0x{end:X} is not within a single stored item"),
+            Self::ReadOnly => write!(f, "cannot change a store with a read-only backing"),
+            #[cfg(feature = "encryption")]
+            Self::Decrypt(id) => write!(f, "could not decrypt the data at {id:?}"),
+            #[cfg(feature = "serde")]
+            Self::Dump(e) => write!(f, "could not write or read dump: {e}"),
+            Self::Labelled { label, .. } => write!(f, "error in {label}"),
         }
     }
 }
@@ -96,9 +157,18 @@ pub enum OpenError {
     /// [`OpenStoreOptions`][crate::raw_store::OpenStoreOptions#header-specialization].
     #[error("mismatch between spec magic: expected {:?}, found {:?}", .expected, .found)]
     SpecMagic { found: BString, expected: BString },
+    /// The header's specialized magic bytes were rejected by the checker given to
+    /// [`spec_magic_with`][crate::raw_store::OpenStoreOptions::spec_magic_with].
+    #[error("spec magic {:?} was rejected", .found)]
+    SpecMagicRejected { found: BString },
     /// The header version is unknown.
     #[error("unknown version {:?}", .0)]
     UnknownVersion([u8; 2]),
+    /// The header version is older than the current one, and upgrading it was turned off with
+    /// [`migrate`][crate::raw_store::OpenStoreOptions::migrate] or is not possible as the
+    /// [`Backing`][crate::Backing] is read-only.
+    #[error("version {:?} needs to be upgraded", .0)]
+    NeedsMigration([u8; 2]),
     /// See [`Error::EntryCorrupt`].
     #[error("found incomplete write of length {} at 0x{:X}", .length, .position)]
     PartialWrite { position: usize, length: usize },
@@ -112,6 +182,14 @@ pub enum OpenError {
     /// This most likely means that the file has been externally modified.
     #[error("no end tag found")]
     NoEnd,
+    /// An error from opening the store with the given
+    /// [`label`][crate::raw_store::OpenStoreOptions::label].
+    #[error("could not open {}", .label)]
+    Labelled {
+        label: String,
+        #[source]
+        source: Box<OpenError>,
+    },
 }
 
 // This exists to prevent a `private_interfaces` warning without exposing MagicTag
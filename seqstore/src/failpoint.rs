@@ -0,0 +1,175 @@
+use std::{
+    io,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::{backing::BackingInner, error::Error, Backing};
+
+/// Failures to inject into a [`Backing`], for testing how a store copes with crashes.
+///
+/// This is a handle that is shared with every [`Backing`] it is [given to][Backing::failpoints],
+/// so failures can be set up at any point, including after a store has been opened. Besides
+/// injecting failures, it keeps a copy of everything the backing has flushed, which is what would
+/// be left on disk if the process crashed at that point (see [`crash_image`][Self::crash_image]).
+///
+/// Every flush of the backing is counted, so a test can find how many flushes an operation takes
+/// with [`flushes`][Self::flushes] and then crash it at each of them in turn.
+#[derive(Debug, Clone, Default)]
+pub struct Failpoints(Arc<Mutex<Inner>>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    flushes: usize,
+    resizes: usize,
+    fail_flush: Option<(usize, FailAction)>,
+    fail_resize: Option<usize>,
+    /// Everything flushed so far.
+    flushed: Vec<u8>,
+}
+
+/// What happens at a flush set to fail with [`Failpoints::fail_flush`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum FailAction {
+    /// Nothing is flushed, and the flush returns [`Error::Flush`].
+    Error,
+    /// Only the first `n` bytes of what was to be flushed are, and the flush returns
+    /// [`Error::Flush`], as if the process crashed part way through writing back the range.
+    ShortWrite(usize),
+}
+
+impl Failpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the flush `after` flushes from now fail (so `0` is the next one), see [`FailAction`].
+    ///
+    /// Only one flush can be set to fail at a time, and this replaces any set before.
+    pub fn fail_flush(&self, after: usize, action: FailAction) {
+        let mut inner = self.lock();
+        inner.fail_flush = Some((inner.flushes + after, action));
+    }
+
+    /// Makes the resize `after` resizes from now fail (so `0` is the next one) with
+    /// [`Error::Resize`], leaving the backing the size it was.
+    pub fn fail_resize(&self, after: usize) {
+        let mut inner = self.lock();
+        inner.fail_resize = Some(inner.resizes + after);
+    }
+
+    /// The number of flushes made so far, including failed ones.
+    pub fn flushes(&self) -> usize {
+        self.lock().flushes
+    }
+
+    /// A copy of everything flushed so far, which can be opened with [`Backing::new_from_buffer`]
+    /// to see what a store would look like had the process crashed now.
+    ///
+    /// Resizes are taken to be written straight away, so this is always as long as the backing.
+    pub fn crash_image(&self) -> Vec<u8> {
+        self.lock().flushed.clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Called before every flush of `start..end` of `backing`, returning an error if it is to fail
+    /// instead.
+    pub(crate) fn flush(&self, backing: &[u8], start: usize, end: usize) -> Result<(), Error> {
+        let mut inner = self.lock();
+        let this = inner.flushes;
+        inner.flushes += 1;
+        let end = match inner.fail_flush {
+            Some((at, FailAction::Error)) if at == this => start,
+            Some((at, FailAction::ShortWrite(n))) if at == this => end.min(start + n),
+            _ => {
+                inner.flushed[start..end].copy_from_slice(&backing[start..end]);
+                return Ok(());
+            }
+        };
+        inner.fail_flush = None;
+        inner.flushed[start..end].copy_from_slice(&backing[start..end]);
+        Err(Error::Flush(io::Error::other("injected failure")))
+    }
+
+    /// Called before every resize to `size`, returning an error if it is to fail instead.
+    pub(crate) fn resize(&self, size: usize) -> Result<(), Error> {
+        let mut inner = self.lock();
+        let this = inner.resizes;
+        inner.resizes += 1;
+        if inner.fail_resize == Some(this) {
+            inner.fail_resize = None;
+            return Err(Error::Resize(io::Error::other("injected failure")));
+        }
+        inner.flushed.resize(size, 0);
+        Ok(())
+    }
+}
+
+impl Backing {
+    /// Injects the failures set up in `failpoints` into this backing, see [`Failpoints`].
+    ///
+    /// The contents of the backing so far are taken to have already been flushed.
+    pub fn failpoints(mut self, failpoints: &Failpoints) -> Self {
+        failpoints.lock().flushed = self.0.to_vec();
+        self.0.failpoints = Some(failpoints.clone());
+        self
+    }
+}
+
+impl BackingInner {
+    /// Runs the failpoints for a flush of `start..end`, if there are any.
+    pub(crate) fn fail_flush(&self, start: usize, end: usize) -> Result<(), Error> {
+        match &self.failpoints {
+            Some(failpoints) => failpoints.flush(self, start, end),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw_store::{RawStore, RecoveryStrategy};
+
+    #[test]
+    fn failpoints() {
+        let failpoints = Failpoints::new();
+        let backing = Backing::new_anon().unwrap().failpoints(&failpoints);
+        let mut s = RawStore::options().new(backing).unwrap();
+        let kept = s.add(b"kept").unwrap();
+        let before = failpoints.flushes();
+        s.add(b"counted").unwrap();
+        let flushes = failpoints.flushes() - before;
+        assert!(flushes > 0);
+
+        // Crashing at any flush of an add leaves either the whole item or none of it
+        for at in 0..flushes {
+            for action in [FailAction::Error, FailAction::ShortWrite(1)] {
+                let failpoints = Failpoints::new();
+                let backing = s.with_bytes(Backing::new_from_buffer).unwrap().failpoints(&failpoints);
+                let mut s = RawStore::options().open(backing).unwrap();
+                failpoints.fail_flush(at, action);
+                assert!(matches!(s.add(b"crashed"), Err(Error::Flush(_))));
+                let crashed = Backing::new_from_buffer(&failpoints.crash_image()).unwrap();
+                // A torn write can lose the End tag after the new item, which only Scan gets past
+                let strategy = match action {
+                    FailAction::Error => RecoveryStrategy::Rollback,
+                    _ => RecoveryStrategy::Scan,
+                };
+                let mut s = RawStore::options().recovery_strategy(strategy).open(crashed).unwrap();
+                s.verify().unwrap();
+                assert_eq!(s.get(kept, <[u8]>::to_vec).unwrap(), b"kept");
+                let items = s.iter().map(|r| r.unwrap().1.to_vec()).collect::<Vec<_>>();
+                assert!(items.len() == 2 || items[2] == b"crashed", "{items:?}");
+            }
+        }
+
+        failpoints.fail_resize(0);
+        assert!(matches!(s.add(&[0; 1000]), Err(Error::Resize(_))));
+        s.add(&[0; 1000]).unwrap();
+        s.verify().unwrap();
+    }
+}
@@ -58,6 +58,23 @@ impl Id {
         let (at, length) = PackedId::unpack(packed);
         Self { at, marker: length }
     }
+
+    /// Encodes this as 8 bytes, for storing it without going through [`PackedId`] or serde.
+    ///
+    /// The bytes are as opaque as the `Id` itself, but their layout is stable: they are the
+    /// big-endian form of [`PackedId::get`], so bytes written by any version of this crate can be
+    /// read back with [`from_bytes`][Self::from_bytes] by any later one (a different layout would
+    /// come with new methods rather than changing these). Being big-endian, comparing the bytes of
+    /// two `Id`s from the same store orders them by where their items are stored.
+    pub fn to_bytes(self) -> [u8; 8] {
+        self.pack().get().to_be_bytes()
+    }
+
+    /// Decodes bytes written by [`to_bytes`][Self::to_bytes], returning `None` if they are all zero
+    /// (which no `Id` is encoded as).
+    pub fn from_bytes(bytes: [u8; 8]) -> Option<Self> {
+        PackedId::new(u64::from_be_bytes(bytes)).map(Self::from_packed)
+    }
 }
 
 impl Debug for Id {
@@ -153,6 +170,19 @@ mod tests {
         }
     }
 
+    #[test]
+    fn roundtrip_bytes() {
+        for &position in POSITIONS {
+            for length in 0..u8::MAX {
+                let id = Id::new(position as _, length as _);
+                assert_eq!(Id::from_bytes(id.to_bytes()), Some(id));
+            }
+        }
+        // The layout must never change
+        assert_eq!(Id::new(0x0102_0304, 3).to_bytes(), [0, 0, 0, 1, 2, 3, 4, Id::marker(3)]);
+        assert_eq!(Id::from_bytes([0; 8]), None);
+    }
+
     #[test]
     fn verify_correct() {
         for &position in POSITIONS {
@@ -3,11 +3,28 @@
 #[cfg(not(target_pointer_width = "64"))]
 compile_error!("only available on 64-bit targets");
 
-pub use backing::Backing;
+pub use backing::{Backing, GrowthPolicy, MapAdvice, MapOptions};
 pub use id::{Id, PackedId};
+pub use sync_store::{Flusher, SyncStore};
+#[cfg(feature = "tokio")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+pub use async_store::AsyncRawStore;
+#[cfg(feature = "encryption")]
+#[cfg_attr(docsrs, doc(cfg(feature = "encryption")))]
+pub use encrypted_store::EncryptedStore;
+#[cfg(feature = "failpoints")]
+#[cfg_attr(docsrs, doc(cfg(feature = "failpoints")))]
+pub use failpoint::{FailAction, Failpoints};
 
+#[cfg(feature = "tokio")]
+mod async_store;
 pub(crate) mod backing;
+#[cfg(feature = "encryption")]
+mod encrypted_store;
+#[cfg(feature = "failpoints")]
+mod failpoint;
 mod id;
+mod sync_store;
 pub(crate) mod tag;
 pub(crate) mod util;
 
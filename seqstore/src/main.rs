@@ -98,6 +98,16 @@ fn main() -> anyhow::Result<()> {
                             let name = checker.names().nth(i % l).unwrap();
                             with(&mut write_dur, || checker.execute(CheckItem::Remove(name)))?;
                         }
+                        Action::Replace(i, b) => {
+                            let l = checker.names().len();
+                            if l == 0 {
+                                continue;
+                            }
+                            let name = checker.names().nth(i % l).unwrap();
+                            const ALPHA: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+                            let b = &vec![ALPHA[j % ALPHA.len()]; b.len()];
+                            with(&mut write_dur, || checker.execute(CheckItem::Replace(name, b)))?;
+                        }
                     }
                 }
                 with(&mut check_dur, || checker.check_all())?;
@@ -136,6 +146,7 @@ fn with<R>(d: &mut Duration, f: impl FnOnce() -> R) -> R {
 enum Action<'a> {
     Add(&'a [u8]),
     Remove(usize),
+    Replace(usize, &'a [u8]),
 }
 
 impl<'a> Arbitrary<'a> for Action<'a> {
@@ -143,6 +154,8 @@ impl<'a> Arbitrary<'a> for Action<'a> {
         let tag = u.arbitrary::<u8>()?;
         if tag <= 100 {
             Ok(Self::Remove(u.arbitrary()?))
+        } else if tag <= 140 {
+            Ok(Self::Replace(u.arbitrary()?, u.arbitrary()?))
         } else {
             Ok(Self::Add(u.arbitrary()?))
         }
@@ -154,6 +167,7 @@ impl Debug for Action<'_> {
         match self {
             Self::Add(bytes) => f.debug_tuple("Add").field(&BStr::new(bytes)).finish(),
             Self::Remove(idx) => f.debug_tuple("Remove").field(idx).finish(),
+            Self::Replace(idx, bytes) => f.debug_tuple("Replace").field(idx).field(&BStr::new(bytes)).finish(),
         }
     }
 }
@@ -1,7 +1,13 @@
+use std::{
+    borrow::Cow,
+    io::Read,
+    ops::{Deref, Range},
+};
+
 use crate::{
     backing::{Backing, BackingInner},
     error::Error,
-    tag::MagicTag,
+    tag::{MagicTag, TagFormat},
     Id,
 };
 
@@ -11,8 +17,40 @@ pub mod checker;
 
 mod open;
 pub use open::{OpenStoreOptions, RecoveryStrategy};
+mod allocate;
+pub use allocate::GapStrategy;
+mod batch;
+pub use batch::Batch;
+mod coalesce;
+mod describe;
+#[cfg(feature = "serde")]
+mod dump;
+pub use describe::{describe, Anomaly, Region, RegionKind, StoreReport};
+mod compact;
+pub use compact::CompactReport;
+mod counters;
+pub use counters::OpCounters;
+mod extent;
 mod filter;
 pub use filter::Filter;
+mod flush;
+pub use flush::FlushPolicy;
+mod free_list;
+mod generation;
+mod iter;
+pub use iter::Iter;
+mod migrate;
+mod reserve;
+pub use reserve::Reservation;
+mod salvage;
+pub use salvage::RecoveryReport;
+mod shared;
+pub use shared::{RawStoreReader, RawStoreWriter};
+mod stats;
+pub use stats::StoreStats;
+#[cfg(test)]
+mod test_util;
+mod verify;
 
 /// A "raw" [`Id`]-to-bytes store, either file-backed or entirely in memory, where [`Id`] is
 /// represented by an opaque (_i.e._ not corresponding to file offset) [`u64`].
@@ -24,7 +62,7 @@ pub use filter::Filter;
 /// If using [file-backed storage][Backing::new_file], only a minimal amount of data is stored in memory,
 /// and may potentially be reduced further in the future. No items are stored in memory.
 // The path to reduction is by moving `Self.gaps` into another file-backed buffer, though given that
-// Gap is fairly small (< 2*usize) this shouldn't be an issue for maps where either deletion is rare
+// Gap is fairly small (3 words) this shouldn't be an issue for maps where either deletion is rare
 // or new additions are common (as old gaps get filled) or both.
 #[derive(Debug)]
 pub struct RawStore {
@@ -32,6 +70,29 @@ pub struct RawStore {
     end: usize,
     gaps: Vec<Gap>,
     header_length: usize,
+    /// See [`OpenStoreOptions::large_entries`].
+    tag_format: TagFormat,
+    /// The number of Written items, see [`Self::stats`].
+    entries: usize,
+    /// The total length of Written items, not including tags.
+    entry_bytes: usize,
+    /// See [`Self::is_verified`].
+    verified: bool,
+    /// Set while a [`Batch`] is being committed.
+    deferred: Option<batch::Deferred>,
+    flushing: flush::Flushing,
+    /// The generation of the next entry written, if generations are used, see
+    /// [`OpenStoreOptions::generations`].
+    generations: Option<u16>,
+    /// See [`Self::recovery_report`].
+    recovery_report: Option<RecoveryReport>,
+    /// See [`OpenStoreOptions::gap_strategy`].
+    gap_strategy: GapStrategy,
+    /// See [`Self::label`].
+    label: Option<String>,
+    /// The counters to write back to the header, if they are kept, see
+    /// [`OpenStoreOptions::counters`].
+    counters: Option<OpCounters>,
 }
 
 impl RawStore {
@@ -41,6 +102,15 @@ impl RawStore {
 
     /// Flush all outstanding changes and close the store.
     ///
+    /// This also writes a summary of the gaps left by removed items to the end of the backing, so
+    /// that reopening it does not have to read through the whole store to find them. A store that
+    /// is dropped without closing is still opened correctly, just more slowly (though without
+    /// counting any changes since the last [`flush`][Self::flush], see
+    /// [`OpenStoreOptions::counters`]).
+    ///
+    /// The backing is [truncated][Self::truncate_to_end] first, though the summary means it does
+    /// not end exactly at the end of the store.
+    ///
     /// Returns the [`Backing`] used so that the store can be re-opened if desired.
     ///
     /// \*Technically*, while the [`Backing`] is not in active use after this returns, it is unwise
@@ -48,85 +118,219 @@ impl RawStore {
     /// [`Backing::new_file`]. This does not apply if the [`Backing`] was created using an anonymous map,
     /// as there is no underlying file to modify.
     pub fn close(mut self) -> Result<Backing, Error> {
-        self.backing.flush()?;
+        if self.backing.is_writable() {
+            self.write_counters()?;
+            self.truncate_to_end()?;
+            self.write_free_list()?;
+        }
         Ok(Backing(self.backing))
     }
 
+    /// The label the store was opened with, see [`OpenStoreOptions::label`].
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Wraps an error from a public method in [`Error::Labelled`], if the store has a label and it
+    /// has not been already.
+    fn labelled<T>(&self, result: Result<T, Error>) -> Result<T, Error> {
+        result.map_err(|source| match &self.label {
+            Some(label) if !matches!(source, Error::Labelled { .. }) => Error::Labelled {
+                label: label.clone(),
+                source: Box::new(source),
+            },
+            _ => source,
+        })
+    }
+
+    /// Shrinks the backing to end just after the last stored item, releasing the space taken up
+    /// by any items removed from the end of the store, as well as the extra space the backing
+    /// grows by in advance.
+    ///
+    /// The backing otherwise never shrinks, however much is removed.
+    pub fn truncate_to_end(&mut self) -> Result<(), Error> {
+        self.check_writable()?;
+        let old_end = self.end;
+        while let Some(idx) = self
+            .gaps
+            .iter()
+            .position(|g| g.at + g.tag_len as usize + g.length == self.end)
+        {
+            self.end = self.gaps.swap_remove(idx).at;
+        }
+        if self.end != old_end {
+            // The old end tag goes first, as a store that ends without one can be recovered by
+            // rolling back, whereas one with anything but zeroes after its end tag cannot
+            self.backing[old_end] = 0;
+            self.backing.flush_range(old_end, 1)?;
+            self.backing[self.end..old_end].fill(0);
+            MagicTag::End.write(&mut self.backing, &mut { self.end }, self.tag_format)?;
+        }
+        self.backing.flush()?;
+        self.backing.resize_to(self.end + MagicTag::End.written_length(self.tag_format))
+    }
+
     /// Store `bytes` and return the now-associated [`Id`].
     ///
-    /// Currently, the maximum size of a single item is `134_217_727 B` (`= 128 MiB - 1 B`). This may
-    /// change in the future, either by a factor of two down or to significantly higher, but such a
-    /// change is unlikely.
-    /// If storing items anywhere near that large, consider using this map as an index into some
-    /// other storage solution better-suited to large items.
+    /// Items longer than `134_217_727 B` (`= 128 MiB - 1 B`) are stored in several pieces, which
+    /// means they are always added to the end of the store rather than into a gap, and are copied
+    /// into a single buffer when read. Stores created with
+    /// [`large_entries`][OpenStoreOptions::large_entries] hold items of up to 8 TiB in one piece.
+    /// If storing many items anywhere near that large, consider using this map as an index into
+    /// some other storage solution better-suited to large items.
+    pub fn add(&mut self, bytes: &[u8]) -> Result<Id, Error> {
+        let mut rest = bytes;
+        let added = self.add_with(bytes.len(), |buffer| {
+            let (this, next) = rest.split_at(buffer.len());
+            buffer.copy_from_slice(this);
+            rest = next;
+            Ok(())
+        });
+        self.labelled(added)
+    }
+
+    /// Store `length` bytes read from `reader` and return the now-associated [`Id`].
     ///
-    /// # Panics
+    /// The bytes are read straight into the store rather than into a buffer first, so this is the
+    /// way to add items too large to comfortably hold in memory. Like [`add`][Self::add], the item
+    /// only becomes visible once it has been completely written.
     ///
-    /// Panics if attempting to store an item larger than `134_217_727 B`.
-    pub fn add(&mut self, bytes: &[u8]) -> Result<Id, Error> {
-        let (mut position, expected_tag, old_gap) = {
-            fn satisfies_length(new: u32, old: u32) -> bool {
-                new == old || new + 5 <= old
-            }
+    /// If `reader` fails or ends before `length` bytes, then [`Error::Read`] is returned and the
+    /// space that was set aside for the item is freed again.
+    pub fn add_from_reader(&mut self, length: usize, mut reader: impl Read) -> Result<Id, Error> {
+        let added = self.add_with(length, |buffer| reader.read_exact(buffer).map_err(Error::Read));
+        self.labelled(added)
+    }
 
-            let required_length = MagicTag::Writing { length: bytes.len() as u64 }.written_length() + bytes.len();
+    /// Adds an item of `length` bytes, which are written by `fill`.
+    ///
+    /// `fill` is given the space for each piece of the item in turn (see [`add`][Self::add]), and
+    /// must fill all of it.
+    fn add_with(&mut self, length: usize, mut fill: impl FnMut(&mut [u8]) -> Result<(), Error>) -> Result<Id, Error> {
+        self.check_writable()?;
+        let generation = self.next_generation();
+        let length = length + generation.map_or(0, |_| generation::LEN);
+        let mut prefix = generation.map(u16::to_be_bytes);
+        let fill = move |buffer: &mut [u8]| match prefix.take() {
+            Some(prefix) => {
+                buffer[..prefix.len()].copy_from_slice(&prefix);
+                fill(&mut buffer[prefix.len()..])
+            }
+            None => fill(buffer),
+        };
+        let generation = generation.unwrap_or(0);
+        if length as u64 > self.tag_format.max_length() {
+            let start = self.add_chained(length, fill)?;
+            return Ok(self.make_id(start, length, generation));
+        }
+        let (position, expected_tag, slot) = {
+            let required_length = MagicTag::Writing { length: length as u64 }.written_length(self.tag_format) + length;
 
-            if let Some((idx, g)) = self
-                .gaps
-                .iter()
-                .enumerate()
-                .map(|(i, g)| (i, g.length + g.tag_len as u32))
-                .filter(|(_, g)| satisfies_length(required_length as u32, *g))
-                .take(8)
-                .min_by_key(|(_, g)| *g)
-            {
+            if let Some(idx) = self.gap_strategy.pick(&self.gaps, required_length) {
                 let gap = self.gaps.swap_remove(idx);
                 (
                     gap.at,
                     MagicTag::Deleted { length: gap.length as u64 },
-                    if required_length as u32 == g { None } else { Some(gap) },
+                    Some(gap.tag_len as usize + gap.length),
                 )
             } else {
                 (self.end, MagicTag::End, None)
             }
         };
 
-        let existing_tag = MagicTag::read(&self.backing, &mut { position })?;
+        let existing_tag = MagicTag::read(&self.backing, &mut { position }, self.tag_format)?;
         assert_eq!(existing_tag, expected_tag);
 
-        let start = position;
-        MagicTag::Writing { length: bytes.len() as u64 }.write(&mut self.backing, &mut position)?;
-        self.backing.write(bytes, &mut position)?;
+        self.write_entry(position, slot, length, fill)?;
+        self.entries += 1;
+        self.entry_bytes += length;
+        self.count(|c| {
+            c.adds += 1;
+            c.bytes_written += length as u64;
+        });
+        self.changed()?;
 
-        if let Some(old_gap) = old_gap {
-            let total = old_gap.tag_len as usize + old_gap.length as usize;
-            let used = position - start;
-            let remaining = total - used;
+        Ok(self.make_id(position, length, generation))
+    }
 
-            let (tag_len, new_len) = MagicTag::calc_tag_len(remaining);
+    /// Whether an entry taking up `new` bytes (tag included) can be written over `old` bytes, with
+    /// enough left over to be worth keeping as a gap.
+    fn satisfies_length(new: usize, old: usize) -> bool {
+        new == old || new + 5 <= old
+    }
 
-            let new_at = position;
-            MagicTag::Deleted { length: new_len as u64 }.write_exact(&mut self.backing, &mut position, tag_len as usize)?;
-            position += new_len;
-            assert_eq!(position, start + total);
-            self.gaps.push(Gap {
-                at: new_at,
-                length: new_len as u32,
-                tag_len,
-            });
+    /// Writes a new entry of `length` bytes at `start`, either into a slot of `slot` bytes (tags
+    /// included) which is turned into a gap after the entry if it is not filled exactly, or at the
+    /// end if `None`.
+    ///
+    /// The bytes are written by `fill`, and if that (or growing the backing to fit them) fails the
+    /// slot is given back.
+    fn write_entry(
+        &mut self, start: usize, slot: Option<usize>, length: usize, mut fill: impl FnMut(&mut [u8]) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut position = start;
+        MagicTag::Writing { length: length as u64 }.write(&mut self.backing, &mut position, self.tag_format)?;
+        if let Err(e) = self.backing.resize_for(position + length) {
+            self.abandon(start, slot, position)?;
+            return Err(e);
         }
-
-        if expected_tag == MagicTag::End {
-            self.end = position;
-            MagicTag::End.write(&mut self.backing, &mut position)?;
+        if let Err(e) = fill(&mut self.backing[position..position + length]) {
+            self.abandon(start, slot, position + length)?;
+            return Err(e);
+        }
+        position += length;
+
+        match slot {
+            Some(total) if position - start < total => {
+                let used = position - start;
+                let remaining = total - used;
+
+                let (tag_len, new_len) = MagicTag::calc_tag_len(remaining, self.tag_format);
+
+                let new_at = position;
+                MagicTag::Deleted { length: new_len as u64 }.write_exact(&mut self.backing, &mut position, tag_len as usize, self.tag_format)?;
+                self.backing[position..position + new_len].fill(0);
+                position += new_len;
+                assert_eq!(position, start + total);
+                self.gaps.push(Gap {
+                    at: new_at,
+                    length: new_len,
+                    tag_len,
+                });
+            }
+            Some(total) => assert_eq!(position, start + total),
+            None => {
+                self.end = position;
+                MagicTag::End.write(&mut self.backing, &mut position, self.tag_format)?;
+            }
         }
         let end = position;
-        self.backing.flush_start_end(start, end)?;
-
-        self.backing[start] ^= MagicTag::WRITING ^ MagicTag::WRITTEN;
-        self.backing.flush_range(start, 1)?;
+        self.flush_start_end(start, end)?;
+        self.finish_entry(start)
+    }
 
-        Ok(Id::new(start, bytes.len()))
+    /// Gives back the space of an entry that started being written at `start` (into `slot`, as in
+    /// [`Self::write_entry`]) but could not be finished, having written up to `written`.
+    fn abandon(&mut self, start: usize, slot: Option<usize>, written: usize) -> Result<(), Error> {
+        match slot {
+            Some(total) => {
+                let (tag_len, length) = MagicTag::calc_tag_len(total, self.tag_format);
+                MagicTag::Deleted { length: length as u64 }.write_exact(&mut self.backing, &mut { start }, tag_len as usize, self.tag_format)?;
+                self.backing[start + tag_len as usize..start + total].fill(0);
+                self.flush_start_end(start, start + total)?;
+                self.gaps.push(Gap {
+                    at: start,
+                    length,
+                    tag_len,
+                });
+            }
+            None => {
+                self.backing[start..written].fill(0);
+                MagicTag::End.write(&mut self.backing, &mut { start }, self.tag_format)?;
+                self.flush_start_end(start, written)?;
+            }
+        }
+        Ok(())
     }
 
     /// Gets the data stored at `at`, gives a view of it to `f`, and returns the result.
@@ -140,17 +344,104 @@ impl RawStore {
     /// result in a `panic!` or reception of `SIGBUS` (_i.e._ no UB), though returning
     /// bogus data is possible.
     pub fn get<R>(&self, at: Id, f: impl FnOnce(&[u8]) -> R) -> Result<R, Error> {
-        let mut position = at.at();
-        let tag = MagicTag::read(&self.backing, &mut position)?;
+        self.labelled(self.entry(at)).map(|b| f(&b))
+    }
+
+    /// Gets the data stored at `at` into `buffer`, replacing whatever it held before.
+    ///
+    /// This is equivalent to [`get`][Self::get] with a closure that copies into `buffer`, for
+    /// reusing the same buffer across many calls. See [`get`][Self::get] for which [`Id`]s are
+    /// valid.
+    pub fn get_into(&self, at: Id, buffer: &mut Vec<u8>) -> Result<(), Error> {
+        let b = self.labelled(self.entry(at))?;
+        buffer.clear();
+        buffer.extend_from_slice(&b);
+        Ok(())
+    }
+
+    /// Gets a view of the data stored at `at`, without copying it (unless it is stored in several
+    /// pieces, see [`add`][Self::add]).
+    ///
+    /// This is the same as [`get`][Self::get], except that the view is handed back rather than to
+    /// a closure. The returned [`EntryGuard`] borrows the store, so it cannot be changed until the
+    /// guard is dropped - which should be kept short, as is the case for any borrow of the backing
+    /// (see [`Backing::new_file`]).
+    pub fn get_ref(&self, at: Id) -> Result<EntryGuard<'_>, Error> {
+        self.labelled(self.entry(at)).map(EntryGuard)
+    }
+
+    fn entry(&self, at: Id) -> Result<Cow<'_, [u8]>, Error> {
+        let start = self.position(at);
+        let mut position = start;
+        let tag = MagicTag::read(&self.backing, &mut position, self.tag_format)?;
         match tag {
-            MagicTag::Writing { .. } => Err(Error::EntryCorrupt { position: at.at() }),
+            MagicTag::Writing { .. } => Err(Error::EntryCorrupt { position: start }),
             MagicTag::Written { length } => {
-                at.verify(length)?;
-                let b = &self.backing[position..position + length as usize];
-                Ok(f(b))
+                let (b, _) = extent::entry(&self.backing, position..position + length as usize, self.tag_format)?;
+                at.verify(b.len() as u64)?;
+                self.strip_generation(at, b)
             }
             other => Err(Error::IncorrectTag {
-                position: at.at(),
+                position: start,
+                found: other.into(),
+                expected_kind: "Written",
+            }),
+        }
+    }
+
+    /// Gives a mutable view of the data stored at `at` to `f`, writes back any changes it makes,
+    /// and returns the result.
+    ///
+    /// This avoids copying the data (unless it is stored in several pieces, see [`add`][Self::add]),
+    /// so is the cheapest way to make small edits, but it can only change the data, not its
+    /// length. `at` stays valid.
+    ///
+    /// Unlike [`add`][Self::add], this is not atomic: if it is interrupted, any mix of the old and
+    /// new data could be left behind.
+    pub fn update<R>(&mut self, at: Id, f: impl FnOnce(&mut [u8]) -> R) -> Result<R, Error> {
+        let updated = self.update_entry(at, f);
+        self.labelled(updated)
+    }
+
+    fn update_entry<R>(&mut self, at: Id, f: impl FnOnce(&mut [u8]) -> R) -> Result<R, Error> {
+        self.check_writable()?;
+        let start = self.position(at);
+        let mut position = start;
+        let tag = MagicTag::read(&self.backing, &mut position, self.tag_format)?;
+        match tag {
+            MagicTag::Writing { .. } => Err(Error::EntryCorrupt { position: start }),
+            MagicTag::Written { length } => {
+                let data = position..position + length as usize;
+                let pieces = extent::continuations(&self.backing, data.end, self.tag_format).collect::<Result<Vec<_>, _>>()?;
+                if pieces.is_empty() {
+                    at.verify(length)?;
+                    let skip = self.check_generation(at, &self.backing[data.clone()])?;
+                    let ret = f(&mut self.backing[data.start + skip..data.end]);
+                    self.flush_start_end(data.start, data.end)?;
+                    self.count(|c| c.bytes_written += length);
+                    self.changed()?;
+                    return Ok(ret);
+                }
+
+                let (bytes, end) = extent::entry(&self.backing, data.clone(), self.tag_format)?;
+                at.verify(bytes.len() as u64)?;
+                let skip = self.check_generation(at, &bytes)?;
+                let mut bytes = bytes.into_owned();
+                let ret = f(&mut bytes[skip..]);
+                let (first, mut rest) = bytes.split_at(data.len());
+                self.backing[data.clone()].copy_from_slice(first);
+                for piece in pieces {
+                    let (this, next) = rest.split_at(piece.length);
+                    self.backing[piece.data()].copy_from_slice(this);
+                    rest = next;
+                }
+                self.flush_start_end(data.start, end)?;
+                self.count(|c| c.bytes_written += bytes.len() as u64);
+                self.changed()?;
+                Ok(ret)
+            }
+            other => Err(Error::IncorrectTag {
+                position: start,
                 found: other.into(),
                 expected_kind: "Written",
             }),
@@ -160,22 +451,149 @@ impl RawStore {
     /// Attempts to remove the data at `at`. This will return an error for partially-written data
     /// as well as already-deleted data.
     pub fn remove<R>(&mut self, at: Id, f: impl FnOnce(&[u8]) -> R) -> Result<R, Error> {
-        let mut position = at.at();
-        let tag = MagicTag::read(&self.backing, &mut position)?;
+        let removed = self.remove_entry(at, f);
+        self.labelled(removed)
+    }
+
+    fn remove_entry<R>(&mut self, at: Id, f: impl FnOnce(&[u8]) -> R) -> Result<R, Error> {
+        self.check_writable()?;
+        let start = self.position(at);
+        let mut position = start;
+        let tag = MagicTag::read(&self.backing, &mut position, self.tag_format)?;
         match tag {
             MagicTag::End => {
                 panic!("cannot remove end tag")
             }
-            MagicTag::Writing { .. } => Err(Error::EntryCorrupt { position: at.at() }),
+            MagicTag::Writing { .. } => Err(Error::EntryCorrupt { position: start }),
             MagicTag::Written { length } => {
-                at.verify(length)?;
-                let ret = f(&self.backing[position..position + length as usize]);
-
-                self.erase(&mut { at.at() }, position - at.at(), length as usize)?;
+                let (b, _) = extent::entry(&self.backing, position..position + length as usize, self.tag_format)?;
+                at.verify(b.len() as u64)?;
+                let skip = self.check_generation(at, &b)?;
+                let ret = f(&b[skip..]);
+                let total = b.len();
+                let pieces = extent::continuations(&self.backing, position + length as usize, self.tag_format).collect::<Result<Vec<_>, _>>()?;
+
+                // The first piece goes first, as that removes the whole entry at once
+                self.erase(&mut { start }, position - start, length as usize)?;
+                for piece in pieces {
+                    self.erase(&mut { piece.at }, piece.tag_len, piece.length)?;
+                }
+                self.entries -= 1;
+                self.entry_bytes -= total;
+                self.count(|c| c.removes += 1);
+                self.changed()?;
 
                 Ok(ret)
             }
-            MagicTag::Deleted { .. } => Err(Error::AlreadyDeleted { position: at.at() }),
+            MagicTag::Deleted { .. } => Err(Error::AlreadyDeleted { position: start }),
+            other @ MagicTag::Continued { .. } => Err(Error::IncorrectTag {
+                position: start,
+                found: other.into(),
+                expected_kind: "Written",
+            }),
+        }
+    }
+
+    /// Like [`remove`][Self::remove], but returns `Ok(None)` rather than an error if the data at
+    /// `at` has already been removed, for clean-up that may be retried (_e.g._ after a crash).
+    ///
+    /// This covers the space having since been merged into a larger gap or released from the end
+    /// of the store, but not it having been reused: if another item has since been added in the
+    /// same place, then this fails as [`remove`][Self::remove] does (or, if the item is the same
+    /// length and [generations][OpenStoreOptions::generations] are not used, removes it).
+    pub fn remove_if_present<R>(&mut self, at: Id, f: impl FnOnce(&[u8]) -> R) -> Result<Option<R>, Error> {
+        self.labelled(self.check_writable())?;
+        let start = self.position(at);
+        if start >= self.end {
+            return Ok(None);
+        }
+        match self.remove_entry(at, f) {
+            Ok(ret) => Ok(Some(ret)),
+            Err(Error::AlreadyDeleted { .. }) => Ok(None),
+            Err(Error::UnknownTag { .. })
+                if self.gaps.iter().any(|g| g.at < start && start < g.at + g.tag_len as usize + g.length) =>
+            {
+                Ok(None)
+            }
+            Err(e) => self.labelled(Err(e)),
+        }
+    }
+
+    /// Replaces the data at `at` with `bytes`, which do not need to be the same length, returning
+    /// the [`Id`] of the new data. `at` is no longer valid afterwards, even if the new [`Id`] is
+    /// equal to it.
+    ///
+    /// If `bytes` fit in the space taken up by the old data, they are written over it in place,
+    /// so unlike a [`remove`][Self::remove] followed by an [`add`][Self::add], this only takes a
+    /// single round of flushes. If this is interrupted, then the old data is lost as well as the
+    /// new data (as with [`remove`][Self::remove] then [`add`][Self::add]).
+    ///
+    /// Otherwise, `bytes` are [`add`][Self::add]ed and only then is the old data
+    /// [`remove`][Self::remove]d, so if this is interrupted at least the old data is kept.
+    pub fn replace_resize(&mut self, at: Id, bytes: &[u8]) -> Result<Id, Error> {
+        let replaced = self.replace_entry(at, bytes);
+        self.labelled(replaced)
+    }
+
+    fn replace_entry(&mut self, at: Id, bytes: &[u8]) -> Result<Id, Error> {
+        self.check_writable()?;
+        let start = self.position(at);
+        let mut position = start;
+        let tag = MagicTag::read(&self.backing, &mut position, self.tag_format)?;
+        let length = match tag {
+            MagicTag::Written { length } => length as usize,
+            MagicTag::Writing { .. } => return Err(Error::EntryCorrupt { position: start }),
+            MagicTag::Deleted { .. } => return Err(Error::AlreadyDeleted { position: start }),
+            other => {
+                return Err(Error::IncorrectTag {
+                    position: start,
+                    found: other.into(),
+                    expected_kind: "Written",
+                })
+            }
+        };
+        let mut pieces = extent::continuations(&self.backing, position + length, self.tag_format);
+        let total = length + pieces.by_ref().map(|p| p.map(|p| p.length)).sum::<Result<usize, _>>()?;
+        at.verify(total as u64)?;
+        let skip = self.check_generation(at, &self.backing[position..position + length])?;
+
+        let slot = position - start + length;
+        let new_length = skip + bytes.len();
+        let fits = new_length as u64 <= self.tag_format.max_length() && {
+            let required = MagicTag::Writing { length: new_length as u64 }.written_length(self.tag_format) + new_length;
+            Self::satisfies_length(required, slot)
+        };
+        if total != length || !fits {
+            let id = self.add(bytes)?;
+            self.remove_entry(at, |_| ())?;
+            return Ok(id);
+        }
+
+        let generation = self.next_generation();
+        self.write_entry(start, Some(slot), new_length, |buffer| {
+            let (prefix, rest) = buffer.split_at_mut(skip);
+            if let Some(generation) = generation {
+                prefix.copy_from_slice(&generation.to_be_bytes());
+            }
+            rest.copy_from_slice(bytes);
+            Ok(())
+        })?;
+        self.entry_bytes = self.entry_bytes - length + new_length;
+        self.count(|c| {
+            c.adds += 1;
+            c.removes += 1;
+            c.bytes_written += new_length as u64;
+        });
+        self.changed()?;
+        Ok(self.make_id(start, new_length, generation.unwrap_or(0)))
+    }
+
+    /// Returns [`Error::ReadOnly`] if the backing cannot be changed.
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.backing.is_writable() {
+            Ok(())
+        } else {
+            Err(Error::ReadOnly)
         }
     }
 
@@ -184,7 +602,7 @@ impl RawStore {
         let mut before = None;
         let mut after = None;
         for (i, gap) in self.gaps.iter().enumerate() {
-            if gap.at + gap.length as usize + gap.tag_len as usize == at {
+            if gap.at + gap.length + gap.tag_len as usize == at {
                 assert!(before.is_none());
                 before = Some(i);
             } else if *position + tag_len + length == gap.at {
@@ -193,6 +611,21 @@ impl RawStore {
             }
         }
 
+        // Gaps can only be merged as long as a single tag can still cover them
+        let fits = |start: usize, end: usize| end - start <= self.tag_format.max_length() as usize;
+        let gap_end = |i: usize| self.gaps[i].at + self.gaps[i].tag_len as usize + self.gaps[i].length;
+        if let (Some(b), Some(a)) = (before, after) {
+            if !fits(self.gaps[b].at, gap_end(a)) {
+                after = None;
+            }
+        }
+        if before.is_some_and(|b| !fits(self.gaps[b].at, at + tag_len + length)) {
+            before = None;
+        }
+        if after.is_some_and(|a| !fits(at, gap_end(a))) {
+            after = None;
+        }
+
         let s = match (before, after) {
             (None, None) => None,
             (Some(b), None) => {
@@ -201,7 +634,7 @@ impl RawStore {
             }
             (None, Some(a)) => {
                 let a = self.gaps.swap_remove(a);
-                Some((at, a.at + a.tag_len as usize + a.length as usize))
+                Some((at, a.at + a.tag_len as usize + a.length))
             }
             (Some(b), Some(a)) => {
                 let (b, a) = if b < a {
@@ -213,26 +646,26 @@ impl RawStore {
                     let a = self.gaps.swap_remove(a);
                     (b, a)
                 };
-                Some((b.at, a.at + a.tag_len as usize + a.length as usize))
+                Some((b.at, a.at + a.tag_len as usize + a.length))
             }
         };
 
         if let Some((start, end)) = s {
             assert!(start < end);
             let gap_len = end - start;
-            let (tag_len, len) = MagicTag::calc_tag_len(gap_len);
+            let (tag_len, len) = MagicTag::calc_tag_len(gap_len, self.tag_format);
             *position = start;
 
-            MagicTag::Deleted { length: len as u64 }.write_exact(&mut self.backing, position, tag_len as usize)?;
+            MagicTag::Deleted { length: len as u64 }.write_exact(&mut self.backing, position, tag_len as usize, self.tag_format)?;
             assert_eq!(*position + len, end);
 
             self.backing[*position..end].fill(0);
-            self.backing.flush_start_end(start, end)?;
+            self.flush_start_end(start, end)?;
             *position = end;
 
             self.gaps.push(Gap {
                 at: start,
-                length: len as u32,
+                length: len,
                 tag_len,
             });
         } else {
@@ -244,12 +677,12 @@ impl RawStore {
             let end = at + tag_len + length;
 
             self.backing[at + tag_len..end].fill(0);
-            self.backing.flush_range(at, tag_len + length)?;
+            self.flush_start_end(at, end)?;
             *position = end;
 
             self.gaps.push(Gap {
                 at,
-                length: length as u32,
+                length,
                 tag_len: tag_len as u8,
             });
         }
@@ -265,12 +698,82 @@ impl RawStore {
     pub fn with_bytes<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
         f(&self.backing[..])
     }
+
+    /// Provides mutable access to `range` of the underlying bytes (as positioned in
+    /// [`with_bytes`][Self::with_bytes]), once it has been checked to lie entirely within the data
+    /// of a single stored item, writing back any changes `f` makes.
+    ///
+    /// This is for making many small in-place edits to items found through
+    /// [`with_bytes`][Self::with_bytes] or [`iter`][Self::iter], without looking up each one by its
+    /// [`Id`] for [`update`][Self::update]. Finding the item means reading through the tags of every
+    /// item before it, though not their data. For items stored in several pieces (see
+    /// [`add`][Self::add]), `range` must lie within a single piece, and it can never cover the
+    /// generation at the start of an item, if [generations][OpenStoreOptions::generations] are
+    /// used. Otherwise, [`Error::InvalidRange`] is returned.
+    ///
+    /// As with [`update`][Self::update], this is not atomic.
+    pub fn with_bytes_mut<R>(&mut self, range: Range<usize>, f: impl FnOnce(&mut [u8]) -> R) -> Result<R, Error> {
+        self.check_writable()?;
+        let invalid = Error::InvalidRange {
+            start: range.start,
+            end: range.end,
+        };
+        if range.start > range.end || range.end > self.end {
+            return Err(invalid);
+        }
+        let mut position = self.header_length;
+        // Whether the last tag was part of a Written entry, and so can be continued
+        let mut written = false;
+        while position <= range.start {
+            let tag = MagicTag::read(&self.backing, &mut position, self.tag_format)?;
+            let (length, skip, item) = match tag {
+                MagicTag::End => break,
+                MagicTag::Written { length } => (length as usize, self.generations.map_or(0, |_| generation::LEN), true),
+                MagicTag::Continued { length } => (length as usize, 0, written),
+                MagicTag::Writing { length } | MagicTag::Deleted { length } => (length as usize, 0, false),
+            };
+            let data = position + skip.min(length)..position + length;
+            position = data.end;
+            written = item;
+            if range.start < position {
+                if !(item && data.start <= range.start && range.end <= data.end) {
+                    break;
+                }
+                let ret = f(&mut self.backing[range.clone()]);
+                self.flush_start_end(range.start, range.end)?;
+                self.count(|c| c.bytes_written += range.len() as u64);
+                self.changed()?;
+                return Ok(ret);
+            }
+        }
+        Err(invalid)
+    }
+}
+
+/// A view of the data of an entry, returned by [`RawStore::get_ref`].
+///
+/// This borrows the store, so should be dropped as soon as the data has been read.
+#[derive(Debug)]
+pub struct EntryGuard<'a>(Cow<'a, [u8]>);
+
+impl Deref for EntryGuard<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for EntryGuard<'_> {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 struct Gap {
     at: usize,
-    length: u32,
+    length: usize,
     tag_len: u8,
 }
 
@@ -289,7 +792,8 @@ pub fn debug_map(bytes: &[u8]) -> Result<(), Error> {
     let header = &bytes[..RawStore::HEADER_LENGTH];
     assert_eq!(&header[..RawStore::HEADER_MAGIC.len()], RawStore::HEADER_MAGIC);
     let mut position = RawStore::HEADER_MAGIC.len();
-    assert_eq!(&header[position..position + 2], &RawStore::HEADER_VERSION);
+    assert_eq!(header[position], RawStore::HEADER_VERSION[0]);
+    let tag_format = TagFormat::from_flags(header[position + 1]);
     position += 2;
     assert_eq!(position, header.len());
     let s = crate::util::read_varint::<u64>(bytes, &mut position)? as usize;
@@ -298,7 +802,7 @@ pub fn debug_map(bytes: &[u8]) -> Result<(), Error> {
 
     let mut ended = false;
     while position < bytes.len() {
-        let tag = MagicTag::read(bytes, &mut position)?;
+        let tag = MagicTag::read(bytes, &mut position, tag_format)?;
         match tag {
             MagicTag::End => {
                 let b = bytes[position..].iter().find(|b| **b != 0x00);
@@ -325,9 +829,157 @@ pub fn debug_map(bytes: &[u8]) -> Result<(), Error> {
                 position += length as usize;
                 trace!("Deleted length {length}");
             }
+            MagicTag::Continued { length } => {
+                let b = &bytes[position..position + length as usize];
+                position += length as usize;
+                trace!("Continued - {:?}", BStr::new(b));
+            }
         }
     }
     assert!(ended);
     trace!(" === END CHECK === \n");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Backing;
+
+    #[test]
+    fn truncate_to_end() {
+        let mut s = RawStore::options().new(Backing::new_anon().unwrap()).unwrap();
+        let keep = s.add(&[b'a'; 10]).unwrap();
+        let ids = (0..20).map(|_| s.add(&[b'b'; 100]).unwrap()).collect::<Vec<_>>();
+        for id in ids.into_iter().rev().step_by(2) {
+            s.remove(id, |_| ()).unwrap();
+        }
+        assert!(s.stats().file_length > 2000);
+        s.truncate_to_end().unwrap();
+        assert_eq!(s.stats().file_length, s.end + 1);
+        assert_eq!(s.stats().entries, 11);
+
+        let backing = Backing::new_from_buffer(&s.backing).unwrap();
+        let mut reopened = RawStore::options().open(backing).unwrap();
+        assert_eq!(reopened.end, s.end);
+        assert_eq!(reopened.get(keep, |b| b.to_vec()).unwrap(), [b'a'; 10]);
+        reopened.add(&[b'c'; 300]).unwrap();
+        reopened.verify().unwrap();
+    }
+
+    #[test]
+    fn with_bytes_mut() {
+        for generations in [false, true] {
+            let mut s = RawStore::options().generations(generations).new(Backing::new_anon().unwrap()).unwrap();
+            let a = s.add(b"aaaa").unwrap();
+            let b = s.add(b"bbbbbb").unwrap();
+            s.remove(a, |_| ()).unwrap();
+            let c = s.add(b"cc").unwrap();
+            let find = |s: &RawStore, needle: &[u8]| s.with_bytes(|bytes| bytes.windows(needle.len()).position(|w| w == needle).unwrap());
+
+            let at = find(&s, b"bbbbbb");
+            s.with_bytes_mut(at + 1..at + 3, |b| b.copy_from_slice(b"XY")).unwrap();
+            assert_eq!(s.get(b, <[u8]>::to_vec).unwrap(), b"bXYbbb");
+            s.with_bytes_mut(at..at + 6, |b| b.make_ascii_uppercase()).unwrap();
+            assert_eq!(s.get(b, <[u8]>::to_vec).unwrap(), b"BXYBBB");
+
+            // Anything reaching outside of the data of a single item is refused
+            let invalid = |r: Result<(), Error>| matches!(r, Err(Error::InvalidRange { .. }));
+            let tag = s.position(b);
+            assert!(invalid(s.with_bytes_mut(tag..at + 1, |_| ())));
+            assert!(invalid(s.with_bytes_mut(at + 5..at + 9, |_| ())));
+            assert!(invalid(s.with_bytes_mut(0..2, |_| ())));
+            let gap = s.gaps[0].at;
+            assert!(invalid(s.with_bytes_mut(gap + 1..gap + 2, |_| ())));
+            assert!(invalid(s.with_bytes_mut(s.end..s.end, |_| ())));
+            if generations {
+                assert!(invalid(s.with_bytes_mut(tag + 1..at, |_| ())));
+            }
+            assert_eq!(s.get(c, <[u8]>::to_vec).unwrap(), b"cc");
+            s.verify().unwrap();
+        }
+    }
+
+    #[test]
+    fn update() {
+        for generations in [false, true] {
+            let mut s = RawStore::options().generations(generations).new(Backing::new_anon().unwrap()).unwrap();
+            let a = s.add(b"abc").unwrap();
+            let b = s.add(b"defgh").unwrap();
+            let len = s
+                .update(a, |d| {
+                    d.make_ascii_uppercase();
+                    d.len()
+                })
+                .unwrap();
+            assert_eq!(len, 3);
+            assert_eq!(s.get(a, <[u8]>::to_vec).unwrap(), b"ABC");
+            assert_eq!(s.get(b, <[u8]>::to_vec).unwrap(), b"defgh");
+            s.remove(b, |_| ()).unwrap();
+            assert!(matches!(s.update(b, |_| ()), Err(Error::IncorrectTag { .. })));
+
+            let s = RawStore::options().use_free_list(false).open(s.close().unwrap()).unwrap();
+            assert_eq!(s.get(a, <[u8]>::to_vec).unwrap(), b"ABC");
+            assert_eq!(s.stats().entries, 1);
+        }
+    }
+
+    #[test]
+    fn update_large_entry() {
+        let mut s = RawStore::options().new(Backing::new_anon().unwrap()).unwrap();
+        let before = s.add(b"before").unwrap();
+        let max = TagFormat::V1.max_length() as usize;
+        let mut large = (0..max + 100).map(|i| i as u8).collect::<Vec<_>>();
+        let id = s.add_from_reader(large.len(), &large[..]).unwrap();
+        let after = s.add(b"after").unwrap();
+
+        // Either side of where the entry is split into pieces, and both of its ends
+        let edits = [0, max - 1, max, large.len() - 1];
+        let len = s
+            .update(id, |b| {
+                for i in edits {
+                    b[i] = !b[i];
+                }
+                b.len()
+            })
+            .unwrap();
+        assert_eq!(len, large.len());
+        for i in edits {
+            large[i] = !large[i];
+        }
+        assert!(s.get(id, |b| b == large).unwrap());
+
+        let mut s = RawStore::options().use_free_list(false).open(s.close().unwrap()).unwrap();
+        assert!(s.get(id, |b| b == large).unwrap());
+        assert_eq!(s.get(before, <[u8]>::to_vec).unwrap(), b"before");
+        assert_eq!(s.get(after, <[u8]>::to_vec).unwrap(), b"after");
+        s.verify().unwrap();
+    }
+
+    #[test]
+    fn get_into_and_get_ref() {
+        for generations in [false, true] {
+            let mut s = RawStore::options().generations(generations).new(Backing::new_anon().unwrap()).unwrap();
+            let a = s.add(b"abc").unwrap();
+            let b = s.add(b"defgh").unwrap();
+
+            let mut buffer = b"previous contents".to_vec();
+            s.get_into(a, &mut buffer).unwrap();
+            assert_eq!(buffer, b"abc");
+            s.get_into(b, &mut buffer).unwrap();
+            assert_eq!(buffer, b"defgh");
+
+            let guard = s.get_ref(a).unwrap();
+            assert_eq!(&*guard, b"abc");
+            assert_eq!(guard.as_ref(), b"abc");
+            assert!(matches!(guard.0, Cow::Borrowed(_)), "single pieces should not be copied");
+            drop(guard);
+
+            // Nothing is written into the buffer on failure
+            s.remove(a, |_| ()).unwrap();
+            assert!(matches!(s.get_into(a, &mut buffer), Err(Error::IncorrectTag { .. })));
+            assert_eq!(buffer, b"defgh");
+            assert!(matches!(s.get_ref(a), Err(Error::IncorrectTag { .. })));
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use super::{Gap, RawStore};
+
+/// How [`add`][RawStore::add] picks which gap to write a new item into, set with
+/// [`OpenStoreOptions::gap_strategy`][super::OpenStoreOptions::gap_strategy].
+///
+/// Whichever gap is picked, anything left over after the item is kept as a smaller gap (or, if it
+/// would be too small to hold a tag, the gap is not used). If no gap fits, the item is added to the
+/// end of the store. Every strategy looks through the gaps in no particular order, so "first" only
+/// means the first one found.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum GapStrategy {
+    /// Use the first gap that fits.
+    ///
+    /// The cheapest to pick, but tends to split up large gaps for small items.
+    FirstFit,
+    /// Use the smallest gap that fits, out of every gap in the store.
+    ///
+    /// Wastes the least space, but goes through every gap for every item added, so is slower in
+    /// stores with many gaps.
+    BestFit,
+    /// Use the smallest gap that fits, out of the first `n` that fit, as a trade-off between the
+    /// other two.
+    BestOfFirst(usize),
+}
+
+impl Default for GapStrategy {
+    fn default() -> Self {
+        Self::BestOfFirst(8)
+    }
+}
+
+impl GapStrategy {
+    /// The index of the gap in `gaps` to write `required` bytes (tag included) into, if any fit.
+    pub(super) fn pick(self, gaps: &[Gap], required: usize) -> Option<usize> {
+        let mut fitting = gaps
+            .iter()
+            .enumerate()
+            .map(|(i, g)| (i, g.length + g.tag_len as usize))
+            .filter(|&(_, total)| RawStore::satisfies_length(required, total));
+        let picked = match self {
+            Self::FirstFit => fitting.next(),
+            Self::BestFit => fitting.min_by_key(|&(_, total)| total),
+            Self::BestOfFirst(n) => fitting.take(n.max(1)).min_by_key(|&(_, total)| total),
+        };
+        picked.map(|(i, _)| i)
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+
+use super::RawStore;
+use crate::{error::Error, tag::MagicTag, Id};
+
+impl RawStore {
+    /// Starts a [`Batch`] of changes, which are applied together with a single round of flushes.
+    pub fn batch(&mut self) -> Batch<'_> {
+        Batch {
+            store: self,
+            adds: Vec::new(),
+            removes: Vec::new(),
+        }
+    }
+
+    /// Flushes `start..end`, or leaves it to be flushed with the rest of a batch or as the
+    /// [`FlushPolicy`][super::FlushPolicy] says.
+    pub(super) fn flush_start_end(&mut self, start: usize, end: usize) -> Result<(), Error> {
+        match &mut self.deferred {
+            Some(deferred) => {
+                deferred.start = deferred.start.min(start);
+                deferred.end = deferred.end.max(end);
+                Ok(())
+            }
+            None if self.flushing.immediate() => self.backing.flush_start_end(start, end),
+            None => {
+                self.flushing.mark(start, end);
+                Ok(())
+            }
+        }
+    }
+
+    /// Flips the entry being written at `start` to Written, once everything written for it has
+    /// been flushed, or leaves it to be flipped with the rest of a batch.
+    pub(super) fn finish_entry(&mut self, start: usize) -> Result<(), Error> {
+        if let Some(deferred) = &mut self.deferred {
+            deferred.writing.push(start);
+            return Ok(());
+        }
+        self.backing[start] ^= MagicTag::WRITING ^ MagicTag::WRITTEN;
+        self.flush_start_end(start, start + 1)
+    }
+
+    pub(super) fn finish_deferred(&mut self) -> Result<(), Error> {
+        let Some(deferred) = self.deferred.take() else {
+            return Ok(());
+        };
+        if deferred.start >= deferred.end {
+            return Ok(());
+        }
+        self.backing.flush_start_end(deferred.start, deferred.end)?;
+        for &start in &deferred.writing {
+            self.backing[start] ^= MagicTag::WRITING ^ MagicTag::WRITTEN;
+        }
+        if !deferred.writing.is_empty() {
+            self.backing.flush_start_end(deferred.start, deferred.end)?;
+        }
+        Ok(())
+    }
+}
+
+/// Flushes put off until the end of a [`Batch`] (or a [`Reservation`][super::Reservation]).
+#[derive(Debug)]
+pub(super) struct Deferred {
+    start: usize,
+    end: usize,
+    /// The starts of entries to flip to Written once everything else is flushed.
+    pub(super) writing: Vec<usize>,
+}
+
+impl Deferred {
+    pub(super) fn new(adds: usize) -> Self {
+        Self {
+            start: usize::MAX,
+            end: 0,
+            writing: Vec::with_capacity(adds),
+        }
+    }
+}
+
+/// A group of changes applied to a [`RawStore`] together, created by [`RawStore::batch`].
+///
+/// Nothing is changed until [`commit`][Self::commit]; dropping a batch discards its changes.
+///
+/// Committing a batch only flushes once to write every change, and once more to make every added
+/// item visible, rather than a few times per change. An interrupted commit can leave any of the
+/// changes applied or not, though each added item is still either completely written or not there
+/// at all. If an addition fails, those already made are removed again before the error is
+/// returned, so none are left behind without their [`Id`]s.
+#[derive(Debug)]
+pub struct Batch<'a> {
+    store: &'a mut RawStore,
+    adds: Vec<Vec<u8>>,
+    removes: Vec<Id>,
+}
+
+impl Batch<'_> {
+    /// Stores `bytes` once committed. The [`Id`]s of added items are returned by
+    /// [`commit`][Self::commit].
+    pub fn add(&mut self, bytes: &[u8]) -> &mut Self {
+        self.adds.push(bytes.to_vec());
+        self
+    }
+
+    /// Removes the data at `at` once committed.
+    ///
+    /// `at` must be valid before the batch is committed, so cannot be the [`Id`] of an item added
+    /// in the same batch.
+    pub fn remove(&mut self, at: Id) -> &mut Self {
+        self.removes.push(at);
+        self
+    }
+
+    /// The number of changes in this batch.
+    pub fn len(&self) -> usize {
+        self.adds.len() + self.removes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.adds.is_empty() && self.removes.is_empty()
+    }
+
+    /// Applies every change, returning the [`Id`]s of the added items in the order they were
+    /// [`add`][Self::add]ed.
+    ///
+    /// Every [`Id`] to [`remove`][Self::remove] is checked before anything is changed, so if any
+    /// is invalid then nothing is. Removals are applied first, so that their space can be reused
+    /// by the additions. If an addition fails, the removals stay applied, but every item already
+    /// added is removed again.
+    pub fn commit(self) -> Result<Vec<Id>, Error> {
+        let store = self.store;
+        store.check_writable()?;
+        let mut seen = HashSet::with_capacity(self.removes.len());
+        for &at in &self.removes {
+            store.entry(at)?;
+            let position = store.position(at);
+            if !seen.insert(position) {
+                return Err(Error::AlreadyDeleted { position });
+            }
+        }
+
+        store.deferred = Some(Deferred::new(self.adds.len()));
+        let mut added = Vec::with_capacity(self.adds.len());
+        let applied = (|| {
+            for at in self.removes {
+                store.remove(at, |_| ())?;
+            }
+            for bytes in &self.adds {
+                added.push(store.add(bytes)?);
+            }
+            Ok(())
+        })();
+        // Even if something failed, what has been applied so far still has to be finished off
+        store.finish_deferred()?;
+        if let Err(e) = applied {
+            // Only finished entries can be removed, so this takes another round of flushes
+            store.deferred = Some(Deferred::new(0));
+            let removed = added.into_iter().try_for_each(|at| store.remove(at, |_| ()));
+            store.finish_deferred()?;
+            removed?;
+            return Err(e);
+        }
+        Ok(added)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Backing;
+
+    #[test]
+    fn batch() {
+        let mut s = RawStore::options().new(Backing::new_anon().unwrap()).unwrap();
+        let old = (0..10_u8).map(|i| s.add(&[i; 20]).unwrap()).collect::<Vec<_>>();
+        let mut batch = s.batch();
+        for at in &old[..5] {
+            batch.remove(*at);
+        }
+        batch.add(b"a").add(&[b'b'; 19]).add(&[b'c'; 300]);
+        assert_eq!(batch.len(), 8);
+        let ids = batch.commit().unwrap();
+        assert_eq!(s.stats().entries, 8);
+        assert_eq!(s.get(ids[1], |b| b.to_vec()).unwrap(), [b'b'; 19]);
+        assert_eq!(s.get(old[5], |b| b.to_vec()).unwrap(), [5; 20]);
+
+        let mut batch = s.batch();
+        batch.add(b"d").remove(ids[0]).remove(ids[0]);
+        assert!(matches!(batch.commit(), Err(Error::AlreadyDeleted { .. })));
+        assert_eq!(s.stats().entries, 8);
+
+        let backing = Backing::new_from_buffer(&s.backing).unwrap();
+        let mut reopened = RawStore::options().open(backing).unwrap();
+        reopened.verify().unwrap();
+        assert_eq!(reopened.get(ids[2], |b| b.to_vec()).unwrap(), [b'c'; 300]);
+    }
+
+    #[cfg(feature = "failpoints")]
+    #[test]
+    fn batch_rollback() {
+        let failpoints = crate::Failpoints::new();
+        let backing = Backing::new_anon().unwrap().failpoints(&failpoints);
+        let mut s = RawStore::options().new(backing).unwrap();
+        let kept = s.add(b"kept").unwrap();
+
+        // The first addition fits, but the second has to grow the backing
+        failpoints.fail_resize(0);
+        let mut batch = s.batch();
+        batch.add(b"added").add(&[0; 10_000]);
+        assert!(matches!(batch.commit(), Err(Error::Resize(_))));
+        s.verify().unwrap();
+        assert_eq!(s.stats().entries, 1);
+        let items = s.iter().map(|r| r.unwrap().1.to_vec()).collect::<Vec<_>>();
+        assert_eq!(items, [b"kept"]);
+
+        let mut reopened = RawStore::options().open(Backing::new_from_buffer(&failpoints.crash_image()).unwrap()).unwrap();
+        reopened.verify().unwrap();
+        assert_eq!(reopened.stats().entries, 1);
+        assert_eq!(reopened.get(kept, <[u8]>::to_vec).unwrap(), b"kept");
+    }
+}
@@ -1,19 +1,23 @@
 use std::{
     fmt::{Debug, Formatter},
     hash::Hash,
+    io::Write,
+    path::Path,
 };
 
 use bstr::{BStr, BString, ByteSlice};
 use indexmap::IndexMap;
 use log::trace;
 use thiserror::Error;
+use varuint::WriteVarint;
 
-use crate::{backing::Backing, id::PackedId, raw_store::RawStore, Id};
+use crate::{backing::Backing, id::PackedId, raw_store::RawStore, util::read_varint, Id};
 
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub enum CheckItem<'a, N> {
     Add(N, &'a [u8]),
     Remove(N),
+    Replace(N, &'a [u8]),
     Check(N),
     CheckAll,
     Debug,
@@ -25,6 +29,7 @@ impl<N: Debug> Debug for CheckItem<'_, N> {
         match self {
             Self::Add(name, bytes) => f.debug_tuple("Add").field(name).field(&BStr::new(bytes)).finish(),
             Self::Remove(name) => f.debug_tuple("Remove").field(name).finish(),
+            Self::Replace(name, bytes) => f.debug_tuple("Replace").field(name).field(&BStr::new(bytes)).finish(),
             Self::Check(name) => f.debug_tuple("Check").field(name).finish(),
             Self::CheckAll => f.debug_tuple("CheckAll").finish(),
             Self::Debug => f.debug_tuple("Debug").finish(),
@@ -38,6 +43,7 @@ pub struct Checker<N> {
     check: IndexMap<PackedId, Vec<u8>>,
     names: IndexMap<N, Id>,
     map: RawStore,
+    trace: Option<Trace<N>>,
 }
 
 impl<N: Hash + Eq + Debug + Copy> Checker<N> {
@@ -46,10 +52,29 @@ impl<N: Hash + Eq + Debug + Copy> Checker<N> {
             check: IndexMap::new(),
             names: IndexMap::new(),
             map: RawStore::options().exact_spec_magic(b"checker").new(file)?,
+            trace: None,
         })
     }
 
+    /// Records every item executed from now on (as well as every [`reopen`][Self::reopen]) to
+    /// `to`, so that they can be run again later with [`replay`][Checker::replay].
+    ///
+    /// Each item is written before it is executed, so a trace of a failing sequence ends with the
+    /// item that failed. Names are recorded as numbers, counting up from `0` with each item added.
+    pub fn record(&mut self, mut to: impl Write + 'static) -> Result<(), CheckerError> {
+        to.write_all(TRACE_MAGIC)?;
+        self.trace = Some(Trace {
+            to: Box::new(to),
+            names: IndexMap::new(),
+            next: 0,
+        });
+        Ok(())
+    }
+
     pub fn execute(&mut self, item: CheckItem<N>) -> Result<Option<Id>, CheckerError> {
+        if let Some(trace) = &mut self.trace {
+            trace.write(item)?;
+        }
         match item {
             CheckItem::Add(name, bytes) => {
                 let at = self.map.add(bytes)?;
@@ -72,18 +97,18 @@ impl<N: Hash + Eq + Debug + Copy> Checker<N> {
                     Ok(Some(at))
                 }
             }
+            CheckItem::Replace(name, bytes) => {
+                self.check(name)?;
+                let at = self.names[&name];
+                self.check.swap_remove(&at.pack());
+                let new = self.map.replace_resize(at, bytes)?;
+                self.names[&name] = new;
+                assert!(self.check.insert(new.pack(), bytes.to_vec()).is_none());
+                Ok(Some(new))
+            }
             CheckItem::Check(name) => {
-                let at = *self.names.get(&name).expect("checking name that was never inserted");
-                let check = self.check.get(&at.pack()).expect("checking location that was never added");
-                let stored = self.map.get(at, ToOwned::to_owned)?;
-                if *check != stored {
-                    Err(CheckerError::Mismatch {
-                        expected: BString::new(check.to_owned()),
-                        found: BString::new(stored),
-                    })
-                } else {
-                    Ok(None)
-                }
+                self.check(name)?;
+                Ok(None)
             }
             CheckItem::CheckAll => {
                 self.check_all()?;
@@ -101,17 +126,53 @@ impl<N: Hash + Eq + Debug + Copy> Checker<N> {
     }
 
     pub fn reopen(&mut self) -> Result<(), CheckerError> {
+        if let Some(trace) = &mut self.trace {
+            trace.write_reopen()?;
+        }
         let map = std::mem::replace(&mut self.map, RawStore::options().new(Backing::new_anon()?)?);
         let backing = map.close()?;
-        let map = RawStore::options().exact_spec_magic(b"checker").open(backing)?;
+        let mut map = RawStore::options().exact_spec_magic(b"checker").open(backing)?;
+        map.verify()?;
         self.map = map;
         Ok(())
     }
 
+    /// Checks the item added as `name`, without recording it as a separate item.
+    fn check(&self, name: N) -> Result<(), CheckerError> {
+        let at = *self.names.get(&name).expect("checking name that was never inserted");
+        let check = self.check.get(&at.pack()).expect("checking location that was never added");
+        let stored = self.map.get(at, ToOwned::to_owned)?;
+        if *check != stored {
+            return Err(CheckerError::Mismatch {
+                expected: BString::new(check.to_owned()),
+                found: BString::new(stored),
+            });
+        }
+        Ok(())
+    }
+
     pub fn check_all(&mut self) -> Result<(), CheckerError> {
-        for name in self.names.keys().copied().collect::<Vec<_>>() {
-            self.execute(CheckItem::Check(name))?;
+        for &name in self.names.keys() {
+            self.check(name)?;
         }
+        let mut found = 0;
+        for item in self.map.iter() {
+            let (at, stored) = item?;
+            found += 1;
+            let check = self.check.get(&at.pack()).ok_or_else(|| CheckerError::Unexpected {
+                found: BString::new(stored.to_vec()),
+            })?;
+            if **check != *stored {
+                return Err(CheckerError::Mismatch {
+                    expected: BString::new(check.to_owned()),
+                    found: BString::new(stored.into_owned()),
+                });
+            }
+        }
+        assert_eq!(found, self.check.len(), "iteration did not find every item");
+        let stats = self.map.stats();
+        assert_eq!(stats.entries, self.check.len());
+        assert_eq!(stats.entry_bytes, self.check.values().map(Vec::len).sum::<usize>());
         Ok(())
     }
 
@@ -128,6 +189,121 @@ impl<N: Hash + Eq + Debug + Copy> Checker<N> {
     }
 }
 
+impl Checker<u64> {
+    /// Runs every item recorded in the trace at `path` (see [`record`][Checker::record]) against a
+    /// new in-memory store, stopping at the first that fails.
+    ///
+    /// Returns the checker after the last item, so that it can be checked further.
+    pub fn replay(path: impl AsRef<Path>) -> Result<Self, CheckerError> {
+        Self::replay_bytes(&std::fs::read(path)?)
+    }
+
+    /// As [`replay`][Self::replay], but with the trace in `trace`.
+    pub fn replay_bytes(trace: &[u8]) -> Result<Self, CheckerError> {
+        let rest = trace.strip_prefix(TRACE_MAGIC).ok_or(CheckerError::Trace { position: 0 })?;
+        let mut checker = Self::new(Backing::new_anon()?)?;
+        let mut position = 0;
+        let invalid = |position| CheckerError::Trace {
+            position: TRACE_MAGIC.len() + position,
+        };
+        while let Some(&kind) = rest.get(position) {
+            let at = position;
+            position += 1;
+            let name = |position: &mut usize| read_varint::<u64>(rest, position).map_err(|_| invalid(at));
+            let bytes = |position: &mut usize| {
+                let length = read_varint::<u64>(rest, position).map_err(|_| invalid(at))? as usize;
+                let bytes = rest.get(*position..*position + length).ok_or(invalid(at))?;
+                *position += length;
+                Ok::<_, CheckerError>(bytes)
+            };
+            let item = match kind {
+                ADD => CheckItem::Add(name(&mut position)?, bytes(&mut position)?),
+                REMOVE => CheckItem::Remove(name(&mut position)?),
+                REPLACE => CheckItem::Replace(name(&mut position)?, bytes(&mut position)?),
+                CHECK => CheckItem::Check(name(&mut position)?),
+                CHECK_ALL => CheckItem::CheckAll,
+                DEBUG => CheckItem::Debug,
+                PRINT => CheckItem::Print,
+                REOPEN => {
+                    checker.reopen()?;
+                    continue;
+                }
+                _ => return Err(invalid(at)),
+            };
+            checker.execute(item)?;
+        }
+        Ok(checker)
+    }
+}
+
+// A trace starts with `TRACE_MAGIC`, followed by one record per item: a byte for its kind, then its
+// name (for the items that have one) as a varint, then its bytes (for the items that have them) as
+// a varint length followed by the bytes themselves.
+const TRACE_MAGIC: &[u8] = b"\x1FPLFtrace";
+const ADD: u8 = 0;
+const REMOVE: u8 = 1;
+const REPLACE: u8 = 2;
+const CHECK: u8 = 3;
+const CHECK_ALL: u8 = 4;
+const DEBUG: u8 = 5;
+const PRINT: u8 = 6;
+const REOPEN: u8 = 7;
+
+struct Trace<N> {
+    to: Box<dyn Write>,
+    /// The number each name is recorded as.
+    names: IndexMap<N, u64>,
+    next: u64,
+}
+
+impl<N: Hash + Eq + Copy> Trace<N> {
+    fn write(&mut self, item: CheckItem<N>) -> Result<(), CheckerError> {
+        let mut record = Vec::new();
+        let (kind, name, bytes) = match item {
+            CheckItem::Add(name, bytes) => {
+                self.names.insert(name, self.next);
+                self.next += 1;
+                (ADD, Some(name), Some(bytes))
+            }
+            CheckItem::Remove(name) => (REMOVE, Some(name), None),
+            CheckItem::Replace(name, bytes) => (REPLACE, Some(name), Some(bytes)),
+            CheckItem::Check(name) => (CHECK, Some(name), None),
+            CheckItem::CheckAll => (CHECK_ALL, None, None),
+            CheckItem::Debug => (DEBUG, None, None),
+            CheckItem::Print => (PRINT, None, None),
+        };
+        record.push(kind);
+        if let Some(name) = name {
+            // A name that was never added fails when executed, so is recorded as one that cannot
+            // have been either
+            let n = self.names.get(&name).copied().unwrap_or(u64::MAX);
+            record.write_varint(n)?;
+        }
+        if let Some(bytes) = bytes {
+            record.write_varint(bytes.len() as u64)?;
+            record.extend_from_slice(bytes);
+        }
+        if let CheckItem::Remove(name) = item {
+            self.names.swap_remove(&name);
+        }
+        self.to.write_all(&record)?;
+        self.to.flush()?;
+        Ok(())
+    }
+
+    fn write_reopen(&mut self) -> Result<(), CheckerError> {
+        self.to.write_all(&[REOPEN])?;
+        self.to.flush()?;
+        Ok(())
+    }
+}
+
+impl<N> Debug for Trace<N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Trace").field("next", &self.next).finish_non_exhaustive()
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum CheckerError {
     #[error(transparent)]
@@ -136,6 +312,50 @@ pub enum CheckerError {
     Open(#[from] crate::error::OpenError),
     #[error("mismatch: expected {:?}, found {:?}", .expected, .found)]
     Mismatch { expected: BString, found: BString },
+    #[error("found unexpected item {:?}", .found)]
+    Unexpected { found: BString },
+    #[error("invalid trace record at 0x{:X}", .position)]
+    Trace { position: usize },
     #[error(transparent)]
     Other(#[from] std::io::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "debug_map")]
+    #[test]
+    fn checker_replay() {
+        use crate::raw_store::checker::{CheckItem, Checker, CheckerError};
+
+        let path = std::env::temp_dir().join(format!("seqstore-trace-{}", std::process::id()));
+        let mut checker = Checker::new(Backing::new_anon().unwrap()).unwrap();
+        checker.record(std::fs::File::create(&path).unwrap()).unwrap();
+        for item in [
+            CheckItem::Add("a", &[b'a'; 40][..]),
+            CheckItem::Add("b", b"b"),
+            CheckItem::Remove("a"),
+            CheckItem::Add("a", b"new a"),
+            CheckItem::Replace("b", &[b'b'; 20]),
+            CheckItem::CheckAll,
+        ] {
+            checker.execute(item).unwrap();
+        }
+        checker.reopen().unwrap();
+        checker.execute(CheckItem::Check("a")).unwrap();
+
+        let mut replayed = Checker::replay(&path).unwrap();
+        replayed.check_all().unwrap();
+        assert_eq!(replayed.names().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(replayed.map().stats(), checker.map().stats());
+
+        // A record for each item (and the reopen), but not for the checks made by Replace and
+        // CheckAll
+        let mut trace = std::fs::read(&path).unwrap();
+        assert_eq!(trace.len(), b"\x1FPLFtrace".len() + 43 + 4 + 2 + 8 + 23 + 1 + 1 + 2);
+        trace.push(0xFF);
+        assert!(matches!(Checker::replay_bytes(&trace), Err(CheckerError::Trace { .. })));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+use std::mem;
+
+use super::{Gap, RawStore};
+use crate::{error::Error, tag::MagicTag};
+
+impl RawStore {
+    /// Merges every run of gaps that are next to each other into a single gap, returning how many
+    /// fewer gaps there are afterwards.
+    ///
+    /// Gaps are normally merged with their neighbours as items are removed, but not when a removal
+    /// is interrupted part way through, or when partially-written items are
+    /// [rolled back][super::RecoveryStrategy::Rollback] while opening, so such stores can be left
+    /// with neighbouring gaps that are never merged otherwise. Gaps are only merged as long as a
+    /// single tag can still cover them, so very large runs may still be left as a few gaps.
+    ///
+    /// This goes through every gap, but does not read the rest of the store. See also
+    /// [`OpenStoreOptions::coalesce_gaps`][super::OpenStoreOptions::coalesce_gaps] to do this
+    /// whenever a store is opened.
+    pub fn coalesce_gaps(&mut self) -> Result<usize, Error> {
+        self.check_writable()?;
+        let mut gaps = mem::take(&mut self.gaps);
+        gaps.sort_unstable_by_key(|g| g.at);
+        let before = gaps.len();
+        let mut rest = gaps.into_iter().peekable();
+        while let Some(first) = rest.next() {
+            let start = first.at;
+            let mut run = vec![first];
+            while let Some(next) = rest.next_if(|g| g.at == gap_end(&run[run.len() - 1]) && gap_end(g) - start <= self.tag_format.max_length() as usize) {
+                run.push(next);
+            }
+            if run.len() == 1 {
+                self.gaps.append(&mut run);
+                continue;
+            }
+            let end = gap_end(&run[run.len() - 1]);
+            let (tag_len, length) = MagicTag::calc_tag_len(end - start, self.tag_format);
+            let tag = MagicTag::Deleted { length: length as u64 };
+            if let Err(e) = tag.write_exact(&mut self.backing, &mut { start }, tag_len as usize, self.tag_format) {
+                self.gaps.extend(run.into_iter().chain(rest));
+                return Err(e);
+            }
+            // The tags of the merged gaps are now just part of the new one, and are cleared along
+            // with the rest of it
+            self.backing[start + tag_len as usize..end].fill(0);
+            self.gaps.push(Gap {
+                at: start,
+                length,
+                tag_len,
+            });
+            if let Err(e) = self.flush_start_end(start, end) {
+                self.gaps.extend(rest);
+                return Err(e);
+            }
+        }
+        let merged = before - self.gaps.len();
+        if merged > 0 {
+            self.changed()?;
+        }
+        Ok(merged)
+    }
+}
+
+fn gap_end(gap: &Gap) -> usize {
+    gap.at + gap.tag_len as usize + gap.length
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw_store::test_util::prepare;
+
+    #[test]
+    fn coalesce_gaps() {
+        let backing = || {
+            prepare!(
+                MagicTag::Deleted { length: 2 },
+                [0; 2],
+                MagicTag::Deleted { length: 3 },
+                [0; 3],
+                MagicTag::Written { length: 1 },
+                b"a",
+                MagicTag::Deleted { length: 1 },
+                [0],
+                MagicTag::Deleted { length: 0 },
+                MagicTag::Deleted { length: 4 },
+                [0; 4],
+                MagicTag::Written { length: 1 },
+                b"b",
+            )
+        };
+        let mut s = RawStore::options().open(backing()).unwrap();
+        assert_eq!(s.stats().gaps, 5);
+        let gap_bytes = s.stats().gap_bytes;
+        assert_eq!(s.coalesce_gaps().unwrap(), 3);
+        assert_eq!(s.stats().gaps, 2);
+        assert_eq!(s.stats().gap_bytes, gap_bytes);
+        s.verify().unwrap();
+        assert_eq!(s.coalesce_gaps().unwrap(), 0);
+        let items = s.iter().map(|i| i.unwrap().1.into_owned()).collect::<Vec<_>>();
+        assert_eq!(items, [b"a", b"b"]);
+
+        let s = RawStore::options().coalesce_gaps(true).open(backing()).unwrap();
+        assert_eq!(s.stats().gaps, 2);
+    }
+}
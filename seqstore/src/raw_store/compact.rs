@@ -0,0 +1,163 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    mem,
+    path::Path,
+};
+
+use super::{Filter, OpenStoreOptions, RawStore, StoreStats};
+use crate::{
+    error::{Error, OpenError},
+    Backing, Id,
+};
+
+/// What [`RawStore::compact_to`] did to a store.
+///
+/// Compacting never moves an item: every kept item is copied to the same offset in the new file
+/// (see [`Filter`]), so it can still be found with the [`Id`] it had before, and no [`Id`]s held
+/// elsewhere need to be updated. Only the [`Id`]s of the items that were not kept stop working,
+/// and those are listed in [`removed`][Self::removed].
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct CompactReport {
+    /// The store's stats before it was compacted.
+    pub before: StoreStats,
+    /// The store's stats after it was compacted, and reopened.
+    pub after: StoreStats,
+    /// The [`Id`]s of the items that were not kept, in the order they were stored in.
+    pub removed: Vec<Id>,
+}
+
+impl RawStore {
+    /// Replaces this store, which must be backed by the file at `path`, with a copy only holding
+    /// the items chosen by `select`, in one go.
+    ///
+    /// `select` is given a [`Filter`] into a new file next to `path` (named as `path`, but starting
+    /// with a `.` and ending with `~`), to [`add`][Filter::add] or [`retain`][Filter::retain] the
+    /// items to keep. Any error it returns stops the compaction, and is returned as it is, so it can
+    /// be of any type that an [`OpenError`] can be turned into. Once the copy is finished and
+    /// synced to disk, this store is closed, the copy is renamed over `path`, and then opened with
+    /// `options` in place of this store.
+    ///
+    /// Every kept item stays where it was, so keeps its [`Id`], see [`CompactReport`].
+    ///
+    /// As the rename replaces the file at `path` all at once, a crash at any point leaves either the
+    /// original store or the compacted one there, never a mix of the two. If anything fails before
+    /// this store is closed it is left as it was, and the copy is removed. If anything fails after,
+    /// this store is left empty and in memory, and `path` must be opened again.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Backing::new_file`], for both `path` and the copy next to it.
+    pub unsafe fn compact_to<E: From<OpenError>>(
+        &mut self, path: impl AsRef<Path>, options: OpenStoreOptions,
+        select: impl FnOnce(&mut Filter<'_>) -> Result<(), E>,
+    ) -> Result<CompactReport, E> {
+        let path = path.as_ref();
+        let mut copy_name = OsString::from(".");
+        copy_name.push(path.file_name().unwrap_or_default());
+        copy_name.push("~");
+        let copy_path = path.with_file_name(copy_name);
+
+        let before = self.stats();
+        let (file, removed) = match unsafe { self.write_filtered(&copy_path, select) } {
+            Ok(written) => written,
+            Err(e) => {
+                // The copy is of no use, and may not even have been created
+                let _ = fs::remove_file(&copy_path);
+                return Err(e);
+            }
+        };
+        unsafe { self.swap_in(path, &copy_path, file, options) }?;
+        Ok(CompactReport {
+            before,
+            after: self.stats(),
+            removed,
+        })
+    }
+
+    /// Writes the items chosen by `select` to a new file at `path`, and syncs it, returning it along
+    /// with the [`Id`]s of the items that were not chosen.
+    unsafe fn write_filtered<E: From<OpenError>>(
+        &self, path: &Path, select: impl FnOnce(&mut Filter<'_>) -> Result<(), E>,
+    ) -> Result<(File, Vec<Id>), E> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(Error::File)
+            .map_err(OpenError::from)?;
+        let copy = file.try_clone().map_err(Error::File).map_err(OpenError::from)?;
+        let mut filter = self
+            .filter(unsafe { Backing::new_file(copy) }.map_err(OpenError::from)?)
+            .map_err(OpenError::from)?;
+        select(&mut filter)?;
+        let removed = filter.not_kept().map_err(OpenError::from)?;
+        filter.finish().map_err(OpenError::from)?;
+        file.sync_all().map_err(Error::File).map_err(OpenError::from)?;
+        Ok((file, removed))
+    }
+
+    /// Closes this store, renames the finished copy `file` at `copy_path` over `path` and opens it
+    /// in its place.
+    unsafe fn swap_in(&mut self, path: &Path, copy_path: &Path, file: File, options: OpenStoreOptions) -> Result<(), OpenError> {
+        let old = mem::replace(self, OpenStoreOptions::default().new(Backing::new_anon()?)?);
+        drop(old.close()?);
+        fs::rename(copy_path, path).map_err(Error::File)?;
+        sync_parent(path)?;
+        *self = options.open(unsafe { Backing::new_file(file) }?)?;
+        Ok(())
+    }
+}
+
+/// Syncs the directory holding `path`, so that a rename into it is on disk.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<(), Error> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent).and_then(|dir| dir.sync_all()).map_err(Error::File)
+}
+
+/// Directories cannot be synced like this elsewhere, where renames are left to the OS.
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact_to() {
+        let path = std::env::temp_dir().join(format!("seqstore-compact-{}", std::process::id()));
+        let open = || std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+        let mut s = RawStore::options().new(unsafe { Backing::new_file(open()) }.unwrap()).unwrap();
+        let ids = (0..10_u8).map(|i| s.add(&[i; 100]).unwrap()).collect::<Vec<_>>();
+
+        // A failed compaction leaves the store as it was
+        let e = unsafe { s.compact_to(&path, Default::default(), |_| Err(Error::ReadOnly.into())) }.unwrap_err();
+        assert!(matches!(e, OpenError::General(Error::ReadOnly)), "{e:?}");
+        assert_eq!(s.stats().entries, 10);
+
+        let report = unsafe { s.compact_to(&path, Default::default(), |f| f.retain(|_, b| b[0] < 3).map_err(OpenError::from)) }.unwrap();
+        assert_eq!(report.removed, ids[3..]);
+        assert_eq!(report.before.entries - report.after.entries, 7);
+        assert!(report.after.file_length < report.before.file_length);
+        assert!(!path.with_file_name(format!(".seqstore-compact-{}~", std::process::id())).exists());
+        assert_eq!(s.get(ids[2], <[u8]>::to_vec).unwrap(), [2; 100]);
+        assert!(s.get(ids[3], |_| ()).is_err());
+        s.add(b"more").unwrap();
+        drop(s.close().unwrap());
+
+        let s = RawStore::options().open(unsafe { Backing::new_file(open()) }.unwrap()).unwrap();
+        assert_eq!(s.stats().entries, 4);
+        assert_eq!(s.get(ids[0], <[u8]>::to_vec).unwrap(), [0; 100]);
+        drop(s);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
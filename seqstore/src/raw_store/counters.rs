@@ -0,0 +1,126 @@
+use super::RawStore;
+use crate::error::Error;
+
+// With counters turned on (see `OpenStoreOptions::counters`), the header ends (after the spec magic)
+// with the number of items added and removed over the whole life of the store, and the number of
+// bytes written to items, as big-endian u64s. These are kept in memory as the store is changed, and
+// only written back to the header on `flush` and `close`, so any changes made since are not counted
+// if the store is dropped or the program crashes.
+//
+// Whether a store has counters is recorded in the second byte of the header version, as the header
+// of a store with them is longer.
+
+/// The bytes taken up by the counters at the end of the header.
+pub(super) const LEN: usize = 24;
+/// The bit set in the second byte of the header version when counters are kept.
+pub(super) const HEADER_FLAG: u8 = 0b10;
+
+/// How much a [`RawStore`] has been changed over its whole life (not just since it was opened),
+/// see [`OpenStoreOptions::counters`][super::OpenStoreOptions::counters] and
+/// [`StoreStats::counters`][super::StoreStats::counters].
+///
+/// Useful for working out how quickly a store churns, _e.g._ to schedule
+/// [compaction][RawStore::compact_to] from the rate at which items are removed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+#[non_exhaustive]
+pub struct OpCounters {
+    /// The number of items added, including by [`replace_resize`][RawStore::replace_resize].
+    pub adds: u64,
+    /// The number of items removed, including by [`replace_resize`][RawStore::replace_resize].
+    pub removes: u64,
+    /// The total length of the items written by adds and [`update`][RawStore::update]s, counted
+    /// as in [`StoreStats::entry_bytes`][super::StoreStats::entry_bytes].
+    pub bytes_written: u64,
+}
+
+impl OpCounters {
+    /// Reads the counters from the `LEN` bytes at the end of a header.
+    pub(super) fn read(bytes: &[u8]) -> Self {
+        let at = |i: usize| u64::from_be_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap());
+        Self {
+            adds: at(0),
+            removes: at(1),
+            bytes_written: at(2),
+        }
+    }
+
+    pub(super) fn to_bytes(self) -> [u8; LEN] {
+        let mut bytes = [0; LEN];
+        for (chunk, value) in bytes.chunks_exact_mut(8).zip([self.adds, self.removes, self.bytes_written]) {
+            chunk.copy_from_slice(&value.to_be_bytes());
+        }
+        bytes
+    }
+}
+
+impl RawStore {
+    /// Counts a change in the counters, if they are kept.
+    pub(super) fn count(&mut self, f: impl FnOnce(&mut OpCounters)) {
+        if let Some(counters) = &mut self.counters {
+            f(counters);
+        }
+    }
+
+    /// Whether the counters are kept and have changed since they were last written back.
+    pub(super) fn counters_changed(&self) -> bool {
+        self.counters
+            .is_some_and(|c| self.backing[self.header_length - LEN..self.header_length] != c.to_bytes())
+    }
+
+    /// Writes the counters back to the header, if they are kept and have changed.
+    pub(super) fn write_counters(&mut self) -> Result<(), Error> {
+        let Some(counters) = self.counters.filter(|_| self.counters_changed()) else {
+            return Ok(());
+        };
+        let at = self.header_length - LEN;
+        self.backing[at..self.header_length].copy_from_slice(&counters.to_bytes());
+        self.backing.flush_range(at, LEN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{raw_store::test_util::HEADER, Backing};
+
+    #[test]
+    fn counters() {
+        let mut s = RawStore::options().counters(true).exact_spec_magic(b"spec").new(Backing::new_anon().unwrap()).unwrap();
+        assert_eq!(s.stats().counters, Some(OpCounters::default()));
+        let a = s.add(b"abc").unwrap();
+        let b = s.add(b"defg").unwrap();
+        s.update(a, |b| b.make_ascii_uppercase()).unwrap();
+        s.remove(b, |_| ()).unwrap();
+        let a = s.replace_resize(a, b"xy").unwrap();
+        let expected = OpCounters {
+            adds: 3,
+            removes: 2,
+            bytes_written: 12,
+        };
+        assert_eq!(s.stats().counters, Some(expected));
+        assert_eq!(s.header_length, HEADER.len() + 1 + 4 + LEN);
+
+        // Only what has been flushed survives the store being dropped
+        let dropped = Backing::new_from_buffer(&s.backing).unwrap();
+        let dropped = RawStore::options().exact_spec_magic(b"spec").open(dropped).unwrap();
+        assert_eq!(dropped.stats().counters, Some(OpCounters::default()));
+        s.flush().unwrap();
+        assert_eq!(s.describe().counters, Some(expected));
+
+        s.remove(a, |_| ()).unwrap();
+        let closed = s.close().unwrap().0;
+        for use_free_list in [true, false] {
+            let backing = Backing::new_from_buffer(&closed).unwrap();
+            let options = RawStore::options().exact_spec_magic(b"spec").use_free_list(use_free_list);
+            let mut s = options.open(backing).unwrap();
+            assert_eq!(s.stats().counters, Some(OpCounters { removes: 3, ..expected }));
+            assert_eq!(s.stats().entries, 0);
+            s.verify().unwrap();
+        }
+
+        // Stores without them are unchanged
+        let s = RawStore::options().new(Backing::new_anon().unwrap()).unwrap();
+        assert_eq!(s.stats().counters, None);
+        assert_eq!(s.header_length, HEADER.len() + 1);
+    }
+}
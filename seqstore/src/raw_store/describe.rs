@@ -0,0 +1,249 @@
+use bstr::BString;
+
+use super::{counters, free_list, salvage, OpCounters, RawStore};
+use crate::{
+    error::OpenError,
+    tag::{MagicTag, TagFormat},
+};
+
+/// A description of everything in a store's backing, returned by [`describe`] and
+/// [`RawStore::describe`].
+///
+/// This is a plain description of what is there, for tools to display or compare, and is built
+/// without a logger (unlike [`debug_map`][super::debug_map]) or any of the checks made when opening
+/// a store. Anything unexpected is listed in [`anomalies`][Self::anomalies] rather than failing.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct StoreReport {
+    /// The version in the header, see [`OpenError::UnknownVersion`].
+    pub version: [u8; 2],
+    /// The specialized magic bytes in the header, see
+    /// [`OpenStoreOptions`][super::OpenStoreOptions#header-specialization].
+    pub spec_magic: BString,
+    /// The length of the header, which is where the first region starts.
+    pub header_length: usize,
+    /// The counters at the end of the header, if the store keeps them, as last written back, see
+    /// [`OpenStoreOptions::counters`][super::OpenStoreOptions::counters].
+    pub counters: Option<OpCounters>,
+    /// Every region up to the End tag (or up to the first that could not be read), in order.
+    pub regions: Vec<Region>,
+    /// Where the End tag is, if one was found.
+    pub end: Option<usize>,
+    /// Whether there is a summary of the gaps after the End tag, as written by
+    /// [`close`][RawStore::close].
+    pub free_list: bool,
+    /// Everything that would stop the store being opened as it is, in order.
+    pub anomalies: Vec<Anomaly>,
+}
+
+impl StoreReport {
+    /// Whether nothing unexpected was found.
+    pub fn is_clean(&self) -> bool {
+        self.anomalies.is_empty()
+    }
+}
+
+/// A single tag and the data it covers, see [`StoreReport::regions`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Region {
+    pub kind: RegionKind,
+    /// Where the tag starts.
+    pub at: usize,
+    /// The length of the tag itself.
+    pub tag_length: usize,
+    /// The length of the data after the tag, which includes the generation if the store uses
+    /// them.
+    pub length: usize,
+}
+
+/// What a [`Region`] holds.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum RegionKind {
+    /// A stored item, or the first piece of one.
+    Written,
+    /// Another piece of the item before it, for items stored in several pieces.
+    Continued,
+    /// An item that was never finished being written.
+    Writing,
+    /// A gap left by a removed item.
+    Deleted,
+}
+
+/// Something unexpected found in a store, see [`StoreReport::anomalies`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Anomaly {
+    /// An item that was never finished being written, as after a crash.
+    PartialWrite { position: usize },
+    /// A piece of an item that does not follow the rest of it.
+    OrphanedContinuation { position: usize },
+    /// A tag that is not valid, or that covers more than the rest of the backing. Nothing after it
+    /// can be described, as there is no way to tell where the next region starts.
+    UnreadableTag { position: usize, byte: u8 },
+    /// Data after the End tag, other than a summary of the gaps.
+    DataAfterEnd { end: usize, first_data_at: usize },
+    /// The backing runs out without an End tag.
+    NoEnd,
+}
+
+impl RawStore {
+    /// Describes everything in the backing, see [`StoreReport`].
+    ///
+    /// This goes through the whole store. Changes not yet [flushed][Self::flush] are included.
+    pub fn describe(&self) -> StoreReport {
+        describe(&self.backing).expect("an open store has a valid header")
+    }
+}
+
+/// Describes everything in the bytes of a store's backing, see [`StoreReport`].
+///
+/// This does not need the store to be opened (or even to be openable), so can be used on a copy of
+/// a file that is in use, or is corrupt. Only an invalid header stops it.
+pub fn describe(bytes: &[u8]) -> Result<StoreReport, OpenError> {
+    let too_small = |expected| OpenError::TooSmall {
+        found: bytes.len(),
+        expected,
+    };
+    if bytes.len() < RawStore::HEADER_LENGTH + 1 {
+        return Err(too_small(RawStore::HEADER_LENGTH + 1));
+    }
+    if &bytes[..RawStore::HEADER_MAGIC.len()] != RawStore::HEADER_MAGIC {
+        return Err(OpenError::Magic);
+    }
+    let mut position = RawStore::HEADER_MAGIC.len();
+    let version = [bytes[position], bytes[position + 1]];
+    position += 2;
+    let spec_length = crate::util::read_varint::<u64>(bytes, &mut position)? as usize;
+    let spec_end = position + spec_length;
+    let spec_magic = bytes.get(position..spec_end).ok_or(too_small(spec_end))?;
+    let (header_length, counters) = if version[1] & counters::HEADER_FLAG != 0 {
+        let header_length = spec_end + counters::LEN;
+        let counters = bytes.get(spec_end..header_length).ok_or(too_small(header_length))?;
+        (header_length, Some(OpCounters::read(counters)))
+    } else {
+        (spec_end, None)
+    };
+
+    let free_list = free_list::locate(bytes, header_length);
+    // The summary of the gaps is not part of the store itself
+    let bytes = &bytes[..free_list.map_or(bytes.len(), |(start, _)| start)];
+    let mut report = StoreReport {
+        version,
+        spec_magic: BString::from(spec_magic),
+        header_length,
+        counters,
+        regions: Vec::new(),
+        end: None,
+        free_list: free_list.is_some(),
+        anomalies: Vec::new(),
+    };
+    let tag_format = TagFormat::from_flags(version[1]);
+    let mut position = header_length;
+    let mut written = false;
+    while position < bytes.len() {
+        let at = position;
+        let Some(tag) = salvage::read_tag(bytes, &mut position, tag_format) else {
+            report.anomalies.push(Anomaly::UnreadableTag {
+                position: at,
+                byte: bytes[at],
+            });
+            return Ok(report);
+        };
+        let continues = written;
+        written = matches!(tag, MagicTag::Written { .. } | MagicTag::Continued { .. });
+        let (kind, length) = match tag {
+            MagicTag::End => {
+                report.end = Some(at);
+                if let Some(first_data_at) = (position..bytes.len()).find(|&p| bytes[p] != 0) {
+                    report.anomalies.push(Anomaly::DataAfterEnd { end: at, first_data_at });
+                }
+                return Ok(report);
+            }
+            MagicTag::Written { length } => (RegionKind::Written, length),
+            MagicTag::Continued { length } => {
+                if !continues {
+                    report.anomalies.push(Anomaly::OrphanedContinuation { position: at });
+                }
+                (RegionKind::Continued, length)
+            }
+            MagicTag::Writing { length } => {
+                report.anomalies.push(Anomaly::PartialWrite { position: at });
+                (RegionKind::Writing, length)
+            }
+            MagicTag::Deleted { length } => (RegionKind::Deleted, length),
+        };
+        report.regions.push(Region {
+            kind,
+            at,
+            tag_length: position - at,
+            length: length as usize,
+        });
+        position += length as usize;
+    }
+    report.anomalies.push(Anomaly::NoEnd);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        raw_store::test_util::{prepare, HEADER},
+        Backing,
+    };
+
+    #[test]
+    fn describe() {
+        use crate::raw_store::{Anomaly, RegionKind};
+
+        let backing = prepare!(
+            MagicTag::Written { length: 3 },
+            b"abc",
+            MagicTag::Deleted { length: 2 },
+            [0; 2],
+            MagicTag::Continued { length: 1 },
+            b"d",
+            MagicTag::Writing { length: 1 },
+            b"e",
+        );
+        let report = crate::raw_store::describe(&backing.0).unwrap();
+        assert_eq!(report.spec_magic, "");
+        assert_eq!(
+            report.regions.iter().map(|r| (r.kind, r.at, r.length)).collect::<Vec<_>>(),
+            [
+                (RegionKind::Written, HEADER.len() + 1, 3),
+                (RegionKind::Deleted, HEADER.len() + 5, 2),
+                (RegionKind::Continued, HEADER.len() + 8, 1),
+                (RegionKind::Writing, HEADER.len() + 10, 1),
+            ]
+        );
+        assert_eq!(report.end, Some(HEADER.len() + 12));
+        assert_eq!(
+            report.anomalies,
+            [
+                Anomaly::OrphanedContinuation { position: HEADER.len() + 8 },
+                Anomaly::PartialWrite { position: HEADER.len() + 10 },
+            ]
+        );
+
+        let mut s = RawStore::options().exact_spec_magic(b"spec").new(Backing::new_anon().unwrap()).unwrap();
+        let id = s.add(b"a").unwrap();
+        s.add(b"b").unwrap();
+        s.remove(id, |_| ()).unwrap();
+        let report = s.describe();
+        assert!(report.is_clean());
+        assert_eq!(report.spec_magic, "spec");
+        assert_eq!(report.regions.len(), 2);
+        let backing = s.close().unwrap();
+        let report = crate::raw_store::describe(&backing.0).unwrap();
+        assert!(report.free_list && report.is_clean(), "{report:?}");
+
+        let mut bytes = backing.0.to_vec();
+        bytes.truncate(report.end.unwrap());
+        bytes.push(0b010_00000);
+        let report = crate::raw_store::describe(&bytes).unwrap();
+        let position = bytes.len() - 1;
+        assert_eq!(report.anomalies, [Anomaly::UnreadableTag { position, byte: 0b010_00000 }]);
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    borrow::Cow,
+    io::{BufRead, Write},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::RawStore;
+use crate::{error::Error, Id};
+
+/// A single line of a dump, see [`RawStore::dump`].
+#[derive(Serialize, Deserialize)]
+struct DumpEntry<'a> {
+    id: Id,
+    #[serde(serialize_with = "to_base64", deserialize_with = "from_base64")]
+    data: Cow<'a, [u8]>,
+}
+
+fn to_base64<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&STANDARD.encode(data))
+}
+
+fn from_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Cow<'static, [u8]>, D::Error> {
+    let data = <Cow<'de, str>>::deserialize(deserializer)?;
+    STANDARD.decode(data.as_bytes()).map(Cow::Owned).map_err(serde::de::Error::custom)
+}
+
+impl RawStore {
+    /// Writes every item in the store to `writer` as [JSON Lines](https://jsonlines.org), one
+    /// object per item holding its [`Id`] (as a number, see [`PackedId`][crate::PackedId]) and its
+    /// data (as base64), in the order of [`iter`][Self::iter].
+    ///
+    /// This is meant for debugging and for moving items between stores (see
+    /// [`load_dump`][Self::load_dump]), not as a backup format: nothing about the store itself,
+    /// such as its spec magic, is included.
+    pub fn dump(&self, mut writer: impl Write) -> Result<(), Error> {
+        for item in self.iter() {
+            let (id, data) = item?;
+            serde_json::to_writer(&mut writer, &DumpEntry { id, data }).map_err(Error::Dump)?;
+            writer.write_all(b"\n").map_err(|e| Error::Dump(serde_json::Error::io(e)))?;
+        }
+        writer.flush().map_err(|e| Error::Dump(serde_json::Error::io(e)))
+    }
+
+    /// [`add`][Self::add]s every item in a dump written by [`dump`][Self::dump], returning the
+    /// [`Id`] each had in the dumped store alongside its new one, in the order they were dumped.
+    ///
+    /// The new [`Id`]s are only the same as the old ones if this is the first thing done to a new
+    /// store with the same options as the dumped one, and the dumped one had no gaps, so anything
+    /// holding on to [`Id`]s should be updated from the returned pairs.
+    ///
+    /// If reading the dump fails part way through, the items added so far are left in the store.
+    pub fn load_dump(&mut self, reader: impl BufRead) -> Result<Vec<(Id, Id)>, Error> {
+        let mut ids = Vec::new();
+        for line in reader.lines() {
+            let line = line.map_err(|e| Error::Dump(serde_json::Error::io(e)))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = serde_json::from_str::<DumpEntry>(&line).map_err(Error::Dump)?;
+            ids.push((entry.id, self.add(&entry.data)?));
+        }
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Backing;
+
+    #[test]
+    fn dump() {
+        let mut s = RawStore::options().generations(true).new(Backing::new_anon().unwrap()).unwrap();
+        let ids = (0..20_u8).map(|i| s.add(&vec![i; i as usize]).unwrap()).collect::<Vec<_>>();
+        for &id in ids.iter().step_by(3) {
+            s.remove(id, |_| ()).unwrap();
+        }
+        let mut dump = Vec::new();
+        s.dump(&mut dump).unwrap();
+        assert_eq!(dump.iter().filter(|&&b| b == b'\n').count(), s.stats().entries);
+
+        let mut loaded = RawStore::options().new(Backing::new_anon().unwrap()).unwrap();
+        let pairs = loaded.load_dump(&dump[..]).unwrap();
+        assert_eq!(pairs.len(), s.stats().entries);
+        for (old, new) in pairs {
+            assert_eq!(s.get(old, <[u8]>::to_vec).unwrap(), loaded.get(new, <[u8]>::to_vec).unwrap());
+        }
+
+        let e = loaded.load_dump(&b"{\"id\": 1, \"data\": \"not base64!\"}"[..]).unwrap_err();
+        assert!(matches!(e, Error::Dump(_)), "{e:?}");
+    }
+}
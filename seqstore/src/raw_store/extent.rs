@@ -0,0 +1,238 @@
+use std::{borrow::Cow, ops::Range};
+
+use super::RawStore;
+use crate::{
+    error::Error,
+    tag::{MagicTag, TagFormat},
+};
+
+// Entries longer than `TagFormat::max_length` are stored as a chain of pieces laid out one after the
+// other: the first piece has a normal Writing/Written tag, and is followed by the rest, each with a
+// Continued tag. The whole chain takes on the state of the first tag, so it is still made visible
+// with a single flip from Writing to Written.
+//
+// Once removed, every piece becomes a gap of its own, as a single Deleted tag cannot span them all.
+
+/// A piece of an entry after the first, see [`MagicTag::Continued`].
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Piece {
+    pub(crate) at: usize,
+    pub(crate) tag_len: usize,
+    pub(crate) length: usize,
+}
+
+impl Piece {
+    pub(crate) fn data(&self) -> Range<usize> {
+        self.at + self.tag_len..self.end()
+    }
+
+    pub(crate) fn end(&self) -> usize {
+        self.at + self.tag_len + self.length
+    }
+}
+
+/// Iterates over the pieces that continue the entry whose first piece ends at `position`.
+pub(crate) fn continuations(backing: &[u8], position: usize, format: TagFormat) -> Continuations<'_> {
+    Continuations { backing, position, format }
+}
+
+#[derive(Debug)]
+pub(crate) struct Continuations<'a> {
+    backing: &'a [u8],
+    position: usize,
+    format: TagFormat,
+}
+
+impl Iterator for Continuations<'_> {
+    type Item = Result<Piece, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.backing.len() {
+            return None;
+        }
+        let at = self.position;
+        let mut position = at;
+        match MagicTag::read(self.backing, &mut position, self.format) {
+            Ok(MagicTag::Continued { length }) => {
+                let piece = Piece {
+                    at,
+                    tag_len: position - at,
+                    length: length as usize,
+                };
+                self.position = piece.end();
+                Some(Ok(piece))
+            }
+            Ok(_) => None,
+            Err(e) => {
+                self.position = self.backing.len();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Returns the bytes of the entry whose first piece holds `data`, and where the entry ends.
+///
+/// These are only copied if the entry is in more than one piece.
+pub(crate) fn entry(backing: &[u8], data: Range<usize>, format: TagFormat) -> Result<(Cow<'_, [u8]>, usize), Error> {
+    let mut pieces = continuations(backing, data.end, format).peekable();
+    if pieces.peek().is_none() {
+        return Ok((Cow::Borrowed(&backing[data.clone()]), data.end));
+    }
+    let mut bytes = backing[data.clone()].to_vec();
+    let mut end = data.end;
+    for piece in pieces {
+        let piece = piece?;
+        bytes.extend_from_slice(&backing[piece.data()]);
+        end = piece.end();
+    }
+    Ok((Cow::Owned(bytes), end))
+}
+
+impl RawStore {
+    /// [`add`][Self::add]s an entry longer than fits in a single piece, as for
+    /// [`add_with`][Self::add_with].
+    ///
+    /// These always go at the end of the store, as no gap is ever large enough to hold them, and
+    /// where the entry starts is returned.
+    pub(super) fn add_chained(&mut self, length: usize, mut fill: impl FnMut(&mut [u8]) -> Result<(), Error>) -> Result<usize, Error> {
+        let start = self.end;
+        let mut position = start;
+        let mut remaining = length;
+        while remaining > 0 {
+            let piece = remaining.min(self.tag_format.max_length() as usize);
+            let tag = if position == start {
+                MagicTag::Writing { length: piece as u64 }
+            } else {
+                MagicTag::Continued { length: piece as u64 }
+            };
+            let grown = tag
+                .write(&mut self.backing, &mut position, self.tag_format)
+                .and_then(|()| self.backing.resize_for(position + piece));
+            if let Err(e) = grown {
+                self.abandon(start, None, position)?;
+                return Err(e);
+            }
+            if let Err(e) = fill(&mut self.backing[position..position + piece]) {
+                self.abandon(start, None, position + piece)?;
+                return Err(e);
+            }
+            position += piece;
+            remaining -= piece;
+        }
+        self.end = position;
+        MagicTag::End.write(&mut self.backing, &mut position, self.tag_format)?;
+        self.flush_start_end(start, position)?;
+        self.finish_entry(start)?;
+        self.entries += 1;
+        self.entry_bytes += length;
+        self.count(|c| {
+            c.adds += 1;
+            c.bytes_written += length as u64;
+        });
+        self.changed()?;
+
+        Ok(start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::OpenError,
+        raw_store::{test_util::prepare, Gap, RecoveryStrategy},
+        Backing,
+    };
+
+    #[test]
+    fn continued() {
+        let backing = || {
+            prepare!(
+                MagicTag::Written { length: 3 },
+                b"abc",
+                MagicTag::Continued { length: 2 },
+                b"de",
+                MagicTag::Written { length: 1 },
+                b"f",
+            )
+        };
+        let mut s = RawStore::options().open(backing()).unwrap();
+        assert_eq!((s.stats().entries, s.stats().entry_bytes), (2, 6));
+        let items = s.iter().map(|i| i.map(|(id, b)| (id, b.into_owned()))).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(items.iter().map(|(_, b)| &b[..]).collect::<Vec<_>>(), [&b"abcde"[..], b"f"]);
+        let (abcde, f) = (items[0].0, items[1].0);
+        assert_eq!(s.get(abcde, |b| b.to_vec()).unwrap(), b"abcde");
+        assert_eq!(&*s.get_ref(abcde).unwrap(), b"abcde");
+        let mut buffer = b"old".to_vec();
+        s.get_into(f, &mut buffer).unwrap();
+        assert_eq!(buffer, b"f");
+        s.update(abcde, |b| b.make_ascii_uppercase()).unwrap();
+        s.update(f, |b| b.make_ascii_uppercase()).unwrap();
+        assert_eq!(s.remove(abcde, |b| b.to_vec()).unwrap(), b"ABCDE");
+        assert_eq!(s.get(f, |b| b.to_vec()).unwrap(), b"F");
+        s.verify().unwrap();
+
+        let backing = || prepare!(MagicTag::Writing { length: 3 }, b"abc", MagicTag::Continued { length: 2 }, b"de");
+        let e = RawStore::options().open(backing()).unwrap_err();
+        assert!(matches!(e, OpenError::PartialWrite { length: 3, .. }), "{e:?}");
+        let mut s = RawStore::options().recovery_strategy(RecoveryStrategy::Rollback).open(backing()).unwrap();
+        assert_eq!((s.stats().entries, s.stats().gaps), (0, 2));
+        s.verify().unwrap();
+    }
+
+    #[test]
+    fn large_entry() {
+        let mut s = RawStore::options().new(Backing::new_anon().unwrap()).unwrap();
+        let small = s.add(b"small").unwrap();
+        let large = (0..TagFormat::V1.max_length() as usize + 100).map(|i| i as u8).collect::<Vec<_>>();
+        let id = s.add_from_reader(large.len(), &large[..]).unwrap();
+        assert!(s.get(id, |b| b == large).unwrap());
+
+        let mut s = RawStore::options().use_free_list(false).open(s.close().unwrap()).unwrap();
+        assert!(s.get(id, |b| b == large).unwrap());
+        assert_eq!(s.stats().entry_bytes, large.len() + 5);
+        assert!(s.remove(id, |b| b == large).unwrap());
+        s.truncate_to_end().unwrap();
+        s.verify().unwrap();
+        assert_eq!(s.stats().gaps, 0);
+        assert_eq!(s.get(small, |b| b.to_vec()).unwrap(), b"small");
+    }
+
+    #[test]
+    fn large_entries() {
+        use crate::raw_store::RegionKind;
+
+        let kinds = |s: &RawStore| s.describe().regions.iter().map(|r| (r.kind, r.length)).collect::<Vec<_>>();
+        let mut s = RawStore::options().large_entries(true).new(Backing::new_anon().unwrap()).unwrap();
+        assert_ne!(s.describe().version[1] & crate::tag::HEADER_FLAG, 0);
+        s.add(b"small").unwrap();
+        let large = (0..TagFormat::V1.max_length() as usize + 100).map(|i| i as u8).collect::<Vec<_>>();
+        let id = s.add_from_reader(large.len(), &large[..]).unwrap();
+        let after = s.add(b"after").unwrap();
+        assert_eq!(kinds(&s), [(RegionKind::Written, 5), (RegionKind::Written, large.len()), (RegionKind::Written, 5)]);
+        assert!(matches!(s.get_ref(id).unwrap().0, Cow::Borrowed(_)));
+
+        // Unlike an entry in several pieces, it leaves a single gap, which another can go into
+        assert!(s.remove(id, |b| b == large).unwrap());
+        assert_eq!(s.stats().gaps, 1);
+        let end = s.end;
+        let id = s.add_from_reader(large.len(), &large[..]).unwrap();
+        assert_eq!((s.end, s.stats().gaps), (end, 0));
+
+        // The format is kept when reopening, whatever the options
+        let mut s = RawStore::options().large_entries(false).open(s.close().unwrap()).unwrap();
+        assert!(s.get(id, |b| b == large).unwrap());
+        s.remove(id, |_| ()).unwrap();
+        let mut s = RawStore::options().open(s.close().unwrap()).unwrap();
+        assert_eq!(s.gaps, [Gap { at: id.at(), length: large.len(), tag_len: 6 }]);
+        s.verify().unwrap();
+        let id = s.add_from_reader(large.len(), &large[..]).unwrap();
+        assert_eq!(kinds(&s)[1], (RegionKind::Written, large.len()));
+
+        let s = RawStore::options().use_free_list(false).open(s.close().unwrap()).unwrap();
+        assert!(s.get(id, |b| b == large).unwrap());
+        assert_eq!(s.get(after, <[u8]>::to_vec).unwrap(), b"after");
+        assert_eq!(s.stats().entries, 3);
+    }
+}
@@ -1,15 +1,33 @@
+use std::collections::HashSet;
+
+use super::{counters, extent};
 use crate::{backing::BackingInner, error::Error, raw_store::RawStore, tag::MagicTag, Backing, Id};
 
 impl RawStore {
+    /// Starts copying a chosen subset of the items in this store into `to`, see [`Filter`].
     pub fn filter(&self, to: Backing) -> Result<Filter<'_>, Error> {
         Filter::new(self, to)
     }
 }
 
+/// A copy of a [`RawStore`] that only keeps the items it is given, created by [`RawStore::filter`].
+///
+/// Every item [`add`][Self::add]ed is copied to the same position in `to` as it had in the
+/// original store, and everything in between becomes gaps, so every [`Id`] of a kept item is just
+/// as valid in the copy. This means that a filtered copy can replace the original without having
+/// to update any [`Id`]s held elsewhere, which is what [`RawStore::compact_to`] relies on for the
+/// guarantee described in [`CompactReport`][super::CompactReport]. It also means that the copy is
+/// no smaller than the original was up to its last kept item: the space freed is only reused by
+/// later additions.
+///
+/// Items can either be chosen one at a time by their [`Id`] with [`add`][Self::add], or by their
+/// contents with [`retain`][Self::retain], which goes through the whole store itself.
 #[derive(Debug)]
 pub struct Filter<'a> {
     store: &'a RawStore,
     to: BackingInner,
+    /// Where each item kept so far starts.
+    kept: HashSet<usize>,
 }
 
 impl<'a> Filter<'a> {
@@ -17,30 +35,86 @@ impl<'a> Filter<'a> {
         let mut to = to.0;
         to.resize_for(store.header_length)?;
         to[..store.header_length].copy_from_slice(&store.backing[..store.header_length]);
-        Ok(Self { store, to })
+        // The counters carry on from the original's, including anything not yet written back
+        if let Some(c) = store.counters {
+            to[store.header_length - counters::LEN..store.header_length].copy_from_slice(&c.to_bytes());
+        }
+        Ok(Self {
+            store,
+            to,
+            kept: HashSet::new(),
+        })
     }
 
+    /// Keeps the item at `at`, which must be valid in the original store.
     pub fn add(&mut self, at: Id) -> Result<(), Error> {
-        let mut position = at.at();
-        let tag = MagicTag::read(&self.store.backing, &mut position)?;
+        let start = self.store.position(at);
+        let mut position = start;
+        let tag = MagicTag::read(&self.store.backing, &mut position, self.store.tag_format)?;
         match tag {
-            MagicTag::Writing { .. } => Err(Error::EntryCorrupt { position: at.at() }),
+            MagicTag::Writing { .. } => Err(Error::EntryCorrupt { position: start }),
             MagicTag::Written { length } => {
-                at.verify(length)?;
-                self.to.resize_for(position + length as usize)?;
-                let b = &self.store.backing[at.at()..position + length as usize];
-                self.to[at.at()..position + length as usize].copy_from_slice(b);
+                let (bytes, end) = extent::entry(&self.store.backing, position..position + length as usize, self.store.tag_format)?;
+                at.verify(bytes.len() as u64)?;
+                self.store.check_generation(at, &bytes)?;
+                self.to.resize_for(end)?;
+                self.to[start..end].copy_from_slice(&self.store.backing[start..end]);
+                self.kept.insert(start);
                 Ok(())
             }
             other => Err(Error::IncorrectTag {
-                position: at.at(),
+                position: start,
                 found: other.into(),
                 expected_kind: "Written",
             }),
         }
     }
 
+    /// Keeps every item in the original store for which `keep` returns `true`.
+    ///
+    /// This goes through the store as [`RawStore::iter`] does, so stops at the first item that
+    /// cannot be read.
+    pub fn retain(&mut self, keep: impl FnMut(Id, &[u8]) -> bool) -> Result<(), Error> {
+        self.retain_with_progress(keep, |_, _| ())
+    }
+
+    /// Like [`retain`][Self::retain], but also calls `progress` after each item with how many bytes
+    /// of the original store have been gone through so far and how many there are in total.
+    ///
+    /// The last call is always with both equal, once the whole store has been gone through.
+    pub fn retain_with_progress(
+        &mut self, mut keep: impl FnMut(Id, &[u8]) -> bool, mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), Error> {
+        let store = self.store;
+        let total = store.end - store.header_length;
+        for item in store.iter() {
+            let (id, bytes) = item?;
+            if keep(id, &bytes) {
+                self.add(id)?;
+            }
+            progress(store.position(id) - store.header_length, total);
+        }
+        progress(total, total);
+        Ok(())
+    }
+
+    /// The [`Id`]s of every item in the original store that has not been kept, in the order they
+    /// are stored in.
+    pub(super) fn not_kept(&self) -> Result<Vec<Id>, Error> {
+        let mut not_kept = Vec::new();
+        for item in self.store.iter() {
+            let (id, _) = item?;
+            if !self.kept.contains(&self.store.position(id)) {
+                not_kept.push(id);
+            }
+        }
+        Ok(not_kept)
+    }
+
+    /// Fills in the gaps between the kept items and writes the end of the store, after which `to`
+    /// can be [open][super::OpenStoreOptions::open]ed.
     pub fn finish(mut self) -> Result<(), Error> {
+        let format = self.store.tag_format;
         let mut position = self.store.header_length;
         loop {
             if position >= self.to.len() {
@@ -51,19 +125,19 @@ impl<'a> Filter<'a> {
                 if position + zero_run >= self.to.len() {
                     break;
                 }
-                let (tag_len, len) = MagicTag::calc_tag_len(zero_run);
-                MagicTag::Deleted { length: len as u64 }.write_exact(&mut self.to, &mut position, tag_len as usize)?;
+                let (tag_len, len) = MagicTag::calc_tag_len(zero_run, format);
+                MagicTag::Deleted { length: len as u64 }.write_exact(&mut self.to, &mut position, tag_len as usize, format)?;
                 position += len;
             } else {
-                let tag = MagicTag::read(&self.to, &mut position)?;
-                let MagicTag::Written { length } = tag else {
-                    unreachable!("only Written tags are copied across")
+                let tag = MagicTag::read(&self.to, &mut position, format)?;
+                let (MagicTag::Written { length } | MagicTag::Continued { length }) = tag else {
+                    unreachable!("only Written entries are copied across")
                 };
                 position += length as usize;
             }
         }
-        MagicTag::End.write(&mut self.to, &mut position)?;
+        MagicTag::End.write(&mut self.to, &mut position, format)?;
         self.to.flush()?;
-        Ok(())
+        self.to.resize_to(position)
     }
 }
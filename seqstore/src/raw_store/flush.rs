@@ -0,0 +1,100 @@
+use std::time::{Duration, Instant};
+
+use super::RawStore;
+use crate::error::Error;
+
+/// When changes to a [`RawStore`] are flushed to its [`Backing`][crate::Backing], set with
+/// [`OpenStoreOptions::flush_policy`][super::OpenStoreOptions::flush_policy].
+///
+/// Flushing after every change is what makes every [`add`][RawStore::add] atomic: the item is
+/// completely written before it is marked as such. With any other policy, a crash can lose the
+/// changes made since the last flush, and can leave items that were partially written but look
+/// complete, so these are only suitable if the caller can detect and recover from that
+/// (_e.g._ by keeping its own log and rebuilding the store from it).
+///
+/// The backing is always flushed completely on [`close`][RawStore::close] and
+/// [`flush`][RawStore::flush]. To flush on a timer even while nothing is changing, share the store
+/// in a [`SyncStore`][crate::SyncStore] and use [`SyncStore::spawn_flusher`][crate::SyncStore::spawn_flusher].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum FlushPolicy {
+    /// Flush as part of every change.
+    #[default]
+    FlushEachOp,
+    /// Only flush when [`flush`][RawStore::flush] is called.
+    Manual,
+    /// Flush after every `n` changes.
+    EveryNOps(usize),
+    /// Flush on the first change at least this long after the last flush.
+    ///
+    /// Nothing is flushed while the store is not being changed, so this needs to be combined with
+    /// calling [`flush`][RawStore::flush] once changes stop.
+    EveryDuration(Duration),
+}
+
+/// Tracks what has not been flushed yet, for policies other than [`FlushPolicy::FlushEachOp`].
+#[derive(Debug)]
+pub(super) struct Flushing {
+    pub(super) policy: FlushPolicy,
+    pub(super) start: usize,
+    pub(super) end: usize,
+    ops: usize,
+    last: Instant,
+}
+
+impl Flushing {
+    pub(super) fn new(policy: FlushPolicy) -> Self {
+        Self {
+            policy,
+            start: usize::MAX,
+            end: 0,
+            ops: 0,
+            last: Instant::now(),
+        }
+    }
+
+    /// Whether flushes are made as soon as anything is written.
+    pub(super) fn immediate(&self) -> bool {
+        self.policy == FlushPolicy::FlushEachOp
+    }
+
+    pub(super) fn mark(&mut self, start: usize, end: usize) {
+        self.start = self.start.min(start);
+        self.end = self.end.max(end);
+    }
+}
+
+impl RawStore {
+    /// Flushes every change not yet flushed, see [`FlushPolicy`].
+    ///
+    /// With the default [`FlushPolicy::FlushEachOp`], there is never anything left to flush apart
+    /// from the [counters][super::OpenStoreOptions::counters], if they are kept.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.write_counters()?;
+        let (start, end) = (self.flushing.start, self.flushing.end);
+        if start < end {
+            self.backing.flush_start_end(start, end)?;
+        }
+        self.flushing = Flushing::new(self.flushing.policy);
+        Ok(())
+    }
+
+    /// Whether there is anything for [`flush`][Self::flush] to do, _i.e._ whether any change could
+    /// still be lost in a crash.
+    pub fn has_unflushed(&self) -> bool {
+        self.flushing.start < self.flushing.end || self.counters_changed()
+    }
+
+    /// Called once every change has been made, to flush if the policy asks for it.
+    pub(super) fn changed(&mut self) -> Result<(), Error> {
+        self.flushing.ops += 1;
+        let due = match self.flushing.policy {
+            FlushPolicy::FlushEachOp | FlushPolicy::Manual => false,
+            FlushPolicy::EveryNOps(n) => self.flushing.ops >= n,
+            FlushPolicy::EveryDuration(every) => self.flushing.last.elapsed() >= every,
+        };
+        if due {
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
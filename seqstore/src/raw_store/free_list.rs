@@ -0,0 +1,196 @@
+use varuint::WriteVarint;
+
+use super::{Gap, RawStore};
+use crate::{backing::BackingInner, error::Error, tag::MagicTag};
+
+// The free list is written to the very end of the backing on a clean close, after the End tag and
+// its zero padding:
+//
+// [entries][entry bytes][gap count]([at][length][tag_len: u8])*  - varints unless noted
+// [next generation]                                             - only if generations are used
+// [free list start: u64 BE][end: u64 BE][FREE_LIST_MAGIC]       - the trailer
+//
+// It is only valid until the store is next changed, so it is zeroed as soon as it is read, which
+// means a crash after opening always falls back to a full scan.
+//
+// Whether there may be a free list is recorded in the second byte of the header version, so that
+// anything unaware of free lists refuses the store rather than finding data after the End tag. The
+// flag is set before the free list is written and cleared after it is zeroed, so there is never a
+// free list without it.
+
+const FREE_LIST_MAGIC: &[u8; 8] = b"\x1FPLFfree";
+const TRAILER_LENGTH: usize = 8 + 8 + FREE_LIST_MAGIC.len();
+/// The bit set in the second byte of the header version while there may be a free list.
+pub(super) const HEADER_FLAG: u8 = 0b100;
+/// Where the second byte of the header version is.
+const FLAGS_AT: usize = RawStore::HEADER_MAGIC.len() + 1;
+
+/// What is needed to open a store without walking every tag.
+#[derive(Debug)]
+pub(super) struct FreeList {
+    pub(super) end: usize,
+    pub(super) gaps: Vec<Gap>,
+    pub(super) entries: usize,
+    pub(super) entry_bytes: usize,
+    pub(super) generation: Option<u16>,
+}
+
+impl RawStore {
+    /// Writes the free list after the End tag, so that the next [`open`][super::OpenStoreOptions::open]
+    /// does not have to walk every tag to find the gaps.
+    pub(super) fn write_free_list(&mut self) -> Result<(), Error> {
+        fn push(list: &mut Vec<u8>, n: u64) {
+            list.write_varint(n).expect("writing to a Vec cannot fail");
+        }
+
+        let mut list = Vec::new();
+        push(&mut list, self.entries as u64);
+        push(&mut list, self.entry_bytes as u64);
+        push(&mut list, self.gaps.len() as u64);
+        for gap in &self.gaps {
+            push(&mut list, gap.at as u64);
+            push(&mut list, gap.length as u64);
+            list.push(gap.tag_len);
+        }
+        if let Some(generation) = self.generations {
+            push(&mut list, generation as u64);
+        }
+        self.backing[FLAGS_AT] |= HEADER_FLAG;
+        self.backing.flush_range(FLAGS_AT, 1)?;
+        self.backing.resize_for(self.end + MagicTag::End.written_length(self.tag_format) + list.len() + TRAILER_LENGTH)?;
+        let trailer = self.backing.len() - TRAILER_LENGTH;
+        let start = trailer - list.len();
+        self.backing[start..trailer].copy_from_slice(&list);
+        // The trailer only goes in once the list itself is on disk, otherwise a crash part way
+        // through could leave a valid-looking trailer in front of a partial list
+        self.backing.flush_range(start, list.len())?;
+        self.backing[trailer..trailer + 8].copy_from_slice(&(start as u64).to_be_bytes());
+        self.backing[trailer + 8..trailer + 16].copy_from_slice(&(self.end as u64).to_be_bytes());
+        self.backing[trailer + 16..].copy_from_slice(FREE_LIST_MAGIC);
+        self.backing.flush_range(trailer, TRAILER_LENGTH)
+    }
+}
+
+/// Reads and removes the free list written by [`RawStore::write_free_list`], if there is one.
+///
+/// It is left in place if the backing is read-only, as the store cannot be changed anyway.
+///
+/// `Ok(None)` is returned if there is none, or if it could not be used, in which case the store
+/// must be scanned as normal.
+pub(super) fn take_free_list(
+    backing: &mut BackingInner, header_length: usize, generations: bool,
+) -> Result<Option<FreeList>, Error> {
+    let len = backing.len();
+    let located = locate(backing, header_length);
+    let list = located.and_then(|(start, end)| {
+        let valid = backing[end] == MagicTag::END && backing[end + 1..start].iter().all(|&b| b == 0);
+        valid.then(|| read(&backing[start..len - TRAILER_LENGTH], end, header_length, generations)).flatten()
+    });
+    if backing.is_writable() && backing[FLAGS_AT] & HEADER_FLAG != 0 {
+        if let Some((start, _)) = located {
+            backing[start..].fill(0);
+            backing.flush_range(start, len - start)?;
+        }
+        backing[FLAGS_AT] &= !HEADER_FLAG;
+        backing.flush_range(FLAGS_AT, 1)?;
+    }
+    Ok(list)
+}
+
+/// Where the free list at the end of `backing` starts, and where it says the store ends, if there
+/// looks to be one.
+pub(super) fn locate(backing: &[u8], header_length: usize) -> Option<(usize, usize)> {
+    let len = backing.len();
+    if backing[FLAGS_AT] & HEADER_FLAG == 0 {
+        return None;
+    }
+    if len < header_length + TRAILER_LENGTH || &backing[len - FREE_LIST_MAGIC.len()..] != FREE_LIST_MAGIC {
+        return None;
+    }
+    let trailer = len - TRAILER_LENGTH;
+    let start = u64::from_be_bytes(backing[trailer..trailer + 8].try_into().unwrap()) as usize;
+    let end = u64::from_be_bytes(backing[trailer + 8..trailer + 16].try_into().unwrap()) as usize;
+    // Otherwise it is not something we wrote, so is left for the scan to report
+    (end >= header_length && start > end && start <= trailer).then_some((start, end))
+}
+
+fn read(list: &[u8], end: usize, header_length: usize, generations: bool) -> Option<FreeList> {
+    let read = |position: &mut usize| crate::util::read_varint::<u64>(list, position).ok().map(|n| n as usize);
+    let mut position = 0;
+    let entries = read(&mut position)?;
+    let entry_bytes = read(&mut position)?;
+    let count = read(&mut position)?;
+    let mut gaps = Vec::with_capacity(count.min(list.len()));
+    for _ in 0..count {
+        let at = read(&mut position)?;
+        let length = read(&mut position)?;
+        let tag_len = *list.get(position)?;
+        position += 1;
+        // A corrupted length could be large enough to overflow
+        let gap_end = at.checked_add(tag_len as usize)?.checked_add(length)?;
+        if at < header_length || gap_end > end {
+            return None;
+        }
+        gaps.push(Gap { at, length, tag_len });
+    }
+    let generation = if generations {
+        Some(u16::try_from(read(&mut position)?).ok()?)
+    } else {
+        None
+    };
+    (position == list.len()).then_some(FreeList {
+        end,
+        gaps,
+        entries,
+        entry_bytes,
+        generation,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::OpenError, Backing};
+
+    #[test]
+    fn free_list() {
+        let mut s = RawStore::options().new(Backing::new_anon().unwrap()).unwrap();
+        let ids = (0..40_u8).map(|i| s.add(&vec![i; i as usize * 3]).unwrap()).collect::<Vec<_>>();
+        for (i, id) in ids.into_iter().enumerate() {
+            if i % 3 != 0 {
+                s.remove(id, |_| ()).unwrap();
+            }
+        }
+        let closed = s.close().unwrap().0;
+        assert!(closed.ends_with(b"\x1FPLFfree"));
+        assert_eq!(closed[RawStore::HEADER_MAGIC.len() + 1], HEADER_FLAG);
+        let scan = || {
+            let backing = Backing::new_from_buffer(&closed).unwrap();
+            RawStore::options().use_free_list(false).open(backing).unwrap()
+        };
+        let listed = RawStore::options().open(Backing::new_from_buffer(&closed).unwrap()).unwrap();
+        let scanned = scan();
+
+        let sorted = |s: &RawStore| {
+            let mut gaps = s.gaps.clone();
+            gaps.sort_by_key(|g| g.at);
+            gaps
+        };
+        assert_eq!(sorted(&listed), sorted(&scanned));
+        assert_eq!(listed.end, scanned.end);
+        assert_eq!(listed.stats(), scanned.stats());
+        assert!(listed.backing[listed.end + 1..].iter().all(|&b| b == 0));
+        assert_eq!(listed.backing[RawStore::HEADER_MAGIC.len() + 1], 0);
+
+        // Without the flag, the trailer is not ours, so is just data after the End tag
+        let mut unflagged = closed.to_vec();
+        unflagged[RawStore::HEADER_MAGIC.len() + 1] = 0;
+        let e = RawStore::options().open(Backing::new_from_buffer(&unflagged).unwrap()).unwrap_err();
+        assert!(matches!(e, OpenError::DataAfterEnd { .. }), "{e:?}");
+        assert!(scanned.is_verified());
+        assert!(!listed.is_verified());
+        let mut listed = listed;
+        listed.verify().unwrap();
+        assert!(listed.is_verified());
+    }
+}
@@ -0,0 +1,89 @@
+use std::borrow::Cow;
+
+use super::RawStore;
+use crate::{error::Error, Id};
+
+// With generations turned on (see `OpenStoreOptions::generations`), every entry starts with the
+// generation it was written with, as a big-endian u16 taken from a counter that goes up with every
+// entry written. The same generation is kept in the top bits of the position held by its Id, so an
+// Id whose entry has since been removed is rejected even if another entry of the same length has
+// been written in its place, which the length marker alone cannot tell apart.
+//
+// Whether a store uses generations is recorded in the second byte of the header version, as a store
+// written with them cannot be read without them.
+
+/// The bytes taken up by the generation at the start of each entry.
+pub(super) const LEN: usize = 2;
+/// The bit set in the second byte of the header version when generations are used.
+pub(super) const HEADER_FLAG: u8 = 0b1;
+/// Positions are limited to this many bits, so that the generation fits above them in an [`Id`].
+const SHIFT: u32 = 40;
+
+impl RawStore {
+    /// Where the entry referred to by `at` starts.
+    pub(super) fn position(&self, at: Id) -> usize {
+        match self.generations {
+            Some(_) => at.at() & ((1 << SHIFT) - 1),
+            None => at.at(),
+        }
+    }
+
+    /// The [`Id`] of the entry of `length` bytes (generation included) at `start`.
+    pub(super) fn make_id(&self, start: usize, length: usize, generation: u16) -> Id {
+        match self.generations {
+            Some(_) => {
+                assert!(start < 1 << SHIFT, "too big");
+                Id::new(((generation as usize) << SHIFT) | start, length)
+            }
+            None => Id::new(start, length),
+        }
+    }
+
+    /// Takes the generation for a new entry, if generations are used.
+    pub(super) fn next_generation(&mut self) -> Option<u16> {
+        let next = self.generations.as_mut()?;
+        let generation = *next;
+        *next = next.wrapping_add(1);
+        Some(generation)
+    }
+
+    /// Checks that the entry whose bytes start with `bytes` was written with the generation in `at`,
+    /// returning how many bytes the generation takes up.
+    pub(super) fn check_generation(&self, at: Id, bytes: &[u8]) -> Result<usize, Error> {
+        if self.generations.is_none() {
+            return Ok(0);
+        }
+        match read(bytes) {
+            Some(generation) if generation == (at.at() >> SHIFT) as u16 => Ok(LEN),
+            _ => Err(Error::IdCheck(at)),
+        }
+    }
+
+    /// Checks and removes the generation from the start of the entry `bytes`.
+    pub(super) fn strip_generation<'a>(&self, at: Id, bytes: Cow<'a, [u8]>) -> Result<Cow<'a, [u8]>, Error> {
+        let skip = self.check_generation(at, &bytes)?;
+        Ok(match bytes {
+            Cow::Borrowed(bytes) => Cow::Borrowed(&bytes[skip..]),
+            Cow::Owned(mut bytes) => {
+                bytes.drain(..skip);
+                Cow::Owned(bytes)
+            }
+        })
+    }
+
+    /// The [`Id`] and data of the entry `bytes` found at `start`, as for
+    /// [`strip_generation`][Self::strip_generation] but without knowing the [`Id`] beforehand.
+    pub(super) fn split_generation<'a>(&self, start: usize, bytes: Cow<'a, [u8]>) -> Result<(Id, Cow<'a, [u8]>), Error> {
+        let generation = match self.generations {
+            Some(_) => read(&bytes).ok_or(Error::EntryCorrupt { position: start })?,
+            None => 0,
+        };
+        let id = self.make_id(start, bytes.len(), generation);
+        Ok((id, self.strip_generation(id, bytes)?))
+    }
+}
+
+/// The generation stored at the start of an entry's `bytes`, if it is long enough to have one.
+pub(super) fn read(bytes: &[u8]) -> Option<u16> {
+    bytes.get(..LEN).map(|b| u16::from_be_bytes(b.try_into().unwrap()))
+}
@@ -0,0 +1,79 @@
+use std::{borrow::Cow, iter::FusedIterator};
+
+use super::extent;
+use crate::{error::Error, raw_store::RawStore, tag::MagicTag, Id};
+
+impl RawStore {
+    /// Iterates over every stored item, in the order that they are laid out in the backing (which
+    /// is **not** necessarily the order they were [`add`][Self::add]ed in).
+    ///
+    /// This walks the whole store from start to end, so it is intended for recovery and inspection
+    /// rather than regular lookups, which should go through [`get`][Self::get] with a known [`Id`].
+    /// The yielded [`Id`]s are exactly those that [`get`][Self::get] would accept.
+    ///
+    /// Items are only copied if they are stored in more than one piece (see [`add`][Self::add]).
+    /// Partially-written and deleted items are skipped. If a tag cannot be read, the error is
+    /// yielded and iteration stops, as there is no way to tell where the next item starts.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            store: self,
+            position: self.header_length,
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a RawStore {
+    type Item = Result<(Id, Cow<'a, [u8]>), Error>;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// An iterator over the items of a [`RawStore`], created by [`RawStore::iter`].
+#[derive(Debug, Clone)]
+pub struct Iter<'a> {
+    store: &'a RawStore,
+    position: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Result<(Id, Cow<'a, [u8]>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let backing = &self.store.backing;
+        while self.position < self.store.end {
+            let at = self.position;
+            let tag = match MagicTag::read(backing, &mut self.position, self.store.tag_format) {
+                Ok(tag) => tag,
+                Err(e) => {
+                    self.position = self.store.end;
+                    return Some(Err(e));
+                }
+            };
+            match tag {
+                MagicTag::End => break,
+                MagicTag::Written { length } => match extent::entry(backing, self.position..self.position + length as usize, self.store.tag_format)
+                    .and_then(|(bytes, end)| Ok((self.store.split_generation(at, bytes)?, end)))
+                {
+                    Ok((item, end)) => {
+                        self.position = end;
+                        return Some(Ok(item));
+                    }
+                    Err(e) => {
+                        self.position = self.store.end;
+                        return Some(Err(e));
+                    }
+                },
+                MagicTag::Writing { length } | MagicTag::Deleted { length } | MagicTag::Continued { length } => {
+                    self.position += length as usize;
+                }
+            }
+        }
+        self.position = self.store.end;
+        None
+    }
+}
+
+impl FusedIterator for Iter<'_> {}
@@ -0,0 +1,46 @@
+use super::RawStore;
+use crate::{
+    backing::BackingInner,
+    error::{Error, OpenError},
+};
+
+// The first byte of the header version is the format version, which goes up whenever a change is
+// made that older versions of this crate cannot read (the second byte holds flags for optional
+// features, which are set when a store is created and never migrated).
+//
+// Stores written with an older format version are upgraded when they are opened, by running every
+// migration from their version up to the current one in turn. Each migration rewrites the store in
+// place, and is only recorded as done by bumping the version in the header once everything it wrote
+// has been flushed, so it must be safe to run again on a store it was interrupted part way through.
+// Migrations must not change the length of the header, which the rest of the store starts after.
+//
+// When the format version is bumped, the migration from the previous version is added here.
+
+/// A rewrite of a store from one format version to the next.
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Migration {
+    /// The version this upgrades from, to the one after it.
+    pub(super) from: u8,
+    /// Rewrites the store, given the length of its header.
+    pub(super) apply: fn(&mut BackingInner, usize) -> Result<(), Error>,
+}
+
+/// Every migration, see above.
+pub(super) const MIGRATIONS: &[Migration] = &[];
+
+/// Upgrades the store in `backing` from format version `from` to `to` using `migrations`.
+pub(super) fn run(
+    backing: &mut BackingInner, header_length: usize, migrations: &[Migration], from: u8, to: u8,
+) -> Result<(), OpenError> {
+    let at = RawStore::HEADER_MAGIC.len();
+    for version in from..to {
+        let Some(migration) = migrations.iter().find(|m| m.from == version) else {
+            return Err(OpenError::UnknownVersion([version, backing[at + 1]]));
+        };
+        (migration.apply)(backing, header_length)?;
+        backing.flush()?;
+        backing[at] = version + 1;
+        backing.flush_range(at, 1)?;
+    }
+    Ok(())
+}
@@ -1,10 +1,17 @@
-use std::fmt::Debug;
+use std::fmt::{Debug, Formatter};
 
-use super::{Gap, RawStore};
+use super::{
+    counters::{self, OpCounters},
+    flush::Flushing,
+    free_list::{self, take_free_list},
+    generation, migrate,
+    salvage::{self, RecoveryReport},
+    FlushPolicy, Gap, GapStrategy, RawStore,
+};
 use crate::{
     error::{Error, OpenError},
-    tag::MagicTag,
-    Backing,
+    tag::{self, MagicTag, TagFormat},
+    Backing, GrowthPolicy,
 };
 
 /// Options to open a [`RawStore`] with.
@@ -16,7 +23,20 @@ use crate::{
 #[derive(Debug)]
 pub struct OpenStoreOptions<'a> {
     spec_magic: &'a [u8],
+    spec_magic_check: Option<SpecMagicCheck<'a>>,
     recovery_strategy: RecoveryStrategy,
+    use_free_list: bool,
+    flush_policy: FlushPolicy,
+    generations: bool,
+    migrate: bool,
+    growth_policy: GrowthPolicy,
+    strict_durability: bool,
+    gap_strategy: GapStrategy,
+    coalesce_gaps: bool,
+    label: Option<String>,
+    counters: bool,
+    large_entries: bool,
+    progress: Option<Progress<'a>>,
 }
 
 /// Methods that consume [`self`][Self] to open or create a [store][RawStore].
@@ -24,7 +44,7 @@ impl OpenStoreOptions<'_> {
     /// Create a new store.
     #[allow(clippy::new_ret_no_self, clippy::wrong_self_convention)]
     pub fn new(self, backing: Backing) -> Result<RawStore, Error> {
-        RawStore::new(backing, self.spec_magic)
+        RawStore::new(backing, self)
     }
 
     /// Attempts to open an existing store.
@@ -36,6 +56,29 @@ impl OpenStoreOptions<'_> {
     }
 }
 
+type Check<'a> = dyn FnOnce(&[u8]) -> bool + 'a;
+
+/// A checker set with [`OpenStoreOptions::spec_magic_with`].
+struct SpecMagicCheck<'a>(Box<Check<'a>>);
+
+impl Debug for SpecMagicCheck<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SpecMagicCheck").finish_non_exhaustive()
+    }
+}
+
+/// A callback set with [`OpenStoreOptions::progress`].
+struct Progress<'a>(Box<dyn FnMut(usize, usize) + 'a>);
+
+impl Debug for Progress<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Progress").finish_non_exhaustive()
+    }
+}
+
+/// How far apart calls to the [`Progress`] callback are, in bytes of the store read through.
+const PROGRESS_STEP: usize = 1 << 20;
+
 /// Methods that allow configuring behaviour when opening a store.
 ///
 /// # Header specialization
@@ -48,20 +91,18 @@ impl OpenStoreOptions<'_> {
 /// These are called "specialization
 /// [magic bytes](https://en.wikipedia.org/wiki/File_format#Magic_number)" (or simply "spec magic").
 /// Spec magic can be written as any arbitrary byte sequence - though should be kept reasonably
-/// short - and is _currently_ checked for exact equality upon opening.[^1]
+/// short - and is checked for exact equality upon opening, unless a checker is given with
+/// [`spec_magic_with`][Self::spec_magic_with].
 ///
 /// Note that it is not possible to change a given store's spec magic after initial creation, even
 /// by closing anr reopening it.
-///
-/// [^1]: Later, an option may be added to allow for a `FnOnce(&[u8]) -> bool` or similar to be used
-/// as a spec magic checker.
 // TODO: Notes here
 impl<'a> OpenStoreOptions<'a> {
     /// Do not use "spec magic" (see above).
     ///
     /// Equivalent to [`self.exact_spec_magic(b"")`][Self::exact_spec_magic].
     pub fn no_spec_magic(self) -> Self {
-        Self { spec_magic: b"", ..self }
+        self.exact_spec_magic(b"")
     }
 
     /// Set the "spec magic" (see above) bytes.
@@ -70,6 +111,21 @@ impl<'a> OpenStoreOptions<'a> {
     pub fn exact_spec_magic(self, expected: &'a [u8]) -> Self {
         Self {
             spec_magic: expected,
+            spec_magic_check: None,
+            ..self
+        }
+    }
+
+    /// Accept any "spec magic" (see above) that `check` returns `true` for when opening, rather
+    /// than only exactly the bytes set by [`exact_spec_magic`][Self::exact_spec_magic].
+    ///
+    /// This allows a specialized store to still open files written with older or alternative spec
+    /// magic, _e.g._ one that includes a version number. The bytes set by
+    /// [`exact_spec_magic`][Self::exact_spec_magic] are still the ones written by
+    /// [`new`][Self::new], which does not use `check`.
+    pub fn spec_magic_with(self, check: impl FnOnce(&[u8]) -> bool + 'a) -> Self {
+        Self {
+            spec_magic_check: Some(SpecMagicCheck(Box::new(check))),
             ..self
         }
     }
@@ -83,6 +139,151 @@ impl<'a> OpenStoreOptions<'a> {
             ..self
         }
     }
+
+    /// Sets whether to use the summary of gaps written by [`close`][RawStore::close], if there is
+    /// one, instead of reading through the whole store to find them.
+    ///
+    /// Defaults to `true`, in which case opening takes time proportional to the number of gaps
+    /// rather than the size of the store, and the rest of the checks made when opening are
+    /// deferred to [`verify`][RawStore::verify]. Turning this off is only useful if the summary is
+    /// suspected to be wrong, as it is otherwise only written and read when the store is known to
+    /// be consistent.
+    pub fn use_free_list(self, use_free_list: bool) -> Self {
+        Self { use_free_list, ..self }
+    }
+
+    /// Sets when changes are flushed to the backing, see [`FlushPolicy`].
+    ///
+    /// Defaults to [`FlushPolicy::FlushEachOp`].
+    pub fn flush_policy(self, flush_policy: FlushPolicy) -> Self {
+        Self { flush_policy, ..self }
+    }
+
+    /// Sets whether a new store keeps a generation with every item, so that an [`Id`][crate::Id]
+    /// is never mistaken for a different item written in the same place after it was removed.
+    ///
+    /// Without this, [`Id`][crate::Id]s are only checked against the length of the item they
+    /// refer to, so if an item is removed and another of the same length takes its space, the old
+    /// [`Id`][crate::Id] returns the new item. With it, each item takes up 2 more bytes (counted in
+    /// [`stats`][RawStore::stats]), an old [`Id`][crate::Id] is only mistaken for a new one if
+    /// 65536 items were written in between, and the store is limited to 1 TiB.
+    ///
+    /// Defaults to `false`. This only applies to [`new`][Self::new], as whether a store uses
+    /// generations is recorded when it is created and cannot be changed.
+    pub fn generations(self, generations: bool) -> Self {
+        Self { generations, ..self }
+    }
+
+    /// Sets whether a new store keeps count of how many items are added and removed, and how many
+    /// bytes are written, over its whole life, see [`OpCounters`].
+    ///
+    /// The counts are kept in the header, which is 24 bytes longer for it, but are only written
+    /// back to it by [`flush`][RawStore::flush] and [`close`][RawStore::close] (not with every
+    /// change, even with [`FlushPolicy::FlushEachOp`]), so any changes made since are not counted if
+    /// the store is dropped or the program crashes. They are read with [`stats`][RawStore::stats].
+    ///
+    /// Defaults to `false`. This only applies to [`new`][Self::new], as whether a store keeps
+    /// counts is recorded when it is created and cannot be changed.
+    pub fn counters(self, counters: bool) -> Self {
+        Self { counters, ..self }
+    }
+
+    /// Sets whether a new store uses a tag format that can hold items of up to 8 TiB in a single
+    /// piece, rather than 128 MiB.
+    ///
+    /// Longer items are otherwise stored in several pieces (see [`add`][RawStore::add]), which are
+    /// copied into a single buffer whenever they are read and can never go into a gap. With this,
+    /// any item fits in one piece, so is read without copying like any other, while items from
+    /// 512 KiB up to 128 MiB take up 2 more bytes each.
+    ///
+    /// Defaults to `false`. This only applies to [`new`][Self::new], as the format is recorded
+    /// when a store is created and cannot be changed. Versions of this crate from before this
+    /// option existed cannot open stores created with it, and return
+    /// [`OpenError::UnknownVersion`] instead.
+    pub fn large_entries(self, large_entries: bool) -> Self {
+        Self { large_entries, ..self }
+    }
+
+    /// Sets whether a store written by an older version of this crate, in a format it has since
+    /// moved on from, is upgraded to the current format when opened.
+    ///
+    /// Defaults to `true`. The upgrade is made in place, after which older versions of this crate
+    /// can no longer open the store, so this can be turned off to get
+    /// [`OpenError::NeedsMigration`] instead (_e.g._ to take a copy of the backing first), which
+    /// is also what a read-only backing gets.
+    pub fn migrate(self, migrate: bool) -> Self {
+        Self { migrate, ..self }
+    }
+
+    /// Sets how the backing grows when more space is needed, see [`GrowthPolicy`].
+    ///
+    /// Defaults to [`GrowthPolicy::Chunk(256)`][GrowthPolicy::Chunk].
+    pub fn growth_policy(self, growth_policy: GrowthPolicy) -> Self {
+        Self { growth_policy, ..self }
+    }
+
+    /// Sets how new items are fitted into the gaps left by removed ones, see [`GapStrategy`].
+    ///
+    /// Defaults to [`GapStrategy::BestOfFirst(8)`][GapStrategy::BestOfFirst]. This is not recorded
+    /// in the store, so can be changed each time it is opened.
+    pub fn gap_strategy(self, gap_strategy: GapStrategy) -> Self {
+        Self { gap_strategy, ..self }
+    }
+
+    /// Sets whether to merge any gaps that are next to each other when opening a store, see
+    /// [`RawStore::coalesce_gaps`].
+    ///
+    /// Defaults to `false`. This does nothing for stores opened from a read-only backing.
+    pub fn coalesce_gaps(self, coalesce_gaps: bool) -> Self {
+        Self { coalesce_gaps, ..self }
+    }
+
+    /// Sets a label for the store (such as the path of its file), which is kept with it (see
+    /// [`RawStore::label`]) and added to any error from opening or using it, so that an
+    /// application with many stores can tell which one failed.
+    ///
+    /// With a label, [`open`][Self::open] returns every error wrapped in
+    /// [`OpenError::Labelled`], and the store's methods for adding, reading, changing, removing
+    /// and [verifying][RawStore::verify] items return theirs wrapped in [`Error::Labelled`] (see
+    /// [`Error::unlabelled`]).
+    pub fn label(self, label: impl Into<String>) -> Self {
+        Self {
+            label: Some(label.into()),
+            ..self
+        }
+    }
+
+    /// Sets a callback to be told how far through the store [`open`][Self::open] has read, for
+    /// showing progress while opening a large store.
+    ///
+    /// `progress` is called with how many bytes of the backing (after the header) have been read
+    /// through so far and how many there are in total, about every MiB. The last call is always
+    /// with both equal, once the store has been opened. When the store is opened from the summary
+    /// written by [`close`][RawStore::close] (see [`use_free_list`][Self::use_free_list]), nothing
+    /// needs reading through, so that is the only call.
+    pub fn progress(self, progress: impl FnMut(usize, usize) + 'a) -> Self {
+        Self {
+            progress: Some(Progress(Box::new(progress))),
+            ..self
+        }
+    }
+
+    /// Sets whether every flush also syncs the backing's file (with `fdatasync` or the platform's
+    /// equivalent), rather than only its memory map.
+    ///
+    /// Flushing a memory map writes its contents back to the file, but on some systems and
+    /// filesystems not necessarily the file's metadata, so a store that has grown could come back
+    /// from a power loss shorter than it was, even though every flush succeeded. This closes that
+    /// gap, at the cost of a sync per flush, so is best combined with a relaxed
+    /// [`FlushPolicy`] or with [`batch`][RawStore::batch]es.
+    ///
+    /// Defaults to `false`. This does nothing for backings that are not file-backed.
+    pub fn strict_durability(self, strict_durability: bool) -> Self {
+        Self {
+            strict_durability,
+            ..self
+        }
+    }
 }
 
 impl<'a> Default for OpenStoreOptions<'a> {
@@ -105,6 +306,19 @@ pub enum RecoveryStrategy {
     ///
     /// This will add an end tag to the end of the backing if not present.
     Rollback,
+    /// Keep everything that can still be read.
+    ///
+    /// As well as everything [`Rollback`][Self::Rollback] does, anything that cannot be read (_e.g._
+    /// an unknown tag, or one whose length runs past the end of the backing) is skipped up to the
+    /// next point where the store looks valid again and turned into gaps, so every intact item
+    /// after it is kept. What was skipped is summarised by [`RawStore::recovery_report`].
+    ///
+    /// The summary written by [`close`][RawStore::close] is never used with this strategy (see
+    /// [`use_free_list`][OpenStoreOptions::use_free_list]), as the whole store has to be read.
+    ///
+    /// Where a valid store carries on is only a guess, so items in or right after the damaged
+    /// data can be lost, and corrupted items that still look valid are kept.
+    Scan,
 }
 
 impl RawStore {
@@ -112,81 +326,260 @@ impl RawStore {
     pub fn options() -> OpenStoreOptions<'static> {
         OpenStoreOptions {
             spec_magic: b"",
+            spec_magic_check: None,
             recovery_strategy: RecoveryStrategy::Error,
+            use_free_list: true,
+            flush_policy: FlushPolicy::FlushEachOp,
+            generations: false,
+            migrate: true,
+            growth_policy: GrowthPolicy::Chunk(256),
+            strict_durability: false,
+            gap_strategy: GapStrategy::BestOfFirst(8),
+            coalesce_gaps: false,
+            label: None,
+            counters: false,
+            large_entries: false,
+            progress: None,
         }
     }
 
-    fn new(backing: Backing, spec_magic: &[u8]) -> Result<Self, Error> {
+    fn new(backing: Backing, options: OpenStoreOptions<'_>) -> Result<Self, Error> {
+        let spec_magic = options.spec_magic;
         let mut backing = backing.0;
+        backing.growth = options.growth_policy;
+        backing.sync_file = options.strict_durability;
         // TODO: Error if nonempty
         let mut position = 0;
         backing.write(Self::HEADER_MAGIC, &mut position)?; // magic bytes
-        backing.write(&Self::HEADER_VERSION, &mut position)?; // header version
+        let mut version = Self::HEADER_VERSION;
+        if options.generations {
+            version[1] |= generation::HEADER_FLAG;
+        }
+        if options.counters {
+            version[1] |= counters::HEADER_FLAG;
+        }
+        let tag_format = if options.large_entries {
+            version[1] |= tag::HEADER_FLAG;
+            TagFormat::V2
+        } else {
+            TagFormat::V1
+        };
+        backing.write(&version, &mut position)?; // header version
         debug_assert_eq!(position, Self::HEADER_LENGTH);
         crate::util::write_varint_backing(spec_magic.len() as u64, &mut backing, &mut position)?;
         backing.write(spec_magic, &mut position)?;
+        let counters = options.counters.then(OpCounters::default);
+        if let Some(counters) = counters {
+            backing.write(&counters.to_bytes(), &mut position)?;
+        }
         let header_length = position;
-        MagicTag::End.write(&mut backing, &mut position)?;
+        MagicTag::End.write(&mut backing, &mut position, tag_format)?;
         backing.flush()?;
         Ok(Self {
             backing,
             end: header_length,
             gaps: vec![],
             header_length,
+            tag_format,
+            entries: 0,
+            entry_bytes: 0,
+            verified: true,
+            deferred: None,
+            flushing: Flushing::new(options.flush_policy),
+            generations: options.generations.then_some(0),
+            recovery_report: None,
+            gap_strategy: options.gap_strategy,
+            label: options.label,
+            counters,
         })
     }
 
-    fn open(backing: Backing, options: OpenStoreOptions<'_>) -> Result<Self, OpenError> {
+    fn open(backing: Backing, mut options: OpenStoreOptions<'_>) -> Result<Self, OpenError> {
+        let coalesce = options.coalesce_gaps;
+        let label = options.label.take();
+        let store = Self::open_backing(backing, options).and_then(|mut store| {
+            if coalesce && store.backing.is_writable() {
+                store.coalesce_gaps()?;
+            }
+            Ok(store)
+        });
+        match (store, label) {
+            (Ok(store), label) => Ok(Self { label, ..store }),
+            (Err(e), Some(label)) => Err(OpenError::Labelled {
+                label,
+                source: Box::new(e),
+            }),
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    fn open_backing(backing: Backing, options: OpenStoreOptions<'_>) -> Result<Self, OpenError> {
         let mut backing = backing.0;
+        backing.growth = options.growth_policy;
+        backing.sync_file = options.strict_durability;
         let spec_var_len = <u64 as varuint::VarintSizeHint>::varint_size(options.spec_magic.len() as _);
-        let h_len = Self::HEADER_LENGTH + spec_var_len + options.spec_magic.len();
+        // Without a checker, anything shorter than the expected spec magic is known to be too small
+        // before reading it
+        let h_len = match options.spec_magic_check {
+            Some(_) => Self::HEADER_LENGTH + 1,
+            None => Self::HEADER_LENGTH + spec_var_len + options.spec_magic.len(),
+        };
         if backing.len() < h_len {
             return Err(OpenError::TooSmall {
                 found: backing.len(),
                 expected: h_len,
             });
         }
-        let header = &backing[..h_len];
+        let header = &backing[..Self::HEADER_LENGTH];
         if &header[..Self::HEADER_MAGIC.len()] != Self::HEADER_MAGIC {
             return Err(OpenError::Magic);
         }
         let mut hpos = Self::HEADER_MAGIC.len();
         let v: [u8; 2] = (&header[hpos..hpos + Self::HEADER_VERSION.len()]).try_into().unwrap();
-        if v != Self::HEADER_VERSION {
+        let flags = generation::HEADER_FLAG | counters::HEADER_FLAG | free_list::HEADER_FLAG | tag::HEADER_FLAG;
+        if v[0] > Self::HEADER_VERSION[0] || v[1] & !flags != Self::HEADER_VERSION[1] {
             return Err(OpenError::UnknownVersion(v));
         }
+        let generations = v[1] & generation::HEADER_FLAG != 0;
+        let has_counters = v[1] & counters::HEADER_FLAG != 0;
+        let tag_format = TagFormat::from_flags(v[1]);
         hpos += Self::HEADER_VERSION.len();
 
         let s = crate::util::read_varint::<u64>(&backing, &mut hpos)?;
-        if s as usize != options.spec_magic.len() {
-            return Err(OpenError::SpecMagicLen {
-                found: s as usize,
-                expected: options.spec_magic.len(),
-            });
-        }
-        if &backing[hpos..hpos + s as usize] != options.spec_magic {
-            return Err(OpenError::SpecMagic {
-                found: bstr::BString::new(backing[hpos..hpos + s as usize].to_owned()),
-                expected: bstr::BString::new(options.spec_magic.to_owned()),
-            });
+        if let Some(SpecMagicCheck(check)) = options.spec_magic_check {
+            if backing.len() < hpos + s as usize {
+                return Err(OpenError::TooSmall {
+                    found: backing.len(),
+                    expected: hpos + s as usize,
+                });
+            }
+            let found = &backing[hpos..hpos + s as usize];
+            if !check(found) {
+                return Err(OpenError::SpecMagicRejected {
+                    found: bstr::BString::new(found.to_owned()),
+                });
+            }
+        } else {
+            if s as usize != options.spec_magic.len() {
+                return Err(OpenError::SpecMagicLen {
+                    found: s as usize,
+                    expected: options.spec_magic.len(),
+                });
+            }
+            if &backing[hpos..hpos + s as usize] != options.spec_magic {
+                return Err(OpenError::SpecMagic {
+                    found: bstr::BString::new(backing[hpos..hpos + s as usize].to_owned()),
+                    expected: bstr::BString::new(options.spec_magic.to_owned()),
+                });
+            }
+            // This should not be possible to hit, but is kept to ensure that the reading checks
+            // are kept in line with changes to the header size
+            assert_eq!(hpos + s as usize, h_len);
         }
         hpos += s as usize;
+        let counters = if has_counters {
+            if backing.len() < hpos + counters::LEN {
+                return Err(OpenError::TooSmall {
+                    found: backing.len(),
+                    expected: hpos + counters::LEN,
+                });
+            }
+            hpos += counters::LEN;
+            Some(OpCounters::read(&backing[hpos - counters::LEN..hpos]))
+        } else {
+            None
+        };
+        let h_len = hpos;
+
+        if v[0] < Self::HEADER_VERSION[0] {
+            if !options.migrate || !backing.is_writable() {
+                return Err(OpenError::NeedsMigration(v));
+            }
+            // The summary is in the old format, so is left for the scan to replace
+            take_free_list(&mut backing, h_len, false)?;
+            migrate::run(&mut backing, h_len, migrate::MIGRATIONS, v[0], Self::HEADER_VERSION[0])?;
+        }
 
-        // This should not be possible to hit, but is kept to ensure that the reading checks
-        // are kept in line with changes to the header size
-        assert_eq!(hpos, header.len());
+        // This is always taken (and so removed) even if it is not used, as it is out of date as
+        // soon as the store is changed
+        // Nothing can be recovered without changing the store
+        let recovery_strategy = if backing.is_writable() {
+            options.recovery_strategy
+        } else {
+            RecoveryStrategy::Error
+        };
+        let scan = matches!(recovery_strategy, RecoveryStrategy::Scan);
+        let mut progress = match options.progress {
+            Some(Progress(progress)) => progress,
+            None => Box::new(|_, _| ()),
+        };
+        if let Some(free_list) = take_free_list(&mut backing, h_len, generations)?.filter(|_| options.use_free_list && !scan) {
+            let total = backing.len() - h_len;
+            progress(total, total);
+            return Ok(Self {
+                backing,
+                end: free_list.end,
+                gaps: free_list.gaps,
+                header_length: h_len,
+                tag_format,
+                entries: free_list.entries,
+                entry_bytes: free_list.entry_bytes,
+                verified: false,
+                deferred: None,
+                flushing: Flushing::new(options.flush_policy),
+                generations: free_list.generation,
+                recovery_report: None,
+                gap_strategy: options.gap_strategy,
+                label: None,
+                counters,
+            });
+        }
 
         let mut pos = hpos;
         let mut end = None;
         let mut gaps = Vec::new();
+        let mut entries = 0;
+        let mut entry_bytes = 0;
+        // Whether the last tag was part of a Written entry, and so can be continued
+        let mut written = false;
+        // The latest generation seen, which is taken to be the largest as the summary that keeps
+        // the next one was not used
+        let mut latest = None;
+        let mut report = RecoveryReport::default();
+        let total = backing.len() - h_len;
+        let mut next_progress = 0;
         while pos < backing.len() {
+            if pos - h_len >= next_progress {
+                progress(pos - h_len, total);
+                next_progress = pos - h_len + PROGRESS_STEP;
+            }
             let here = pos;
-            let tag = MagicTag::read(&backing, &mut pos)?;
+            let tag = if scan {
+                match salvage::read_tag(&backing, &mut pos, tag_format) {
+                    Some(tag) => tag,
+                    // Just the padding after a missing End tag
+                    None if backing[here..].iter().all(|&b| b == 0) => break,
+                    None => {
+                        pos = salvage::skip_damaged(&mut backing, here, &mut gaps, &mut report, tag_format)?;
+                        written = false;
+                        continue;
+                    }
+                }
+            } else {
+                MagicTag::read(&backing, &mut pos, tag_format)?
+            };
+            let continues = written;
+            written = matches!(tag, MagicTag::Written { .. } | MagicTag::Continued { .. });
             match tag {
                 MagicTag::End => {
                     end = Some(here);
                     let rest = &backing[pos..];
                     if let Some((idx, b)) = rest.iter().copied().enumerate().find(|(_, b)| *b != 0) {
+                        if scan {
+                            pos = salvage::skip_damaged(&mut backing, here, &mut gaps, &mut report, tag_format)?;
+                            written = false;
+                            continue;
+                        }
                         return Err(OpenError::DataAfterEnd {
                             end: here,
                             first_data_at: pos + idx,
@@ -195,33 +588,46 @@ impl RawStore {
                     }
                     break;
                 }
-                MagicTag::Writing { length } => match options.recovery_strategy {
+                MagicTag::Continued { length } if continues => {
+                    entry_bytes += length as usize;
+                    pos += length as usize;
+                }
+                // A continuation of anything else is left over from an interrupted write or
+                // removal of the entry before it
+                MagicTag::Writing { length } | MagicTag::Continued { length } => match recovery_strategy {
                     RecoveryStrategy::Error => {
                         return Err(OpenError::PartialWrite {
                             position: here,
                             length: length as usize,
                         });
                     }
-                    RecoveryStrategy::Rollback => {
+                    RecoveryStrategy::Rollback | RecoveryStrategy::Scan => {
+                        report.partial_writes += 1;
                         let tag_len = pos - here;
-                        MagicTag::Deleted { length }.write_exact(&mut backing, &mut { here }, tag_len)?;
+                        MagicTag::Deleted { length }.write_exact(&mut backing, &mut { here }, tag_len, tag_format)?;
                         backing[pos..pos + length as usize].fill(0);
                         backing.flush_range(here, tag_len + length as usize)?;
                         gaps.push(Gap {
                             at: here,
-                            length: length as u32,
+                            length: length as usize,
                             tag_len: tag_len as u8,
                         });
                         pos += length as usize;
+                        written = false;
                     }
                 },
                 MagicTag::Written { length } => {
+                    if generations {
+                        latest = latest.max(generation::read(&backing[pos..pos + length as usize]));
+                    }
+                    entries += 1;
+                    entry_bytes += length as usize;
                     pos += length as usize;
                 }
                 MagicTag::Deleted { length } => {
                     gaps.push(Gap {
                         at: here,
-                        length: length as u32,
+                        length: length as usize,
                         tag_len: (pos - here) as u8,
                     });
                     pos += length as usize;
@@ -231,21 +637,33 @@ impl RawStore {
         let end = if let Some(end) = end {
             end
         } else {
-            match options.recovery_strategy {
+            match recovery_strategy {
                 RecoveryStrategy::Error => return Err(OpenError::NoEnd),
-                RecoveryStrategy::Rollback => {
+                RecoveryStrategy::Rollback | RecoveryStrategy::Scan => {
                     let end = pos;
-                    MagicTag::End.write(&mut backing, &mut pos)?;
+                    MagicTag::End.write(&mut backing, &mut pos, tag_format)?;
                     end
                 }
             }
         };
+        progress(total, total);
 
         Ok(Self {
             backing,
             end,
             gaps,
             header_length: h_len,
+            tag_format,
+            entries,
+            entry_bytes,
+            verified: true,
+            deferred: None,
+            flushing: Flushing::new(options.flush_policy),
+            generations: generations.then(|| latest.map_or(0, |g: u16| g.wrapping_add(1))),
+            recovery_report: scan.then_some(report),
+            gap_strategy: options.gap_strategy,
+            label: None,
+            counters,
         })
     }
 }
@@ -253,83 +671,10 @@ impl RawStore {
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    trait Byteable {
-        fn write_len(&self) -> usize;
-
-        fn write(&self, bytes: &mut [u8], position: &mut usize);
-    }
-
-    impl Byteable for [u8] {
-        fn write_len(&self) -> usize {
-            self.len()
-        }
-
-        fn write(&self, bytes: &mut [u8], position: &mut usize) {
-            bytes[*position..*position + self.len()].copy_from_slice(self);
-            *position += self.len();
-        }
-    }
-
-    impl Byteable for u8 {
-        fn write_len(&self) -> usize {
-            1
-        }
-
-        fn write(&self, bytes: &mut [u8], position: &mut usize) {
-            bytes[*position] = *self;
-            *position += 1;
-        }
-    }
-
-    impl<const N: usize> Byteable for [u8; N] {
-        fn write_len(&self) -> usize {
-            N
-        }
-
-        fn write(&self, bytes: &mut [u8], position: &mut usize) {
-            bytes[*position..*position + N].copy_from_slice(self);
-            *position += N;
-        }
-    }
-
-    impl<T: Byteable + ?Sized> Byteable for &T {
-        fn write_len(&self) -> usize {
-            <T as Byteable>::write_len(self)
-        }
-
-        fn write(&self, bytes: &mut [u8], position: &mut usize) {
-            <T as Byteable>::write(self, bytes, position)
-        }
-    }
-
-    impl Byteable for MagicTag {
-        fn write_len(&self) -> usize {
-            self.written_length()
-        }
-
-        fn write(&self, bytes: &mut [u8], position: &mut usize) {
-            self.write_buffer(bytes, position);
-        }
-    }
-
-    macro_rules! prepare_raw {
-        ($($e:expr),* $(,)?) => {{
-            let l = 0 $(+ Byteable::write_len(&$e))*;
-            let mut bytes = vec![0_u8; l];
-            let mut pos = 0;
-            $(
-            Byteable::write(&$e, &mut bytes, &mut pos);
-            )*
-            Backing::new_from_buffer(&bytes).unwrap()
-        }};
-    }
-
-    macro_rules! prepare {
-        ($($e:expr),* $(,)?) => {prepare_raw!(HEADER, 0, $($e,)* MagicTag::End)};
-    }
-
-    const HEADER: &[u8] = b"\x1FPLFmap\x00\x00";
+    use crate::{
+        backing::BackingInner,
+        raw_store::test_util::{prepare, prepare_raw, HEADER},
+    };
 
     #[test]
     fn test_header() {
@@ -361,6 +706,41 @@ mod tests {
         RawStore::open(prepare!(), Default::default()).unwrap();
     }
 
+    #[test]
+    fn spec_magic_with() {
+        let accept = |magic: &[u8]| magic.strip_prefix(b"v").is_some_and(|v| v == b"1" || v == b"2");
+        for (magic, ok) in [(&b"v1"[..], true), (b"v2", true), (b"v3", false), (b"", false)] {
+            let backing = RawStore::options().exact_spec_magic(magic).new(Backing::new_anon().unwrap()).unwrap().close().unwrap();
+            let r = RawStore::open(backing, RawStore::options().exact_spec_magic(b"v2").spec_magic_with(accept));
+            match r {
+                Ok(s) => assert!(ok && s.header_length == HEADER.len() + 1 + magic.len()),
+                Err(e) => assert!(!ok && matches!(e, OpenError::SpecMagicRejected { .. }), "{e:?}"),
+            }
+        }
+
+        let e = RawStore::open(prepare_raw!(HEADER, 5, b"v1"), RawStore::options().spec_magic_with(accept)).unwrap_err();
+        assert!(matches!(e, OpenError::TooSmall { found: 12, expected: 15 }), "{e:?}");
+        let e = RawStore::open(prepare_raw!(HEADER, 2, b"v1"), RawStore::options().exact_spec_magic(b"v2")).unwrap_err();
+        assert!(matches!(e, OpenError::SpecMagic { .. }), "{e:?}");
+    }
+
+    #[test]
+    fn migrate() {
+        fn upper(backing: &mut BackingInner, header_length: usize) -> Result<(), Error> {
+            backing[header_length..].make_ascii_uppercase();
+            Ok(())
+        }
+        let migrations = [migrate::Migration { from: 0, apply: upper }];
+
+        let mut backing = prepare!(MagicTag::Written { length: 3 }, b"abc").0;
+        migrate::run(&mut backing, HEADER.len() + 1, &migrations, 0, 1).unwrap();
+        assert_eq!(backing[RawStore::HEADER_MAGIC.len()], 1);
+        assert_eq!(&backing[HEADER.len() + 2..HEADER.len() + 5], b"ABC");
+
+        let e = migrate::run(&mut backing, HEADER.len() + 1, &migrations, 1, 2).unwrap_err();
+        assert!(matches!(e, OpenError::UnknownVersion([1, 0])), "{e:?}");
+    }
+
     #[test]
     fn partial_write() {
         let backing = || prepare!(MagicTag::Writing { length: 10 }, [b'a'; 10]);
@@ -389,4 +769,287 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn add_from_reader() {
+        let mut s = RawStore::options().new(Backing::new_anon().unwrap()).unwrap();
+        let a = s.add_from_reader(5, &b"abcdefg"[..]).unwrap();
+        let e = s.add_from_reader(10, &b"short"[..]).unwrap_err();
+        assert!(matches!(e, Error::Read(_)), "{e:?}");
+        let removed = s.add(b"removed").unwrap();
+        s.remove(removed, |_| ()).unwrap();
+        let e = s.add_from_reader(7, &b"short"[..]).unwrap_err();
+        assert!(matches!(e, Error::Read(_)), "{e:?}");
+
+        assert_eq!(s.get(a, |b| b.to_vec()).unwrap(), b"abcde");
+        assert_eq!(s.stats().entries, 1);
+        assert_eq!(s.stats().gaps, 1);
+        s.verify().unwrap();
+        let backing = Backing::new_from_buffer(&s.backing).unwrap();
+        RawStore::open(backing, Default::default()).unwrap().verify().unwrap();
+    }
+
+    #[test]
+    fn flush_policy() {
+        let backing = Backing::new_anon().unwrap();
+        let mut s = RawStore::options().flush_policy(FlushPolicy::Manual).new(backing).unwrap();
+        let a = s.add(b"a").unwrap();
+        let b = s.add(b"b").unwrap();
+        s.remove(a, |_| ()).unwrap();
+        assert_eq!(s.get(b, |b| b.to_vec()).unwrap(), b"b");
+        assert!(s.flushing.start < s.flushing.end);
+        s.flush().unwrap();
+        assert!(s.flushing.start > s.flushing.end);
+
+        let backing = Backing::new_anon().unwrap();
+        let mut s = RawStore::options().flush_policy(FlushPolicy::EveryNOps(2)).new(backing).unwrap();
+        s.add(b"a").unwrap();
+        assert!(s.flushing.start < s.flushing.end);
+        s.add(b"b").unwrap();
+        assert!(s.flushing.start > s.flushing.end);
+    }
+
+    #[test]
+    fn generations() {
+        let mut plain = RawStore::options().new(Backing::new_anon().unwrap()).unwrap();
+        let stale = plain.add(b"old").unwrap();
+        plain.remove(stale, |_| ()).unwrap();
+        plain.add(b"new").unwrap();
+        assert_eq!(plain.get(stale, |b| b.to_vec()).unwrap(), b"new");
+
+        let backing = Backing::new_anon().unwrap();
+        let mut s = RawStore::options().generations(true).new(backing).unwrap();
+        let stale = s.add(b"old").unwrap();
+        s.remove(stale, |_| ()).unwrap();
+        let new = s.add(b"new").unwrap();
+        assert!(matches!(s.get(stale, |_| ()), Err(Error::IdCheck(_))));
+        assert!(matches!(s.remove(stale, |_| ()), Err(Error::IdCheck(_))));
+        assert_eq!(s.get(new, |b| b.to_vec()).unwrap(), b"new");
+        let new = s.replace_resize(new, b"NEW").unwrap();
+        s.update(new, |b| b.make_ascii_lowercase()).unwrap();
+        assert_eq!(s.iter().map(|i| i.unwrap().1.into_owned()).collect::<Vec<_>>(), [b"new"]);
+        assert_eq!(s.stats().entry_bytes, 3 + generation::LEN);
+
+        let closed = s.close().unwrap().0;
+        let flags = generation::HEADER_FLAG | free_list::HEADER_FLAG;
+        assert_eq!(closed[RawStore::HEADER_MAGIC.len()..RawStore::HEADER_LENGTH], [0, flags]);
+        for use_free_list in [true, false] {
+            let backing = Backing::new_from_buffer(&closed).unwrap();
+            let mut s = RawStore::open(backing, RawStore::options().use_free_list(use_free_list)).unwrap();
+            assert_eq!(s.generations, Some(3));
+            assert_eq!(s.get(new, |b| b.to_vec()).unwrap(), b"new");
+            s.remove(new, |_| ()).unwrap();
+            let again = s.add(b"new").unwrap();
+            assert_eq!(s.get(again, |b| b.to_vec()).unwrap(), b"new");
+            assert!(matches!(s.get(new, |_| ()), Err(Error::IdCheck(_))));
+        }
+
+        let e = RawStore::open(prepare_raw!(RawStore::HEADER_MAGIC, [0, 0x10], 0), Default::default()).unwrap_err();
+        assert!(matches!(e, OpenError::UnknownVersion([0, 0x10])), "{e:?}");
+    }
+
+    #[test]
+    fn growth_policy() {
+        let backing = Backing::new_anon().unwrap();
+        let mut s = RawStore::options().growth_policy(GrowthPolicy::Chunk(4096)).new(backing).unwrap();
+        s.add(&[1; 5000]).unwrap();
+        assert_eq!(s.backing.len(), 8192);
+
+        let backing = Backing::new_anon().unwrap();
+        let mut s = RawStore::options().growth_policy(GrowthPolicy::Double).new(backing).unwrap();
+        let mut lengths = vec![s.backing.len()];
+        for _ in 0..20 {
+            s.add(&[2; 100]).unwrap();
+            lengths.push(s.backing.len());
+        }
+        lengths.dedup();
+        assert!(lengths.windows(2).all(|w| w[1] >= w[0] * 2), "{lengths:?}");
+
+        let path = std::env::temp_dir().join(format!("seqstore-growth-{}", std::process::id()));
+        let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        let backing = unsafe { Backing::new_file_with_capacity(file, 10_000) }.unwrap();
+        let options = RawStore::options().growth_policy(GrowthPolicy::Allocate(1 << 16));
+        let mut s = options.new(backing).unwrap();
+        assert_eq!(s.backing.len(), 10_000);
+        let id = s.add(&[3; 20_000]).unwrap();
+        assert_eq!(s.backing.len(), 1 << 16);
+        let backing = s.close().unwrap();
+        let s = RawStore::open(backing, Default::default()).unwrap();
+        assert_eq!(s.get(id, |b| b.len()).unwrap(), 20_000);
+        drop(s);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn read_only() {
+        let mut s = RawStore::options().new(Backing::new_anon().unwrap()).unwrap();
+        let a = s.add(b"a").unwrap();
+        let b = s.add(b"b").unwrap();
+        s.remove(a, |_| ()).unwrap();
+        let bytes: &'static [u8] = s.close().unwrap().0.to_vec().leak();
+
+        let path = std::env::temp_dir().join(format!("seqstore-read-only-{}", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        let backings = [Backing::from_slice(bytes), unsafe { Backing::open_file_readonly(file) }.unwrap()];
+        for backing in backings {
+            let mut s = RawStore::open(backing, Default::default()).unwrap();
+            assert_eq!(s.get(b, |b| b.to_vec()).unwrap(), b"b");
+            assert!(matches!(s.add(b"c"), Err(Error::ReadOnly)));
+            assert!(matches!(s.remove(b, |_| ()), Err(Error::ReadOnly)));
+            assert!(matches!(s.batch().commit(), Err(Error::ReadOnly)));
+            s.verify().unwrap();
+            s.flush().unwrap();
+            let backing = s.close().unwrap();
+            assert_eq!(&backing.0[..], bytes);
+        }
+        std::fs::remove_file(path).unwrap();
+
+        let partial = prepare!(MagicTag::Writing { length: 1 }, b"a").0.to_vec().leak();
+        let options = RawStore::options().recovery_strategy(RecoveryStrategy::Rollback);
+        let e = RawStore::open(Backing::from_slice(partial), options).unwrap_err();
+        assert!(matches!(e, OpenError::PartialWrite { .. }), "{e:?}");
+    }
+
+    #[test]
+    fn map_options() {
+        let options = crate::MapOptions::new().advice(crate::MapAdvice::Random).populate(true).lock(true);
+        let backing = Backing::new_anon().unwrap().map_options(options).unwrap();
+        let mut s = RawStore::options().new(backing).unwrap();
+        let id = s.add(&[1; 3000]).unwrap();
+        assert_eq!(s.get(id, |b| b.len()).unwrap(), 3000);
+        s.close().unwrap();
+    }
+
+    #[test]
+    fn strict_durability() {
+        let path = std::env::temp_dir().join(format!("seqstore-strict-{}", std::process::id()));
+        let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        let backing = unsafe { Backing::new_file(file) }.unwrap();
+        let mut s = RawStore::options().strict_durability(true).new(backing).unwrap();
+        assert!(s.backing.sync_file);
+        let id = s.add(&[1; 1000]).unwrap();
+        s.remove(id, |_| ()).unwrap();
+        let backing = s.close().unwrap();
+        let s = RawStore::open(backing, Default::default()).unwrap();
+        assert!(!s.backing.sync_file);
+        drop(s);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn filter_retain() {
+        let path = std::env::temp_dir().join(format!("seqstore-retain-{}", std::process::id()));
+        let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        let mut s = RawStore::new(Backing::new_anon().unwrap(), Default::default()).unwrap();
+        let ids = (0..10_u8).map(|i| s.add(&vec![i; i as usize * 10]).unwrap()).collect::<Vec<_>>();
+        s.remove(ids[4], |_| ()).unwrap();
+
+        let mut filter = s.filter(unsafe { Backing::new_file(file.try_clone().unwrap()) }.unwrap()).unwrap();
+        let mut calls = Vec::new();
+        filter
+            .retain_with_progress(|_, bytes| bytes.len() % 20 == 0, |done, total| calls.push((done, total)))
+            .unwrap();
+        filter.finish().unwrap();
+        assert_eq!(calls.len(), 10);
+        assert!(calls.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 == w[1].1));
+        let &(done, total) = calls.last().unwrap();
+        assert_eq!(done, total);
+
+        let mut f = RawStore::open(unsafe { Backing::new_file(file) }.unwrap(), Default::default()).unwrap();
+        f.verify().unwrap();
+        assert_eq!(f.stats().entries, 4);
+        for (i, &id) in ids.iter().enumerate() {
+            match f.get(id, <[u8]>::to_vec) {
+                Ok(bytes) => assert_eq!(bytes, s.get(id, <[u8]>::to_vec).unwrap()),
+                Err(_) => assert!(i % 2 == 1 || i == 4),
+            }
+        }
+        drop(f);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn gap_strategy() {
+        // Gaps of 50, 10 and 30 bytes, in that order
+        let fill = |strategy| {
+            let mut s = RawStore::options().gap_strategy(strategy).new(Backing::new_anon().unwrap()).unwrap();
+            let ids = [50, 1, 10, 1, 30, 1].map(|n| s.add(&vec![0; n]).unwrap());
+            for i in [0, 2, 4] {
+                s.remove(ids[i], |_| ()).unwrap();
+            }
+            let id = s.add(&[1; 4]).unwrap();
+            (s.position(id), ids.map(|id| s.position(id)))
+        };
+        let (at, ids) = fill(GapStrategy::FirstFit);
+        assert_eq!(at, ids[0]);
+        let (at, ids) = fill(GapStrategy::BestFit);
+        assert_eq!(at, ids[2]);
+        let (at, ids) = fill(GapStrategy::BestOfFirst(1));
+        assert_eq!(at, ids[0]);
+    }
+
+    #[test]
+    fn remove_if_present() {
+        let mut s = RawStore::new(Backing::new_anon().unwrap(), Default::default()).unwrap();
+        let ids = [b"a", b"b", b"c", b"d"].map(|b| s.add(b).unwrap());
+        assert_eq!(s.remove_if_present(ids[1], |b| b.to_vec()).unwrap(), Some(b"b".to_vec()));
+        assert_eq!(s.remove_if_present(ids[1], |_| ()).unwrap(), None);
+        assert!(matches!(s.remove(ids[1], |_| ()), Err(Error::AlreadyDeleted { .. })));
+        // Merged into the gap before it
+        s.remove(ids[2], |_| ()).unwrap();
+        assert_eq!(s.remove_if_present(ids[2], |_| ()).unwrap(), None);
+        // Released from the end
+        s.remove(ids[3], |_| ()).unwrap();
+        s.truncate_to_end().unwrap();
+        assert_eq!(s.remove_if_present(ids[3], |_| ()).unwrap(), None);
+        assert_eq!(s.stats().entries, 1);
+        s.verify().unwrap();
+    }
+
+    #[test]
+    fn progress() {
+        let mut s = RawStore::options().new(Backing::new_anon().unwrap()).unwrap();
+        for i in 0..40_u8 {
+            s.add(&vec![i; 100_000]).unwrap();
+        }
+        let header_length = s.header_length;
+        let closed = s.close().unwrap().0;
+
+        for use_free_list in [true, false] {
+            let mut calls = Vec::new();
+            let options = RawStore::options().use_free_list(use_free_list).progress(|done, total| calls.push((done, total)));
+            let s = RawStore::open(Backing::new_from_buffer(&closed).unwrap(), options).unwrap();
+            let total = s.stats().file_length - header_length;
+            assert_eq!(calls.last(), Some(&(total, total)));
+            if use_free_list {
+                assert_eq!(calls.len(), 1);
+            } else {
+                assert_eq!(calls[0], (0, total));
+                assert!(calls.len() > 3, "{calls:?}");
+                assert!(calls.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 == w[1].1), "{calls:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn labelled_errors() {
+        let mut s = RawStore::options().label("items.bin").new(Backing::new_anon().unwrap()).unwrap();
+        let id = s.add(b"abc").unwrap();
+        s.remove(id, |_| ()).unwrap();
+        let e = s.remove(id, |_| ()).unwrap_err();
+        assert!(matches!(&e, Error::Labelled { label, source } if label == "items.bin" && matches!(**source, Error::AlreadyDeleted { .. })), "{e:?}");
+        assert!(matches!(e.unlabelled(), Error::AlreadyDeleted { .. }));
+        assert_eq!(e.to_string(), "error in items.bin");
+        assert!(matches!(s.get(id, |_| ()).unwrap_err().unlabelled(), Error::IncorrectTag { .. }));
+        assert_eq!(s.remove_if_present(id, |_| ()).unwrap(), None);
+
+        // Errors are only labelled once, even when one method uses another
+        let e = s.replace_resize(id, &[1; 100]).unwrap_err();
+        assert!(matches!(&e, Error::Labelled { source, .. } if matches!(**source, Error::AlreadyDeleted { .. })), "{e:?}");
+
+        s.end += 1;
+        let e = s.verify().unwrap_err();
+        assert!(matches!(e, Error::Labelled { .. }) && matches!(e.unlabelled(), Error::Inconsistent { .. }), "{e:?}");
+    }
 }
@@ -0,0 +1,199 @@
+use std::ops::Range;
+
+use super::{batch::Deferred, extent, generation, RawStore};
+use crate::{error::Error, tag::MagicTag, Id};
+
+impl RawStore {
+    /// Sets aside space for an item of `length` bytes, which can then be written a part at a time
+    /// through the returned [`Reservation`] before being made visible all at once.
+    ///
+    /// The space is chosen as for [`add`][Self::add], and the item is written in the same way, with
+    /// the filling in and the final flip to a complete item split between
+    /// [`write`][Reservation::write] and [`commit`][Reservation::commit].
+    pub fn reserve(&mut self, length: usize) -> Result<Reservation<'_>, Error> {
+        self.check_writable()?;
+        self.deferred = Some(Deferred::new(1));
+        let id = self.add_with(length, |buffer| {
+            buffer.fill(0);
+            Ok(())
+        });
+        let id = match id {
+            Ok(id) => id,
+            Err(e) => {
+                self.finish_deferred()?;
+                return Err(e);
+            }
+        };
+        let deferred = self.deferred.take().expect("set above");
+
+        let start = self.position(id);
+        let mut position = start;
+        let MagicTag::Writing { length: first } = MagicTag::read(&self.backing, &mut position, self.tag_format)? else {
+            unreachable!("just written")
+        };
+        let skip = self.generations.map_or(0, |_| generation::LEN);
+        let first = position..position + first as usize;
+        let mut pieces = Vec::new();
+        pieces.push(first.start + skip..first.end);
+        for piece in extent::continuations(&self.backing, first.end, self.tag_format) {
+            pieces.push(piece?.data());
+        }
+        Ok(Reservation {
+            store: self,
+            id,
+            pieces,
+            written: 0,
+            length,
+            deferred: Some(deferred),
+        })
+    }
+}
+
+/// Space set aside for an item, created by [`RawStore::reserve`].
+///
+/// The item is written with [`write`][Self::write], and only becomes visible (to
+/// [`get`][RawStore::get], [`iter`][RawStore::iter], and after a crash) once it is
+/// [`commit`][Self::commit]ted. Until then, it takes up space in the store (and is counted in its
+/// [`stats`][RawStore::stats]) like any other item, and is rolled back if the store is opened with
+/// [`RecoveryStrategy::Rollback`][super::RecoveryStrategy::Rollback] after a crash.
+///
+/// Dropping a reservation without committing it [`abort`][Self::abort]s it, ignoring any error.
+#[derive(Debug)]
+pub struct Reservation<'a> {
+    store: &'a mut RawStore,
+    id: Id,
+    /// Where the data of each piece of the item is, excluding any generation.
+    pieces: Vec<Range<usize>>,
+    written: usize,
+    length: usize,
+    /// What still needs flushing, and the item to flip to Written. Taken once finished.
+    deferred: Option<Deferred>,
+}
+
+impl Reservation<'_> {
+    /// The [`Id`] the item will have once committed.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// How many more bytes can be written.
+    pub fn remaining(&self) -> usize {
+        self.length - self.written
+    }
+
+    /// Writes `bytes` after everything written so far.
+    ///
+    /// # Panics
+    ///
+    /// If there are more than [`remaining`][Self::remaining] bytes.
+    pub fn write(&mut self, mut bytes: &[u8]) {
+        assert!(bytes.len() <= self.remaining(), "write past the end of the reservation");
+        let mut offset = self.written;
+        for piece in &self.pieces {
+            if bytes.is_empty() {
+                break;
+            }
+            if offset >= piece.len() {
+                offset -= piece.len();
+                continue;
+            }
+            let (this, rest) = bytes.split_at(bytes.len().min(piece.len() - offset));
+            let at = piece.start + offset;
+            self.store.backing[at..at + this.len()].copy_from_slice(this);
+            self.written += this.len();
+            bytes = rest;
+            offset = 0;
+        }
+    }
+
+    /// Makes the item visible, returning its [`Id`].
+    ///
+    /// Anything not [`write`][Self::write]ten is left as zeroes.
+    pub fn commit(mut self) -> Result<Id, Error> {
+        self.store.deferred = self.deferred.take();
+        self.store.finish_deferred()?;
+        Ok(self.id)
+    }
+
+    /// Gives back the space set aside, as if the item had been added and then removed.
+    pub fn abort(mut self) -> Result<(), Error> {
+        self.abort_inner()
+    }
+
+    fn abort_inner(&mut self) -> Result<(), Error> {
+        let Some(mut deferred) = self.deferred.take() else {
+            return Ok(());
+        };
+        deferred.writing.clear();
+        let store = &mut *self.store;
+        store.deferred = Some(deferred);
+        let erased = (|| {
+            let start = store.position(self.id);
+            let mut position = start;
+            let tag = MagicTag::read(&store.backing, &mut position, store.tag_format)?;
+            let MagicTag::Writing { length } = tag else {
+                unreachable!("reserved entries stay Writing until committed")
+            };
+            let pieces = extent::continuations(&store.backing, position + length as usize, store.tag_format).collect::<Result<Vec<_>, _>>()?;
+            store.erase(&mut { start }, position - start, length as usize)?;
+            let mut total = length as usize;
+            for piece in pieces {
+                store.erase(&mut { piece.at }, piece.tag_len, piece.length)?;
+                total += piece.length;
+            }
+            store.entries -= 1;
+            store.entry_bytes -= total;
+            store.count(|c| c.removes += 1);
+            Ok(())
+        })();
+        store.finish_deferred()?;
+        erased
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let _ = self.abort_inner();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::OpenError, Backing};
+
+    #[test]
+    fn reserve() {
+        for generations in [false, true] {
+            let mut s = RawStore::options().generations(generations).new(Backing::new_anon().unwrap()).unwrap();
+            let a = s.add(b"a").unwrap();
+            let mut r = s.reserve(6).unwrap();
+            r.write(b"abc");
+            r.write(b"de");
+            assert_eq!(r.remaining(), 1);
+            let id = r.id();
+            assert_eq!(r.commit().unwrap(), id);
+            assert_eq!(s.get(id, <[u8]>::to_vec).unwrap(), b"abcde\0");
+
+            // Aborting, explicitly or by dropping, leaves nothing behind
+            s.remove(a, |_| ()).unwrap();
+            let r = s.reserve(1).unwrap();
+            let aborted = r.id();
+            r.abort().unwrap();
+            assert!(s.get(aborted, |_| ()).is_err());
+            let mut r = s.reserve(100).unwrap();
+            r.write(&[1; 50]);
+            drop(r);
+            assert_eq!(s.stats().entries, 1);
+            assert_eq!(s.iter().count(), 1);
+            s.verify().unwrap();
+
+            // An uncommitted reservation is rolled back after a crash
+            let mut r = s.reserve(4).unwrap();
+            r.write(b"lost");
+            std::mem::forget(r);
+            let backing = Backing::new_from_buffer(&s.backing).unwrap();
+            assert!(matches!(RawStore::options().open(backing), Err(OpenError::PartialWrite { .. })));
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::ops::Range;
+
+use super::{Gap, RawStore};
+use crate::{
+    backing::BackingInner,
+    error::Error,
+    tag::{MagicTag, TagFormat},
+};
+
+/// What [`RecoveryStrategy::Scan`][super::RecoveryStrategy::Scan] had to skip over when opening a
+/// store, see [`RawStore::recovery_report`].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct RecoveryReport {
+    /// The ranges of the backing that could not be read, which were turned into gaps.
+    pub damaged: Vec<Range<usize>>,
+    /// The number of partially-written items that were removed, as with
+    /// [`RecoveryStrategy::Rollback`][super::RecoveryStrategy::Rollback].
+    pub partial_writes: usize,
+}
+
+impl RecoveryReport {
+    /// Whether nothing had to be skipped.
+    pub fn is_clean(&self) -> bool {
+        self.damaged.is_empty() && self.partial_writes == 0
+    }
+}
+
+impl RawStore {
+    /// What had to be skipped to open this store, if it was opened with
+    /// [`RecoveryStrategy::Scan`][super::RecoveryStrategy::Scan].
+    pub fn recovery_report(&self) -> Option<&RecoveryReport> {
+        self.recovery_report.as_ref()
+    }
+}
+
+/// Reads the tag at `position` like [`MagicTag::read`], but returns `None` rather than an error or
+/// a `panic!` if it is not valid, or if what it covers does not fit in `backing`.
+pub(super) fn read_tag(backing: &[u8], position: &mut usize, format: TagFormat) -> Option<MagicTag> {
+    let &byte = backing.get(*position)?;
+    let length_bytes = format.length_bytes()[((byte & 0b0001_1000) >> 3) as usize];
+    if *position + 1 + length_bytes > backing.len() {
+        return None;
+    }
+    let mut end = *position;
+    let tag = MagicTag::read(backing, &mut end, format).ok()?;
+    let length = match tag {
+        MagicTag::End => 0,
+        MagicTag::Writing { length }
+        | MagicTag::Written { length }
+        | MagicTag::Deleted { length }
+        | MagicTag::Continued { length } => length as usize,
+    };
+    if end + length > backing.len() {
+        return None;
+    }
+    *position = end;
+    Some(tag)
+}
+
+/// Whether a valid store looks like it could carry on from `position`.
+fn resumes_at(backing: &[u8], position: usize, format: TagFormat) -> bool {
+    let mut next = position;
+    match read_tag(backing, &mut next, format) {
+        Some(MagicTag::End) => backing[next..].iter().all(|&b| b == 0),
+        Some(MagicTag::Written { length } | MagicTag::Writing { length } | MagicTag::Deleted { length }) => {
+            let next = next + length as usize;
+            next == backing.len() || read_tag(backing, &mut { next }, format).is_some()
+        }
+        Some(MagicTag::Continued { .. }) | None => false,
+    }
+}
+
+/// Skips over the unreadable data starting at `start` up to the next point the store looks like it
+/// carries on from (or the end of the backing), turning it into gaps, and returns where to carry on.
+pub(super) fn skip_damaged(
+    backing: &mut BackingInner, start: usize, gaps: &mut Vec<Gap>, report: &mut RecoveryReport, format: TagFormat,
+) -> Result<usize, Error> {
+    let end = (start + 1..backing.len()).find(|&p| resumes_at(backing, p, format)).unwrap_or(backing.len());
+    let max = format.max_length();
+    let mut position = start;
+    while position < end {
+        // Any length can be covered, as a 1-byte tag can have a length of 0
+        let total = (end - position).min(max as usize + MagicTag::Deleted { length: max }.written_length(format));
+        let (tag_len, length) = MagicTag::calc_tag_len(total, format);
+        MagicTag::Deleted { length: length as u64 }.write_exact(backing, &mut { position }, tag_len as usize, format)?;
+        backing[position + tag_len as usize..position + total].fill(0);
+        gaps.push(Gap {
+            at: position,
+            length,
+            tag_len,
+        });
+        position += total;
+    }
+    backing.flush_range(start, end - start)?;
+    report.damaged.push(start..end);
+    Ok(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::OpenError,
+        raw_store::{
+            test_util::{prepare, prepare_raw, HEADER},
+            RecoveryStrategy,
+        },
+        Backing,
+    };
+
+    #[test]
+    fn scan() {
+        let backing = || {
+            prepare!(
+                MagicTag::Written { length: 3 },
+                b"abc",
+                [0x00, 0x1F, 0x00],
+                MagicTag::Written { length: 3 },
+                b"def",
+                MagicTag::Writing { length: 2 },
+                b"gh",
+                MagicTag::End,
+                0x01,
+                MagicTag::Written { length: 1 },
+                b"i",
+            )
+        };
+        let e = RawStore::options().open(backing()).unwrap_err();
+        let OpenError::General(Error::UnknownTag { surrounding, .. }) = e else {
+            panic!("{e:?}")
+        };
+        assert_eq!(surrounding, *b"abc\x00\x1F\x00\x83");
+        let e = RawStore::options().label("scan.bin").open(backing()).unwrap_err();
+        assert!(matches!(&e, OpenError::Labelled { label, source } if label == "scan.bin" && matches!(**source, OpenError::General(Error::UnknownTag { .. }))), "{e:?}");
+        let mut s = RawStore::options().recovery_strategy(RecoveryStrategy::Scan).open(backing()).unwrap();
+        let items = s.iter().map(|i| i.unwrap().1.into_owned()).collect::<Vec<_>>();
+        assert_eq!(items, [&b"abc"[..], b"def", b"i"]);
+        let report = s.recovery_report().unwrap();
+        let start = HEADER.len() + 1 + 4;
+        assert_eq!(report.damaged, [start..start + 3, start + 10..start + 12]);
+        assert_eq!(report.partial_writes, 1);
+        assert_eq!(s.stats().gaps, 3);
+        s.verify().unwrap();
+
+        let backing = Backing::new_from_buffer(&s.backing).unwrap();
+        let s = RawStore::options().recovery_strategy(RecoveryStrategy::Scan).label("scan.bin").open(backing).unwrap();
+        assert!(s.recovery_report().unwrap().is_clean());
+        assert_eq!(s.stats().entries, 3);
+        assert_eq!(s.label(), Some("scan.bin"));
+
+        let backing = prepare_raw!(HEADER, 0, MagicTag::Written { length: 1 }, b"a", [0; 10]);
+        let s = RawStore::options().recovery_strategy(RecoveryStrategy::Scan).open(backing).unwrap();
+        assert!(s.recovery_report().unwrap().is_clean());
+        assert_eq!(s.end, HEADER.len() + 3);
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::{Arc, PoisonError, RwLock};
+
+use super::RawStore;
+use crate::{error::Error, Id};
+
+impl RawStore {
+    /// Splits the store into a [`RawStoreWriter`], which makes every change, and a
+    /// [`RawStoreReader`], which can be cloned and sent to other threads to read from it at the
+    /// same time.
+    ///
+    /// Readers share the store with each other, but wait for any change the writer is making, and
+    /// the writer waits for any reads in progress. Every change is made all at once as far as the
+    /// readers can tell, so they never see an item part way through being added or removed.
+    pub fn split(self) -> (RawStoreWriter, RawStoreReader) {
+        let store = Arc::new(RwLock::new(self));
+        let reader = RawStoreReader {
+            store: Arc::clone(&store),
+        };
+        (RawStoreWriter { store }, reader)
+    }
+}
+
+/// The half of a [`RawStore`] that can change it, created by [`RawStore::split`].
+#[derive(Debug)]
+pub struct RawStoreWriter {
+    store: Arc<RwLock<RawStore>>,
+}
+
+impl RawStoreWriter {
+    /// Creates another reader of the store.
+    pub fn reader(&self) -> RawStoreReader {
+        RawStoreReader {
+            store: Arc::clone(&self.store),
+        }
+    }
+
+    /// Runs `f` with exclusive access to the store, for anything not covered by the other methods.
+    ///
+    /// Readers wait until `f` returns, so it should be kept short.
+    pub fn write<R>(&mut self, f: impl FnOnce(&mut RawStore) -> R) -> R {
+        f(&mut self.store.write().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Stores `bytes`, as [`RawStore::add`].
+    pub fn add(&mut self, bytes: &[u8]) -> Result<Id, Error> {
+        self.write(|store| store.add(bytes))
+    }
+
+    /// Removes the data at `at`, as [`RawStore::remove`].
+    pub fn remove<R>(&mut self, at: Id, f: impl FnOnce(&[u8]) -> R) -> Result<R, Error> {
+        self.write(|store| store.remove(at, f))
+    }
+
+    /// Replaces the data at `at`, as [`RawStore::replace_resize`].
+    pub fn replace_resize(&mut self, at: Id, bytes: &[u8]) -> Result<Id, Error> {
+        self.write(|store| store.replace_resize(at, bytes))
+    }
+
+    /// Flushes every change not yet flushed, as [`RawStore::flush`].
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.write(RawStore::flush)
+    }
+
+    /// Gets the store back, once every reader has been dropped.
+    ///
+    /// Returns `self` unchanged if there are still readers.
+    pub fn into_inner(self) -> Result<RawStore, Self> {
+        match Arc::try_unwrap(self.store) {
+            Ok(store) => Ok(store.into_inner().unwrap_or_else(PoisonError::into_inner)),
+            Err(store) => Err(Self { store }),
+        }
+    }
+}
+
+/// A handle to read from a [`RawStore`] while a [`RawStoreWriter`] changes it, created by
+/// [`RawStore::split`].
+///
+/// This is cheap to clone, and can be shared between threads.
+#[derive(Debug, Clone)]
+pub struct RawStoreReader {
+    store: Arc<RwLock<RawStore>>,
+}
+
+impl RawStoreReader {
+    /// Runs `f` with shared access to the store, for anything not covered by the other methods.
+    ///
+    /// The writer waits until `f` returns, so it should be kept short.
+    pub fn read<R>(&self, f: impl FnOnce(&RawStore) -> R) -> R {
+        f(&self.store.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Gets the data stored at `at`, as [`RawStore::get`].
+    pub fn get<R>(&self, at: Id, f: impl FnOnce(&[u8]) -> R) -> Result<R, Error> {
+        self.read(|store| store.get(at, f))
+    }
+
+    /// Gets the data stored at `at` into `buffer`, as [`RawStore::get_into`].
+    pub fn get_into(&self, at: Id, buffer: &mut Vec<u8>) -> Result<(), Error> {
+        self.read(|store| store.get_into(at, buffer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Backing;
+
+    #[test]
+    fn split() {
+        let (mut writer, reader) = RawStore::options().new(Backing::new_anon().unwrap()).unwrap().split();
+        let first = writer.add(b"first").unwrap();
+        let readers = (0..4)
+            .map(|_| {
+                let reader = reader.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        assert_eq!(reader.get(first, |b| b.to_vec()).unwrap(), b"first");
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let ids = (0..100_u8).map(|i| writer.add(&[i; 50]).unwrap()).collect::<Vec<_>>();
+        for id in &ids[..50] {
+            writer.remove(*id, |_| ()).unwrap();
+        }
+        for handle in readers {
+            handle.join().unwrap();
+        }
+        let mut buffer = Vec::new();
+        reader.get_into(ids[60], &mut buffer).unwrap();
+        assert_eq!(buffer, [60; 50]);
+
+        let writer = writer.into_inner().unwrap_err();
+        drop(reader);
+        let s = writer.into_inner().unwrap();
+        assert_eq!(s.stats().entries, 51);
+    }
+}
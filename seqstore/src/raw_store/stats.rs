@@ -0,0 +1,41 @@
+use crate::raw_store::{OpCounters, RawStore};
+
+/// A snapshot of how the space in a [`RawStore`] is used, returned by [`RawStore::stats`].
+///
+/// Mostly useful for deciding when the store is worth [`filter`][RawStore::filter]ing into a new
+/// backing _e.g._ once `gap_bytes` is a large enough fraction of `file_length`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+#[non_exhaustive]
+pub struct StoreStats {
+    /// The number of stored items.
+    pub entries: usize,
+    /// The total length of all stored items, not including their tags.
+    pub entry_bytes: usize,
+    /// The number of gaps left by removed items that have not (yet) been filled by new ones.
+    pub gaps: usize,
+    /// The total length of all gaps, including their tags, _i.e._ how much space they take up.
+    pub gap_bytes: usize,
+    /// The length of the backing, which is typically larger than the data in it as it grows in
+    /// steps.
+    pub file_length: usize,
+    /// How much the store has been changed over its whole life, if it keeps count, see
+    /// [`OpenStoreOptions::counters`][super::OpenStoreOptions::counters].
+    pub counters: Option<OpCounters>,
+}
+
+impl RawStore {
+    /// Returns the current [`StoreStats`].
+    ///
+    /// This is cheap, as everything is tracked as the store is changed, apart from summing up the
+    /// gaps.
+    pub fn stats(&self) -> StoreStats {
+        StoreStats {
+            entries: self.entries,
+            entry_bytes: self.entry_bytes,
+            gaps: self.gaps.len(),
+            gap_bytes: self.gaps.iter().map(|g| g.tag_len as usize + g.length).sum(),
+            file_length: self.backing.len(),
+            counters: self.counters,
+        }
+    }
+}
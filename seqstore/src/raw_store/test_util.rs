@@ -0,0 +1,93 @@
+use crate::tag::{MagicTag, TagFormat};
+
+/// Anything that [`prepare_raw`] can lay out in a backing.
+pub(crate) trait Byteable {
+    fn write_len(&self) -> usize;
+
+    fn write(&self, bytes: &mut [u8], position: &mut usize);
+}
+
+impl Byteable for [u8] {
+    fn write_len(&self) -> usize {
+        self.len()
+    }
+
+    fn write(&self, bytes: &mut [u8], position: &mut usize) {
+        bytes[*position..*position + self.len()].copy_from_slice(self);
+        *position += self.len();
+    }
+}
+
+impl Byteable for u8 {
+    fn write_len(&self) -> usize {
+        1
+    }
+
+    fn write(&self, bytes: &mut [u8], position: &mut usize) {
+        bytes[*position] = *self;
+        *position += 1;
+    }
+}
+
+impl<const N: usize> Byteable for [u8; N] {
+    fn write_len(&self) -> usize {
+        N
+    }
+
+    fn write(&self, bytes: &mut [u8], position: &mut usize) {
+        bytes[*position..*position + N].copy_from_slice(self);
+        *position += N;
+    }
+}
+
+impl<T: Byteable + ?Sized> Byteable for &T {
+    fn write_len(&self) -> usize {
+        <T as Byteable>::write_len(self)
+    }
+
+    fn write(&self, bytes: &mut [u8], position: &mut usize) {
+        <T as Byteable>::write(self, bytes, position)
+    }
+}
+
+impl Byteable for MagicTag {
+    fn write_len(&self) -> usize {
+        self.written_length(TagFormat::V1)
+    }
+
+    fn write(&self, bytes: &mut [u8], position: &mut usize) {
+        self.write_buffer(bytes, position, TagFormat::V1);
+    }
+}
+
+/// Creates a backing holding each argument in turn.
+macro_rules! prepare_raw {
+    ($($e:expr),* $(,)?) => {{
+        use $crate::raw_store::test_util::Byteable;
+        let l = 0 $(+ Byteable::write_len(&$e))*;
+        let mut bytes = vec![0_u8; l];
+        let mut pos = 0;
+        $(
+        Byteable::write(&$e, &mut bytes, &mut pos);
+        )*
+        $crate::Backing::new_from_buffer(&bytes).unwrap()
+    }};
+}
+
+/// Creates a backing holding a store with no spec magic, made up of each argument in turn and
+/// then an End tag.
+macro_rules! prepare {
+    ($($e:expr),* $(,)?) => {
+        $crate::raw_store::test_util::prepare_raw!(
+            $crate::raw_store::test_util::HEADER,
+            0,
+            $($e,)*
+            $crate::tag::MagicTag::End,
+        )
+    };
+}
+
+pub(crate) use {prepare, prepare_raw};
+
+/// The header magic and version of every store, which is followed by the spec magic length.
+pub(crate) const HEADER: &[u8] = b"\x1FPLFmap\x00\x00";
@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+
+use super::{extent, Gap, RawStore};
+use crate::{error::Error, tag::MagicTag};
+
+impl RawStore {
+    /// Whether the whole store has been read through and checked, either when it was
+    /// [open][super::OpenStoreOptions::open]ed or since with [`verify`][Self::verify].
+    ///
+    /// This is only `false` for a store opened lazily from the summary written by
+    /// [`close`][Self::close] (see [`use_free_list`][super::OpenStoreOptions::use_free_list]), and
+    /// is always `true` for a newly-created store.
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+
+    /// Reads through the whole store, checking that every tag is valid and that the gaps and
+    /// [`stats`][Self::stats] it was opened with are correct.
+    ///
+    /// Opening from the summary written by [`close`][Self::close] only does a cursory check of it,
+    /// so that startup does not scale with the size of the store, which defers the full check to
+    /// here. It can be called whenever convenient _e.g._ once startup has finished, or not at all
+    /// if the summary is trusted. Nothing is changed if the check fails.
+    pub fn verify(&mut self) -> Result<(), Error> {
+        let verified = self.verify_entries();
+        self.labelled(verified)
+    }
+
+    fn verify_entries(&mut self) -> Result<(), Error> {
+        let mut position = self.header_length;
+        let mut gaps = HashSet::new();
+        let mut entries = 0;
+        let mut entry_bytes = 0;
+        loop {
+            if position >= self.backing.len() {
+                return Err(Error::Inconsistent { position });
+            }
+            let here = position;
+            match MagicTag::read(&self.backing, &mut position, self.tag_format)? {
+                MagicTag::End => {
+                    if here != self.end {
+                        return Err(Error::Inconsistent { position: here });
+                    }
+                    break;
+                }
+                MagicTag::Writing { .. } => return Err(Error::EntryCorrupt { position: here }),
+                MagicTag::Written { length } => {
+                    let (bytes, end) = extent::entry(&self.backing, position..position + length as usize, self.tag_format)?;
+                    entries += 1;
+                    entry_bytes += bytes.len();
+                    position = end;
+                }
+                MagicTag::Continued { .. } => return Err(Error::EntryCorrupt { position: here }),
+                MagicTag::Deleted { length } => {
+                    gaps.insert(Gap {
+                        at: here,
+                        length: length as usize,
+                        tag_len: (position - here) as u8,
+                    });
+                    position += length as usize;
+                }
+            }
+        }
+
+        let listed = self.gaps.iter().cloned().collect::<HashSet<_>>();
+        if let Some(position) = gaps.symmetric_difference(&listed).map(|g| g.at).min() {
+            return Err(Error::Inconsistent { position });
+        }
+        if entries != self.entries || entry_bytes != self.entry_bytes {
+            return Err(Error::Inconsistent {
+                position: self.header_length,
+            });
+        }
+        self.verified = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Backing;
+
+    #[test]
+    fn lazy_open_corrupted_tag() {
+        let mut s = RawStore::options().new(Backing::new_anon().unwrap()).unwrap();
+        let a = s.add(b"abc").unwrap();
+        let b = s.add(b"defgh").unwrap();
+        let c = s.add(b"ijk").unwrap();
+        s.remove(a, |_| ()).unwrap();
+        let tag = s.position(b);
+        let closed = s.close().unwrap().0;
+
+        let corrupted = |byte: u8| {
+            let mut bytes = closed.to_vec();
+            bytes[tag] = byte;
+            let mut s = RawStore::options().open(Backing::new_from_buffer(&bytes).unwrap()).unwrap();
+            assert!(!s.is_verified());
+            assert_eq!(s.get(c, <[u8]>::to_vec).unwrap(), b"ijk");
+            let e = s.verify().unwrap_err();
+            assert!(!s.is_verified());
+            e
+        };
+
+        let e = corrupted(0);
+        assert!(matches!(e, Error::UnknownTag { position, .. } if position == tag), "{e:?}");
+        // A Written tag turned into a Deleted one, which the summary doesn't list as a gap
+        let e = corrupted(closed[tag] ^ MagicTag::WRITTEN ^ MagicTag::DELETED);
+        assert!(matches!(e, Error::Inconsistent { position } if position == tag), "{e:?}");
+    }
+}
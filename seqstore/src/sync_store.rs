@@ -0,0 +1,251 @@
+use std::{
+    io::Read,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, PoisonError, RwLock, Weak,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::{error::Error, raw_store::RawStore, Id};
+
+/// A [`RawStore`] that can be shared between threads, with every operation taking `&self`.
+///
+/// Reads share the store with each other, while changes take it over entirely, so each change is
+/// made all at once as far as every other thread can tell: an [`Id`] returned by
+/// [`add`][Self::add] can be used straight away from any thread, and a [`get`][Self::get] never
+/// sees an item part way through being added or removed. Share it with an
+/// [`Arc`][std::sync::Arc], or see [`RawStore::split`] for a single writer with many readers.
+///
+/// With a [`FlushPolicy`][crate::raw_store::FlushPolicy] other than the default, a change that
+/// other threads can already see may not have been flushed yet, so should not be relied on to
+/// survive a crash until [`flush`][Self::flush] has returned.
+#[derive(Debug)]
+pub struct SyncStore {
+    store: RwLock<RawStore>,
+}
+
+impl SyncStore {
+    pub fn new(store: RawStore) -> Self {
+        Self {
+            store: RwLock::new(store),
+        }
+    }
+
+    /// Runs `f` with shared access to the store, for anything not covered by the other methods.
+    ///
+    /// Changes wait until `f` returns, so it should be kept short.
+    pub fn read<R>(&self, f: impl FnOnce(&RawStore) -> R) -> R {
+        f(&self.store.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Runs `f` with exclusive access to the store, for anything not covered by the other methods.
+    ///
+    /// Everything else waits until `f` returns, so it should be kept short.
+    pub fn write<R>(&self, f: impl FnOnce(&mut RawStore) -> R) -> R {
+        f(&mut self.store.write().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Stores `bytes`, as [`RawStore::add`].
+    pub fn add(&self, bytes: &[u8]) -> Result<Id, Error> {
+        self.write(|store| store.add(bytes))
+    }
+
+    /// Stores `length` bytes read from `reader`, as [`RawStore::add_from_reader`].
+    ///
+    /// Everything else waits while `reader` is read from, so it should not block for long.
+    pub fn add_from_reader(&self, length: usize, reader: impl Read) -> Result<Id, Error> {
+        self.write(|store| store.add_from_reader(length, reader))
+    }
+
+    /// Gets the data stored at `at`, as [`RawStore::get`].
+    pub fn get<R>(&self, at: Id, f: impl FnOnce(&[u8]) -> R) -> Result<R, Error> {
+        self.read(|store| store.get(at, f))
+    }
+
+    /// Gets the data stored at `at` into `buffer`, as [`RawStore::get_into`].
+    pub fn get_into(&self, at: Id, buffer: &mut Vec<u8>) -> Result<(), Error> {
+        self.read(|store| store.get_into(at, buffer))
+    }
+
+    /// Changes the data stored at `at` in place, as [`RawStore::update`].
+    pub fn update<R>(&self, at: Id, f: impl FnOnce(&mut [u8]) -> R) -> Result<R, Error> {
+        self.write(|store| store.update(at, f))
+    }
+
+    /// Removes the data at `at`, as [`RawStore::remove`].
+    pub fn remove<R>(&self, at: Id, f: impl FnOnce(&[u8]) -> R) -> Result<R, Error> {
+        self.write(|store| store.remove(at, f))
+    }
+
+    /// Replaces the data at `at`, as [`RawStore::replace_resize`].
+    pub fn replace_resize(&self, at: Id, bytes: &[u8]) -> Result<Id, Error> {
+        self.write(|store| store.replace_resize(at, bytes))
+    }
+
+    /// Flushes every change not yet flushed, as [`RawStore::flush`].
+    pub fn flush(&self) -> Result<(), Error> {
+        self.write(RawStore::flush)
+    }
+
+    pub fn into_inner(self) -> RawStore {
+        self.store.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Starts a thread that [`flush`][Self::flush]es the store every `interval` while there is
+    /// anything to flush, until the returned [`Flusher`] is stopped or dropped.
+    ///
+    /// Combined with [`FlushPolicy::Manual`][crate::raw_store::FlushPolicy::Manual], this takes
+    /// flushing off the path of every change, while still bounding how much can be lost in a crash
+    /// to roughly the last `interval` of changes. Changes still wait for a flush that is in
+    /// progress, as it needs exclusive access to the store.
+    ///
+    /// The thread only holds a weak reference to the store, and stops once the last [`Arc`] to it is
+    /// dropped (without a final flush).
+    pub fn spawn_flusher(self: &Arc<Self>, interval: Duration) -> Flusher {
+        let store = Arc::downgrade(self);
+        let (stop, stopped) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("seqstore-flusher".into())
+            .spawn(move || flush_every(&store, interval, &stopped))
+            .expect("failed to spawn flusher thread");
+        Flusher {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+fn flush_every(store: &Weak<SyncStore>, interval: Duration, stopped: &mpsc::Receiver<()>) -> Result<(), Error> {
+    loop {
+        let stopping = match stopped.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => false,
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+        };
+        let Some(store) = store.upgrade() else {
+            return Ok(());
+        };
+        if store.read(RawStore::has_unflushed) {
+            store.flush()?;
+        }
+        if stopping {
+            return Ok(());
+        }
+    }
+}
+
+/// A thread flushing a [`SyncStore`], started by [`SyncStore::spawn_flusher`].
+///
+/// Dropping this stops the thread like [`stop`][Self::stop], but ignores any error.
+#[derive(Debug)]
+pub struct Flusher {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<Result<(), Error>>>,
+}
+
+impl Flusher {
+    /// Stops the thread, after flushing the store one last time.
+    ///
+    /// If a flush fails, the thread stops there, and the error is returned from this.
+    pub fn stop(mut self) -> Result<(), Error> {
+        self.stop_inner()
+    }
+
+    /// Whether the thread has stopped by itself, because a flush failed or the store was dropped.
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+
+    fn stop_inner(&mut self) -> Result<(), Error> {
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+        // The thread may have already stopped
+        let _ = self.stop.take().map(|stop| stop.send(()));
+        match thread.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            let _ = self.stop_inner();
+        }
+    }
+}
+
+impl From<RawStore> for SyncStore {
+    fn from(store: RawStore) -> Self {
+        Self::new(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{raw_store::FlushPolicy, Backing};
+
+    #[test]
+    fn threads() {
+        let store = Arc::new(SyncStore::new(RawStore::options().new(Backing::new_anon().unwrap()).unwrap()));
+        let handles = (0..4_u8)
+            .map(|t| {
+                let store = Arc::clone(&store);
+                std::thread::spawn(move || {
+                    for i in 0..50_u8 {
+                        let id = store.add(&[t, i]).unwrap();
+                        assert_eq!(store.get(id, |b| b.to_vec()).unwrap(), [t, i]);
+                        if i % 2 == 0 {
+                            assert_eq!(store.remove(id, |b| b.to_vec()).unwrap(), [t, i]);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let mut s = Arc::into_inner(store).unwrap().into_inner();
+        assert_eq!(s.stats().entries, 100);
+        s.verify().unwrap();
+    }
+
+    #[test]
+    fn flusher() {
+        let options = RawStore::options().flush_policy(FlushPolicy::Manual).counters(true);
+        let store = Arc::new(SyncStore::new(options.new(Backing::new_anon().unwrap()).unwrap()));
+        let flusher = store.spawn_flusher(Duration::from_millis(1));
+        store.add(b"abc").unwrap();
+        let start = Instant::now();
+        while store.read(RawStore::has_unflushed) {
+            assert!(start.elapsed().as_secs() < 10, "never flushed");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!flusher.is_finished());
+
+        // The last changes are flushed on stopping
+        let flusher2 = store.spawn_flusher(Duration::from_secs(3600));
+        flusher.stop().unwrap();
+        store.add(b"def").unwrap();
+        assert!(store.read(RawStore::has_unflushed));
+        flusher2.stop().unwrap();
+        assert!(!store.read(RawStore::has_unflushed));
+        assert_eq!(store.read(|s| s.describe().counters.unwrap().adds), 2);
+
+        // And the thread stops by itself once the store is gone
+        let flusher = store.spawn_flusher(Duration::from_millis(1));
+        drop(store);
+        let start = Instant::now();
+        while !flusher.is_finished() {
+            assert!(start.elapsed().as_secs() < 10, "never stopped");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        flusher.stop().unwrap();
+    }
+}
@@ -2,12 +2,68 @@
 
 use crate::{backing::BackingInner, error::Error};
 
+// A tag is a single byte, laid out as [kind: 3 bits][length bytes: 2 bits][top of the length: 3 bits],
+// followed by the rest of the length (if any) as big-endian bytes. How many bytes the 2 bits stand for
+// depends on the store's TagFormat.
+
+/// How the lengths in a store's tags are laid out, which is chosen when the store is created (see
+/// [`OpenStoreOptions::large_entries`][crate::raw_store::OpenStoreOptions::large_entries]).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum TagFormat {
+    /// Up to 3 length bytes, so entries of up to 128 MiB fit in a single piece.
+    V1,
+    /// Up to 5 length bytes, so entries of up to 8 TiB fit in a single piece, at the cost of lengths
+    /// that would take 3 bytes in [`V1`][Self::V1] taking 5.
+    V2,
+}
+
+/// The bit set in the second byte of the header version for stores with tags in
+/// [`TagFormat::V2`].
+pub(crate) const HEADER_FLAG: u8 = 0b1000;
+
+impl TagFormat {
+    /// The format of a store with the given second byte of the header version.
+    pub(crate) fn from_flags(flags: u8) -> Self {
+        if flags & HEADER_FLAG != 0 {
+            Self::V2
+        } else {
+            Self::V1
+        }
+    }
+
+    /// The number of length bytes that each value of the 2 bits in the tag byte stands for.
+    pub(crate) fn length_bytes(self) -> [usize; 4] {
+        match self {
+            Self::V1 => [0, 1, 2, 3],
+            Self::V2 => [0, 1, 2, 5],
+        }
+    }
+
+    /// The longest length that can be stored in a single tag.
+    pub(crate) fn max_length(self) -> u64 {
+        (1 << (self.length_bytes()[3] * 8 + 3)) - 1
+    }
+
+    /// The value of the 2 bits in the tag byte for the fewest length bytes that `length` fits in.
+    fn length_bits(self, length: u64) -> u8 {
+        let needed_bits = 64 - length.leading_zeros();
+        let needed_bytes = needed_bits.saturating_sub(3).div_ceil(8) as usize; // 3 bits can be stored in tag
+        match self.length_bytes().iter().position(|&bytes| bytes >= needed_bytes) {
+            Some(bits) => bits as u8,
+            None => panic!("length is too large to store item [{length}]"),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub(crate) enum MagicTag {
     End,
     Writing { length: u64 },
     Written { length: u64 },
     Deleted { length: u64 },
+    /// Another piece of the entry before it, which is in the same state, for entries longer than
+    /// [`TagFormat::max_length`].
+    Continued { length: u64 },
 }
 
 impl MagicTag {
@@ -17,24 +73,25 @@ impl MagicTag {
     pub(crate) const WRITING: u8 = 0b101_00000;
     pub(crate) const WRITTEN: u8 = 0b100_00000;
     pub(crate) const DELETED: u8 = 0b110_00000;
+    pub(crate) const CONTINUED: u8 = 0b011_00000;
 
-    pub(crate) fn read(backing: &[u8], position: &mut usize) -> Result<Self, Error> {
-        fn read_with_length(tag: u8, backing: &[u8], position: &mut usize) -> Result<u64, Error> {
+    pub(crate) fn read(backing: &[u8], position: &mut usize, format: TagFormat) -> Result<Self, Error> {
+        fn read_with_length(tag: u8, backing: &[u8], position: &mut usize, format: TagFormat) -> Result<u64, Error> {
             let extra_bits = tag & 0b000_00_111;
-            let len_bytes = (tag & 0b000_11_000) >> 3;
+            let len_bytes = format.length_bytes()[((tag & 0b000_11_000) >> 3) as usize];
             if len_bytes == 0 {
                 return Ok(extra_bits as _);
             }
 
             // TODO: Return error if does not fit
-            let buffer = &backing[*position..*position + len_bytes as usize];
-            *position += len_bytes as usize;
+            let buffer = &backing[*position..*position + len_bytes];
+            *position += len_bytes;
             let mut bytes = [0; 8];
             for (i, byte) in buffer.iter().copied().enumerate() {
-                bytes[i + 8 - len_bytes as usize] = byte;
+                bytes[i + 8 - len_bytes] = byte;
             }
             if extra_bits != 0 {
-                bytes[7 - len_bytes as usize] = extra_bits;
+                bytes[7 - len_bytes] = extra_bits;
             }
             let n = u64::from_be_bytes(bytes);
             Ok(n)
@@ -45,133 +102,108 @@ impl MagicTag {
         match tag & Self::MASK {
             Self::END => Ok(Self::End),
             Self::WRITING => Ok(Self::Writing {
-                length: read_with_length(tag, backing, position)?,
+                length: read_with_length(tag, backing, position, format)?,
             }),
             Self::WRITTEN => Ok(Self::Written {
-                length: read_with_length(tag, backing, position)?,
+                length: read_with_length(tag, backing, position, format)?,
             }),
             Self::DELETED => Ok(Self::Deleted {
-                length: read_with_length(tag, backing, position)?,
+                length: read_with_length(tag, backing, position, format)?,
+            }),
+            Self::CONTINUED => Ok(Self::Continued {
+                length: read_with_length(tag, backing, position, format)?,
             }),
             _ => {
                 *position -= 1;
+                let mut surrounding = [0; 7];
+                for (i, byte) in surrounding.iter_mut().enumerate() {
+                    if let Some(&b) = (*position + i).checked_sub(3).and_then(|p| backing.get(p)) {
+                        *byte = b;
+                    }
+                }
                 Err(Error::UnknownTag {
                     position: *position,
                     byte: tag,
+                    surrounding,
                 })
             }
         }
     }
 
-    pub(crate) fn write(self, backing: &mut BackingInner, position: &mut usize) -> Result<(), Error> {
-        backing.resize_for(*position + self.written_length())?;
-        self.write_buffer(backing, position);
+    pub(crate) fn write(self, backing: &mut BackingInner, position: &mut usize, format: TagFormat) -> Result<(), Error> {
+        backing.resize_for(*position + self.written_length(format))?;
+        self.write_buffer(backing, position, format);
         Ok(())
     }
 
-    pub(crate) fn write_buffer(self, buffer: &mut [u8], position: &mut usize) {
-        fn write_with_length(buffer: &mut [u8], position: &mut usize, length: u64, tag: u8) {
-            if length != 0 {
-                let needed_bits = 64 - length.leading_zeros();
-                let needed_bytes = needed_bits.saturating_sub(3).div_ceil(8); // 3 bits can be stored in tag
-
-                assert!(needed_bytes <= 0b11, "length is too large to store item [{length}]");
-                let mut bytes = length.to_be_bytes();
-                let tag_extra_bytes = (needed_bytes as u8) << 3;
-                let bytes = if needed_bits % 8 <= 3 && needed_bits % 8 > 0 {
-                    let tag_byte_idx = length.leading_zeros() as usize / 8;
-                    assert_eq!(bytes[tag_byte_idx] & !0b111, 0, "tag overflowed its bounds: {length}");
-                    bytes[tag_byte_idx] = bytes[tag_byte_idx] | tag | tag_extra_bytes;
-                    &bytes[tag_byte_idx..]
-                } else {
-                    let tag_byte_idx = (length.leading_zeros() as usize / 8)
-                        .checked_sub(1)
-                        .expect("should be caught by size check");
-                    debug_assert_eq!(bytes[tag_byte_idx], 0);
-                    bytes[tag_byte_idx] = tag | tag_extra_bytes;
-                    &bytes[tag_byte_idx..]
-                };
-
-                buffer[*position..*position + bytes.len()].copy_from_slice(bytes);
-                *position += bytes.len();
-            } else {
-                buffer[*position..*position + 1].copy_from_slice(&[tag]);
-                *position += 1;
-            }
-        }
-
-        match self {
-            Self::End => {
+    pub(crate) fn write_buffer(self, buffer: &mut [u8], position: &mut usize, format: TagFormat) {
+        match self.kind_and_length() {
+            Some((tag, length)) => write_with_length(buffer, position, tag, length, format.length_bits(length), format),
+            None => {
                 buffer[*position..*position + 1].copy_from_slice(&[Self::END]);
                 *position += 1;
             }
-            Self::Writing { length } => write_with_length(buffer, position, length, Self::WRITING),
-            Self::Written { length } => write_with_length(buffer, position, length, Self::WRITTEN),
-            Self::Deleted { length } => write_with_length(buffer, position, length, Self::DELETED),
         }
     }
 
-    pub(crate) fn write_exact(self, backing: &mut BackingInner, position: &mut usize, tag_len: usize) -> Result<(), Error> {
-        assert!(tag_len <= 0b11 + 1, "length is too large to store item");
-        let (tag, len) = match self {
-            Self::Writing { length } => (Self::WRITING, length),
-            Self::Written { length } => (Self::WRITTEN, length),
-            Self::Deleted { length } => (Self::DELETED, length),
-            _ => panic!("unsupported: {self:?}"),
+    /// Writes this tag taking up exactly `tag_len` bytes, which can be more than it needs (see
+    /// [`calc_tag_len`][Self::calc_tag_len]).
+    pub(crate) fn write_exact(self, backing: &mut BackingInner, position: &mut usize, tag_len: usize, format: TagFormat) -> Result<(), Error> {
+        let Some((tag, len)) = self.kind_and_length() else {
+            panic!("unsupported: {self:?}")
         };
-
-        let needed_bits = 64 - len.leading_zeros();
-        let needed_bytes = needed_bits.saturating_sub(3).div_ceil(8); // 3 bits can be stored in tag
-        if 1 + needed_bytes > tag_len as _ {
-            panic!("required {} bytes, have {}", 1 + needed_bytes, tag_len)
+        let Some(bits) = format.length_bytes().iter().position(|&bytes| 1 + bytes == tag_len) else {
+            panic!("no tag is {tag_len} bytes long in {format:?}")
+        };
+        let needed = self.written_length(format);
+        if needed > tag_len {
+            panic!("required {needed} bytes, have {tag_len}")
         }
+        backing.resize_for(*position + tag_len)?;
+        write_with_length(backing, position, tag, len, bits as u8, format);
+        Ok(())
+    }
 
-        if self.written_length() == tag_len {
-            return self.write(backing, position);
+    pub(crate) fn written_length(self, format: TagFormat) -> usize {
+        match self.kind_and_length() {
+            Some((_, length)) => 1 + format.length_bytes()[format.length_bits(length) as usize],
+            None => 1,
         }
+    }
 
-        if len > 0b11111111_11111111_11111111 {
-            // TODO: Is this not caught by the above check?
-            let start = *position;
-            self.write(backing, position)?;
-            assert_eq!(*position, start + tag_len);
+    /// The length of the tag, and of what it covers, for a tag covering `total_len` bytes
+    /// altogether.
+    pub(crate) fn calc_tag_len(total_len: usize, format: TagFormat) -> (u8, usize) {
+        for tag_len in format.length_bytes().map(|bytes| 1 + bytes) {
+            let new_len = total_len - tag_len;
+            if new_len as u64 <= format.max_length() && (MagicTag::Writing { length: new_len as _ }).written_length(format) <= tag_len {
+                return (tag_len as u8, new_len);
+            }
         }
-
-        // Otherwise, we don't _need_ to pack any bits in the tag
-        let populated_bytes = needed_bits.div_ceil(8);
-        assert!(populated_bytes <= 0b11, "length is too large to store item");
-        let mut b = len.to_be_bytes();
-        b[7 - populated_bytes as usize] = tag | ((populated_bytes as u8) << 3);
-        backing.write(&b[7 - populated_bytes as usize..], position)?;
-        Ok(())
+        panic!("tag length overflow")
     }
 
-    pub(crate) fn written_length(self) -> usize {
+    /// The bits of the tag byte for this kind of tag, and its length, for every tag but End.
+    fn kind_and_length(self) -> Option<(u8, u64)> {
         match self {
-            MagicTag::End => 1,
-            MagicTag::Writing { length } | MagicTag::Written { length } | MagicTag::Deleted { length } => {
-                let needed_bits = 64 - length.leading_zeros();
-                let needed_bytes = needed_bits.saturating_sub(3).div_ceil(8); // 3 bits can be stored in tag
-                1 + needed_bytes as usize
-            }
+            Self::End => None,
+            Self::Writing { length } => Some((Self::WRITING, length)),
+            Self::Written { length } => Some((Self::WRITTEN, length)),
+            Self::Deleted { length } => Some((Self::DELETED, length)),
+            Self::Continued { length } => Some((Self::CONTINUED, length)),
         }
     }
+}
 
-    pub(crate) fn calc_tag_len(total_len: usize) -> (u8, usize) {
-        let mut tag_len = 1;
-        let new_len = loop {
-            if tag_len > 4 {
-                panic!("tag length overflow")
-            }
-            let new_len = total_len - tag_len;
-            if (MagicTag::Writing { length: new_len as _ }).written_length() <= tag_len {
-                break new_len;
-            }
-            tag_len += 1;
-        };
-        assert!(tag_len + new_len <= total_len);
-        (tag_len as u8, total_len - tag_len)
-    }
+/// Writes the tag byte `tag` followed by `length`, using as many length bytes as `bits` stands for in
+/// `format` (and the top 3 bits of the tag byte for the rest).
+fn write_with_length(buffer: &mut [u8], position: &mut usize, tag: u8, length: u64, bits: u8, format: TagFormat) {
+    let len_bytes = format.length_bytes()[bits as usize];
+    let top = length >> (len_bytes * 8);
+    assert!(top <= 0b111, "tag overflowed its bounds: {length}");
+    buffer[*position] = tag | (bits << 3) | top as u8;
+    buffer[*position + 1..*position + 1 + len_bytes].copy_from_slice(&length.to_be_bytes()[8 - len_bytes..]);
+    *position += 1 + len_bytes;
 }
 
 #[cfg(test)]
@@ -183,7 +215,14 @@ mod tests {
 
     #[test]
     fn no_overlap() {
-        let items = [MagicTag::END, MagicTag::WRITING, MagicTag::WRITTEN, MagicTag::DELETED, 0];
+        let items = [
+            MagicTag::END,
+            MagicTag::WRITING,
+            MagicTag::WRITTEN,
+            MagicTag::DELETED,
+            MagicTag::CONTINUED,
+            0,
+        ];
         let iter = items.iter().copied().enumerate().flat_map(|(i, t)| {
             items
                 .iter()
@@ -205,39 +244,61 @@ mod tests {
     fn max_size() {
         let max_size = 0x7_FF_FF_FF;
         assert_eq!(134217728, max_size + 1);
+        assert_eq!(TagFormat::V1.max_length(), max_size);
+        let mut backing = Backing::new_anon().unwrap().0;
+        MagicTag::Writing { length: max_size }.write(&mut backing, &mut 0, TagFormat::V1).unwrap();
+        MagicTag::Writing { length: max_size + 1 }.write(&mut backing, &mut 0, TagFormat::V1).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "length is too large to store item [8796093022208]")]
+    fn max_size_v2() {
+        let max_size = TagFormat::V2.max_length();
+        assert_eq!(max_size + 1, 8 << 40);
+        check_write_length(max_size, TagFormat::V2);
         let mut backing = Backing::new_anon().unwrap().0;
-        MagicTag::Writing { length: max_size }.write(&mut backing, &mut 0).unwrap();
-        MagicTag::Writing { length: max_size + 1 }.write(&mut backing, &mut 0).unwrap();
+        MagicTag::Writing { length: max_size + 1 }.write(&mut backing, &mut 0, TagFormat::V2).unwrap();
+    }
+
+    /// Every length in [`LENGTHS`], and the longest lengths that fit in each number of length bytes,
+    /// in every format.
+    fn lengths() -> impl Iterator<Item = (u64, TagFormat)> {
+        [TagFormat::V1, TagFormat::V2].into_iter().flat_map(|format| {
+            let longest = format.length_bytes().map(|bytes| (1 << (bytes * 8 + 3)) - 1);
+            LENGTHS.iter().copied().chain(longest).map(move |length| (length, format))
+        })
     }
 
     #[inline(always)]
-    fn check_write_length(length: u64) {
+    fn check_write_length(length: u64, format: TagFormat) {
         let mut backing = Backing::new_anon().unwrap().0;
-        MagicTag::Writing { length }.write(&mut backing, &mut 0).unwrap();
-        let r = MagicTag::read(&backing, &mut 0).unwrap();
+        MagicTag::Writing { length }.write(&mut backing, &mut 0, format).unwrap();
+        let r = MagicTag::read(&backing, &mut 0, format).unwrap();
         assert_eq!(r, MagicTag::Writing { length });
     }
 
     #[inline(always)]
-    fn check_write_exact(length: u64, n: usize) {
-        if (MagicTag::Writing { length }).written_length() <= n {
+    fn check_write_exact(length: u64, n: usize, format: TagFormat) {
+        if (MagicTag::Writing { length }).written_length(format) <= n {
             let mut backing = Backing::new_anon().unwrap().0;
-            MagicTag::Writing { length }.write_exact(&mut backing, &mut 0, n).unwrap();
-            let r = MagicTag::read(&backing, &mut 0).unwrap();
+            let mut position = 0;
+            MagicTag::Writing { length }.write_exact(&mut backing, &mut position, n, format).unwrap();
+            assert_eq!(position, n);
+            let r = MagicTag::read(&backing, &mut 0, format).unwrap();
             assert_eq!(r, MagicTag::Writing { length });
         }
     }
 
     #[test]
     fn test_buffer_write() {
-        let mut buffer = [0; 4];
+        let mut buffer = [0; 6];
         let mut backing = Backing::new_anon().unwrap().0;
-        for &length in LENGTHS {
+        for (length, format) in lengths() {
             let o = MagicTag::Writing { length };
-            o.write_buffer(&mut buffer, &mut 0);
-            o.write(&mut backing, &mut 0).unwrap();
-            let t = MagicTag::read(&buffer, &mut 0).unwrap();
-            let r = MagicTag::read(&backing, &mut 0).unwrap();
+            o.write_buffer(&mut buffer, &mut 0, format);
+            o.write(&mut backing, &mut 0, format).unwrap();
+            let t = MagicTag::read(&buffer, &mut 0, format).unwrap();
+            let r = MagicTag::read(&backing, &mut 0, format).unwrap();
             assert_eq!(t, o);
             assert_eq!(t, r);
         }
@@ -245,44 +306,72 @@ mod tests {
 
     #[test]
     fn test_writing_length() {
-        for &length in LENGTHS {
-            check_write_length(length);
+        for (length, format) in lengths() {
+            check_write_length(length, format);
         }
     }
 
     #[test]
     fn test_exact() {
-        for &length in LENGTHS {
-            for n in 0..=0b11 + 1 {
-                check_write_exact(length, n);
+        for (length, format) in lengths() {
+            for n in format.length_bytes().map(|bytes| 1 + bytes) {
+                check_write_exact(length, n, format);
             }
         }
     }
 
     #[test]
     fn test_no_further_length() {
-        for &length in LENGTHS {
+        for (length, format) in lengths() {
             let mut backing = Backing::new_anon().unwrap().0;
             let mut written = 0;
-            MagicTag::Writing { length }.write(&mut backing, &mut written).unwrap();
+            MagicTag::Writing { length }.write(&mut backing, &mut written, format).unwrap();
             let mut read = 0;
-            MagicTag::read(&backing, &mut read).unwrap();
+            MagicTag::read(&backing, &mut read, format).unwrap();
             assert_eq!(written, read);
         }
     }
 
     #[test]
     fn test_computed_length() {
-        for &length in LENGTHS {
+        for (length, format) in lengths() {
             let mut backing = Backing::new_anon().unwrap().0;
             let tag = MagicTag::Writing { length };
             let mut position = 0;
-            tag.write(&mut backing, &mut position).unwrap();
-            assert_eq!(position, tag.written_length());
+            tag.write(&mut backing, &mut position, format).unwrap();
+            assert_eq!(position, tag.written_length(format));
             position = 0;
-            let tag2 = MagicTag::read(&backing, &mut position).unwrap();
-            assert_eq!(position, tag.written_length());
+            let tag2 = MagicTag::read(&backing, &mut position, format).unwrap();
+            assert_eq!(position, tag.written_length(format));
             assert_eq!(tag, tag2);
         }
     }
+
+    #[test]
+    fn formats() {
+        let write = |length, format| {
+            let mut buffer = [0; 6];
+            let mut position = 0;
+            MagicTag::Written { length }.write_buffer(&mut buffer, &mut position, format);
+            buffer[..position].to_vec()
+        };
+        // Only lengths that need 3 length bytes in V1 are written differently
+        for length in [5, 0x7_FF, 0x7_FF_FF] {
+            assert_eq!(write(length, TagFormat::V1), write(length, TagFormat::V2));
+        }
+        assert_eq!(write(0x7_FF_FF_FF, TagFormat::V1), [0b100_11_111, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(write(0x7_FF_FF_FF, TagFormat::V2), [0b100_11_000, 0, 0x07, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(write(0x7_FF_FF_FF_FF_FF, TagFormat::V2), [0b100_11_111, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+        assert_eq!(TagFormat::from_flags(0b101), TagFormat::V1);
+        assert_eq!(TagFormat::from_flags(0b101 | HEADER_FLAG), TagFormat::V2);
+        for format in [TagFormat::V1, TagFormat::V2] {
+            // Every total length can be covered by a single tag, as long as what it covers fits
+            for total in (1..100).chain([0x80_000, 0x80_003, 0x80_005, 0x80_006]) {
+                let (tag_len, length) = MagicTag::calc_tag_len(total, format);
+                assert_eq!(tag_len as usize + length, total);
+                check_write_exact(length as u64, tag_len as usize, format);
+            }
+        }
+    }
 }